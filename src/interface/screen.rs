@@ -5,16 +5,24 @@ use crate::message_structs::block_message::BlockMessage;
 use crate::message_structs::common_traits::csv_format::CSVFormat;
 use crate::message_structs::tx_message::TXMessage;
 use crate::node::interface::interface_communicator::InterfaceMessages;
-use crate::utils::array_tools::u8_array_to_hex_string;
+use crate::node::wallets::encryption::UNLOCK_TIMEOUT_SECS;
+use crate::node::wallets::keys_handler::AddressType;
+use crate::node::wallets::payment_request::PaymentUri;
+use crate::node::wallets::payments::{parse_payments_csv, total_amount, Payment, MAX_MONEY};
+use crate::node::wallets::wallet_handler::{HistoryRow, PaymentRequestRow, UtxoRow};
+use crate::utils::array_tools::txid_to_hex;
 use chrono::Utc;
 use glib::{self, Receiver};
 use gtk::{
-    prelude::*, ButtonsType, ComboBoxText, DialogFlags, Entry, Fixed, ListStore, MenuItem,
-    MessageDialog, MessageType, ScrolledWindow, SpinButton, TreePath, TreeView, WindowType, ProgressBar,
+    prelude::*, ButtonsType, CheckButton, ComboBoxText, Dialog, DialogFlags, Entry,
+    FileChooserAction, FileChooserDialog, Fixed, Grid, ListStore, MenuItem, MessageDialog,
+    MessageType, ResponseType, ScrolledWindow, SpinButton, TextView, TreePath, TreeView,
+    WindowType, ProgressBar,
 };
 use gtk::{Adjustment, Builder, Button, Label, Window};
 use std::collections::HashMap;
 use std::default::Default;
+use std::fs;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 
@@ -22,6 +30,9 @@ const HEADER_COLUMNS: usize = 6;
 const TRANSACTIONS_COLUMNS: usize = 5;
 const MY_CREATED_TRANSACTIONS_COLUMNS: usize = 3;
 const BLOCKS_COLUMNS: usize = 4;
+const HISTORY_COLUMNS: usize = 7;
+const HISTORY_LABEL_COLUMN: u32 = 6;
+const REQUESTS_COLUMNS: usize = 6;
 
 pub struct WidgetsGtk {
     tree_view_headers: TreeView,
@@ -254,11 +265,12 @@ impl Screen {
 
     /// Handle GTK Objects only by actions ----------------------------------
 
-    /// Create the window to add a new wallet.
+    /// Create the window to add a new wallet, from a private key, a new mnemonic, restoring a mnemonic
+    /// or from the keys of a multisig.
     fn add_wallet(add_wallet_order: Sender<InterfaceMessages>) {
         let window = Window::new(WindowType::Toplevel);
         window.set_title("New Wallet");
-        window.set_default_size(400, 300);
+        window.set_default_size(450, 590);
         let fixed = Fixed::new();
         window.add(&fixed);
         let name_entry = Entry::new();
@@ -273,25 +285,180 @@ impl Screen {
         key_entry.set_visible(true);
         key_entry.set_can_focus(true);
         key_entry.set_widget_name("key_wallet_entry");
-        fixed.put(&key_entry, 221, 103);
-        let key_label = Label::new(Some("Private Key:"));
-        fixed.put(&key_label, 61, 110);
+        key_entry.set_tooltip_text(Some(
+            "Private key (hex or WIF), xprv, or an xpub, descriptors or addresses to watch",
+        ));
+        fixed.put(&key_entry, 221, 80);
+        let key_label = Label::new(Some("Key or addresses:"));
+        fixed.put(&key_label, 61, 87);
         let create_button = Button::with_label("Create Wallet");
         create_button.set_visible(true);
         create_button.set_can_focus(true);
         create_button.set_widget_name("button_create_wallet");
-        fixed.put(&create_button, 154, 165);
+        fixed.put(&create_button, 154, 125);
+        let mnemonic_entry = Entry::new();
+        mnemonic_entry.set_visible(true);
+        mnemonic_entry.set_can_focus(true);
+        mnemonic_entry.set_widget_name("mnemonic_wallet_entry");
+        fixed.put(&mnemonic_entry, 221, 190);
+        let mnemonic_label = Label::new(Some("Recovery words:"));
+        fixed.put(&mnemonic_label, 61, 197);
+        let passphrase_entry = Entry::new();
+        passphrase_entry.set_visible(true);
+        passphrase_entry.set_can_focus(true);
+        passphrase_entry.set_visibility(false);
+        passphrase_entry.set_widget_name("passphrase_wallet_entry");
+        fixed.put(&passphrase_entry, 221, 235);
+        let passphrase_label = Label::new(Some("Passphrase (optional):"));
+        fixed.put(&passphrase_label, 41, 242);
+        let words_combo = ComboBoxText::new();
+        words_combo.append_text("12");
+        words_combo.append_text("24");
+        words_combo.set_active(Some(0));
+        fixed.put(&words_combo, 221, 280);
+        let words_label = Label::new(Some("Words:"));
+        fixed.put(&words_label, 61, 287);
+        let generate_button = Button::with_label("New Recovery Words");
+        generate_button.set_widget_name("button_generate_mnemonic");
+        fixed.put(&generate_button, 61, 330);
+        let restore_button = Button::with_label("Restore Wallet");
+        restore_button.set_widget_name("button_restore_wallet");
+        fixed.put(&restore_button, 251, 330);
+        let multisig_entry = Entry::new();
+        multisig_entry.set_visible(true);
+        multisig_entry.set_can_focus(true);
+        multisig_entry.set_widget_name("multisig_wallet_entry");
+        multisig_entry.set_tooltip_text(Some(
+            "Public keys or xpubs of the participants, separated by commas",
+        ));
+        fixed.put(&multisig_entry, 221, 395);
+        let multisig_label = Label::new(Some("Multisig keys:"));
+        fixed.put(&multisig_label, 61, 402);
+        let threshold_spin = SpinButton::with_range(1.0, 16.0, 1.0);
+        fixed.put(&threshold_spin, 221, 440);
+        let threshold_label = Label::new(Some("Signatures needed:"));
+        fixed.put(&threshold_label, 51, 447);
+        let script_combo = ComboBoxText::new();
+        script_combo.append_text("P2WSH");
+        script_combo.append_text("P2SH");
+        script_combo.set_active(Some(0));
+        fixed.put(&script_combo, 221, 485);
+        let script_label = Label::new(Some("Script type:"));
+        fixed.put(&script_label, 61, 492);
+        let multisig_button = Button::with_label("Create Multisig Wallet");
+        multisig_button.set_widget_name("button_multisig_wallet");
+        fixed.put(&multisig_button, 134, 530);
+
+        let sender = add_wallet_order.clone();
+        let (name, key) = (name_entry.clone(), key_entry.clone());
         create_button.connect_clicked(move |_| {
-            let wallet_name = name_entry.get_text().to_string();
-            let private_key = key_entry.get_text().to_string();
-            name_entry.set_text("");
-            key_entry.set_text("");
+            let wallet_name = name.get_text().to_string();
+            let private_key = key.get_text().to_string();
+            name.set_text("");
+            key.set_text("");
             let message = InterfaceMessages::AddWalletOrder((wallet_name, private_key));
+            if sender.send(message).is_ok() {}
+        });
+        let sender = add_wallet_order.clone();
+        let (name, keys) = (name_entry.clone(), multisig_entry.clone());
+        multisig_button.connect_clicked(move |_| {
+            let segwit = script_combo
+                .get_active_text()
+                .is_none_or(|text| text == "P2WSH");
+            let message = InterfaceMessages::CreateMultisigWallet((
+                name.get_text().to_string(),
+                threshold_spin.get_value_as_int() as usize,
+                keys.get_text().to_string(),
+                segwit,
+            ));
+            name.set_text("");
+            keys.set_text("");
+            if sender.send(message).is_ok() {}
+        });
+        let sender = add_wallet_order.clone();
+        let (name, passphrase) = (name_entry.clone(), passphrase_entry.clone());
+        generate_button.connect_clicked(move |_| {
+            let word_count = match words_combo.get_active_text() {
+                Some(text) => text.parse().unwrap_or(12),
+                None => 12,
+            };
+            let message = InterfaceMessages::CreateMnemonicWallet((
+                name.get_text().to_string(),
+                word_count,
+                passphrase.get_text().to_string(),
+            ));
+            name.set_text("");
+            passphrase.set_text("");
+            if sender.send(message).is_ok() {}
+        });
+        restore_button.connect_clicked(move |_| {
+            let message = InterfaceMessages::RestoreWallet((
+                name_entry.get_text().to_string(),
+                mnemonic_entry.get_text().to_string(),
+                passphrase_entry.get_text().to_string(),
+            ));
+            name_entry.set_text("");
+            mnemonic_entry.set_text("");
+            passphrase_entry.set_text("");
             if add_wallet_order.send(message).is_ok() {}
         });
         window.show_all();
     }
 
+    /// Shows a modal dialog with a hidden entry for each label and returns their texts, None if cancelled.
+    fn passphrase_dialog(title: &str, labels: &[&str]) -> Option<Vec<String>> {
+        let dialog = Dialog::with_buttons(
+            Some(title),
+            None::<&Window>,
+            DialogFlags::MODAL,
+            &[("Ok", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
+        );
+        let content = dialog.get_content_area();
+        let mut entries = Vec::new();
+        for text in labels {
+            let label = Label::new(Some(text));
+            content.add(&label);
+            let entry = Entry::new();
+            entry.set_visibility(false);
+            entry.set_activates_default(true);
+            content.add(&entry);
+            entries.push(entry);
+        }
+        dialog.set_default_response(ResponseType::Ok);
+        dialog.show_all();
+        let response = dialog.run();
+        let values: Vec<String> = entries.iter().map(|e| e.get_text().to_string()).collect();
+        dialog.close();
+        if response != ResponseType::Ok || values.iter().any(|value| value.is_empty()) {
+            return None;
+        }
+        Some(values)
+    }
+
+    /// Asks for the passphrase before signing the pending transaction
+    fn unlock_dialog(
+        transaction_order: (String, String, i32, i32),
+        sender_to_node: &Sender<InterfaceMessages>,
+    ) {
+        if let Some(values) = Self::passphrase_dialog("Unlock wallets", &["Passphrase:"]) {
+            let message = InterfaceMessages::UnlockWallets((
+                values[0].clone(),
+                UNLOCK_TIMEOUT_SECS,
+                Some(transaction_order),
+            ));
+            if sender_to_node.send(message).is_ok() {}
+        }
+    }
+
+    /// Asks for the passphrase of the encrypted wallets file to open the wallets
+    fn open_wallets_dialog(sender_to_node: &Sender<InterfaceMessages>) {
+        if let Some(values) = Self::passphrase_dialog("Open wallets", &["Passphrase:"]) {
+            let message =
+                InterfaceMessages::UnlockWallets((values[0].clone(), UNLOCK_TIMEOUT_SECS, None));
+            _ = sender_to_node.send(message);
+        }
+    }
+
     fn create_popup() -> MessageDialog {
         MessageDialog::new(
             None::<&gtk::Window>,
@@ -340,7 +507,7 @@ impl Screen {
         let datetime = current_datetime + chrono::Duration::seconds(time as i64);
         let formatted_datetime = datetime.format("%d/%m/%Y %H:%M:%S").to_string();
         values.push(formatted_datetime);
-        let label = u8_array_to_hex_string(&transaction.get_id());
+        let label = txid_to_hex(&transaction.get_id());
         let amounts = transaction.get_output_amounts();
         println!(
             "mi transaccion tiene {:?} y el amount es {:?} y id {:?}",
@@ -376,7 +543,7 @@ impl Screen {
         let datetime = current_datetime + chrono::Duration::seconds(time as i64);
         let formatted_datetime = datetime.format("%d/%m/%Y %H:%M:%S").to_string();
         values.push(formatted_datetime);
-        let label = u8_array_to_hex_string(&transaction.1.get_id());
+        let label = txid_to_hex(&transaction.1.get_id());
         values.push(label);
         let amounts = transaction.1.get_output_amounts();
         let mut amount_values = Vec::new();
@@ -405,35 +572,822 @@ impl Screen {
         */
     }
 
+    /// Reads the send entries: address, label, amount and fee rate in sat/vB.
+    /// None if the amount is dust or the fee rate below 1 sat/vB.
+    fn transaction_order(builder: &Builder) -> Option<(String, String, i32, i32)> {
+        let entry_address: Option<Entry> = builder.get_object("entry_bitcoin_address");
+        let entry_label: Option<Entry> = builder.get_object("entry_label");
+        let entry_amount: Option<Entry> = builder.get_object("entry_amount");
+        let entry_fee: Option<Entry> = builder.get_object("entry_fee");
+        let address = Self::get_text_from_entry(entry_address);
+        let label = Self::get_text_from_entry(entry_label);
+        let amount = Self::get_text_from_entry(entry_amount);
+        let fee = Self::get_text_from_entry(entry_fee);
+        if let Ok(am) = amount.parse::<i32>() {
+            if let Ok(fe) = fee.parse::<i32>() {
+                if am >= 546 && fe >= 1 {
+                    return Some((address, label, am, fe));
+                }
+            }
+        }
+        None
+    }
+
     /// Sends the signal to the node to create a transaction with the provided data.
+    /// A BIP21 URI pasted as the address fills the send entries with its data.
     fn send_button(&mut self, send_transaction: Sender<InterfaceMessages>) {
         let button: Option<Button> = self.builder.get_object("button_send");
         let builder_aux = self.builder.clone();
         let sender_clone = send_transaction;
+        let entry_address: Option<Entry> = self.builder.get_object("entry_bitcoin_address");
+        if let Some(entry) = entry_address {
+            let builder = self.builder.clone();
+            entry.connect_changed(move |entry| {
+                let text = entry.get_text().to_string();
+                if !text.trim().to_lowercase().starts_with("bitcoin:") {
+                    return;
+                }
+                if let Ok(uri) = PaymentUri::parse(&text) {
+                    Self::fill_send_entries(&builder, &uri);
+                }
+            });
+        }
         if let Some(b) = button {
             b.set_sensitive(false);
             b.connect_clicked(move |_| {
-                let entry_address: Option<Entry> = builder_aux.get_object("entry_bitcoin_address");
-                let entry_label: Option<Entry> = builder_aux.get_object("entry_label");
-                let entry_amount: Option<Entry> = builder_aux.get_object("entry_amount");
-                let entry_fee: Option<Entry> = builder_aux.get_object("entry_fee");
-                let address = Self::get_text_from_entry(entry_address);
-                let label = Self::get_text_from_entry(entry_label);
-                let amount = Self::get_text_from_entry(entry_amount);
-                let fee = Self::get_text_from_entry(entry_fee);
-                if let Ok(am) = amount.parse::<i32>() {
-                    if let Ok(fe) = fee.parse::<i32>() {
-                        if am >= 546 && fe >= 1000 {
-                            let message =
-                                InterfaceMessages::SendTransaction((address, label, am, fe));
-                            if sender_clone.send(message).is_ok() {}
-                        }
+                if let Some(order) = Self::transaction_order(&builder_aux) {
+                    let message = InterfaceMessages::SendTransaction(order);
+                    if sender_clone.send(message).is_ok() {}
+                }
+            });
+        }
+    }
+
+    /// Sets the send entries to the address, amount and label of a payment URI,
+    /// its message is the label if it has none. Sends pay up to i32::MAX satoshis,
+    /// a larger amount is left out to be entered by hand.
+    fn fill_send_entries(builder: &Builder, uri: &PaymentUri) {
+        let label = match uri.label.is_empty() {
+            true => &uri.message,
+            false => &uri.label,
+        };
+        let amount = match uri.amount.map(i32::try_from) {
+            Some(Ok(amount)) => amount.to_string(),
+            _ => String::new(),
+        };
+        let values = [
+            ("entry_bitcoin_address", uri.address.clone()),
+            ("entry_label", label.clone()),
+            ("entry_amount", amount),
+        ];
+        for (id, value) in values.iter() {
+            let entry: Option<Entry> = builder.get_object(id);
+            if let Some(entry) = entry {
+                entry.set_text(value);
+            }
+        }
+    }
+
+    /// Handles the receive tab: the button that creates a payment request with a fresh address
+    /// and the list of requests, which is refreshed every time the tab is shown.
+    fn receive_tab(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let tree_view: Option<TreeView> = self.builder.get_object("list_requests");
+        if let Some(tree_view) = tree_view {
+            Self::set_columns(
+                &tree_view,
+                vec!["Address", "Amount", "Label", "Date", "Status", "URI"],
+            );
+        }
+        let button: Option<Button> = self.builder.get_object("button_request");
+        if let Some(button) = button {
+            let builder = self.builder.clone();
+            let sender = sender_to_node.clone();
+            button.connect_clicked(move |_| {
+                let amount_entry: Option<Entry> = builder.get_object("entry_request_amount");
+                let amount = Self::get_text_from_entry(amount_entry);
+                let amount = match amount.trim() {
+                    "" => None,
+                    amount => match amount.parse::<i64>() {
+                        Ok(amount) if amount > 0 => Some(amount),
+                        _ => return,
+                    },
+                };
+                let label = Self::get_text_from_entry(builder.get_object("entry_request_label"));
+                let message =
+                    Self::get_text_from_entry(builder.get_object("entry_request_message"));
+                let order = InterfaceMessages::CreatePaymentRequest((amount, label, message));
+                _ = sender.send(order);
+            });
+        }
+        let stack: Option<gtk::Stack> = self.builder.get_object("stack1");
+        if let Some(stack) = stack {
+            stack.connect_property_visible_child_name_notify(move |stack| {
+                if stack.get_visible_child_name().as_deref() != Some("receive") {
+                    return;
+                }
+                _ = sender_to_node.send(InterfaceMessages::ListPaymentRequests(()));
+            });
+        }
+    }
+
+    /// Shows the payment requests of the actual wallet, the newest first with its URI
+    /// over the list to be copied.
+    fn show_payment_requests(builder: &Builder, rows: Vec<PaymentRequestRow>) {
+        let uri = rows.first().map(|row| row.5.clone()).unwrap_or_default();
+        let uri_label: Option<Label> = builder.get_object("label_request_uri");
+        if let Some(uri_label) = uri_label {
+            uri_label.set_text(&uri);
+        }
+        let tree_view: Option<TreeView> = builder.get_object("list_requests");
+        let tree_view = match tree_view {
+            Some(tree_view) => tree_view,
+            None => return,
+        };
+        let list = gtk::ListStore::new(&vec![String::static_type(); REQUESTS_COLUMNS][..]);
+        for (address, amount, label, created, paid, uri) in rows.iter() {
+            let date = chrono::NaiveDateTime::from_timestamp_opt(*created as i64, 0)
+                .map(|date| date.format("%d/%m/%Y %H:%M:%S").to_string())
+                .unwrap_or_default();
+            let status = if *paid { "Paid" } else { "Pending" };
+            let values = [
+                address.clone(),
+                amount.map(|amount| amount.to_string()).unwrap_or_default(),
+                label.clone(),
+                date,
+                status.to_string(),
+                uri.clone(),
+            ];
+            let row = list.append();
+            for (i, value) in values.iter().enumerate() {
+                list.set_value(&row, i as u32, &value.to_value());
+            }
+        }
+        tree_view.set_model(Some(&list));
+    }
+
+    /// Handles the button that creates a PSBT with the send entries and the menu item that
+    /// opens the PSBT window to sign, combine or finalize PSBTs made elsewhere.
+    fn psbt_buttons(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let create_button: Option<Button> = self.builder.get_object("button_create_psbt");
+        if let Some(b) = create_button {
+            let builder_aux = self.builder.clone();
+            let sender = sender_to_node.clone();
+            b.connect_clicked(move |_| {
+                if let Some(order) = Self::transaction_order(&builder_aux) {
+                    if sender.send(InterfaceMessages::CreatePsbt(order)).is_ok() {}
+                }
+            });
+        }
+        let psbt_item: Option<MenuItem> = self.builder.get_object("psbt");
+        if let Some(item) = psbt_item {
+            item.connect_activate(move |_| {
+                Self::psbt_window("", &sender_to_node);
+            });
+        }
+    }
+
+    /// Shows a window with the PSBT in base64 to copy it or paste others. Combine takes
+    /// several PSBTs separated by new lines, finalize relays the signed transaction.
+    fn psbt_window(psbt: &str, sender_to_node: &Sender<InterfaceMessages>) {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("PSBT");
+        window.set_default_size(600, 400);
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        window.add(&vbox);
+        let text_view = TextView::new();
+        text_view.set_wrap_mode(gtk::WrapMode::Char);
+        if let Some(buffer) = text_view.get_buffer() {
+            buffer.set_text(psbt);
+        }
+        let scrolled_window = ScrolledWindow::new::<gtk::Adjustment, gtk::Adjustment>(None, None);
+        scrolled_window.set_shadow_type(gtk::ShadowType::In);
+        scrolled_window.add(&text_view);
+        vbox.pack_start(&scrolled_window, true, true, 0);
+        let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        vbox.pack_start(&buttons, false, false, 5);
+        let actions: [(&str, fn(String) -> InterfaceMessages); 3] = [
+            ("Sign", InterfaceMessages::SignPsbt),
+            ("Combine", InterfaceMessages::CombinePsbts),
+            ("Finalize and send", InterfaceMessages::FinalizePsbt),
+        ];
+        for (label, action) in actions {
+            let button = Button::with_label(label);
+            buttons.pack_start(&button, true, true, 0);
+            let text_view = text_view.clone();
+            let window = window.clone();
+            let sender = sender_to_node.clone();
+            button.connect_clicked(move |_| {
+                let text = match text_view.get_buffer() {
+                    Some(buffer) => {
+                        let (start, end) = buffer.get_bounds();
+                        buffer.get_text(&start, &end, false)
+                    }
+                    None => None,
+                };
+                if let Some(text) = text.filter(|text| !text.trim().is_empty()) {
+                    if sender.send(action(text.to_string())).is_ok() {}
+                    window.close();
+                }
+            });
+        }
+        window.show_all();
+    }
+
+    /// Handles the menu item that asks the node for the utxos of the actual wallet,
+    /// the coin control window opens when they arrive.
+    fn coin_control_item(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let item: Option<MenuItem> = self.builder.get_object("coin_control");
+        if let Some(item) = item {
+            item.connect_activate(move |_| {
+                if sender_to_node
+                    .send(InterfaceMessages::ListUtxos(()))
+                    .is_ok()
+                {}
+            });
+        }
+    }
+
+    /// Shows the utxos of the actual wallet to choose the ones the next transactions must spend
+    /// and the ones they can't. Spending a utxo unfreezes it and freezing it unselects it.
+    fn coin_control_window(rows: Vec<UtxoRow>, sender_to_node: &Sender<InterfaceMessages>) {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("Coin control");
+        window.set_default_size(800, 300);
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        window.add(&vbox);
+        let grid = Grid::new();
+        grid.set_column_spacing(10);
+        grid.set_row_spacing(5);
+        for (column, title) in ["Outpoint", "Address", "Amount"].iter().enumerate() {
+            grid.attach(&Label::new(Some(*title)), column as i32, 0, 1, 1);
+        }
+        let mut checks = vec![];
+        for (row, (outpoint, address, value, selected, frozen)) in rows.iter().enumerate() {
+            let top = row as i32 + 1;
+            let outpoint_label = Label::new(Some(outpoint));
+            outpoint_label.set_selectable(true);
+            grid.attach(&outpoint_label, 0, top, 1, 1);
+            grid.attach(&Label::new(Some(address)), 1, top, 1, 1);
+            grid.attach(&Label::new(Some(&value.to_string())), 2, top, 1, 1);
+            let spend = CheckButton::with_label("Spend");
+            spend.set_active(*selected);
+            grid.attach(&spend, 3, top, 1, 1);
+            let freeze = CheckButton::with_label("Freeze");
+            freeze.set_active(*frozen);
+            grid.attach(&freeze, 4, top, 1, 1);
+            for (check, other) in [(&spend, &freeze), (&freeze, &spend)] {
+                let other = other.clone();
+                check.connect_toggled(move |check| {
+                    if check.get_active() {
+                        other.set_active(false);
                     }
+                });
+            }
+            checks.push((outpoint.clone(), spend, freeze));
+        }
+        let scrolled_window = ScrolledWindow::new::<gtk::Adjustment, gtk::Adjustment>(None, None);
+        scrolled_window.add(&grid);
+        vbox.pack_start(&scrolled_window, true, true, 0);
+        if rows.is_empty() {
+            vbox.pack_start(
+                &Label::new(Some("The wallet has no utxos")),
+                false,
+                false,
+                5,
+            );
+        }
+        let apply = Button::with_label("Apply");
+        vbox.pack_start(&apply, false, false, 5);
+        let sender = sender_to_node.clone();
+        let window_clone = window.clone();
+        apply.connect_clicked(move |_| {
+            let selected: Vec<String> = checks
+                .iter()
+                .filter(|(_, spend, _)| spend.get_active())
+                .map(|(outpoint, _, _)| outpoint.clone())
+                .collect();
+            let frozen: Vec<String> = checks
+                .iter()
+                .filter(|(_, _, freeze)| freeze.get_active())
+                .map(|(outpoint, _, _)| outpoint.clone())
+                .collect();
+            let message = InterfaceMessages::SetCoinControl((selected, frozen));
+            if sender.send(message).is_ok() {}
+            window_clone.close();
+        });
+        window.show_all();
+    }
+
+    /// Opens the fee bumping dialog of the transaction activated in the list of my transactions.
+    fn fee_bump_list(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        self.widgets
+            .tree_view_my_transactions
+            .connect_row_activated(move |tree_view, tree_path, _| {
+                let row = Self::get_selected_row(tree_view, tree_path);
+                if let Some(txid) = row.get(1).filter(|txid| !txid.is_empty()) {
+                    Self::fee_bump_dialog(txid, &sender_to_node);
+                }
+            });
+    }
+
+    /// Asks for the new fee rate of an unconfirmed transaction and whether to replace it (RBF)
+    /// or to spend its change with a child that pays for both (CPFP). It can also be abandoned
+    /// if it won't be confirmed, so its utxos can be spent again.
+    fn fee_bump_dialog(txid: &str, sender_to_node: &Sender<InterfaceMessages>) {
+        let dialog = Dialog::with_buttons(
+            Some("Bump fee"),
+            None::<&Window>,
+            DialogFlags::MODAL,
+            &[
+                ("Replace (RBF)", ResponseType::Apply),
+                ("Child pays for parent", ResponseType::Accept),
+                ("Abandon", ResponseType::Reject),
+                ("Cancel", ResponseType::Cancel),
+            ],
+        );
+        let content = dialog.get_content_area();
+        let label = Label::new(Some(&format!("Transaction {}", txid)));
+        label.set_selectable(true);
+        content.add(&label);
+        let entry = Entry::new();
+        entry.set_placeholder_text(Some("New fee rate in sat/vB"));
+        content.add(&entry);
+        dialog.show_all();
+        let response = dialog.run();
+        let fee_rate = entry.get_text().parse::<i32>();
+        dialog.close();
+        if response == ResponseType::Reject {
+            let message = InterfaceMessages::AbandonTransaction(txid.to_string());
+            if sender_to_node.send(message).is_ok() {}
+            return;
+        }
+        let fee_rate = match fee_rate {
+            Ok(fee_rate) if fee_rate >= 1 => fee_rate,
+            _ => return,
+        };
+        let order = (txid.to_string(), fee_rate);
+        let message = match response {
+            ResponseType::Apply => InterfaceMessages::BumpFee(order),
+            ResponseType::Accept => InterfaceMessages::ChildPaysForParent(order),
+            _ => return,
+        };
+        if sender_to_node.send(message).is_ok() {}
+    }
+
+    /// Handles the menu item that sweeps the utxos of a private key into the actual wallet.
+    fn sweep_item(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let item: Option<MenuItem> = self.builder.get_object("sweep");
+        if let Some(item) = item {
+            item.connect_activate(move |_| {
+                Self::sweep_dialog(&sender_to_node);
+            });
+        }
+    }
+
+    /// Asks for a private key in WIF or hex and the fee rate of the transaction moving its utxos.
+    fn sweep_dialog(sender_to_node: &Sender<InterfaceMessages>) {
+        let dialog = Dialog::with_buttons(
+            Some("Sweep private key"),
+            None::<&Window>,
+            DialogFlags::MODAL,
+            &[
+                ("Sweep", ResponseType::Accept),
+                ("Cancel", ResponseType::Cancel),
+            ],
+        );
+        let content = dialog.get_content_area();
+        content.add(&Label::new(Some(
+            "Every utxo of the key is sent to the actual wallet",
+        )));
+        let key_entry = Entry::new();
+        key_entry.set_visibility(false);
+        key_entry.set_placeholder_text(Some("Private key in WIF or hex"));
+        content.add(&key_entry);
+        let fee_entry = Entry::new();
+        fee_entry.set_placeholder_text(Some("Fee rate in sat/vB"));
+        content.add(&fee_entry);
+        dialog.show_all();
+        let response = dialog.run();
+        let private_key = key_entry.get_text().trim().to_string();
+        let fee_rate = fee_entry.get_text().parse::<i32>();
+        dialog.close();
+        if response != ResponseType::Accept || private_key.is_empty() {
+            return;
+        }
+        let fee_rate = match fee_rate {
+            Ok(fee_rate) if fee_rate >= 1 => fee_rate,
+            _ => return,
+        };
+        let message = InterfaceMessages::Sweep((private_key, fee_rate));
+        if sender_to_node.send(message).is_ok() {}
+    }
+
+    /// Handles the menu item that opens the dialog to sign or verify a message.
+    fn sign_message_item(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let item: Option<MenuItem> = self.builder.get_object("sign_message");
+        if let Some(item) = item {
+            item.connect_activate(move |_| {
+                Self::sign_message_dialog(&sender_to_node);
+            });
+        }
+    }
+
+    /// Asks for an address and a message, signs it with the actual wallet's key of the address
+    /// or verifies the signature given with them.
+    fn sign_message_dialog(sender_to_node: &Sender<InterfaceMessages>) {
+        let dialog = Dialog::with_buttons(
+            Some("Sign or verify message"),
+            None::<&Window>,
+            DialogFlags::MODAL,
+            &[
+                ("Sign", ResponseType::Accept),
+                ("Verify", ResponseType::Apply),
+                ("Cancel", ResponseType::Cancel),
+            ],
+        );
+        let content = dialog.get_content_area();
+        let address_entry = Entry::new();
+        address_entry.set_placeholder_text(Some("Address"));
+        content.add(&address_entry);
+        let message_entry = Entry::new();
+        message_entry.set_placeholder_text(Some("Message"));
+        content.add(&message_entry);
+        let signature_entry = Entry::new();
+        signature_entry.set_placeholder_text(Some("Signature in base64, to verify"));
+        content.add(&signature_entry);
+        dialog.show_all();
+        let response = dialog.run();
+        let address = address_entry.get_text().trim().to_string();
+        let message = message_entry.get_text().to_string();
+        let signature = signature_entry.get_text().trim().to_string();
+        dialog.close();
+        if address.is_empty() {
+            return;
+        }
+        let order = match response {
+            ResponseType::Accept => InterfaceMessages::SignMessage((address, message)),
+            ResponseType::Apply if !signature.is_empty() => {
+                InterfaceMessages::VerifyMessage((address, message, signature))
+            }
+            _ => return,
+        };
+        if sender_to_node.send(order).is_ok() {}
+    }
+
+    /// Handles the menu item that asks the node for the history of the actual wallet,
+    /// the history window opens when it arrives.
+    fn history_item(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let item: Option<MenuItem> = self.builder.get_object("history");
+        if let Some(item) = item {
+            item.connect_activate(move |_| {
+                if sender_to_node
+                    .send(InterfaceMessages::ListHistory(()))
+                    .is_ok()
+                {}
+            });
+        }
+    }
+
+    /// Shows the transactions of the actual wallet with their confirmations and labels.
+    /// Double clicking a transaction edits its label and the history can be exported to CSV.
+    fn history_window(rows: Vec<HistoryRow>, sender_to_node: &Sender<InterfaceMessages>) {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("History");
+        window.set_default_size(1000, 400);
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        window.add(&vbox);
+        let tree_view = TreeView::new();
+        Self::set_columns(
+            &tree_view,
+            vec![
+                "Txid",
+                "Amount",
+                "Fee",
+                "Height",
+                "Confirmations",
+                "Date",
+                "Label",
+            ],
+        );
+        let list = gtk::ListStore::new(&vec![String::static_type(); HISTORY_COLUMNS][..]);
+        tree_view.set_model(Some(&list));
+        for (txid, amount, fee, height, confirmations, timestamp, label) in rows.iter() {
+            let date = chrono::NaiveDateTime::from_timestamp_opt(*timestamp as i64, 0)
+                .map(|date| date.format("%d/%m/%Y %H:%M:%S").to_string())
+                .unwrap_or_default();
+            let values = [
+                txid.clone(),
+                amount.to_string(),
+                fee.map(|fee| fee.to_string()).unwrap_or_default(),
+                height.map(|height| height.to_string()).unwrap_or_default(),
+                confirmations.to_string(),
+                date,
+                label.clone(),
+            ];
+            let row = list.append();
+            for (i, value) in values.iter().enumerate() {
+                list.set_value(&row, i as u32, &value.to_value());
+            }
+        }
+        let sender = sender_to_node.clone();
+        let list_clone = list.clone();
+        tree_view.connect_row_activated(move |tree_view, tree_path, _| {
+            let row = Self::get_selected_row(tree_view, tree_path);
+            if row.len() != HISTORY_COLUMNS {
+                return;
+            }
+            let label = match Self::label_dialog(&row[0], &row[HISTORY_LABEL_COLUMN as usize]) {
+                Some(label) => label,
+                None => return,
+            };
+            if let Some(iter) = list_clone.get_iter(tree_path) {
+                list_clone.set_value(&iter, HISTORY_LABEL_COLUMN, &label.to_value());
+            }
+            let message = InterfaceMessages::SetLabel((row[0].clone(), label));
+            if sender.send(message).is_ok() {}
+        });
+        let scrolled_window = ScrolledWindow::new::<gtk::Adjustment, gtk::Adjustment>(None, None);
+        scrolled_window.add(&tree_view);
+        vbox.pack_start(&scrolled_window, true, true, 0);
+        if rows.is_empty() {
+            vbox.pack_start(
+                &Label::new(Some("The wallet has no transactions")),
+                false,
+                false,
+                5,
+            );
+        }
+        let export = Button::with_label("Export CSV");
+        vbox.pack_start(&export, false, false, 5);
+        let sender = sender_to_node.clone();
+        let window_clone = window.clone();
+        export.connect_clicked(move |_| {
+            let dialog = FileChooserDialog::with_buttons(
+                Some("Export history"),
+                Some(&window_clone),
+                FileChooserAction::Save,
+                &[
+                    ("Cancel", ResponseType::Cancel),
+                    ("Save", ResponseType::Accept),
+                ],
+            );
+            dialog.set_current_name("history.csv");
+            let response = dialog.run();
+            let filename = dialog.get_filename();
+            dialog.close();
+            if let Some(path) = filename.filter(|_| response == ResponseType::Accept) {
+                let message = InterfaceMessages::ExportHistory(path.to_string_lossy().to_string());
+                if sender.send(message).is_ok() {}
+            }
+        });
+        window.show_all();
+    }
+
+    /// Asks for the new label of a transaction, None if the dialog is cancelled.
+    fn label_dialog(txid: &str, label: &str) -> Option<String> {
+        let dialog = Dialog::with_buttons(
+            Some("Label"),
+            None::<&Window>,
+            DialogFlags::MODAL,
+            &[
+                ("Save", ResponseType::Accept),
+                ("Cancel", ResponseType::Cancel),
+            ],
+        );
+        let content = dialog.get_content_area();
+        let txid_label = Label::new(Some(&format!("Transaction {}", txid)));
+        txid_label.set_selectable(true);
+        content.add(&txid_label);
+        let entry = Entry::new();
+        entry.set_text(label);
+        entry.set_placeholder_text(Some("Label"));
+        content.add(&entry);
+        dialog.show_all();
+        let response = dialog.run();
+        let label = entry.get_text().trim().to_string();
+        dialog.close();
+        Some(label).filter(|_| response == ResponseType::Accept)
+    }
+
+    /// Handles the menu item that rescans the actual wallet from a block height or a date,
+    /// which also becomes the birthday of the wallet.
+    fn rescan_item(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let item: Option<MenuItem> = self.builder.get_object("rescan");
+        if let Some(item) = item {
+            item.connect_activate(move |_| {
+                let dialog = Dialog::with_buttons(
+                    Some("Rescan"),
+                    None::<&Window>,
+                    DialogFlags::MODAL,
+                    &[
+                        ("Rescan", ResponseType::Accept),
+                        ("Cancel", ResponseType::Cancel),
+                    ],
+                );
+                let content = dialog.get_content_area();
+                content.add(&Label::new(Some(
+                    "Rebuild the history and utxos of the wallet from the stored blocks",
+                )));
+                let entry = Entry::new();
+                entry.set_placeholder_text(Some("Block height or date as YYYY-MM-DD"));
+                content.add(&entry);
+                dialog.show_all();
+                let response = dialog.run();
+                let start = entry.get_text().trim().to_string();
+                dialog.close();
+                if response != ResponseType::Accept || start.is_empty() {
+                    return;
                 }
+                if sender_to_node
+                    .send(InterfaceMessages::Rescan(start))
+                    .is_ok()
+                {}
             });
         }
     }
 
+    /// Handles the menu item that opens the window to pay several recipients in one transaction.
+    fn batch_send_item(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let item: Option<MenuItem> = self.builder.get_object("batch_send");
+        if let Some(item) = item {
+            item.connect_activate(move |_| {
+                Self::batch_window(&sender_to_node);
+            });
+        }
+    }
+
+    /// Shows a window with a row of address, label and amount for each recipient of the batch.
+    /// Rows can be added by hand or imported from a CSV file of address,amount,label lines.
+    fn batch_window(sender_to_node: &Sender<InterfaceMessages>) {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("Batch send");
+        window.set_default_size(800, 400);
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        window.add(&vbox);
+        let rows = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        Self::add_batch_row(&rows, &(String::new(), String::new(), 0));
+        let scrolled_window = ScrolledWindow::new::<gtk::Adjustment, gtk::Adjustment>(None, None);
+        scrolled_window.add(&rows);
+        vbox.pack_start(&scrolled_window, true, true, 0);
+
+        let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        vbox.pack_start(&buttons, false, false, 5);
+        let add = Button::with_label("Add recipient");
+        buttons.pack_start(&add, false, false, 0);
+        let rows_clone = rows.clone();
+        add.connect_clicked(move |_| {
+            Self::add_batch_row(&rows_clone, &(String::new(), String::new(), 0));
+        });
+        let import = Button::with_label("Import CSV");
+        buttons.pack_start(&import, false, false, 0);
+        let rows_clone = rows.clone();
+        let window_clone = window.clone();
+        import.connect_clicked(move |_| {
+            Self::import_batch_csv(&window_clone, &rows_clone);
+        });
+        let fee_entry = Entry::new();
+        fee_entry.set_placeholder_text(Some("Fee rate in sat/vB"));
+        buttons.pack_start(&fee_entry, true, true, 0);
+        let send = Button::with_label("Send");
+        buttons.pack_start(&send, false, false, 0);
+        let sender = sender_to_node.clone();
+        let window_clone = window.clone();
+        send.connect_clicked(move |_| {
+            let fee_rate = match fee_entry.get_text().parse::<i32>() {
+                Ok(fee_rate) if fee_rate >= 1 => fee_rate,
+                _ => {
+                    Self::batch_message(&window_clone, "The fee rate must be at least 1 sat/vB");
+                    return;
+                }
+            };
+            match Self::batch_payments(&rows) {
+                Ok(payments) => {
+                    let message = InterfaceMessages::SendBatch((payments, fee_rate));
+                    if sender.send(message).is_ok() {}
+                    window_clone.close();
+                }
+                Err(text) => Self::batch_message(&window_clone, &text),
+            }
+        });
+        window.show_all();
+    }
+
+    /// Appends a row with the entries of a payment and a button that removes it.
+    fn add_batch_row(rows: &gtk::Box, (address, label, amount): &Payment) {
+        let row = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        let amount = match amount {
+            0 => String::new(),
+            amount => amount.to_string(),
+        };
+        let texts = [
+            (address.clone(), "Address"),
+            (label.clone(), "Label"),
+            (amount, "Amount in satoshis"),
+        ];
+        for (text, placeholder) in texts.iter() {
+            let entry = Entry::new();
+            entry.set_text(text);
+            entry.set_placeholder_text(Some(placeholder));
+            row.pack_start(&entry, true, true, 0);
+        }
+        let remove = Button::with_label("Remove");
+        row.pack_start(&remove, false, false, 0);
+        let rows_clone = rows.clone();
+        let row_clone = row.clone();
+        remove.connect_clicked(move |_| {
+            rows_clone.remove(&row_clone);
+        });
+        rows.pack_start(&row, false, false, 0);
+        row.show_all();
+    }
+
+    /// Reads the payments of the rows, empty rows are skipped. Err with the reason
+    /// if a row has no address, its amount is dust or above MAX_MONEY, or the
+    /// amounts add up to more than MAX_MONEY.
+    fn batch_payments(rows: &gtk::Box) -> Result<Vec<Payment>, String> {
+        let mut payments = vec![];
+        for row in rows.get_children() {
+            let texts: Vec<String> = match row.downcast::<gtk::Box>() {
+                Ok(row) => row
+                    .get_children()
+                    .into_iter()
+                    .filter_map(|widget| widget.downcast::<Entry>().ok())
+                    .map(|entry| entry.get_text().trim().to_string())
+                    .collect(),
+                Err(_) => continue,
+            };
+            if texts.len() != 3 || texts.iter().all(|text| text.is_empty()) {
+                continue;
+            }
+            match texts[2].parse::<i64>() {
+                Ok(amount) if (546..=MAX_MONEY).contains(&amount) && !texts[0].is_empty() => {
+                    payments.push((texts[0].clone(), texts[1].clone(), amount));
+                }
+                _ => return Err(format!("Invalid payment to \"{}\"", texts[0])),
+            }
+        }
+        if payments.is_empty() {
+            return Err("Add at least one recipient".to_string());
+        }
+        if total_amount(&payments).is_none() {
+            return Err("The payments add up to more than 21 million bitcoin".to_string());
+        }
+        Ok(payments)
+    }
+
+    /// Asks for a CSV file and replaces the rows with its payments.
+    fn import_batch_csv(window: &Window, rows: &gtk::Box) {
+        let dialog = FileChooserDialog::with_buttons(
+            Some("Import payments"),
+            Some(window),
+            FileChooserAction::Open,
+            &[
+                ("Cancel", ResponseType::Cancel),
+                ("Open", ResponseType::Accept),
+            ],
+        );
+        let response = dialog.run();
+        let filename = dialog.get_filename();
+        dialog.close();
+        let path = match filename {
+            Some(path) if response == ResponseType::Accept => path,
+            _ => return,
+        };
+        let payments = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|csv| parse_payments_csv(&csv).map_err(|e| e.to_string()));
+        match payments {
+            Ok(payments) => {
+                for row in rows.get_children() {
+                    rows.remove(&row);
+                }
+                for payment in payments.iter() {
+                    Self::add_batch_row(rows, payment);
+                }
+                let text = format!(
+                    "Imported {} payments of {} satoshis in total",
+                    payments.len(),
+                    total_amount(&payments).unwrap_or_default()
+                );
+                Self::batch_message(window, &text);
+            }
+            Err(text) => Self::batch_message(window, &text),
+        }
+    }
+
+    /// Shows a message over the batch window.
+    fn batch_message(window: &Window, text: &str) {
+        let dialog = MessageDialog::new(
+            Some(window),
+            DialogFlags::MODAL,
+            MessageType::Info,
+            ButtonsType::Ok,
+            text,
+        );
+        dialog.run();
+        dialog.close();
+    }
+
     /// Sends a signal to the node indicating a change of wallet, specifying the name of the new wallet to be set as the default.
     fn wallet_switch_button(&mut self, wallet_switch: Sender<InterfaceMessages>) {
         let switch: Option<ComboBoxText> = self.builder.get_object("wallet_switch");
@@ -462,6 +1416,93 @@ impl Screen {
         }
     }
 
+    /// Handles the taskbar options to encrypt the wallets, change their passphrase, lock them
+    /// and export the descriptors of the actual wallet.
+    fn encryption_buttons(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let encrypt_button: Option<MenuItem> = self.builder.get_object("encrypt_wallets");
+        if let Some(b) = encrypt_button {
+            let sender = sender_to_node.clone();
+            b.connect_activate(move |_| {
+                let labels = ["New passphrase:", "Repeat passphrase:"];
+                if let Some(values) = Self::passphrase_dialog("Encrypt wallets", &labels) {
+                    if values[0] == values[1] {
+                        let message = InterfaceMessages::EncryptWallets(values[0].clone());
+                        if sender.send(message).is_ok() {}
+                    }
+                }
+            });
+        }
+        let change_button: Option<MenuItem> = self.builder.get_object("change_passphrase");
+        if let Some(b) = change_button {
+            let sender = sender_to_node.clone();
+            b.connect_activate(move |_| {
+                let labels = [
+                    "Old passphrase:",
+                    "New passphrase:",
+                    "Repeat new passphrase:",
+                ];
+                if let Some(values) = Self::passphrase_dialog("Change passphrase", &labels) {
+                    if values[1] == values[2] {
+                        let message = InterfaceMessages::ChangePassphrase((
+                            values[0].clone(),
+                            values[1].clone(),
+                        ));
+                        if sender.send(message).is_ok() {}
+                    }
+                }
+            });
+        }
+        let export_button: Option<MenuItem> = self.builder.get_object("export_descriptors");
+        if let Some(b) = export_button {
+            let sender = sender_to_node.clone();
+            b.connect_activate(move |_| {
+                if sender
+                    .send(InterfaceMessages::ExportDescriptors(()))
+                    .is_ok()
+                {}
+            });
+        }
+        let export_wif_button: Option<MenuItem> = self.builder.get_object("export_wif");
+        if let Some(b) = export_wif_button {
+            let sender = sender_to_node.clone();
+            b.connect_activate(
+                move |_| {
+                    if sender.send(InterfaceMessages::ExportWif(())).is_ok() {}
+                },
+            );
+        }
+        let lock_button: Option<MenuItem> = self.builder.get_object("lock_wallets");
+        if let Some(b) = lock_button {
+            b.connect_activate(move |_| {
+                if sender_to_node
+                    .send(InterfaceMessages::LockWallets(()))
+                    .is_ok()
+                {}
+            });
+        }
+    }
+
+    /// Handles the button that asks the actual HD wallet for a fresh receive address
+    /// of the type selected in the address type combo.
+    fn new_address_button(&mut self, sender_to_node: Sender<InterfaceMessages>) {
+        let new_address_button: Option<Button> = self.builder.get_object("new_address_button");
+        let address_type_combo: Option<ComboBoxText> =
+            self.builder.get_object("address_type_combo");
+        if let (Some(b), Some(address_type_combo)) = (new_address_button, address_type_combo) {
+            b.connect_clicked(move |_| {
+                let address_type = match address_type_combo.get_active_text() {
+                    Some(text) if text == "SegWit" => AddressType::SegWit,
+                    Some(text) if text == "Taproot" => AddressType::Taproot,
+                    _ => AddressType::Legacy,
+                };
+                if sender_to_node
+                    .send(InterfaceMessages::NewAddress(address_type))
+                    .is_ok()
+                {}
+            });
+        }
+    }
+
     fn get_selected_row(tree_view: &TreeView, tree_path: &TreePath) -> Vec<String> {
         let mut values = Vec::new();
         if let Some(tree_model) = tree_view.get_model() {
@@ -593,6 +1634,12 @@ impl Screen {
         }
     }
 
+    fn show_popup_message(widgets: &WidgetsGtk, message: &str) {
+        widgets.popup.set_markup(message);
+        widgets.popup.run();
+        widgets.popup.hide();
+    }
+
     fn show_popup_inclusion(widgets: &WidgetsGtk, show: bool) {
         let message = if show {
             "The transaction is included in the block."
@@ -628,21 +1675,37 @@ impl Screen {
         receiver_from_node: Receiver<InterfaceMessages>,
     ) {
         self.send_button(sender_to_node.clone());
+        self.receive_tab(sender_to_node.clone());
         self.add_wallet_button(sender_to_node.clone());
         self.wallet_switch_button(sender_to_node.clone());
-        self.proof_of_inclusion_button(sender_to_node);
+        self.encryption_buttons(sender_to_node.clone());
+        self.new_address_button(sender_to_node.clone());
+        self.psbt_buttons(sender_to_node.clone());
+        self.coin_control_item(sender_to_node.clone());
+        self.batch_send_item(sender_to_node.clone());
+        self.fee_bump_list(sender_to_node.clone());
+        self.history_item(sender_to_node.clone());
+        self.rescan_item(sender_to_node.clone());
+        self.sweep_item(sender_to_node.clone());
+        self.sign_message_item(sender_to_node.clone());
+        self.proof_of_inclusion_button(sender_to_node.clone());
         let widgets = self.widgets.clone();
         let builder = self.builder.clone();
         receiver_from_node.attach(None, move |message| {
             let widgets = widgets.clone();
             let builder = builder.clone();
-            Self::handle_messages(builder, message, widgets);
+            Self::handle_messages(builder, message, widgets, &sender_to_node);
             glib::Continue(true)
         });
     }
 
     /// Matches all communication channels with the node, and depending on which one is triggered, sends the necessary command to be executed.
-    fn handle_messages(builder: Builder, message: InterfaceMessages, widgets: WidgetsGtk) {
+    fn handle_messages(
+        builder: Builder,
+        message: InterfaceMessages,
+        widgets: WidgetsGtk,
+        sender_to_node: &Sender<InterfaceMessages>,
+    ) {
         match message {
             InterfaceMessages::DebugHeaders(headers) => {
                 Self::set_data_headers(headers, &widgets);
@@ -668,6 +1731,30 @@ impl Screen {
             InterfaceMessages::Open(open_screen) => {
                 Self::open_signal(&builder, open_screen);
             }
+            InterfaceMessages::UnlockRequest(transaction_order) => {
+                Self::unlock_dialog(transaction_order, sender_to_node);
+            }
+            InterfaceMessages::PassphraseRequest(_) => {
+                Self::open_wallets_dialog(sender_to_node);
+            }
+            InterfaceMessages::EncryptionResult(result) => {
+                Self::show_popup_message(&widgets, &result);
+            }
+            InterfaceMessages::WalletMessage(text) => {
+                Self::show_popup_message(&widgets, &text);
+            }
+            InterfaceMessages::PsbtResult(psbt) => {
+                Self::psbt_window(&psbt, sender_to_node);
+            }
+            InterfaceMessages::Utxos(rows) => {
+                Self::coin_control_window(rows, sender_to_node);
+            }
+            InterfaceMessages::History(rows) => {
+                Self::history_window(rows, sender_to_node);
+            }
+            InterfaceMessages::PaymentRequests(rows) => {
+                Self::show_payment_requests(&builder, rows);
+            }
             _ => {}
         }
     }
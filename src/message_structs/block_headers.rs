@@ -1,10 +1,13 @@
 use super::common_traits::csv_format::CSVFormat;
 use crate::message_structs::bitcoin_message_header::BitcoinMessageHeader;
 use crate::utils::array_tools::{from_le_bytes_i32, from_le_bytes_u32, reverse_array};
-use chrono::{prelude::*, LocalResult};
 use std::io::Write;
 use std::net::TcpStream;
 
+/// Time blocks were downloaded from before wallets had a birthday, 2023-04-10 09:00 UTC.
+/// Wallets opened without a birthday are born then
+pub const DEFAULT_BIRTHDAY: u32 = 1_681_117_200;
+
 #[derive(Debug, PartialEq)]
 pub struct BlockHeader {
     pub version: i32,
@@ -127,14 +130,9 @@ impl BlockHeader {
         Ok(vector)
     }
 
-    pub fn should_download(&self) -> bool {
-        let start_date = Utc.with_ymd_and_hms(2023, 4, 10, 9, 0, 0);
-        if let LocalResult::Single(s) = start_date {
-            let number = s.timestamp() as u32;
-            self.time > number
-        } else {
-            false
-        }
+    /// Returns true if the block was mined after `start`, the birthday of the oldest wallet
+    pub fn should_download(&self, start: u32) -> bool {
+        self.time > start
     }
 
     pub fn previous_block_header_hash(&self) -> [u8; 32] {
@@ -0,0 +1,73 @@
+use crate::message_structs::bitcoin_message_header::BitcoinMessageHeader;
+use std::error::Error;
+use std::io::Write;
+use std::net::TcpStream;
+
+/// BIP133 feefilter: the peer asks not to be announced transactions below this fee rate (sat/kvB)
+#[derive(Debug, PartialEq)]
+pub struct FeeFilter {
+    fee_rate: u64,
+}
+
+impl FeeFilter {
+    pub fn new(fee_rate: u64) -> FeeFilter {
+        Self { fee_rate }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.fee_rate.to_le_bytes().to_vec()
+    }
+
+    /// # Errors
+    /// Returns an error if the message could not be sent
+    pub fn send(&self, mut stream: &TcpStream) -> Result<&str, Box<dyn Error>> {
+        let serialize_fee_filter = self.serialize();
+        let payload = 8;
+        let header_fee_filter = BitcoinMessageHeader::message(
+            &serialize_fee_filter,
+            [
+                b'f', b'e', b'e', b'f', b'i', b'l', b't', b'e', b'r', 0x00, 0x00, 0x00,
+            ],
+            payload as u32,
+        );
+        let header = header_fee_filter.header(&serialize_fee_filter);
+        stream.write_all(&header)?;
+        Ok("mensaje enviado correctamente")
+    }
+
+    /// # Errors
+    /// Returns an error if the payload is shorter than 8 bytes
+    pub fn deserialize(payload: &mut Vec<u8>) -> Result<FeeFilter, Box<dyn Error>> {
+        if payload.len() < 8 {
+            return Err("failed to deserialize feefilter".into());
+        }
+        let bytes: [u8; 8] = match payload.drain(0..8).collect::<Vec<u8>>().try_into() {
+            Ok(a) => a,
+            Err(_) => return Err("failed to deserialize feefilter".into()),
+        };
+        Ok(FeeFilter::new(u64::from_le_bytes(bytes)))
+    }
+
+    pub fn get_fee_rate(&self) -> u64 {
+        self.fee_rate
+    }
+}
+
+#[cfg(test)]
+mod fee_filter_tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_and_deserialize() {
+        let fee_filter = FeeFilter::new(1000);
+        let mut serialized = fee_filter.serialize();
+        assert_eq!(serialized, vec![0xe8, 0x03, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(FeeFilter::deserialize(&mut serialized).unwrap(), fee_filter);
+    }
+
+    #[test]
+    fn test_deserialize_short_payload() {
+        let mut payload = vec![0xe8, 0x03];
+        assert!(FeeFilter::deserialize(&mut payload).is_err());
+    }
+}
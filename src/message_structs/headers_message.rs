@@ -88,11 +88,11 @@ impl HeadersMessage {
         hash2.to_byte_array()
     }
 
-    pub fn create_get_data(&self) -> InvOrGetDataMessage {
+    pub fn create_get_data(&self, start: u32) -> InvOrGetDataMessage {
         let mut inventory: Vec<Inv> = Vec::new();
         let mut count: usize = 0;
         for i in &self.headers {
-            if i.should_download() {
+            if i.should_download(start) {
                 let new_inv = Inv::new(2, i.previous_block_header_hash());
                 inventory.push(new_inv);
                 count += 1;
@@ -126,14 +126,15 @@ impl HeadersMessage {
         }
     }
 
-    pub fn get_data_with_type(&self, inv_type: u32) -> Vec<InvOrGetDataMessage> {
+    /// Returns a get data message of the given inventory type for each block mined after `start`
+    pub fn get_data_with_type(&self, inv_type: u32, start: u32) -> Vec<InvOrGetDataMessage> {
         let block_header = match self.headers.last() {
             Some(v) => v,
             None => {
                 return Vec::new();
             }
         };
-        if !block_header.should_download() {
+        if !block_header.should_download(start) {
             return Vec::new();
         }
 
@@ -142,7 +143,7 @@ impl HeadersMessage {
         let mut headers_left = self.count.get_number();
         while headers_left > 0 {
             let mut inventory: Vec<Inv> = Vec::new();
-            if self.headers[headers_read].should_download() {
+            if self.headers[headers_read].should_download(start) {
                 let hash = header_calculate_doublehash_array_be(&self.headers[headers_read])
                     .unwrap_or([0; 32]);
                 let new_inv = Inv::new(inv_type, hash);
@@ -7,6 +7,7 @@ pub mod block_txn;
 pub mod cmpct_block;
 pub mod common_traits;
 pub mod compact_size;
+pub mod fee_filter;
 pub mod filter_load_message;
 pub mod get_block_message;
 pub mod get_block_txn;
@@ -1,6 +1,7 @@
 use super::common_traits::csv_format::CSVFormat;
-use crate::utils::array_tools::reverse_array;
+use crate::utils::array_tools::{cast_str_to_fixed_bytes, reverse_array, u8_array_to_hex_string};
 use std::error::Error;
+use std::fmt;
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Outpoint {
     hash: [u8; 32],
@@ -38,6 +39,18 @@ impl Outpoint {
         self.index
     }
 
+    /// Parses the "txid:index" text of an outpoint, the txid in hex as shown by its Display
+    pub fn parse(text: &str) -> Result<Outpoint, Box<dyn Error>> {
+        let (hash, index) = match text.trim().split_once(':') {
+            Some((hash, index)) if hash.is_ascii() => (hash, index),
+            _ => return Err("The outpoint must be txid:index".into()),
+        };
+        Ok(Outpoint {
+            hash: cast_str_to_fixed_bytes(hash)?,
+            index: index.parse()?,
+        })
+    }
+
     fn from_le_bytes_u32(payload: &mut Vec<u8>) -> u32 {
         u32::from_le_bytes([
             payload.remove(0),
@@ -48,6 +61,12 @@ impl Outpoint {
     }
 }
 
+impl fmt::Display for Outpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", u8_array_to_hex_string(&self.hash), self.index)
+    }
+}
+
 impl CSVFormat for Outpoint {
     fn get_csv_format(&self) -> Vec<String> {
         //     hash:[u8;32],
@@ -100,4 +119,15 @@ mod outpoint_tests {
         ];
         assert_eq!(outpoint_csv, outpoint_csv_expected);
     }
+
+    #[test]
+    fn test_display_and_parse() {
+        let outpoint = Outpoint::new([0xab; 32], 3);
+        let text = outpoint.to_string();
+        assert_eq!(text, format!("{}:3", "ab".repeat(32)));
+        assert_eq!(Outpoint::parse(&text).unwrap(), outpoint);
+        assert!(Outpoint::parse("abab:1").is_err());
+        assert!(Outpoint::parse(&"ab".repeat(32)).is_err());
+        assert!(Outpoint::parse(&format!("{}:x", "ab".repeat(32))).is_err());
+    }
 }
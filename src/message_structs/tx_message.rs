@@ -3,11 +3,24 @@ use crate::message_structs::bitcoin_message_header::BitcoinMessageHeader;
 use crate::message_structs::compact_size::CompactSize;
 use crate::message_structs::input::Input;
 use crate::message_structs::output::Output;
+use crate::node::validation_engine::hashes::tagged_hash;
 use crate::utils::array_tools::{from_le_bytes_i32, from_le_bytes_u32};
 use bitcoin_hashes::{sha256, sha256d, Hash};
 use std::io::Write;
 use std::net::TcpStream;
 
+/// Marker and flag bytes of the segwit serialization (BIP144)
+const SEGWIT_MARKER: u8 = 0x00;
+const SEGWIT_FLAG: u8 = 0x01;
+const WITNESS_SCALE_FACTOR: usize = 4;
+const SIGHASH_ALL: u32 = 1;
+/// BIP341 hash types, SIGHASH_DEFAULT signs like SIGHASH_ALL with a 64 bytes signature
+pub const TAPROOT_SIGHASH_DEFAULT: u8 = 0x00;
+const TAPROOT_SIGHASH_ALL: u8 = 0x01;
+const TAPROOT_SIGHASH_NONE: u8 = 0x02;
+const TAPROOT_SIGHASH_SINGLE: u8 = 0x03;
+const TAPROOT_SIGHASH_ANYONECANPAY: u8 = 0x80;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct TXMessage {
     version: i32,
@@ -16,6 +29,7 @@ pub struct TXMessage {
     output_count: CompactSize,
     output_list: Vec<Output>,
     pub time: u32,
+    witnesses: Vec<Vec<Vec<u8>>>, // witness stack of each input, empty for legacy transactions
 }
 
 impl TXMessage {
@@ -34,13 +48,48 @@ impl TXMessage {
             output_count,
             output_list,
             time,
+            witnesses: vec![],
         }
     }
 
+    /// Serializes the transaction, with the witnesses (BIP144) if any input has one
     #[must_use]
     pub fn serialize(&self) -> Vec<u8> {
+        if !self.has_witness() {
+            return self.serialize_without_witness();
+        }
+        let mut tx_message: Vec<u8> = Vec::new();
+        tx_message.extend_from_slice(&self.version.to_le_bytes());
+        tx_message.extend_from_slice(&[SEGWIT_MARKER, SEGWIT_FLAG]);
+        tx_message.extend_from_slice(&self.serialize_inputs_and_outputs());
+        for index in 0..self.input_list.len() {
+            let witness = self.get_witness(index);
+            tx_message.extend_from_slice(
+                &CompactSize::from_usize_to_compact_size(witness.len()).serialize(),
+            );
+            for item in witness {
+                tx_message.extend_from_slice(
+                    &CompactSize::from_usize_to_compact_size(item.len()).serialize(),
+                );
+                tx_message.extend_from_slice(&item);
+            }
+        }
+        tx_message.extend_from_slice(&self.time.to_le_bytes());
+        tx_message
+    }
+
+    /// Serializes the transaction without the witnesses, as hashed by the txid
+    #[must_use]
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
         let mut tx_message: Vec<u8> = Vec::new();
         tx_message.extend_from_slice(&self.version.to_le_bytes());
+        tx_message.extend_from_slice(&self.serialize_inputs_and_outputs());
+        tx_message.extend_from_slice(&self.time.to_le_bytes());
+        tx_message
+    }
+
+    fn serialize_inputs_and_outputs(&self) -> Vec<u8> {
+        let mut tx_message: Vec<u8> = Vec::new();
         tx_message.extend_from_slice(&self.input_count.serialize());
         for i in &self.input_list {
             let serialize_input = i.serialize();
@@ -55,7 +104,6 @@ impl TXMessage {
                 tx_message.extend_from_slice(&[j]);
             }
         }
-        tx_message.extend_from_slice(&self.time.to_le_bytes());
         tx_message
     }
 
@@ -71,6 +119,19 @@ impl TXMessage {
         size
     }
 
+    /// Returns the weight of the transaction: 4 weight units per non witness byte and 1 per witness byte
+    #[must_use]
+    pub fn weight(&self) -> usize {
+        let base_size = self.serialize_without_witness().len();
+        base_size * (WITNESS_SCALE_FACTOR - 1) + self.serialize().len()
+    }
+
+    /// Returns the virtual size of the transaction in vbytes, used to compute fee rates
+    #[must_use]
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(WITNESS_SCALE_FACTOR)
+    }
+
     /// # Errors
     /// Returns an error if the message could not be sent
     pub fn send(&self, mut stream: &TcpStream) -> Result<&str, Box<dyn std::error::Error>> {
@@ -94,6 +155,10 @@ impl TXMessage {
     pub fn deserialize(payload: &mut Vec<u8>) -> Result<TXMessage, Box<dyn std::error::Error>> {
         //payload.remove(0);
         let version = from_le_bytes_i32(payload);
+        let segwit = payload.len() > 1 && payload[0] == SEGWIT_MARKER && payload[1] == SEGWIT_FLAG;
+        if segwit {
+            payload.drain(..2);
+        }
         let input_count = CompactSize::deserialize(payload);
         let input_list = match Input::deserialize_to_vec(payload, input_count.get_number() as u32) {
             Ok(input_list) => input_list,
@@ -102,6 +167,12 @@ impl TXMessage {
 
         let output_count = CompactSize::deserialize(payload);
         let output_list = Output::deserialize_to_vec(payload, output_count.get_number() as u32);
+        let mut witnesses = vec![];
+        if segwit {
+            for _ in 0..input_list.len() {
+                witnesses.push(Self::deserialize_witness(payload)?);
+            }
+        }
         let time = from_le_bytes_u32(payload);
 
         Ok(TXMessage {
@@ -111,9 +182,25 @@ impl TXMessage {
             output_count,
             output_list,
             time,
+            witnesses,
         })
     }
 
+    fn deserialize_witness(
+        payload: &mut Vec<u8>,
+    ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        let item_count = CompactSize::deserialize(payload).get_number();
+        let mut witness = vec![];
+        for _ in 0..item_count {
+            let item_len = CompactSize::deserialize(payload).get_number();
+            if item_len > payload.len() {
+                return Err("Witness item out of bounds".into());
+            }
+            witness.push(payload.drain(..item_len).collect());
+        }
+        Ok(witness)
+    }
+
     /// # Errors
     /// Returns an error if the payload could not be deserialized
     pub fn deserialize_to_vec(
@@ -149,23 +236,49 @@ impl TXMessage {
         amounts
     }
 
+    pub fn get_version(&self) -> i32 {
+        self.version
+    }
+
     pub fn get_input(&self) -> Vec<Input> {
         let mut vector: Vec<Input> = vec![];
         for i in &self.input_list {
-            let Ok(input) =Input::deserialize(&mut i.serialize())else {panic!("problemas con el input")};
+            let Ok(input) = Input::deserialize(&mut i.serialize()) else {
+                panic!("problemas con el input")
+            };
             vector.push(input);
         }
         vector
     }
 
     pub fn get_id(&self) -> [u8; 32] {
-        let vector = Self::serialize(self);
+        let vector = Self::serialize_without_witness(self);
         let hash = sha256::Hash::hash(&vector);
         //println!("hash previo a hash:{:?}",hash.to_byte_array());
         let hash2 = sha256::Hash::hash(&hash.to_byte_array());
         hash2.to_byte_array()
     }
 
+    /// Returns true if any input has a witness
+    pub fn has_witness(&self) -> bool {
+        self.witnesses.iter().any(|witness| !witness.is_empty())
+    }
+
+    /// Returns the witness stack of the input, empty if it has none
+    pub fn get_witness(&self, index: usize) -> Vec<Vec<u8>> {
+        self.witnesses.get(index).cloned().unwrap_or_default()
+    }
+
+    /// Sets the witness stack of the input
+    pub fn set_witness(&mut self, index: usize, witness: Vec<Vec<u8>>) {
+        if self.witnesses.len() < self.input_list.len() {
+            self.witnesses.resize(self.input_list.len(), vec![]);
+        }
+        if let Some(input_witness) = self.witnesses.get_mut(index) {
+            *input_witness = witness;
+        }
+    }
+
     pub fn update_empty_script(&mut self, new_script: Vec<u8>) {
         for i in self.input_list.iter_mut() {
             if i.get_script().is_empty() {
@@ -211,6 +324,118 @@ impl TXMessage {
         println!("ready to sign: {:?}", vector.to_vec());
         vector.to_vec()
     }
+
+    /// BIP143 signature hash (SIGHASH_ALL) of a segwit v0 input spending `amount` with `script_code`
+    pub fn segwit_sig_hash(&self, index: usize, script_code: &[u8], amount: i64) -> Vec<u8> {
+        let mut prevouts = vec![];
+        let mut sequences = vec![];
+        for input in &self.input_list {
+            prevouts.extend_from_slice(&input.get_outpoint().serialize());
+            sequences.extend_from_slice(&input.get_sequence_number().to_le_bytes());
+        }
+        let mut outputs = vec![];
+        for output in &self.output_list {
+            outputs.extend_from_slice(&output.serialize());
+        }
+
+        let mut preimage: Vec<u8> = Vec::new();
+        preimage.extend_from_slice(&self.version.to_le_bytes());
+        preimage.extend_from_slice(&sha256d::Hash::hash(&prevouts).to_byte_array());
+        preimage.extend_from_slice(&sha256d::Hash::hash(&sequences).to_byte_array());
+        if let Some(input) = self.input_list.get(index) {
+            preimage.extend_from_slice(&input.get_outpoint().serialize());
+            preimage.extend_from_slice(
+                &CompactSize::from_usize_to_compact_size(script_code.len()).serialize(),
+            );
+            preimage.extend_from_slice(script_code);
+            preimage.extend_from_slice(&amount.to_le_bytes());
+            preimage.extend_from_slice(&input.get_sequence_number().to_le_bytes());
+        }
+        preimage.extend_from_slice(&sha256d::Hash::hash(&outputs).to_byte_array());
+        preimage.extend_from_slice(&self.time.to_le_bytes());
+        preimage.extend_from_slice(&SIGHASH_ALL.to_le_bytes());
+        sha256d::Hash::hash(&preimage).to_byte_array().to_vec()
+    }
+
+    /// BIP341 signature hash of a taproot key path spend of the input `index`. `prevouts` are
+    /// the outputs spent by every input. Returns None if the hash type is invalid, a prevout
+    /// is missing or a SIGHASH_SINGLE input has no output with its index
+    pub fn taproot_sig_hash(
+        &self,
+        index: usize,
+        prevouts: &[Output],
+        hash_type: u8,
+        annex: Option<&[u8]>,
+    ) -> Option<[u8; 32]> {
+        let input = self.input_list.get(index)?;
+        if prevouts.len() != self.input_list.len() {
+            return None;
+        }
+        let output_type = match hash_type & !TAPROOT_SIGHASH_ANYONECANPAY {
+            TAPROOT_SIGHASH_DEFAULT if hash_type == TAPROOT_SIGHASH_DEFAULT => TAPROOT_SIGHASH_ALL,
+            TAPROOT_SIGHASH_ALL | TAPROOT_SIGHASH_NONE | TAPROOT_SIGHASH_SINGLE => {
+                hash_type & !TAPROOT_SIGHASH_ANYONECANPAY
+            }
+            _ => return None,
+        };
+        let anyone_can_pay = hash_type & TAPROOT_SIGHASH_ANYONECANPAY != 0;
+        let serialize_script = |script: Vec<u8>| {
+            let mut serialized = CompactSize::from_usize_to_compact_size(script.len()).serialize();
+            serialized.extend(script);
+            serialized
+        };
+
+        // Epoch 0, hash type and transaction data
+        let mut message = vec![0, hash_type];
+        message.extend_from_slice(&self.version.to_le_bytes());
+        message.extend_from_slice(&self.time.to_le_bytes());
+        if !anyone_can_pay {
+            let mut outpoints = vec![];
+            let mut amounts = vec![];
+            let mut scripts = vec![];
+            let mut sequences = vec![];
+            for (input, prevout) in self.input_list.iter().zip(prevouts) {
+                outpoints.extend(input.get_outpoint().serialize());
+                amounts.extend_from_slice(&prevout.value.to_le_bytes());
+                scripts.extend(serialize_script(prevout.get_script()));
+                sequences.extend_from_slice(&input.get_sequence_number().to_le_bytes());
+            }
+            for data in [outpoints, amounts, scripts, sequences] {
+                message.extend_from_slice(&sha256::Hash::hash(&data).to_byte_array());
+            }
+        }
+        if output_type == TAPROOT_SIGHASH_ALL {
+            let mut outputs = vec![];
+            for output in &self.output_list {
+                outputs.extend(output.serialize());
+            }
+            message.extend_from_slice(&sha256::Hash::hash(&outputs).to_byte_array());
+        }
+
+        // Data about this input, key path spends have no extension
+        message.push(u8::from(annex.is_some()));
+        if anyone_can_pay {
+            let prevout = &prevouts[index];
+            message.extend(input.get_outpoint().serialize());
+            message.extend_from_slice(&prevout.value.to_le_bytes());
+            message.extend(serialize_script(prevout.get_script()));
+            message.extend_from_slice(&input.get_sequence_number().to_le_bytes());
+        } else {
+            message.extend_from_slice(&(index as u32).to_le_bytes());
+        }
+        if let Some(annex) = annex {
+            message.extend_from_slice(
+                &sha256::Hash::hash(&serialize_script(annex.to_vec())).to_byte_array(),
+            );
+        }
+
+        // Data about the output with the same index
+        if output_type == TAPROOT_SIGHASH_SINGLE {
+            let output = self.output_list.get(index)?;
+            message.extend_from_slice(&sha256::Hash::hash(&output.serialize()).to_byte_array());
+        }
+        Some(tagged_hash("TapSighash", &message))
+    }
 }
 
 impl CSVFormat for TXMessage {
@@ -271,6 +496,7 @@ impl CSVFormat for TXMessage {
 mod tx_message_tests {
     use super::*;
     use crate::message_structs::outpoint::Outpoint;
+    use crate::utils::array_tools::{cast_str_to_bytes_vec, u8_vec_to_hex_string};
 
     #[test]
     fn test_get_csv_format() {
@@ -326,6 +552,7 @@ mod tx_message_tests {
             },
             output_list: vec![output1, output2],
             time: 1433835532,
+            witnesses: vec![],
         };
 
         let csv_format = tx_message.get_csv_format();
@@ -340,4 +567,44 @@ mod tx_message_tests {
 
         assert_eq!(csv_format, expected_csv_format);
     }
+
+    // BIP143 native P2WPKH example, second input
+    #[test]
+    fn test_segwit_sig_hash() {
+        let mut payload = cast_str_to_bytes_vec("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000").unwrap();
+        let tx = TXMessage::deserialize(&mut payload).unwrap();
+        let script_code =
+            cast_str_to_bytes_vec("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap();
+
+        let sig_hash = tx.segwit_sig_hash(1, &script_code, 600_000_000);
+
+        assert_eq!(
+            u8_vec_to_hex_string(&sig_hash),
+            "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+        );
+    }
+
+    #[test]
+    fn test_witness_serialization() {
+        let mut payload = cast_str_to_bytes_vec("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000").unwrap();
+        let mut tx = TXMessage::deserialize(&mut payload).unwrap();
+        let legacy = tx.serialize();
+        let txid = tx.get_id();
+        assert!(!tx.has_witness());
+        assert_eq!(tx.vsize(), legacy.len());
+
+        tx.set_witness(1, vec![vec![1; 71], vec![2; 33]]);
+        let serialized = tx.serialize();
+
+        assert!(tx.has_witness());
+        assert_eq!(serialized[4..6], [SEGWIT_MARKER, SEGWIT_FLAG]);
+        // marker and flag, an empty witness and one with two items
+        assert_eq!(serialized.len(), legacy.len() + 2 + 1 + 1 + 1 + 71 + 1 + 33);
+        assert_eq!(tx.get_id(), txid);
+        assert_eq!(
+            tx.vsize(),
+            (legacy.len() * 4 + 2 + 1 + 1 + 1 + 71 + 1 + 33).div_ceil(4)
+        );
+        assert_eq!(TXMessage::deserialize(&mut serialized.clone()).unwrap(), tx);
+    }
 }
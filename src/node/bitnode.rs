@@ -11,6 +11,7 @@ use crate::message_structs::block_headers::BlockHeader;
 use crate::message_structs::block_message::BlockMessage;
 use crate::message_structs::inv::Inv;
 use crate::message_structs::cmpct_block::CmpctBlock;
+use crate::message_structs::fee_filter::FeeFilter;
 use crate::message_structs::merkel_block::MerkleBlock;
 use crate::message_structs::outpoint::Outpoint;
 use crate::message_structs::ping_or_pong::PingOrPong;
@@ -20,11 +21,13 @@ use crate::node::connection_manager::peers_connection::{deserialize_message_from
 
 use crate::message_structs::get_block_txn::GetBlockTxn;
 use crate::node::validation_engine::hashes::header_calculate_doublehash_array_be;
+use crate::node::validation_engine::locktime::{check_script_sequence_locks, ChainTimes, INDEXED_HEADERS};
 use crate::node::validation_engine::merkles::merkle_tree::MerkleTree;
+use crate::node::validation_engine::taproot::check_taproot_spends;
 use crate::utils::array_tools::u8_array_to_hex_string;
 use crate::utils::commands::{get_type, MessageType};
 use crate::utils::configs::config::get_server_seed;
-use crate::utils::script_tools::bitcoin_address_in_b58_output;
+use crate::utils::script_tools::{bitcoin_address_in_b58_output, bitcoin_address_in_witness};
 use std::collections::HashMap;
 use std::io::Read;
 use std::net::{TcpStream, TcpListener};
@@ -37,9 +40,12 @@ use crate::message_structs::filter_load_message::FilterLoadMessage;
 use crate::message_structs::inv_or_get_data_message::InvOrGetDataMessage;
 use crate::message_structs::output::Output;
 use crate::node::interface::interface_communicator::InterfaceCommunicator;
+use crate::node::mempool::package::Package;
+use crate::node::mempool::tx_relay::{PeerWriteLock, TxRelay};
 use crate::node::peer_discovery::obtain_peers::obtain_peers;
 use crate::node::storage_engine::storage_manager::StorageManager;
 use crate::node::utxo_collector::UtxoCollector;
+use crate::node::wallets::rescan::BLOCK_TIME_DRIFT;
 use crate::utils::logger::Logger;
 use crate::{
     message_structs::compact_size::*, message_structs::headers_message::*,
@@ -53,6 +59,7 @@ type Storage = Arc<Mutex<StorageManager>>;
 type SenderLogClone = Arc<Mutex<Sender<(String, Vec<u8>)>>>;
 type SenderInterface = InterfaceSender<InterfaceMessages>;
 type StartInterfaceElements = (Vec<JoinHandle<()>>, SenderInterface);
+type Relay = Arc<Mutex<TxRelay>>;
 
 pub const MAX_OUTBOUND_CONNECTIONS: usize = 30;
 
@@ -134,8 +141,10 @@ pub struct BitcoinNode {
     pub headers: Arc<Mutex<Vec<Vec<u8>>>>,
     pub tx: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,
     pub last_header:Arc<Mutex<[u8;32]>>,
+    pub tx_relay: Relay,
+    pub chain_times: Arc<Mutex<ChainTimes>>,
     peers: Option<Vec<String>>,
-    utxo_collector: UtxoCollector,
+    utxo_collector: Arc<Mutex<UtxoCollector>>,
     interface_communicator: InterfaceCommunicator,
     total_blocks_to_receive: Arc<Mutex<(usize, bool)>>,
     is_client: bool, // This does not give the node any special behaviour, it's just to allow testing of the primary node
@@ -156,6 +165,8 @@ impl Clone for BitcoinNode {
             headers: self.headers.clone(),
             tx:self.tx.clone(),
             last_header:self.last_header.clone(),
+            tx_relay: self.tx_relay.clone(),
+            chain_times: self.chain_times.clone(),
             utxo_collector: self.utxo_collector.clone(),
             interface_communicator: self.interface_communicator.clone(),
             total_blocks_to_receive: self.total_blocks_to_receive.clone(),
@@ -168,15 +179,19 @@ impl BitcoinNode {
     /// It creates a new BitcoinNode. No peers or blocks are initialized.
     pub fn new() -> BitcoinNode {
         // Initialization without peers
+        let tx = Arc::new(Mutex::new(HashMap::new()));
+        let interface_communicator = InterfaceCommunicator::new();
         BitcoinNode {
             peers: None,
             blocks: Arc::new(Mutex::new(HashMap::new())),
             merkle_blocks: Arc::new(Mutex::new(HashMap::new())),
             headers:Arc::new(Mutex::new(vec![])),
-            tx:Arc::new(Mutex::new(HashMap::new())),
+            tx_relay: Arc::new(Mutex::new(TxRelay::new(tx.clone()))),
+            tx,
+            chain_times: Arc::new(Mutex::new(ChainTimes::new())),
             last_header:Arc::new(Mutex::new([0;32])),
-            utxo_collector: UtxoCollector::new(),
-            interface_communicator: InterfaceCommunicator::new(),
+            utxo_collector: Arc::clone(&interface_communicator.utxo_collector),
+            interface_communicator,
             total_blocks_to_receive: Arc::new(Mutex::new((0, false))),
             is_client: false,
         }
@@ -187,7 +202,7 @@ impl BitcoinNode {
     pub fn build() -> Option<BitcoinNode> {
         let mut node = BitcoinNode::new();
 
-        let peers = Self::build_connections(node.blocks.clone(), node.merkle_blocks.clone(), node.headers.clone(),node.tx.clone());
+        let peers = Self::build_connections(node.blocks.clone(), node.merkle_blocks.clone(), node.headers.clone(),node.tx.clone(), node.tx_relay.clone());
         node.peers = peers;
         node.is_client = Self::is_client();
         
@@ -206,7 +221,8 @@ impl BitcoinNode {
     fn build_connections(blocks: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,
         merkle_blocks: Arc<Mutex<HashMap<[u8;32],MerkleBlock>>>,
         headers: Arc<Mutex<Vec<Vec<u8>>>>,
-        tx: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,) -> Option<Vec<String>> {
+        tx: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,
+        tx_relay: Relay,) -> Option<Vec<String>> {
         let server_seed = match get_server_seed() {
             Ok(seed) => seed,
             Err(_) => todo!(),
@@ -214,7 +230,7 @@ impl BitcoinNode {
         let args: Vec<String> = env::args().collect();
         println!("mis args son {:?}", args);
         let server_address = format!("{}:{}", server_seed, args[1]);
-        Self::build_server(server_address,blocks,merkle_blocks, headers,tx);
+        Self::build_server(server_address,blocks,merkle_blocks, headers,tx, tx_relay);
         let client_address: Option<String>=if args.len() == 3 {
             Some(format!("{}:{}", server_seed, args[2]))
         } else {
@@ -233,7 +249,8 @@ impl BitcoinNode {
         blocks: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,
         merkle_blocks: Arc<Mutex<HashMap<[u8;32],MerkleBlock>>>,
         headers: Arc<Mutex<Vec<Vec<u8>>>>,
-        tx: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,) {
+        tx: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,
+        tx_relay: Relay,) {
         thread::spawn(move || {
             println!("se queda esperando conexion en {}", seed);
             if let Ok(listener) = TcpListener::bind(seed) {
@@ -242,11 +259,12 @@ impl BitcoinNode {
                     let headers = headers.clone();
                     let merkles = merkle_blocks.clone();
                     let tx =tx.clone();
+                    let tx_relay = tx_relay.clone();
                     println!("aca no entra nunca");
                     match stream {
                         Ok(stream) => {
                             thread::spawn(move || {
-                                Self::handle_client(stream,blocks, merkles, headers, tx);
+                                Self::handle_client(stream,blocks, merkles, headers, tx, tx_relay);
                             });
                         }
                         Err(err) => {
@@ -262,8 +280,10 @@ impl BitcoinNode {
         blocks: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,
         merkle_blocks: Arc<Mutex<HashMap<[u8;32],MerkleBlock>>>,
         headers: Arc<Mutex<Vec<Vec<u8>>>>,
-        tx: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,) {
+        tx: Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,
+        tx_relay: Relay,) {
         let mut buffer = vec![0u8; 24];
+        let peer_address = Self::peer_address(&stream);
     
         loop {
             match stream.read(&mut buffer) {
@@ -275,10 +295,13 @@ impl BitcoinNode {
                     println!("el buffer que me llega es {:?}", buffer.to_vec());
                     //println!("el buffer es de tipo  {:?}", deserialize_message_from_client(&mut buffer.to_vec(), &stream));
                     let (message_type,mut vector) = deserialize_message_from_client(&mut buffer.to_vec(), &stream);
+                    let write_lock = Self::peer_write_lock(&tx_relay, &peer_address);
+                    let _writing = write_lock.lock();
                     match message_type {
                         MessageType::VersionMessage => {
                             println!("Sending version to client");
                             handshake(&stream);
+                            Self::relay_to_peer(&tx_relay, &peer_address, &stream);
                         }
                         MessageType::Mempool => {
                             Self::mempool(&mut stream,&mut vector,tx.clone());
@@ -298,6 +321,7 @@ impl BitcoinNode {
                             merkle_blocks.clone()  
                         ),
                         MessageType::GetBlockTxn=>Self::get_block_tx(&mut stream,&mut vector,blocks.clone()),
+                        MessageType::FeeFilter => Self::fee_filter_message(&tx_relay, &mut vector, &peer_address),
 
                         _ => {}
                     }
@@ -311,6 +335,37 @@ impl BitcoinNode {
                 }
             }
         }
+        if let Ok(mut relay) = tx_relay.lock() {
+            relay.remove_peer(&peer_address);
+        }
+    }
+
+    /// Returns the address of the peer on the other side of the stream
+    fn peer_address(stream: &TcpStream) -> String {
+        match stream.peer_addr() {
+            Ok(address) => address.to_string(),
+            Err(_) => String::new(),
+        }
+    }
+
+    /// Returns the lock held while writing a message to the peer, so the relay
+    /// doesn't write to its stream at the same time
+    fn peer_write_lock(tx_relay: &Relay, address: &str) -> PeerWriteLock {
+        match tx_relay.lock() {
+            Ok(relay) => relay.write_lock(address),
+            Err(_) => Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Starts relaying our mempool transactions to a connected peer
+    fn relay_to_peer(tx_relay: &Relay, address: &str, stream: &TcpStream) {
+        let relay_stream = match stream.try_clone() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        if let Ok(mut relay) = tx_relay.lock() {
+            relay.add_peer(address.to_string(), relay_stream);
+        }
     }
 
     /// It starts the node. It connects to the peers and starts downloading the blocks.
@@ -379,6 +434,13 @@ impl BitcoinNode {
                 Ok(v) => v,
                 Err(_v) => vec![vec![]],
             };
+            // Only the blocks after the stored ones are downloaded, older ones are never fetched again
+            if let Some(first) = merkles_strings.first() {
+                let first_time = BlockMessage::blocks_from_str(first.clone()).get_block_header().time;
+                if first_time > self.download_start().saturating_add(BLOCK_TIME_DRIFT) {
+                    println!("Wallets born before the first stored block miss the payments before it");
+                }
+            }
             let mut blocks = match self.blocks.lock(){
                 Ok(v)=>v,
                 Err(_v)=>return block,
@@ -388,8 +450,12 @@ impl BitcoinNode {
                 println!("leyendo bloque");
                 let mut used_tx: HashMap<[u8; 32], Vec<u32>> = HashMap::new();
                 block = BlockMessage::blocks_from_str(i);
-                blocks.insert(header_calculate_doublehash_array_be(&block.get_block_header()).unwrap_or([0; 32]), block.serialize());
+                let block_hash = header_calculate_doublehash_array_be(&block.get_block_header()).unwrap_or([0; 32]);
+                blocks.insert(block_hash, block.serialize());
                 let mut txid = block.get_ids();
+                if let Ok(mut chain_times) = self.chain_times.lock() {
+                    chain_times.add_block(block_hash, &txid);
+                }
                 Self::add_to_utxo_available(utxo_set, &mut block, &mut txid);
                 Self::add_to_used_tx(&mut used_tx, &mut block);
                 Self::remove_use_tx(utxo_set, &mut used_tx);
@@ -509,6 +575,8 @@ impl BitcoinNode {
                 Some(v)=>headers_lock.extend_from_slice(&v),
                 None=>return(get_data_block,get_data_merkel,last_header),
             }
+            let first_indexed = headers_lock.len().saturating_sub(INDEXED_HEADERS);
+            self.index_headers(&headers_lock[first_indexed..], first_indexed);
             let block_header = last_block.get_block_header();
             headers = headers.blocks_missing(block_header.clone());
             //println!("headers {:?}",headers);
@@ -516,8 +584,8 @@ impl BitcoinNode {
             let last_header_block =
                 header_calculate_doublehash_array_be(&block_header).unwrap_or([0; 32]);
             if last_header != last_header_block {
-                get_data_block = headers.get_data_with_type(2);
-                get_data_merkel = headers.get_data_with_type(3);
+                get_data_block = headers.get_data_with_type(2, self.download_start());
+                get_data_merkel = headers.get_data_with_type(3, self.download_start());
             }
             println!("headers Stored:{:?}",self.headers);
             println!("last_header{:?}", last_header);
@@ -536,7 +604,8 @@ impl BitcoinNode {
         last_header: [u8; 32],
     ) {
         //Interfaz y wallet
-        let (handles_interface, sender_to_interface) = self.start_interface(peers.clone());
+        let (handles_interface, sender_to_interface) = self.start_interface();
+        TxRelay::start(self.tx_relay.clone());
 
         //Store Headers
         let storage_manager_sender = Arc::new(Mutex::new(storage_managers.0));
@@ -687,8 +756,9 @@ impl BitcoinNode {
         };
 
         writer(&write_stream);
-
         get_headers(&mut write_stream, &self.last_header);
+        let peer_address = Self::peer_address(&stream);
+        Self::relay_to_peer(&self.tx_relay, &peer_address, &stream);
 
         self.node_internal_threads(
             stream,
@@ -698,6 +768,9 @@ impl BitcoinNode {
             storage,
             node_coms,
         );
+        if let Ok(mut relay) = self.tx_relay.lock() {
+            relay.remove_peer(&peer_address);
+        }
         println!("Function: node_connection_threads for address finished");
     }
 
@@ -822,6 +895,8 @@ impl BitcoinNode {
             vec![Inv::new(0, [0; 32])],
         );
         let mut data_loaded = false;
+        let peer_address = Self::peer_address(&write_block_stream);
+        let write_lock = Self::peer_write_lock(&self.tx_relay, &peer_address);
         loop {
             let mut lock_stop_flag = stop_flag.lock();
             while lock_stop_flag.is_err() {
@@ -854,6 +929,8 @@ impl BitcoinNode {
                 Ok(a) => a,
                 Err(_) => panic!("Failed to convert vector to array"),
             };
+            // Held until the message is handled, as handling it may write to the peer
+            let _writing = write_lock.lock();
             match get_type(&command) {
                 MessageType::End => {
                     let Ok(_result) = sender.send(0) else {return};
@@ -883,16 +960,27 @@ impl BitcoinNode {
                 MessageType::MerkleBlock => {
                     self.merkel_block(&mut vector, storage.storage_manager_merkles.clone(), &mut get_data_vector, &write_block_stream)
                 }
-                MessageType::InvMessage => Self::inv_message(
+                MessageType::InvMessage => self.inv_message(
                     &mut write_block_stream,
                     &mut vector,
                     &data_loaded,
                     &reading_headers,
+                    &peer_address,
                 ),
                 MessageType::Tx => {
-                    self.tx_message_was_received(&mut vector, sender_to_interface.clone());
+                    self.tx_message_was_received(&mut vector, &utxo_set, &peer_address, sender_to_interface.clone());
                     tx_recieved = true;
                 }
+                MessageType::FeeFilter => {
+                    Self::fee_filter_message(&self.tx_relay, &mut vector, &peer_address)
+                }
+                MessageType::GetDataMessage => Self::get_data_message(
+                    &mut write_block_stream,
+                    &mut vector,
+                    self.tx.clone(),
+                    self.blocks.clone(),
+                    self.merkle_blocks.clone(),
+                ),
                 MessageType::Ping => {
                     let ping = PingOrPong::deserialize(&mut vector);
                     let _result = ping.send_pong(&write_block_stream);
@@ -955,9 +1043,9 @@ impl BitcoinNode {
                 message,
                 sender_to_interface,
                 storage_manager,
-                blocks_to_read,
-                merkel_to_read,
+                (&mut *blocks_to_read, &mut *merkel_to_read),
                 &self.last_header,
+                self.download_start(),
             ){
                 Some(v) =>v,
                 None=> return true,
@@ -966,10 +1054,12 @@ impl BitcoinNode {
                 Ok(v)=>v,
                 Err(_v)=>return response,
             };
+            let first_new = headers.len();
             match header.to_serialized(){
                 Some(v)=>headers.extend_from_slice(&v),
                 None=>return true,
             }
+            self.index_headers(&headers[first_new..], first_new);
 
 
             if header.get_headers().len() < 2000 {
@@ -1010,7 +1100,6 @@ impl BitcoinNode {
         let mut vector_copy = vector.clone();
         let sender_blocks_clone = sender_blocks_clone.clone();
 
-        get_blocks(sender_blocks_clone, vector);
         let mut block = match BlockMessage::deserialize(&mut vector_copy) {
             Ok(v) => v,
             Err(_) => BlockMessage::new(
@@ -1026,7 +1115,21 @@ impl BitcoinNode {
                 vec![],
             ),
         };
+        let block_hash = header_calculate_doublehash_array_be(&block.get_block_header()).unwrap_or([0; 32]);
+        if !self.block_is_final(&block, block_hash, utxo_set) {
+            println!("block with non final or invalid transactions rejected");
+            return false;
+        }
+        get_blocks(sender_blocks_clone, vector);
         let mut txid = block.get_ids();
+        if let Ok(mut chain_times) = self.chain_times.lock() {
+            chain_times.add_block(block_hash, &txid);
+        }
+        self.record_block_history(&block, block_hash);
+        self.process_block_fees(block_hash, &txid, &sender_to_interface);
+        if let Ok(mut relay) = self.tx_relay.lock() {
+            relay.block_connected(&block.get_tx());
+        }
 
         self.add_to_utxo(utxo_set, &mut block, &mut txid, sender_to_interface);
         Self::add_to_used_tx(&mut used_tx, &mut block);
@@ -1102,15 +1205,25 @@ impl BitcoinNode {
 
     ///An inv_message is recieved and depending on its contents it gets a response or not
     fn inv_message(
+        &mut self,
         read_stream: &mut TcpStream,
         vector: &mut Vec<u8>,
         data_loaded: &bool,
         reading_headers: &bool,
+        peer_address: &str,
     ) {
         let inv_message = match InvOrGetDataMessage::deserialize(vector) {
             Ok(v) => v,
             Err(_v) => return,
         };
+        // The peer already has these transactions, there's no need to announce them back
+        if let Ok(mut relay) = self.tx_relay.lock() {
+            for inv in inv_message.inv() {
+                if inv.type_equal(1) {
+                    relay.mark_known(peer_address, inv.hash());
+                }
+            }
+        }
         if !*data_loaded && !*reading_headers {
             inv_message.ask_for_tx(read_stream);
         }
@@ -1371,26 +1484,338 @@ impl BitcoinNode {
     fn tx_message_was_received(
         &mut self,
         vector: &mut Vec<u8>,
+        utxo_set: &HashMap<[u8; 32], Vec<Output>>,
+        peer_address: &str,
         sender_to_interface: SenderInterface,
     ) {
         let tx = match TXMessage::deserialize(vector) {
             Ok(v) => v,
             Err(_v) => return,
         };
-        let mut txs_lock = match self.tx.lock(){
-            Ok(v)=>v,
-            Err(_v)=>return
+        let txid = tx.get_id();
+        let parent = match self.tx_relay.lock() {
+            Ok(mut relay) => {
+                relay.mark_known(peer_address, txid);
+                relay.low_fee_parent(&tx)
+            }
+            Err(_v) => None,
         };
-        txs_lock.insert(tx.get_id(), tx.serialize());
+        let (is_new, fee, child_fee, mut unconfirmed) = match self.tx.lock() {
+            Ok(txs) => (
+                !txs.contains_key(&txid),
+                Self::transaction_fee(&tx, utxo_set, &txs, None),
+                parent
+                    .as_ref()
+                    .and_then(|(parent, _)| Self::transaction_fee(&tx, utxo_set, &txs, Some(parent))),
+                Self::mempool_outputs(&tx, &txs),
+            ),
+            Err(_v) => return,
+        };
+        if !is_new {
+            return;
+        }
+        // Txs spending more than their inputs create value and are invalid
+        if fee.is_some_and(|fee| fee < 0) || child_fee.is_some_and(|fee| fee < 0) {
+            println!("transaction spending more than its inputs rejected");
+            return;
+        }
+        if let Some((parent, _)) = &parent {
+            unconfirmed.insert(parent.get_id(), parent.get_output());
+        }
+        // Only txs that could be mined in the next block are accepted
+        if !self.is_final_at(&tx, self.current_height() + 1, utxo_set, &unconfirmed) {
+            println!("non final transaction rejected");
+            return;
+        }
+        if !Self::taproot_spends_are_valid(&tx, utxo_set, &unconfirmed) {
+            println!("transaction with an invalid taproot signature rejected");
+            return;
+        }
 
+        // The tx may be a child paying for a parent we rejected because of its low fee
+        if let (Some((parent, parent_fee)), Some(child_fee)) = (parent, child_fee) {
+            let accepted = match Package::new(parent.clone(), tx.clone()) {
+                Ok(package) => match self.tx_relay.lock() {
+                    Ok(mut relay) => relay
+                        .submit_package(&package, (parent_fee, child_fee as u64), false)
+                        .is_ok(),
+                    Err(_v) => false,
+                },
+                Err(_v) => false,
+            };
+            if accepted {
+                self.show_unconfirmed_tx(parent, &sender_to_interface);
+                self.show_unconfirmed_tx(tx, &sender_to_interface);
+            }
+            return;
+        }
+
+        // Txs spending outputs we don't know can't be checked, so they aren't accepted nor relayed
+        let fee = match fee {
+            Some(fee) => fee as u64,
+            None => {
+                println!("transaction spending unknown outputs not accepted");
+                return;
+            }
+        };
+        // Fee filters and the mempool minimum are expressed in sat/kvB
+        let vsize = tx.vsize() as u64;
+        let fee_rate = fee * 1000 / vsize;
+        if let Ok(mut relay) = self.tx_relay.lock() {
+            if !relay.accepts_fee_rate(fee_rate) {
+                relay.add_low_fee_transaction(tx, fee);
+                return;
+            }
+        }
+        // Replaced transactions and the cheapest ones if the mempool is full leave it,
+        // replacements not following BIP125 are rejected
+        let added = match self.tx_relay.lock() {
+            Ok(mut relay) => relay.add_to_mempool(&tx, fee),
+            Err(_v) => false,
+        };
+        if !added {
+            return;
+        }
+        // Entries are counted from the last connected block, the one confirmations are counted at
+        let tip = self.current_height();
+        if let Ok(mut estimator) = self.interface_communicator.fee_estimator.lock() {
+            let height = match estimator.best_height() {
+                0 => tip,
+                best => best,
+            };
+            estimator.process_transaction(txid, fee as f64 / vsize as f64, height);
+        }
+        if let Ok(mut relay) = self.tx_relay.lock() {
+            relay.announce(txid, fee_rate);
+        }
+        self.show_unconfirmed_tx(tx, &sender_to_interface);
+    }
+
+    /// Sends an unconfirmed tx to the interface, checking if any wallet has an address involved in it
+    fn show_unconfirmed_tx(&self, tx: TXMessage, sender_to_interface: &SenderInterface) {
         if let Ok(mut txs) = self.interface_communicator.transactions.lock() {
             txs.insert(u8_array_to_hex_string(&tx.get_id()), tx.clone());
         }
+        self.interface_communicator.record_transaction(&tx);
+        //Pertenece al usuario
         let belongs = self.is_user_tx(tx.clone());
         let message = InterfaceMessages::AllTransactions(false, tx, belongs);
         if sender_to_interface.send(message).is_ok() {}
+    }
 
-        //Pertenece al usuario
+    /// A peer sent its feefilter, transactions below that fee rate won't be announced to it
+    fn fee_filter_message(tx_relay: &Relay, vector: &mut Vec<u8>, peer_address: &str) {
+        let fee_filter = match FeeFilter::deserialize(vector) {
+            Ok(v) => v,
+            Err(_v) => return,
+        };
+        if let Ok(mut relay) = tx_relay.lock() {
+            relay.set_fee_filter(peer_address, fee_filter.get_fee_rate());
+        }
+    }
+
+    /// Returns the fee paid by a tx, negative if it spends more than its inputs,
+    /// or None if any of the outputs it spends is unknown.
+    /// Outputs are searched in the utxo set, the given parent and the mempool
+    fn transaction_fee(
+        tx: &TXMessage,
+        utxo_set: &HashMap<[u8; 32], Vec<Output>>,
+        mempool: &HashMap<[u8; 32], Vec<u8>>,
+        parent: Option<&TXMessage>,
+    ) -> Option<i64> {
+        let mut input_value: i64 = 0;
+        for input in tx.get_input() {
+            let outpoint = input.get_outpoint();
+            let hash = outpoint.get_hash();
+            let index = outpoint.get_index() as usize;
+            let previous_output = match utxo_set.get(&hash) {
+                Some(outputs) => outputs.get(index).cloned(),
+                None => match parent.filter(|parent| parent.get_id() == hash) {
+                    Some(parent) => parent.get_output().get(index).cloned(),
+                    None => match mempool.get(&hash) {
+                        Some(serialized) => TXMessage::deserialize(&mut serialized.clone())
+                            .ok()
+                            .and_then(|parent| parent.get_output().get(index).cloned()),
+                        None => None,
+                    },
+                },
+            }?;
+            // Spent outputs are left empty in the utxo set
+            if previous_output.get_script().is_empty() {
+                return None;
+            }
+            input_value += previous_output.get_value();
+        }
+        let output_value: i64 = tx.get_output_amounts().iter().sum();
+        Some(input_value - output_value)
+    }
+
+    /// Returns the outputs of the mempool txs spent by a tx
+    fn mempool_outputs(
+        tx: &TXMessage,
+        mempool: &HashMap<[u8; 32], Vec<u8>>,
+    ) -> HashMap<[u8; 32], Vec<Output>> {
+        let mut outputs = HashMap::new();
+        for input in tx.get_input() {
+            let hash = input.get_outpoint().get_hash();
+            if let Some(serialized) = mempool.get(&hash) {
+                if let Ok(parent) = TXMessage::deserialize(&mut serialized.clone()) {
+                    outputs.insert(hash, parent.get_output());
+                }
+            }
+        }
+        outputs
+    }
+
+    /// Returns true if a tx can be included in a block at `height`, checking its absolute locktime,
+    /// the BIP68 relative locktimes of its inputs and the OP_CHECKSEQUENCEVERIFY of the scripts it spends.
+    /// `unconfirmed` has the outputs spent by the tx that aren't confirmed before `height`
+    fn is_final_at(
+        &self,
+        tx: &TXMessage,
+        height: u32,
+        utxo_set: &HashMap<[u8; 32], Vec<Output>>,
+        unconfirmed: &HashMap<[u8; 32], Vec<Output>>,
+    ) -> bool {
+        let pending: Vec<[u8; 32]> = unconfirmed.keys().copied().collect();
+        match self.chain_times.lock() {
+            Ok(chain_times) => {
+                if !chain_times.is_final(tx, height, &pending) {
+                    return false;
+                }
+            }
+            Err(_) => return false,
+        }
+        for (index, input) in tx.get_input().iter().enumerate() {
+            let outpoint = input.get_outpoint();
+            let previous_output = utxo_set
+                .get(&outpoint.get_hash())
+                .or_else(|| unconfirmed.get(&outpoint.get_hash()))
+                .and_then(|outputs| outputs.get(outpoint.get_index() as usize));
+            if let Some(previous_output) = previous_output {
+                if !check_script_sequence_locks(tx, index, &previous_output.get_script()) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Checks the BIP341 key path spends of a tx. Its inputs are searched in the utxo set and
+    /// in `unconfirmed`, txs spending unknown outputs or stripped of witnesses can't be checked
+    fn taproot_spends_are_valid(
+        tx: &TXMessage,
+        utxo_set: &HashMap<[u8; 32], Vec<Output>>,
+        unconfirmed: &HashMap<[u8; 32], Vec<Output>>,
+    ) -> bool {
+        let prevouts: Vec<Option<Output>> = tx
+            .get_input()
+            .iter()
+            .map(|input| {
+                let outpoint = input.get_outpoint();
+                utxo_set
+                    .get(&outpoint.get_hash())
+                    .or_else(|| unconfirmed.get(&outpoint.get_hash()))
+                    .and_then(|outputs| outputs.get(outpoint.get_index() as usize))
+                    .cloned()
+            })
+            .collect();
+        check_taproot_spends(tx, &prevouts)
+    }
+
+    /// Returns true if every tx of the block is final at the block's height and its taproot
+    /// key path signatures are valid. Blocks whose header isn't indexed can't be checked and are accepted
+    fn block_is_final(
+        &self,
+        block: &BlockMessage,
+        block_hash: [u8; 32],
+        utxo_set: &HashMap<[u8; 32], Vec<Output>>,
+    ) -> bool {
+        let height = match self.chain_times.lock() {
+            Ok(chain_times) => match chain_times.height_of(&block_hash) {
+                Some(height) => height,
+                None => return true,
+            },
+            Err(_) => return true,
+        };
+        let mut unconfirmed: HashMap<[u8; 32], Vec<Output>> = HashMap::new();
+        for (index, tx) in block.get_tx().iter().enumerate() {
+            // The coinbase has no inputs to check
+            if index > 0
+                && (!self.is_final_at(tx, height, utxo_set, &unconfirmed)
+                    || !Self::taproot_spends_are_valid(tx, utxo_set, &unconfirmed))
+            {
+                return false;
+            }
+            unconfirmed.insert(tx.get_id(), tx.get_output());
+        }
+        true
+    }
+
+    /// Indexes the height and time of serialized headers, the first one being at position `first` of the chain.
+    /// The first header after genesis has height 1
+    fn index_headers(&self, headers: &[Vec<u8>], first: usize) {
+        let mut chain_times = match self.chain_times.lock() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        for (n, serialized) in headers.iter().enumerate() {
+            let header = match BlockHeader::deserialize(&mut serialized.clone()) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if let Some(hash) = header_calculate_doublehash_array_be(&header) {
+                chain_times.add_header(hash, (first + n + 1) as u32, header.time);
+            }
+        }
+    }
+
+    /// Adds the txs of the block to the history of the wallets, before the utxos they spend are removed.
+    /// Blocks whose header isn't indexed have no known height and aren't recorded
+    fn record_block_history(&self, block: &BlockMessage, block_hash: [u8; 32]) {
+        if let Some(height) = self.block_height(&block_hash) {
+            let position = (block_hash, height, block.get_block_header().time);
+            self.interface_communicator
+                .record_block(block, position, self.current_height());
+        }
+    }
+
+    /// Returns the time blocks are downloaded from, the birthday of the oldest wallet
+    fn download_start(&self) -> u32 {
+        self.interface_communicator.download_start()
+    }
+
+    /// Returns the height of the best known header
+    fn current_height(&self) -> u32 {
+        match self.headers.lock() {
+            Ok(headers) => headers.len() as u32,
+            Err(_) => 0,
+        }
+    }
+
+    /// Returns the height of the block from the index of headers, None if its header isn't indexed
+    fn block_height(&self, block_hash: &[u8; 32]) -> Option<u32> {
+        match self.chain_times.lock() {
+            Ok(chain_times) => chain_times.height_of(block_hash),
+            Err(_) => None,
+        }
+    }
+
+    /// Records which mempool transactions were confirmed by a block at its own height
+    /// and refreshes the suggested fees. Blocks of unknown height aren't recorded
+    fn process_block_fees(
+        &mut self,
+        block_hash: [u8; 32],
+        txids: &[[u8; 32]],
+        sender_to_interface: &SenderInterface,
+    ) {
+        if let Some(height) = self.block_height(&block_hash) {
+            if let Ok(mut estimator) = self.interface_communicator.fee_estimator.lock() {
+                estimator.process_block(height, txids);
+            }
+        }
+        self.interface_communicator
+            .update_fee_estimates(sender_to_interface);
     }
 
     fn is_user_tx(&self, tx: TXMessage) -> bool {
@@ -1406,19 +1831,19 @@ impl BitcoinNode {
                 Some(v) => v,
                 None => return belongs,
             };
-            let address = wallet_pass.get_address();
             let output = tx.get_output();
             let input = tx.get_input();
 
             for i in output {
                 let output_address = bitcoin_address_in_b58_output(&i.get_script());
-                if address == output_address {
+                if wallet_pass.is_mine(&output_address) {
                     belongs = true;
                 }
             }
-            for j in input {
+            for (index, j) in input.iter().enumerate() {
                 let output_address = bitcoin_address_in_b58_output(&j.get_script());
-                if address == output_address {
+                let witness_address = bitcoin_address_in_witness(&tx.get_witness(index));
+                if wallet_pass.is_mine(&output_address) || wallet_pass.is_mine(&witness_address) {
                     belongs = true;
                 }
             }
@@ -1562,24 +1987,29 @@ impl BitcoinNode {
 
     /// searches for an address outputs
     fn create_address_utxo(&mut self, utxo_set: &mut MutexGuard<HashMap<[u8; 32], Vec<Output>>>) {
-        self.utxo_collector.create_address_utxo(utxo_set);
-        println!(
-            "{:?}",
-            self.utxo_collector
-                .get_utxos()
-                .get_key_value("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc")
-        );
+        if let Ok(mut utxo_collector) = self.utxo_collector.lock() {
+            utxo_collector.create_address_utxo(utxo_set);
+            println!(
+                "{:?}",
+                utxo_collector
+                    .get_utxos()
+                    .get_key_value("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc")
+            );
+        }
         println!("no more addreses");
 
         // Wallet
-        self.interface_communicator
-            .update_balance(&self.utxo_collector);
+        self.interface_communicator.update_balance();
     }
 
     fn _outputs_for_tx(&mut self, amount: i64) -> Vec<(Outpoint, Output)> {
         let mut vector: Vec<(Outpoint, Output)> = vec![];
 
-        let utxo_hash = &self.utxo_collector.get_utxos();
+        let mut utxo_collector = match self.utxo_collector.lock() {
+            Ok(utxo_collector) => utxo_collector,
+            Err(_) => return vector,
+        };
+        let utxo_hash = utxo_collector.get_utxos();
 
         for (_key, val) in utxo_hash.iter() {
             let mut balance = 0;
@@ -1595,7 +2025,7 @@ impl BitcoinNode {
     }
 
     /// Initialize the necessary structures to launch the interface, returning the channels it will need for communication.
-    fn start_interface(&mut self, peers: Vec<String>) -> StartInterfaceElements {
+    fn start_interface(&mut self) -> StartInterfaceElements {
         let (sender_to_interface, receiver_from_node) =
             MainContext::channel(glib::PRIORITY_DEFAULT);
         let (sender_to_node, receiver_from_interface) =
@@ -1603,8 +2033,11 @@ impl BitcoinNode {
         let mut handles_interface = Vec::new();
         let handle_interface = InterfaceHandler::start(sender_to_node, receiver_from_node);
         self.interface_communicator = InterfaceCommunicator::new();
+        self.interface_communicator.utxo_collector = Arc::clone(&self.utxo_collector);
+        self.interface_communicator.headers = Arc::clone(&self.headers);
+        self.interface_communicator.node_blocks = Arc::clone(&self.blocks);
         self.interface_communicator.start(
-            peers,
+            self.tx_relay.clone(),
             sender_to_interface.clone(),
             receiver_from_interface,
         );
@@ -30,15 +30,17 @@ type Storage = Arc<Mutex<StorageManager>>;
 type SenderToInterface = InterfaceSender<InterfaceMessages>;
 
 /// Extracts the Headers from a HeadersMessage and validates them. If valid, it stores them and pushes them to the InterfaceChannel.
+/// The blocks and merkle blocks mined after `download_start` are queued to be read
 pub fn get_all_headers(
     read_stream: &TcpStream,
     mut message: Vec<u8>,
     sender_to_interface: SenderToInterface,
     storage_manager: &Storage,
-    blocks_to_read: &mut MutexGuard<Vec<InvOrGetDataMessage>>,
-    merkel_to_read: &mut MutexGuard<Vec<InvOrGetDataMessage>>,
+    to_read: (&mut MutexGuard<Vec<InvOrGetDataMessage>>, &mut MutexGuard<Vec<InvOrGetDataMessage>>),
     last_header: &Arc<Mutex<[u8; 32]>>,
+    download_start: u32,
 )->Option<HeadersMessage>{
+    let (blocks_to_read, merkel_to_read) = to_read;
     let headers = match HeadersMessage::deserialize(&mut message) {
         Ok(v) => v,
         Err(_e) => return None,
@@ -69,12 +71,12 @@ pub fn get_all_headers(
         *hash = last_hash;
         println!("cambio : {:?}", hash);
 
-        let mut get_data_block = headers.get_data_with_type(2);
+        let mut get_data_block = headers.get_data_with_type(2, download_start);
         if !get_data_block.is_empty() {
             blocks_to_read.append(&mut get_data_block);
         }
         blocks_to_read.dedup();
-        let mut get_merkel_block = headers.get_data_with_type(3);
+        let mut get_merkel_block = headers.get_data_with_type(3, download_start);
         if !get_merkel_block.is_empty() {
             merkel_to_read.append(&mut get_merkel_block);
         }
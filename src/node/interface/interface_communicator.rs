@@ -2,25 +2,47 @@ use glib::Sender;
 
 use crate::{
     message_structs::{
-        block_headers::BlockHeader, block_message::BlockMessage, tx_message::TXMessage,
+        block_headers::{BlockHeader, DEFAULT_BIRTHDAY},
+        block_message::BlockMessage,
+        tx_message::TXMessage,
     },
     node::{
-        connection_manager::peers_connection::writer, utxo_collector::UtxoCollector,
-        wallets::wallet_handler::WalletHandler,
+        mempool::{
+            fee_estimator::{FeeEstimator, FEE_ESTIMATES_PATH},
+            tx_relay::TxRelay,
+        },
+        utxo_collector::UtxoCollector,
+        wallets::{
+            encryption::{
+                is_sealed, WalletEncryption, ENCRYPTED_HEADER, SCRYPT_LOG_N, UNLOCK_TIMEOUT_SECS,
+            },
+            hd_keys::Network,
+            history::{BlockPosition, HISTORY_PATH},
+            keys_handler::AddressType,
+            message_signing::verify_message,
+            payment_request::PAYMENT_REQUESTS_PATH,
+            payments::Payment,
+            pending::PENDING_PATH,
+            rescan::{RescanStart, BIRTHDAYS_PATH},
+            wallet_handler::{HistoryRow, PaymentRequestRow, UtxoRow, WalletHandler, WALLETS_PATH},
+        },
     },
 };
 use std::{
     collections::HashMap,
-    fs::{File, OpenOptions},
+    error::Error,
+    fs::{self, File, OpenOptions},
     io::{BufRead, BufReader, Write},
-    net::TcpStream,
     process,
     sync::{mpsc::Receiver, Arc, Mutex},
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
+use crate::node::validation_engine::hashes::header_calculate_doublehash_array_be;
 use crate::node::validation_engine::merkles::merkle_tree::MerkleTree;
 use crate::node::wallets::wallet::Wallet;
+use crate::utils::array_tools::txid_from_hex;
 
 /// Communication channels node - interface
 pub enum InterfaceMessages {
@@ -28,7 +50,7 @@ pub enum InterfaceMessages {
     DebugBlocks(i32, BlockMessage, usize),
     AllTransactions(bool, TXMessage, bool),
     MyTransactions(TXMessage),
-    SendTransaction((String, String, i32, i32)),
+    SendTransaction(TransactionOrder),
     AddWalletOrder((String, String)),
     WalletName(String),
     WalletSwitch(String),
@@ -37,13 +59,66 @@ pub enum InterfaceMessages {
     InclusionProofResult(bool),
     Close(()),
     Open((Vec<String>, HashMap<String, String>)),
+    UnlockRequest(TransactionOrder),
+    PassphraseRequest(()),
+    UnlockWallets((String, u64, Option<TransactionOrder>)),
+    LockWallets(()),
+    ExportDescriptors(()),
+    EncryptWallets(String),
+    ChangePassphrase((String, String)),
+    EncryptionResult(String),
+    NewAddress(AddressType),
+    CreateMnemonicWallet((String, usize, String)),
+    RestoreWallet((String, String, String)),
+    CreateMultisigWallet((String, usize, String, bool)),
+    WalletMessage(String),
+    CreatePsbt(TransactionOrder),
+    SignPsbt(String),
+    CombinePsbts(String),
+    FinalizePsbt(String),
+    PsbtResult(String),
+    ListUtxos(()),
+    Utxos(Vec<UtxoRow>),
+    SetCoinControl((Vec<String>, Vec<String>)),
+    SendBatch((Vec<Payment>, i32)),
+    BumpFee((String, i32)),
+    ChildPaysForParent((String, i32)),
+    AbandonTransaction(String),
+    ListHistory(()),
+    History(Vec<HistoryRow>),
+    SetLabel((String, String)),
+    ExportHistory(String),
+    Rescan(String),
+    ExportWif(()),
+    Sweep((String, i32)),
+    SignMessage((String, String)),
+    VerifyMessage((String, String, String)),
+    CreatePaymentRequest((Option<i64>, String, String)),
+    ListPaymentRequests(()),
+    PaymentRequests(Vec<PaymentRequestRow>),
 }
 
+/// Serialized headers and blocks stored by the node, the blocks by the hash of their header
+type StoredChain = (
+    Arc<Mutex<Vec<Vec<u8>>>>,
+    Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+);
+
+/// Payment ordered from the send entries: address, label, amount and fee rate in sat/vB
+pub type TransactionOrder = (String, String, i32, i32);
+
+/// Transactions received by the node, by their hex id
+type KnownTransactions = Arc<Mutex<HashMap<String, TXMessage>>>;
+
 pub struct InterfaceCommunicator {
     pub wallet_handler: Arc<Mutex<WalletHandler>>,
     opened: bool,
     pub blocks: Arc<Mutex<HashMap<String, BlockMessage>>>,
     pub transactions: Arc<Mutex<HashMap<String, TXMessage>>>,
+    pub fee_estimator: Arc<Mutex<FeeEstimator>>,
+    pub utxo_collector: Arc<Mutex<UtxoCollector>>,
+    pub headers: Arc<Mutex<Vec<Vec<u8>>>>,
+    pub node_blocks: Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
 }
 
 impl Clone for InterfaceCommunicator {
@@ -53,6 +128,10 @@ impl Clone for InterfaceCommunicator {
             opened: self.opened,
             blocks: self.blocks.clone(),
             transactions: self.transactions.clone(),
+            fee_estimator: self.fee_estimator.clone(),
+            utxo_collector: self.utxo_collector.clone(),
+            headers: self.headers.clone(),
+            node_blocks: self.node_blocks.clone(),
         }
     }
 }
@@ -68,8 +147,12 @@ impl InterfaceCommunicator {
     pub fn new() -> InterfaceCommunicator {
         let mut opened = false;
         let wallets = match InterfaceCommunicator::open() {
-            Some(wallets) => {
+            Some(mut wallets) => {
                 opened = true;
+                // Sealed files are read once the passphrase is given
+                if !wallets.is_sealed() {
+                    Self::open_files(&mut wallets);
+                }
                 wallets
             }
             None => WalletHandler::new(),
@@ -79,13 +162,17 @@ impl InterfaceCommunicator {
             opened,
             blocks: Arc::new(Mutex::new(HashMap::new())),
             transactions: Arc::new(Mutex::new(HashMap::new())),
+            fee_estimator: Arc::new(Mutex::new(FeeEstimator::open(FEE_ESTIMATES_PATH))),
+            utxo_collector: Arc::new(Mutex::new(UtxoCollector::new())),
+            headers: Arc::new(Mutex::new(vec![])),
+            node_blocks: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     /// Matches the channels and depending on which one it is, sends the necessary command to be executed
     pub fn start(
         &mut self,
-        peers: Vec<String>,
+        tx_relay: Arc<Mutex<TxRelay>>,
         sender_to_interface: Sender<InterfaceMessages>,
         receiver_from_interface: Receiver<InterfaceMessages>,
     ) -> JoinHandle<()> {
@@ -95,21 +182,30 @@ impl InterfaceCommunicator {
         let wallet_handler = Arc::clone(&self.wallet_handler);
         let blocks = Arc::clone(&self.blocks);
         let transactions = Arc::clone(&self.transactions);
+        let fee_estimator = Arc::clone(&self.fee_estimator);
+        let utxo_collector = Arc::clone(&self.utxo_collector);
+        let headers = Arc::clone(&self.headers);
+        let node_blocks = Arc::clone(&self.node_blocks);
+        Self::lock_on_timeout(Arc::clone(&wallet_handler), sender_to_interface.clone());
 
         thread::spawn(move || {
             for message in receiver_from_interface {
                 let sender_to_interface = sender_to_interface.clone();
-                let peers = peers.clone();
+                let tx_relay = Arc::clone(&tx_relay);
                 let wallet_handler = Arc::clone(&wallet_handler);
                 let blocks = Arc::clone(&blocks);
                 let transactions = Arc::clone(&transactions);
+                let fee_estimator = Arc::clone(&fee_estimator);
+                let utxo_collector = Arc::clone(&utxo_collector);
+                let chain = (Arc::clone(&headers), Arc::clone(&node_blocks));
                 Self::handle_messages(
                     message,
                     sender_to_interface,
-                    peers,
+                    tx_relay,
                     wallet_handler,
                     blocks,
                     transactions,
+                    (fee_estimator, utxo_collector, chain),
                 );
             }
         })
@@ -118,18 +214,58 @@ impl InterfaceCommunicator {
     fn handle_messages(
         message: InterfaceMessages,
         sender_to_interface: Sender<InterfaceMessages>,
-        peers: Vec<String>,
+        tx_relay: Arc<Mutex<TxRelay>>,
         wallet_handler: Arc<Mutex<WalletHandler>>,
         blocks: Arc<Mutex<HashMap<String, BlockMessage>>>,
         transactions: Arc<Mutex<HashMap<String, TXMessage>>>,
+        shared: (
+            Arc<Mutex<FeeEstimator>>,
+            Arc<Mutex<UtxoCollector>>,
+            StoredChain,
+        ),
     ) {
+        let (fee_estimator, utxo_collector, chain) = shared;
         match message {
             InterfaceMessages::SendTransaction(send_transaction_node) => {
                 Self::receive_send_transaction_order(
                     send_transaction_node,
                     sender_to_interface,
                     Arc::clone(&wallet_handler),
-                    peers,
+                    tx_relay,
+                    transactions,
+                );
+            }
+            InterfaceMessages::SendBatch(batch_order) => {
+                Self::receive_send_batch_order(
+                    batch_order,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    tx_relay,
+                    transactions,
+                );
+            }
+            InterfaceMessages::BumpFee(order) => {
+                Self::receive_bump_fee_order(
+                    order,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    tx_relay,
+                );
+            }
+            InterfaceMessages::ChildPaysForParent(order) => {
+                Self::receive_child_order(
+                    order,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    tx_relay,
+                );
+            }
+            InterfaceMessages::AbandonTransaction(txid) => {
+                Self::receive_abandon_order(
+                    txid,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    tx_relay,
                 );
             }
             InterfaceMessages::AddWalletOrder(add_wallet_node) => {
@@ -139,6 +275,37 @@ impl InterfaceCommunicator {
                     Arc::clone(&wallet_handler),
                 );
             }
+            InterfaceMessages::CreateMnemonicWallet(order) => {
+                Self::receive_create_mnemonic_order(
+                    order,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                );
+            }
+            InterfaceMessages::RestoreWallet(order) => {
+                Self::receive_restore_order(
+                    order,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    utxo_collector,
+                    chain,
+                );
+            }
+            InterfaceMessages::CreateMultisigWallet(order) => {
+                Self::receive_create_multisig_order(
+                    order,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    utxo_collector,
+                );
+            }
+            InterfaceMessages::NewAddress(address_type) => {
+                Self::receive_new_address_order(
+                    address_type,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                );
+            }
             InterfaceMessages::WalletSwitch(switch_node) => {
                 Self::receive_switch_request(
                     switch_node,
@@ -156,57 +323,456 @@ impl InterfaceCommunicator {
                 );
             }
             InterfaceMessages::Close(_) => {
-                Self::receive_save_order(Arc::clone(&wallet_handler));
+                Self::receive_save_order(Arc::clone(&wallet_handler), fee_estimator);
+            }
+            InterfaceMessages::UnlockWallets((passphrase, timeout, order)) => {
+                Self::receive_unlock_order(
+                    (passphrase, timeout),
+                    order,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    (tx_relay, transactions, utxo_collector),
+                );
+            }
+            InterfaceMessages::LockWallets(_) => {
+                if let Ok(mut wallets) = wallet_handler.lock() {
+                    wallets.lock();
+                }
+                let message = InterfaceMessages::EncryptionResult("Wallets locked".to_string());
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::ExportDescriptors(_) => {
+                let descriptors = match wallet_handler.lock() {
+                    Ok(wallets) => wallets.export_descriptors(),
+                    Err(_) => vec![],
+                };
+                let message = InterfaceMessages::WalletMessage(descriptors.join("\n"));
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::ExportWif(_) => {
+                let text = match wallet_handler.lock() {
+                    Ok(wallets) => match wallets.export_wif(Network::Testnet) {
+                        Ok(keys) => keys.join("\n"),
+                        Err(e) => e.to_string(),
+                    },
+                    Err(_) => return,
+                };
+                let message = InterfaceMessages::WalletMessage(text);
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::Sweep(order) => {
+                Self::receive_sweep_order(
+                    order,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    chain.1,
+                    tx_relay,
+                );
+            }
+            InterfaceMessages::SignMessage((address, message)) => {
+                let text = match wallet_handler.lock() {
+                    Ok(wallets) => match wallets.sign_message(&address, &message) {
+                        Ok(signature) => signature,
+                        Err(e) => e.to_string(),
+                    },
+                    Err(_) => return,
+                };
+                let message = InterfaceMessages::WalletMessage(text);
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::VerifyMessage((address, message, signature)) => {
+                let text = match verify_message(address.trim(), &message, signature.trim()) {
+                    Ok(true) => "The signature is valid".to_string(),
+                    Ok(false) => {
+                        "The signature is not valid for this address and message".to_string()
+                    }
+                    Err(e) => e.to_string(),
+                };
+                let message = InterfaceMessages::WalletMessage(text);
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::CreatePsbt(order) => {
+                let message = match (wallet_handler.lock(), transactions.lock()) {
+                    (Ok(mut wallets), Ok(transactions)) => {
+                        Self::psbt_message(wallets.create_psbt(order, &transactions))
+                    }
+                    _ => return,
+                };
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::SignPsbt(psbt) => {
+                let message = match wallet_handler.lock() {
+                    Ok(wallets) if wallets.is_locked() => InterfaceMessages::WalletMessage(
+                        "Unlock the wallets to sign the PSBT".to_string(),
+                    ),
+                    Ok(wallets) => Self::psbt_message(wallets.sign_psbt(&psbt)),
+                    Err(_) => return,
+                };
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::CombinePsbts(psbts) => {
+                let message = Self::psbt_message(WalletHandler::combine_psbts(&psbts));
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::FinalizePsbt(psbt) => {
+                Self::receive_finalize_psbt_order(
+                    psbt,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    tx_relay,
+                );
+            }
+            InterfaceMessages::ListUtxos(_) => {
+                let message = match wallet_handler.lock() {
+                    Ok(wallets) => InterfaceMessages::Utxos(wallets.get_utxo_rows()),
+                    Err(_) => return,
+                };
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::SetCoinControl((selected, frozen)) => {
+                let text = match wallet_handler.lock() {
+                    Ok(mut wallets) => match wallets.set_coin_control(&selected, &frozen) {
+                        Ok(()) => format!(
+                            "{} utxos selected and {} frozen",
+                            selected.len(),
+                            frozen.len()
+                        ),
+                        Err(e) => e.to_string(),
+                    },
+                    Err(_) => return,
+                };
+                let message = InterfaceMessages::WalletMessage(text);
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::ListHistory(_) => {
+                let message = match wallet_handler.lock() {
+                    Ok(wallets) => InterfaceMessages::History(wallets.get_history_rows()),
+                    Err(_) => return,
+                };
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::CreatePaymentRequest(order) => {
+                let message = match wallet_handler.lock() {
+                    Ok(mut wallets) => match wallets.new_payment_request(order) {
+                        Ok(_) => {
+                            InterfaceMessages::PaymentRequests(wallets.get_payment_request_rows())
+                        }
+                        Err(e) => InterfaceMessages::WalletMessage(e.to_string()),
+                    },
+                    Err(_) => return,
+                };
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::ListPaymentRequests(_) => {
+                let message = match wallet_handler.lock() {
+                    Ok(wallets) => {
+                        InterfaceMessages::PaymentRequests(wallets.get_payment_request_rows())
+                    }
+                    Err(_) => return,
+                };
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::SetLabel((txid, label)) => {
+                // The history window already shows the new label
+                let text = match wallet_handler.lock() {
+                    Ok(mut wallets) => match wallets.set_label(&txid, &label) {
+                        Ok(()) => return,
+                        Err(e) => e.to_string(),
+                    },
+                    Err(_) => return,
+                };
+                let message = InterfaceMessages::WalletMessage(text);
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::ExportHistory(path) => {
+                let text = match wallet_handler.lock() {
+                    Ok(wallets) => match wallets.history_csv() {
+                        Ok(csv) => match fs::write(&path, csv) {
+                            Ok(()) => format!("History exported to {}", path),
+                            Err(e) => format!("Error exporting the history: {}", e),
+                        },
+                        Err(e) => e.to_string(),
+                    },
+                    Err(_) => return,
+                };
+                let message = InterfaceMessages::WalletMessage(text);
+                if sender_to_interface.send(message).is_ok() {}
+            }
+            InterfaceMessages::Rescan(start) => {
+                Self::receive_rescan_order(
+                    start,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                    utxo_collector,
+                    chain,
+                );
+            }
+            InterfaceMessages::EncryptWallets(passphrase) => {
+                Self::receive_encrypt_order(
+                    passphrase,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                );
+            }
+            InterfaceMessages::ChangePassphrase(passphrases) => {
+                Self::receive_change_passphrase_order(
+                    passphrases,
+                    sender_to_interface,
+                    Arc::clone(&wallet_handler),
+                );
             }
             _ => {}
         }
     }
 
-    pub fn update_balance(&mut self, utxo: &UtxoCollector) {
+    /// Returns the time blocks are downloaded from, the birthday of the oldest wallet
+    pub fn download_start(&self) -> u32 {
+        match self.wallet_handler.lock() {
+            Ok(wallets) => wallets.download_start(),
+            Err(_) => DEFAULT_BIRTHDAY,
+        }
+    }
+
+    /// Updates the balances with the utxos collected by the node
+    pub fn update_balance(&mut self) {
+        if let Ok(utxo) = self.utxo_collector.lock() {
+            if let Ok(mut wallets) = self.wallet_handler.lock() {
+                wallets.add_utxo_to_wallets(&utxo);
+            }
+        }
+    }
+
+    /// Adds the transactions of a block to the history of the wallets, `tip` is the height
+    /// of the best header. Must be called before the utxos of the block are collected
+    pub fn record_block(&self, block: &BlockMessage, position: BlockPosition, tip: u32) {
         if let Ok(mut wallets) = self.wallet_handler.lock() {
-            wallets.add_utxo_to_wallets(utxo);
+            wallets.set_tip(tip);
+            wallets.record_block(&block.get_tx(), position);
         }
     }
 
+    /// Adds an unconfirmed transaction to the history of the wallets it involves
+    pub fn record_transaction(&self, tx: &TXMessage) {
+        if let Ok(mut wallets) = self.wallet_handler.lock() {
+            wallets.record_transaction(tx);
+        }
+    }
+
+    /// Refreshes the suggested fees shown to the user with the latest estimations
+    pub fn update_fee_estimates(&mut self, sender_to_interface: &Sender<InterfaceMessages>) {
+        let suggested = match self.fee_estimator.lock() {
+            Ok(estimator) => estimator.suggested_fee_rates(),
+            Err(_) => return,
+        };
+        if let Ok(mut wallets) = self.wallet_handler.lock() {
+            wallets.set_suggested_fee_rates(suggested);
+            if wallets.is_empty() {
+                return;
+            }
+            let message = InterfaceMessages::ActualWallet(wallets.actual_wallet_get_data());
+            if sender_to_interface.send(message).is_ok() {}
+        }
+    }
+
+    /// Forgets the decrypted keys once the unlock timeout expires
+    fn lock_on_timeout(
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        sender_to_interface: Sender<InterfaceMessages>,
+    ) {
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            if let Ok(mut wallets) = wallet_handler.lock() {
+                if wallets.lock_if_expired() {
+                    let message = InterfaceMessages::EncryptionResult("Wallets locked".to_string());
+                    if sender_to_interface.send(message).is_ok() {}
+                }
+            }
+        });
+    }
+
     /// Save the information on disk
     fn save(wallet_handler: Arc<Mutex<WalletHandler>>) {
+        if let Ok(wallets) = wallet_handler.lock() {
+            Self::save_files(&wallets);
+        }
+    }
+
+    /// Writes the wallets file and the files kept apart from it. Sealed files that weren't
+    /// opened are left as they are, the wallets in them aren't loaded
+    fn save_files(wallets: &WalletHandler) {
+        if wallets.is_sealed() {
+            return;
+        }
+        Self::save_wallets(wallets);
+        Self::save_history(wallets);
+        Self::save_birthdays(wallets);
+        Self::save_payment_requests(wallets);
+        Self::save_pending(wallets);
+    }
+
+    /// Reads the files kept apart from the wallets file, if any
+    fn open_files(wallets: &mut WalletHandler) {
+        Self::open_history(wallets);
+        Self::open_birthdays(wallets);
+        Self::open_payment_requests(wallets);
+        Self::open_pending(wallets);
+    }
+
+    /// Writes the lines of a file of the wallets, sealed if they have a passphrase
+    fn write_lines(
+        path: &str,
+        wallets: &WalletHandler,
+        lines: Vec<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut text = String::new();
+        for line in wallets.seal(lines)? {
+            text.push_str(&format!("{}\n", line));
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Reads the lines of a file of the wallets, none if it doesn't exist or can't be unsealed
+    fn read_lines(path: &str, wallets: &WalletHandler) -> Vec<String> {
+        let lines: Vec<String> = match File::open(path) {
+            Ok(file) => BufReader::new(file).lines().map_while(Result::ok).collect(),
+            Err(_) => return vec![],
+        };
+        match wallets.unseal(&lines) {
+            Ok(lines) => lines,
+            Err(error) => {
+                println!("Error reading {}. {}", path, error);
+                vec![]
+            }
+        }
+    }
+
+    /// Writes the history of every wallet
+    fn save_history(wallets: &WalletHandler) {
+        if Self::write_lines(HISTORY_PATH, wallets, wallets.get_history_lines()).is_err() {
+            println!("Error saving the wallets history");
+        }
+    }
+
+    /// Reads the history of the wallets saved on disk, if any
+    fn open_history(wallets: &mut WalletHandler) {
+        let lines = Self::read_lines(HISTORY_PATH, wallets);
+        wallets.open_history(&lines);
+    }
+
+    /// Writes the birthday of every wallet, kept apart from the wallets file so they are
+    /// known to download the blocks while the keys are locked. Sealed birthdays are only known
+    /// once the passphrase is given, until then blocks are downloaded from the default birthday
+    fn save_birthdays(wallets: &WalletHandler) {
+        if Self::write_lines(BIRTHDAYS_PATH, wallets, wallets.get_birthday_lines()).is_err() {
+            println!("Error saving the wallets birthdays");
+        }
+    }
+
+    /// Reads the birthdays of the wallets saved on disk, if any
+    fn open_birthdays(wallets: &mut WalletHandler) {
+        let lines = Self::read_lines(BIRTHDAYS_PATH, wallets);
+        wallets.open_birthdays(&lines);
+    }
+
+    /// Writes the payment requests of every wallet
+    fn save_payment_requests(wallets: &WalletHandler) {
+        if Self::write_lines(PAYMENT_REQUESTS_PATH, wallets, wallets.get_request_lines()).is_err() {
+            println!("Error saving the payment requests");
+        }
+    }
+
+    /// Writes the transactions every wallet sent that aren't confirmed yet, so their utxos
+    /// aren't spent again after a restart
+    fn save_pending(wallets: &WalletHandler) {
+        if Self::write_lines(PENDING_PATH, wallets, wallets.get_pending_lines()).is_err() {
+            println!("Error saving the pending transactions");
+        }
+    }
+
+    /// Reads the pending transactions saved on disk, if any
+    fn open_pending(wallets: &mut WalletHandler) {
+        let lines = Self::read_lines(PENDING_PATH, wallets);
+        wallets.open_pending(&lines);
+    }
+
+    /// Reads the payment requests saved on disk, if any
+    fn open_payment_requests(wallets: &mut WalletHandler) {
+        let lines = Self::read_lines(PAYMENT_REQUESTS_PATH, wallets);
+        wallets.open_payment_requests(&lines);
+    }
+
+    /// Writes the wallets file, the keys are encrypted and the wallets sealed if they have a passphrase
+    /// The file is left as it was if any wallet can't be written
+    fn save_wallets(wallets: &WalletHandler) {
+        let lines = match wallets.get_file_lines() {
+            Ok(lines) => lines,
+            Err(error) => {
+                println!("Wallets not saved. {}", error);
+                return;
+            }
+        };
         if let Ok(mut file) = OpenOptions::new()
             .write(true)
             .truncate(true)
-            .open("./storage/wallets.txt")
+            .open(WALLETS_PATH)
         {
-            if let Ok(wallets) = wallet_handler.lock() {
-                for wallet in wallets.wallets.iter() {
-                    if file
-                        .write_all(format!("{}\n", wallet.get_all_data()).as_bytes())
-                        .is_ok()
-                    {
-                        println!("Error escribiendo en el archivo");
-                    }
+            for line in lines {
+                if file.write_all(format!("{}\n", line).as_bytes()).is_err() {
+                    println!("Error escribiendo en el archivo");
                 }
             }
             if file.flush().is_ok() {}
         }
     }
 
+    /// Reads the wallets of a sealed wallets file once the passphrase was given,
+    /// with the files kept apart from it
+    /// # Errors
+    /// Returns an error if the file can't be read or unsealed
+    fn open_sealed_files(wallets: &mut WalletHandler) -> Result<(), Box<dyn Error>> {
+        let file = File::open(WALLETS_PATH)?;
+        let lines: Vec<String> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .skip(1)
+            .collect();
+        wallets.open_sealed_wallets(&lines)?;
+        Self::open_files(wallets);
+        Ok(())
+    }
+
     /// Returns an option containing a Wallet Handler if there is data in the wallets file and none if it was empty
     fn open() -> Option<WalletHandler> {
         let mut wallets = Vec::new();
-        if let Ok(file) = File::open("./storage/wallets.txt") {
+        if let Ok(file) = File::open(WALLETS_PATH) {
             if let Ok(metdata) = file.metadata() {
                 if metdata.len() == 0 {
                     return None;
                 }
             }
-            let reader = BufReader::new(file);
-            for line_result in reader.lines().flatten() {
-                let parts: Vec<&str> = line_result.split(',').collect();
-                if let Ok(id) = parts[0].parse() {
-                    if let Ok(private) = parts[2].parse() {
-                        if let Ok(balance) = parts[3].parse() {
-                            wallets.push(Wallet::open(id, parts[1].to_string(), private, balance));
-                        }
+            let mut lines = BufReader::new(file)
+                .lines()
+                .map_while(Result::ok)
+                .peekable();
+            if let Some(header) = lines.peek() {
+                if header.starts_with(ENCRYPTED_HEADER) {
+                    let encryption = WalletEncryption::from_header(header)?;
+                    lines.next();
+                    let lines: Vec<String> = lines.collect();
+                    if is_sealed(&lines) {
+                        return Some(WalletHandler::open_sealed(encryption));
                     }
+                    let wallets = lines
+                        .iter()
+                        .filter_map(|line| Wallet::open_encrypted(line))
+                        .collect();
+                    return Some(WalletHandler::open_encrypted(wallets, encryption));
+                }
+            }
+            for line_result in lines {
+                if let Some(wallet) = Wallet::open_line(&line_result) {
+                    wallets.push(wallet);
                 }
             }
         }
@@ -215,17 +781,29 @@ impl InterfaceCommunicator {
 
     /// Handle messages with interface --------------------------------
 
-    /// Sends the command to open the interface with pre-existing data.
+    /// Sends the command to open the interface with pre-existing data,
+    /// or asks for the passphrase first if the wallets file is sealed
     fn open_interface(&self, open_signal: Sender<InterfaceMessages>) {
         if let Ok(wallets) = self.wallet_handler.lock() {
-            let message = InterfaceMessages::Open(wallets.get_all_data());
+            let message = match wallets.is_sealed() {
+                true => InterfaceMessages::PassphraseRequest(()),
+                false => InterfaceMessages::Open(wallets.get_all_data()),
+            };
             if open_signal.send(message).is_ok() {}
         }
     }
 
-    /// Receives the command to save the wallets in file.
-    fn receive_save_order(wallet_handler: Arc<Mutex<WalletHandler>>) {
+    /// Receives the command to save the wallets and the fee estimations in file.
+    fn receive_save_order(
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        fee_estimator: Arc<Mutex<FeeEstimator>>,
+    ) {
         Self::save(wallet_handler);
+        if let Ok(estimator) = fee_estimator.lock() {
+            if estimator.save(FEE_ESTIMATES_PATH).is_err() {
+                println!("Error saving fee estimates");
+            }
+        }
         process::exit(1);
     }
 
@@ -234,25 +812,413 @@ impl InterfaceCommunicator {
         transaction_order: (String, String, i32, i32),
         sender_to_interface: Sender<InterfaceMessages>,
         wallet_handler: Arc<Mutex<WalletHandler>>,
-        peers: Vec<String>,
+        tx_relay: Arc<Mutex<TxRelay>>,
+        transactions: KnownTransactions,
     ) {
-        // Order of the tuple: (address, label, amount, fee)
+        // Order of the tuple: (address, label, amount, fee rate)
         println!("recibe el pedido de transaccion");
         if let Ok(mut wallets) = wallet_handler.lock() {
             println!("lee las wallets de largo {:?}", wallets.wallets.len());
+            // Watch only wallets can't sign, the transaction is exported as a PSBT to sign elsewhere
+            if wallets.is_watch_only() {
+                let message = match transactions.lock() {
+                    Ok(transactions) => {
+                        Self::psbt_message(wallets.create_psbt(transaction_order, &transactions))
+                    }
+                    Err(_) => return,
+                };
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
+            // The interface asks for the passphrase and sends the order again
+            if wallets.is_locked() {
+                let message = InterfaceMessages::UnlockRequest(transaction_order);
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
 
-            if let Ok(transaction) = wallets.create_transaction(transaction_order) {
-                println!("Transaction created: {:?}", transaction);
-                Self::connect_streams(
-                    peers,
-                    transaction,
-                    sender_to_interface,
-                    wallets.actual_wallet_get_data(),
+            match wallets.create_transaction(transaction_order) {
+                Ok((transaction, fee)) => {
+                    println!("Transaction created: {:?}", transaction);
+                    Self::relay_transaction(
+                        tx_relay,
+                        transaction,
+                        fee,
+                        sender_to_interface,
+                        wallets.actual_wallet_get_data(),
+                    );
+                }
+                Err(e) => {
+                    let message = InterfaceMessages::WalletMessage(e.to_string());
+                    if sender_to_interface.send(message).is_ok() {}
+                }
+            }
+        }
+    }
+
+    /// Receives a foreign private key and a fee rate, sends every utxo of the key found in the
+    /// stored blocks to the actual wallet. The sweep is added to its history once relayed
+    fn receive_sweep_order(
+        (private_key, fee_rate): (String, i32),
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        node_blocks: Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+        tx_relay: Arc<Mutex<TxRelay>>,
+    ) {
+        let mut wallets = match wallet_handler.lock() {
+            Ok(wallets) => wallets,
+            Err(_) => return,
+        };
+        let swept = match node_blocks.lock() {
+            Ok(blocks) => wallets.sweep(&private_key, &blocks, fee_rate),
+            Err(_) => return,
+        };
+        let (transaction, fee) = match swept {
+            Ok(swept) => swept,
+            Err(e) => {
+                let message = InterfaceMessages::WalletMessage(e.to_string());
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
+        };
+        let relayed = Self::relay_transaction(
+            tx_relay,
+            transaction.clone(),
+            fee,
+            sender_to_interface,
+            wallets.actual_wallet_get_data(),
+        );
+        if relayed {
+            _ = wallets.record_sweep(&transaction);
+        }
+    }
+
+    /// Receives the payments of a batch and its fee rate, pays them all in one transaction
+    fn receive_send_batch_order(
+        (payments, fee_rate): (Vec<Payment>, i32),
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        tx_relay: Arc<Mutex<TxRelay>>,
+        transactions: KnownTransactions,
+    ) {
+        if let Ok(mut wallets) = wallet_handler.lock() {
+            if wallets.is_watch_only() {
+                let message = match transactions.lock() {
+                    Ok(transactions) => Self::psbt_message(wallets.create_batch_psbt(
+                        &payments,
+                        fee_rate,
+                        &transactions,
+                    )),
+                    Err(_) => return,
+                };
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
+            if wallets.is_locked() {
+                let message = InterfaceMessages::WalletMessage(
+                    "Unlock the wallets to send the batch".to_string(),
+                );
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
+
+            match wallets.create_batch_transaction(&payments, fee_rate) {
+                Ok((transaction, fee)) => {
+                    Self::relay_transaction(
+                        tx_relay,
+                        transaction,
+                        fee,
+                        sender_to_interface,
+                        wallets.actual_wallet_get_data(),
+                    );
+                }
+                Err(e) => {
+                    let message = InterfaceMessages::WalletMessage(e.to_string());
+                    if sender_to_interface.send(message).is_ok() {}
+                }
+            }
+        }
+    }
+
+    /// Receives the id of an unconfirmed own transaction and a fee rate, relays a replacement
+    /// paying that fee rate in its place (RBF)
+    fn receive_bump_fee_order(
+        (txid, fee_rate): (String, i32),
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        tx_relay: Arc<Mutex<TxRelay>>,
+    ) {
+        if let Ok(mut wallets) = wallet_handler.lock() {
+            if wallets.is_locked() {
+                let message = InterfaceMessages::WalletMessage(
+                    "Unlock the wallets to bump the fee".to_string(),
+                );
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
+            let (transaction, fee) = match wallets.bump_fee(&txid, fee_rate) {
+                Ok(replacement) => replacement,
+                Err(e) => {
+                    let message = InterfaceMessages::WalletMessage(e.to_string());
+                    if sender_to_interface.send(message).is_ok() {}
+                    return;
+                }
+            };
+            if let (Ok(mut relay), Ok(replaced)) = (tx_relay.lock(), txid_from_hex(&txid)) {
+                let fee_rate = fee.max(0) as u64 * 1000 / transaction.vsize().max(1) as u64;
+                relay.replace_own_transaction(replaced, &transaction, fee_rate);
+                relay.flush();
+            }
+            let message = InterfaceMessages::MyTransactions(transaction);
+            if sender_to_interface.send(message).is_ok() {}
+            let message = InterfaceMessages::ActualWallet(wallets.actual_wallet_get_data());
+            if sender_to_interface.send(message).is_ok() {}
+        }
+    }
+
+    /// Receives the id of an unconfirmed own transaction, like one peers evicted, and stops
+    /// relaying it and its children so the wallet can spend their utxos again
+    fn receive_abandon_order(
+        txid: String,
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        tx_relay: Arc<Mutex<TxRelay>>,
+    ) {
+        if let Ok(mut wallets) = wallet_handler.lock() {
+            let abandoned = match wallets.abandon_transaction(&txid) {
+                Ok(abandoned) => abandoned,
+                Err(e) => {
+                    let message = InterfaceMessages::WalletMessage(e.to_string());
+                    if sender_to_interface.send(message).is_ok() {}
+                    return;
+                }
+            };
+            if let Ok(mut relay) = tx_relay.lock() {
+                relay.abandon_own_transactions(&abandoned);
+            }
+            let message = InterfaceMessages::WalletMessage(format!(
+                "Abandoned {} unconfirmed transactions",
+                abandoned.len()
+            ));
+            if sender_to_interface.send(message).is_ok() {}
+            let message = InterfaceMessages::ActualWallet(wallets.actual_wallet_get_data());
+            if sender_to_interface.send(message).is_ok() {}
+        }
+    }
+
+    /// Receives the id of an unconfirmed own transaction and a fee rate, relays a child
+    /// spending its change so both together pay that fee rate (CPFP)
+    fn receive_child_order(
+        (txid, fee_rate): (String, i32),
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        tx_relay: Arc<Mutex<TxRelay>>,
+    ) {
+        if let Ok(mut wallets) = wallet_handler.lock() {
+            if wallets.is_locked() {
+                let message = InterfaceMessages::WalletMessage(
+                    "Unlock the wallets to bump the fee".to_string(),
                 );
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
+            let (parent, (child, fee)) = match wallets.create_child(&txid, fee_rate) {
+                Ok(package) => package,
+                Err(e) => {
+                    let message = InterfaceMessages::WalletMessage(e.to_string());
+                    if sender_to_interface.send(message).is_ok() {}
+                    return;
+                }
+            };
+            if let Ok(mut relay) = tx_relay.lock() {
+                let fees = (parent.fee().max(0) as u64, fee.max(0) as u64);
+                relay.add_own_child(parent.get_transaction(), &child, fees);
+                relay.flush();
+            }
+            let message = InterfaceMessages::MyTransactions(child);
+            if sender_to_interface.send(message).is_ok() {}
+            let message = InterfaceMessages::ActualWallet(wallets.actual_wallet_get_data());
+            if sender_to_interface.send(message).is_ok() {}
+        }
+    }
+
+    /// Shows the resulting PSBT or why it couldn't be made
+    fn psbt_message(result: Result<String, Box<dyn Error>>) -> InterfaceMessages {
+        match result {
+            Ok(psbt) => InterfaceMessages::PsbtResult(psbt),
+            Err(e) => InterfaceMessages::WalletMessage(e.to_string()),
+        }
+    }
+
+    /// Receives a fully signed PSBT, finalizes it and relays its transaction
+    fn receive_finalize_psbt_order(
+        psbt: String,
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        tx_relay: Arc<Mutex<TxRelay>>,
+    ) {
+        let (transaction, fee) = match WalletHandler::finalize_psbt(&psbt) {
+            Ok(finalized) => finalized,
+            Err(e) => {
+                let message = InterfaceMessages::WalletMessage(e.to_string());
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
+        };
+        if let Ok(wallets) = wallet_handler.lock() {
+            Self::relay_transaction(
+                tx_relay,
+                transaction,
+                fee,
+                sender_to_interface,
+                wallets.actual_wallet_get_data(),
+            );
+        }
+    }
+
+    /// Receives the passphrase to unlock the wallets for `timeout` seconds
+    /// and, if there's one, the transaction that was waiting for it.
+    /// A sealed wallets file is opened with it, the passphrase is asked again if it's wrong
+    fn receive_unlock_order(
+        unlock_order: (String, u64),
+        transaction_order: Option<TransactionOrder>,
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        (tx_relay, transactions, utxo_collector): (
+            Arc<Mutex<TxRelay>>,
+            KnownTransactions,
+            Arc<Mutex<UtxoCollector>>,
+        ),
+    ) {
+        let (passphrase, timeout) = unlock_order;
+        let timeout = if timeout == 0 {
+            UNLOCK_TIMEOUT_SECS
+        } else {
+            timeout
+        };
+        let (unlocked, sealed) = match wallet_handler.lock() {
+            Ok(mut wallets) => {
+                let sealed = wallets.is_sealed();
+                let mut unlocked = wallets.unlock(&passphrase, Duration::from_secs(timeout));
+                if sealed && unlocked.is_ok() {
+                    unlocked = Self::open_sealed_files(&mut wallets);
+                    if unlocked.is_err() {
+                        wallets.lock();
+                    }
+                }
+                (unlocked, sealed)
             }
+            Err(_) => return,
+        };
+        let succeeded = unlocked.is_ok();
+        let result = match unlocked {
+            Ok(_) => format!("Wallets unlocked for {} seconds", timeout),
+            Err(e) => e.to_string(),
+        };
+        let message = InterfaceMessages::EncryptionResult(result);
+        if sender_to_interface.send(message).is_ok() {}
+        if sealed {
+            Self::open_unsealed(
+                succeeded,
+                &sender_to_interface,
+                &wallet_handler,
+                utxo_collector,
+            );
+        }
+        if let (true, Some(order)) = (succeeded, transaction_order) {
+            Self::receive_send_transaction_order(
+                order,
+                sender_to_interface,
+                wallet_handler,
+                tx_relay,
+                transactions,
+            );
         }
     }
 
+    /// Shows the wallets read from the sealed file with their utxos, or asks for the passphrase
+    /// again if they couldn't be read
+    fn open_unsealed(
+        opened: bool,
+        sender_to_interface: &Sender<InterfaceMessages>,
+        wallet_handler: &Arc<Mutex<WalletHandler>>,
+        utxo_collector: Arc<Mutex<UtxoCollector>>,
+    ) {
+        if !opened {
+            let message = InterfaceMessages::PassphraseRequest(());
+            _ = sender_to_interface.send(message);
+            return;
+        }
+        if let Ok(utxo_collector) = utxo_collector.lock() {
+            if let Ok(mut wallets) = wallet_handler.lock() {
+                if wallets.is_empty() {
+                    return;
+                }
+                wallets.add_utxo_to_wallets(&utxo_collector);
+                let message = InterfaceMessages::Open(wallets.get_all_data());
+                _ = sender_to_interface.send(message);
+            }
+        }
+    }
+
+    /// Receives the passphrase to encrypt the wallets, the files are rewritten right away
+    /// so the plain text keys and wallets data don't stay on disk
+    fn receive_encrypt_order(
+        passphrase: String,
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+    ) {
+        let result = match wallet_handler.lock() {
+            Ok(mut wallets) => {
+                let encrypted = WalletEncryption::new(
+                    &passphrase,
+                    SCRYPT_LOG_N,
+                    Duration::from_secs(UNLOCK_TIMEOUT_SECS),
+                )
+                .and_then(|encryption| wallets.encrypt_wallets(encryption));
+                match encrypted {
+                    Ok(_) => {
+                        Self::save_files(&wallets);
+                        "Wallets encrypted".to_string()
+                    }
+                    Err(e) => e.to_string(),
+                }
+            }
+            Err(_) => return,
+        };
+        let message = InterfaceMessages::EncryptionResult(result);
+        if sender_to_interface.send(message).is_ok() {}
+    }
+
+    /// Receives the old and new passphrases and re-encrypts the wallets
+    fn receive_change_passphrase_order(
+        passphrases: (String, String),
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+    ) {
+        let (old_passphrase, new_passphrase) = passphrases;
+        let result = match wallet_handler.lock() {
+            Ok(mut wallets) => {
+                let changed = WalletEncryption::new(
+                    &new_passphrase,
+                    SCRYPT_LOG_N,
+                    Duration::from_secs(UNLOCK_TIMEOUT_SECS),
+                )
+                .and_then(|encryption| wallets.change_passphrase(&old_passphrase, encryption));
+                match changed {
+                    Ok(_) => {
+                        Self::save_files(&wallets);
+                        "Passphrase changed".to_string()
+                    }
+                    Err(e) => e.to_string(),
+                }
+            }
+            Err(_) => return,
+        };
+        let message = InterfaceMessages::EncryptionResult(result);
+        if sender_to_interface.send(message).is_ok() {}
+    }
+
     /// Receives the command to add a wallet.
     fn receive_add_wallet_order(
         add_wallet_order: (String, String),
@@ -260,6 +1226,13 @@ impl InterfaceCommunicator {
         wallet_handler: Arc<Mutex<WalletHandler>>,
     ) {
         if let Ok(mut wallets) = wallet_handler.lock() {
+            if wallets.is_locked() {
+                let message = InterfaceMessages::EncryptionResult(
+                    "Unlock the wallets to add a new one".to_string(),
+                );
+                if sender_to_interface.send(message).is_ok() {}
+                return;
+            }
             if wallets.new_wallet(add_wallet_order.clone()) {
                 let message = InterfaceMessages::WalletName(add_wallet_order.0);
                 if sender_to_interface.send(message).is_ok() {}
@@ -267,6 +1240,112 @@ impl InterfaceCommunicator {
         }
     }
 
+    /// Receives the command to create a HD wallet with a new mnemonic, which is shown to the user
+    fn receive_create_mnemonic_order(
+        order: (String, usize, String),
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+    ) {
+        let (name, word_count, passphrase) = order;
+        if let Ok(mut wallets) = wallet_handler.lock() {
+            let message = match wallets.new_mnemonic_wallet(name.clone(), word_count, &passphrase) {
+                Ok(mnemonic) => {
+                    let message = InterfaceMessages::WalletName(name);
+                    if sender_to_interface.send(message).is_ok() {}
+                    InterfaceMessages::WalletMessage(format!(
+                        "Write down your recovery words:\n{}",
+                        mnemonic.phrase()
+                    ))
+                }
+                Err(e) => InterfaceMessages::WalletMessage(e.to_string()),
+            };
+            if sender_to_interface.send(message).is_ok() {}
+        }
+    }
+
+    /// Receives the command to restore a HD wallet from its mnemonic, then rescans the stored blocks
+    /// for its transactions. Blocks older than the stored ones aren't downloaded
+    fn receive_restore_order(
+        order: (String, String, String),
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        utxo_collector: Arc<Mutex<UtxoCollector>>,
+        chain: StoredChain,
+    ) {
+        let (name, phrase, passphrase) = order;
+        let restored = match wallet_handler.lock() {
+            Ok(mut wallets) => wallets.restore_wallet(name.clone(), &phrase, &passphrase),
+            Err(_) => return,
+        };
+        if let Err(e) = restored {
+            let message = InterfaceMessages::WalletMessage(e.to_string());
+            if sender_to_interface.send(message).is_ok() {}
+            return;
+        }
+        let (headers, node_blocks) = chain;
+        let text = match Self::rescan_stored_blocks(&wallet_handler, &headers, &node_blocks) {
+            Ok(text) => text,
+            Err(e) => e.to_string(),
+        };
+        if let Ok(utxo_collector) = utxo_collector.lock() {
+            if let Ok(mut wallets) = wallet_handler.lock() {
+                wallets.add_utxo_to_wallets(&utxo_collector);
+                let message = InterfaceMessages::WalletName(name);
+                if sender_to_interface.send(message).is_ok() {}
+                let message = InterfaceMessages::ActualWallet(wallets.actual_wallet_get_data());
+                if sender_to_interface.send(message).is_ok() {}
+            }
+        }
+        Self::save(wallet_handler);
+        let message = InterfaceMessages::WalletMessage(text);
+        if sender_to_interface.send(message).is_ok() {}
+    }
+
+    /// Receives the command to create a multisig wallet of (name, threshold, keys, segwit),
+    /// then looks for its utxos among the collected ones
+    fn receive_create_multisig_order(
+        order: (String, usize, String, bool),
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        utxo_collector: Arc<Mutex<UtxoCollector>>,
+    ) {
+        let (name, threshold, keys, segwit) = order;
+        let utxo_collector = match utxo_collector.lock() {
+            Ok(utxo_collector) => utxo_collector,
+            Err(_) => return,
+        };
+        if let Ok(mut wallets) = wallet_handler.lock() {
+            match wallets.new_multisig_wallet(name.clone(), threshold, &keys, segwit) {
+                Ok(()) => {
+                    wallets.add_utxo_to_wallets(&utxo_collector);
+                    let message = InterfaceMessages::WalletName(name);
+                    if sender_to_interface.send(message).is_ok() {}
+                    let message = InterfaceMessages::ActualWallet(wallets.actual_wallet_get_data());
+                    if sender_to_interface.send(message).is_ok() {}
+                }
+                Err(e) => {
+                    let message = InterfaceMessages::WalletMessage(e.to_string());
+                    if sender_to_interface.send(message).is_ok() {}
+                }
+            }
+        }
+    }
+
+    /// Receives the command to show a fresh receive address of the given type of the actual wallet
+    fn receive_new_address_order(
+        address_type: AddressType,
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+    ) {
+        if let Ok(mut wallets) = wallet_handler.lock() {
+            let message = match wallets.new_receive_address(address_type) {
+                Ok(_) => InterfaceMessages::ActualWallet(wallets.actual_wallet_get_data()),
+                Err(e) => InterfaceMessages::WalletMessage(e.to_string()),
+            };
+            if sender_to_interface.send(message).is_ok() {}
+        }
+    }
+
     /// Receives the command to switch wallets.
     fn receive_switch_request(
         switch_wallet_order: String,
@@ -280,13 +1359,158 @@ impl InterfaceCommunicator {
         }
     }
 
+    /// Receives the command to rescan the actual wallet from a block height or a date:
+    /// rebuilds its history from the stored blocks, sets its birthday and refreshes its utxos.
+    /// Starts before the first stored block are rejected, as older blocks aren't downloaded
+    fn receive_rescan_order(
+        start: String,
+        sender_to_interface: Sender<InterfaceMessages>,
+        wallet_handler: Arc<Mutex<WalletHandler>>,
+        utxo_collector: Arc<Mutex<UtxoCollector>>,
+        chain: StoredChain,
+    ) {
+        let (headers, node_blocks) = chain;
+        let text = match Self::rescan(&start, &wallet_handler, &headers, &node_blocks) {
+            Ok(text) => text,
+            Err(e) => e.to_string(),
+        };
+        if let Ok(utxo_collector) = utxo_collector.lock() {
+            if let Ok(mut wallets) = wallet_handler.lock() {
+                wallets.add_utxo_to_wallets(&utxo_collector);
+                let message = InterfaceMessages::ActualWallet(wallets.actual_wallet_get_data());
+                if sender_to_interface.send(message).is_ok() {}
+            }
+        }
+        Self::save(wallet_handler);
+        let message = InterfaceMessages::WalletMessage(text);
+        if sender_to_interface.send(message).is_ok() {}
+    }
+
+    /// Rescans the stored blocks from the start given by the user, returns the result to show
+    fn rescan(
+        start: &str,
+        wallet_handler: &Arc<Mutex<WalletHandler>>,
+        headers: &Arc<Mutex<Vec<Vec<u8>>>>,
+        node_blocks: &Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+    ) -> Result<String, Box<dyn Error>> {
+        let start = RescanStart::parse(start)?;
+        // The headers are copied so the node can keep adding new ones during the rescan
+        let headers = match headers.lock() {
+            Ok(headers) => headers.clone(),
+            Err(_) => return Err("Error reading the headers".into()),
+        };
+        let (first, birthday) = match start.first_header(&headers) {
+            Some(first) => first,
+            None => return Err("The chain doesn't reach the start of the rescan yet".into()),
+        };
+        match Self::first_stored(&headers, node_blocks)? {
+            Some(stored) if first < stored => {
+                return Err(format!(
+                    "Blocks before height {} aren't stored, the rescan starts from it or later",
+                    stored + 1
+                )
+                .into())
+            }
+            Some(_) => {}
+            None => return Err("There are no stored blocks to rescan yet".into()),
+        }
+        if let Ok(mut wallets) = wallet_handler.lock() {
+            wallets.set_birthday(birthday)?;
+        }
+        Self::rescan_blocks(first, wallet_handler, &headers, node_blocks)
+    }
+
+    /// Rescans the stored blocks for the transactions of the actual wallet, from the first one stored
+    fn rescan_stored_blocks(
+        wallet_handler: &Arc<Mutex<WalletHandler>>,
+        headers: &Arc<Mutex<Vec<Vec<u8>>>>,
+        node_blocks: &Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+    ) -> Result<String, Box<dyn Error>> {
+        let headers = match headers.lock() {
+            Ok(headers) => headers.clone(),
+            Err(_) => return Err("Error reading the headers".into()),
+        };
+        let first = match Self::first_stored(&headers, node_blocks)? {
+            Some(first) => first,
+            None => return Ok("There are no stored blocks to rescan yet".to_string()),
+        };
+        let mut text = Self::rescan_blocks(first, wallet_handler, &headers, node_blocks)?;
+        if first > 0 {
+            text.push_str(&format!(
+                "\nPayments before height {} aren't found, older blocks aren't stored",
+                first + 1
+            ));
+        }
+        Ok(text)
+    }
+
+    /// Returns the index of the header of the first stored block, None if there are none
+    fn first_stored(
+        headers: &[Vec<u8>],
+        node_blocks: &Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+    ) -> Result<Option<usize>, Box<dyn Error>> {
+        match node_blocks.lock() {
+            Ok(blocks) => Ok(headers.iter().position(|serialized| {
+                BlockHeader::deserialize(&mut serialized.clone())
+                    .ok()
+                    .and_then(|header| header_calculate_doublehash_array_be(&header))
+                    .is_some_and(|hash| blocks.contains_key(&hash))
+            })),
+            Err(_) => Err("Error reading the blocks".into()),
+        }
+    }
+
+    /// Rescans the blocks of the headers from index `first` on, returns the result to show
+    fn rescan_blocks(
+        first: usize,
+        wallet_handler: &Arc<Mutex<WalletHandler>>,
+        headers: &[Vec<u8>],
+        node_blocks: &Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut received = vec![];
+        let (mut found, mut missing) = (0, 0);
+        for (index, serialized) in headers.iter().enumerate().skip(first) {
+            let header = BlockHeader::deserialize(&mut serialized.clone())?;
+            let hash = header_calculate_doublehash_array_be(&header).unwrap_or([0; 32]);
+            let stored = match node_blocks.lock() {
+                Ok(blocks) => blocks.get(&hash).cloned(),
+                Err(_) => return Err("Error reading the blocks".into()),
+            };
+            let mut stored = match stored {
+                Some(stored) => stored,
+                None => {
+                    missing += 1;
+                    continue;
+                }
+            };
+            let block = BlockMessage::deserialize(&mut stored)?;
+            let position = (hash, index as u32 + 1, header.time);
+            if let Ok(mut wallets) = wallet_handler.lock() {
+                found += wallets.rescan_block(&block.get_tx(), position, &mut received);
+            }
+        }
+        let mut text = format!(
+            "Rescanned {} blocks from height {}, {} transactions found",
+            headers.len() - first - missing,
+            first + 1,
+            found
+        );
+        if missing > 0 {
+            text.push_str(&format!(
+                "\n{} blocks aren't downloaded yet, they are recorded when they arrive",
+                missing
+            ));
+        }
+        Ok(text)
+    }
+
     /// Receives a proof of inclusion request
     fn receive_inclusion_request(
         block: Vec<String>,
         transaction: Vec<String>,
         sender_to_interface: Sender<InterfaceMessages>,
         blocks: Arc<Mutex<HashMap<String, BlockMessage>>>,
-        transactions: Arc<Mutex<HashMap<String, TXMessage>>>,
+        transactions: KnownTransactions,
     ) {
         let mut include = false;
         if let Ok(list_blocks) = blocks.lock() {
@@ -302,33 +1526,32 @@ impl InterfaceCommunicator {
         if sender_to_interface.send(message).is_ok() {}
     }
 
-    fn connect_streams(
-        mut peers: Vec<String>,
+    /// Adds the transaction to the mempool and announces it to every connected peer until it confirms.
+    /// Returns true if it was relayed
+    fn relay_transaction(
+        tx_relay: Arc<Mutex<TxRelay>>,
         transaction: TXMessage,
+        fee: i64,
         sender_to_interface: Sender<InterfaceMessages>,
         wallet_data: HashMap<String, String>,
-    ) {
-        let mut can_sender_to_interface = false;
-        peers.push("35.195.234.115:18333".to_string());
-        peers.push("44.192.10.119:18333".to_string());
-        peers.push("217.26.47.27:18333".to_string());
-        peers.push("178.128.251.37:18333".to_string());
-        peers.push("173.249.8.236:18333".to_string());
-        for address in peers.iter() {
-            if let Ok(stream) = TcpStream::connect(address) {
-                let cloned_transaction = transaction.clone();
-                let cloned_sender_to_interface = sender_to_interface.clone();
-                writer(&stream);
-                if cloned_transaction.send(&stream).is_ok() && !can_sender_to_interface {
-                    println!("aca entra una vez {:?}", transaction);
-                    let wallet_data = wallet_data.clone();
-                    can_sender_to_interface = true;
-                    let message = InterfaceMessages::MyTransactions(cloned_transaction);
-                    if cloned_sender_to_interface.send(message).is_ok() {}
-                    let message = InterfaceMessages::ActualWallet(wallet_data);
-                    if sender_to_interface.send(message).is_ok() {}
-                }
+    ) -> bool {
+        // Fee rate in sat/kvB, the unit used by peers' fee filters
+        let fee_rate = fee.max(0) as u64 * 1000 / transaction.vsize().max(1) as u64;
+        let relayed = match tx_relay.lock() {
+            Ok(mut relay) => {
+                let added = relay.add_own_transaction(&transaction, fee_rate);
+                relay.flush();
+                added
             }
-        }
+            Err(_) => false,
+        };
+        let message = match relayed {
+            true => InterfaceMessages::MyTransactions(transaction),
+            false => InterfaceMessages::WalletMessage("The transaction couldn't be relayed".into()),
+        };
+        if sender_to_interface.send(message).is_ok() {}
+        let message = InterfaceMessages::ActualWallet(wallet_data);
+        if sender_to_interface.send(message).is_ok() {}
+        relayed
     }
 }
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+/// Path where the estimator statistics are persisted between runs
+pub const FEE_ESTIMATES_PATH: &str = "./storage/fee_estimates.txt";
+
+/// Lowest fee rate tracked, in sat/vB
+const MIN_BUCKET_FEE_RATE: f64 = 1.0;
+/// Highest fee rate tracked, in sat/vB
+const MAX_BUCKET_FEE_RATE: f64 = 10_000.0;
+/// Each bucket starts this many times above the previous one
+const FEE_SPACING: f64 = 1.1;
+/// Highest confirmation target (in blocks) that can be estimated
+pub const MAX_CONFIRMATION_TARGET: usize = 25;
+/// Older observations lose weight on every new block
+const DECAY: f64 = 0.998;
+/// Minimum fraction of transactions that must have confirmed within the target
+const SUCCESS_THRESHOLD: f64 = 0.85;
+/// Minimum amount of (decayed) observations before a group of buckets is trusted
+const SUFFICIENT_TXS: f64 = 2.0;
+
+/// Confirmation targets offered to the user as suggested fees: fast, normal and economic
+pub const SUGGESTED_TARGETS: [usize; 3] = [2, 6, 12];
+
+/// Records when mempool transactions arrive and when they confirm, grouping them
+/// by fee rate, to answer "which fee rate confirms within N blocks".
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeEstimator {
+    buckets: Vec<f64>,
+    tx_count: Vec<f64>,
+    confirmed_within: Vec<Vec<f64>>,
+    unconfirmed: HashMap<[u8; 32], (u32, usize)>,
+    best_height: u32,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeeEstimator {
+    /// Creates an estimator without any observation
+    pub fn new() -> FeeEstimator {
+        let mut buckets = Vec::new();
+        let mut fee_rate = MIN_BUCKET_FEE_RATE;
+        while fee_rate <= MAX_BUCKET_FEE_RATE {
+            buckets.push(fee_rate);
+            fee_rate *= FEE_SPACING;
+        }
+        let buckets_len = buckets.len();
+
+        FeeEstimator {
+            buckets,
+            tx_count: vec![0.0; buckets_len],
+            confirmed_within: vec![vec![0.0; buckets_len]; MAX_CONFIRMATION_TARGET],
+            unconfirmed: HashMap::new(),
+            best_height: 0,
+        }
+    }
+
+    /// Returns the index of the bucket a fee rate belongs to
+    fn bucket_index(&self, fee_rate: f64) -> usize {
+        let mut index = 0;
+        for (i, lower_bound) in self.buckets.iter().enumerate() {
+            if fee_rate >= *lower_bound {
+                index = i;
+            } else {
+                break;
+            }
+        }
+        index
+    }
+
+    /// Returns the height of the last block processed, 0 if there's none
+    pub fn best_height(&self) -> u32 {
+        self.best_height
+    }
+
+    /// Starts tracking a transaction that just entered the mempool at the given height
+    pub fn process_transaction(&mut self, txid: [u8; 32], fee_rate: f64, height: u32) {
+        if self.unconfirmed.contains_key(&txid) || fee_rate < MIN_BUCKET_FEE_RATE {
+            return;
+        }
+        let bucket = self.bucket_index(fee_rate);
+        self.unconfirmed.insert(txid, (height, bucket));
+    }
+
+    /// Stops tracking a transaction that left the mempool without being mined
+    pub fn remove_transaction(&mut self, txid: &[u8; 32]) {
+        self.unconfirmed.remove(txid);
+    }
+
+    /// Records the transactions confirmed by a block at the given height.
+    /// Transactions that waited longer than the maximum target are recorded as failures.
+    pub fn process_block(&mut self, height: u32, txids: &[[u8; 32]]) {
+        if height > self.best_height {
+            self.decay();
+            self.best_height = height;
+        }
+
+        for txid in txids {
+            if let Some((entry_height, bucket)) = self.unconfirmed.remove(txid) {
+                let blocks_to_confirm = height.saturating_sub(entry_height).max(1) as usize;
+                self.record(bucket, Some(blocks_to_confirm));
+            }
+        }
+
+        let mut expired = Vec::new();
+        for (txid, (entry_height, bucket)) in self.unconfirmed.iter() {
+            if height.saturating_sub(*entry_height) as usize > MAX_CONFIRMATION_TARGET {
+                expired.push((*txid, *bucket));
+            }
+        }
+        for (txid, bucket) in expired {
+            self.unconfirmed.remove(&txid);
+            self.record(bucket, None);
+        }
+    }
+
+    /// Adds an observation to a bucket. `None` means it never confirmed within the maximum target
+    fn record(&mut self, bucket: usize, blocks_to_confirm: Option<usize>) {
+        self.tx_count[bucket] += 1.0;
+        if let Some(blocks) = blocks_to_confirm {
+            for target in (blocks - 1)..MAX_CONFIRMATION_TARGET {
+                self.confirmed_within[target][bucket] += 1.0;
+            }
+        }
+    }
+
+    /// Reduces the weight of every previous observation
+    fn decay(&mut self) {
+        for count in self.tx_count.iter_mut() {
+            *count *= DECAY;
+        }
+        for target in self.confirmed_within.iter_mut() {
+            for count in target.iter_mut() {
+                *count *= DECAY;
+            }
+        }
+    }
+
+    /// Returns the lowest fee rate (sat/vB) whose transactions confirmed within `target` blocks
+    /// often enough, or None if there is not enough data yet.
+    pub fn estimate_fee_rate(&self, target: usize) -> Option<u64> {
+        if target == 0 || target > MAX_CONFIRMATION_TARGET {
+            return None;
+        }
+        let confirmed = &self.confirmed_within[target - 1];
+
+        let mut best_bucket = None;
+        let mut total = 0.0;
+        let mut successes = 0.0;
+        // Walk from the most expensive bucket down, grouping buckets until there's enough data
+        for bucket in (0..self.buckets.len()).rev() {
+            total += self.tx_count[bucket];
+            successes += confirmed[bucket];
+            if total < SUFFICIENT_TXS {
+                continue;
+            }
+            if successes / total < SUCCESS_THRESHOLD {
+                break;
+            }
+            best_bucket = Some(bucket);
+            total = 0.0;
+            successes = 0.0;
+        }
+
+        best_bucket.map(|bucket| self.buckets[bucket].ceil() as u64)
+    }
+
+    /// Returns the estimated fee rate for each of the suggested targets
+    pub fn suggested_fee_rates(&self) -> Vec<(usize, Option<u64>)> {
+        SUGGESTED_TARGETS
+            .iter()
+            .map(|target| (*target, self.estimate_fee_rate(*target)))
+            .collect()
+    }
+
+    /// Returns the number of transactions waiting for confirmation
+    pub fn tracked_transactions(&self) -> usize {
+        self.unconfirmed.len()
+    }
+
+    /// Writes the statistics to the given path, one line per bucket
+    /// # Errors
+    /// Returns an error if the file could not be written
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        writeln!(file, "{}", self.best_height)?;
+        for bucket in 0..self.buckets.len() {
+            let mut line = vec![self.tx_count[bucket].to_string()];
+            for target in self.confirmed_within.iter() {
+                line.push(target[bucket].to_string());
+            }
+            writeln!(file, "{}", line.join(","))?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Reads the statistics previously saved in the given path.
+    /// Returns an empty estimator if the file does not exist or is malformed
+    pub fn open(path: &str) -> FeeEstimator {
+        let mut estimator = FeeEstimator::new();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return estimator,
+        };
+        let mut lines = BufReader::new(file).lines().map_while(Result::ok);
+
+        estimator.best_height = match lines.next().map(|line| line.parse::<u32>()) {
+            Some(Ok(height)) => height,
+            _ => return FeeEstimator::new(),
+        };
+        for (bucket, line) in lines.enumerate().take(estimator.buckets.len()) {
+            let values: Vec<f64> = line.split(',').filter_map(|v| v.parse().ok()).collect();
+            if values.len() != MAX_CONFIRMATION_TARGET + 1 {
+                return FeeEstimator::new();
+            }
+            estimator.tx_count[bucket] = values[0];
+            for (target, value) in values.iter().skip(1).enumerate() {
+                estimator.confirmed_within[target][bucket] = *value;
+            }
+        }
+        estimator
+    }
+}
+
+#[cfg(test)]
+mod fee_estimator_tests {
+    use super::*;
+
+    fn txid(n: u8) -> [u8; 32] {
+        [n; 32]
+    }
+
+    #[test]
+    fn test_estimate_without_data_is_none() {
+        let estimator = FeeEstimator::new();
+        assert_eq!(estimator.estimate_fee_rate(2), None);
+    }
+
+    #[test]
+    fn test_estimate_invalid_target_is_none() {
+        let estimator = FeeEstimator::new();
+        assert_eq!(estimator.estimate_fee_rate(0), None);
+        assert_eq!(
+            estimator.estimate_fee_rate(MAX_CONFIRMATION_TARGET + 1),
+            None
+        );
+    }
+
+    #[test]
+    fn test_transactions_confirmed_next_block() {
+        let mut estimator = FeeEstimator::new();
+        for n in 0..10 {
+            estimator.process_transaction(txid(n), 20.0, 100);
+        }
+        let txids: Vec<[u8; 32]> = (0..10).map(txid).collect();
+        estimator.process_block(101, &txids);
+
+        let estimate = estimator.estimate_fee_rate(1).unwrap();
+        assert!((18..=20).contains(&estimate));
+        assert_eq!(estimator.tracked_transactions(), 0);
+    }
+
+    #[test]
+    fn test_cheap_transactions_need_longer_targets() {
+        let mut estimator = FeeEstimator::new();
+        for n in 0..10 {
+            estimator.process_transaction(txid(n), 50.0, 100);
+            estimator.process_transaction(txid(n + 100), 2.0, 100);
+        }
+        let fast: Vec<[u8; 32]> = (0..10).map(txid).collect();
+        estimator.process_block(101, &fast);
+        let slow: Vec<[u8; 32]> = (100..110).map(txid).collect();
+        estimator.process_block(110, &slow);
+
+        let fast_estimate = estimator.estimate_fee_rate(1).unwrap();
+        let slow_estimate = estimator.estimate_fee_rate(10).unwrap();
+        assert!(fast_estimate >= 45);
+        assert!(slow_estimate <= 2);
+    }
+
+    #[test]
+    fn test_expired_transactions_count_as_failures() {
+        let mut estimator = FeeEstimator::new();
+        for n in 0..10 {
+            estimator.process_transaction(txid(n), 5.0, 100);
+        }
+        estimator.process_block(100 + MAX_CONFIRMATION_TARGET as u32 + 1, &[]);
+
+        assert_eq!(estimator.tracked_transactions(), 0);
+        assert_eq!(estimator.estimate_fee_rate(MAX_CONFIRMATION_TARGET), None);
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let mut estimator = FeeEstimator::new();
+        for n in 0..5 {
+            estimator.process_transaction(txid(n), 12.0, 10);
+        }
+        let txids: Vec<[u8; 32]> = (0..5).map(txid).collect();
+        estimator.process_block(12, &txids);
+
+        let path = "./storage/fee_estimates_test.txt";
+        estimator.save(path).unwrap();
+        let opened = FeeEstimator::open(path);
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(opened.estimate_fee_rate(2), estimator.estimate_fee_rate(2));
+        assert_eq!(opened.best_height(), 12);
+    }
+
+    #[test]
+    fn test_open_missing_file_is_empty() {
+        let estimator = FeeEstimator::open("./storage/does_not_exist.txt");
+        assert_eq!(estimator, FeeEstimator::new());
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+/// Fee rate (sat/kvB) below which transactions are never relayed
+pub const MIN_RELAY_FEE_RATE: u64 = 1000;
+/// Size in bytes the mempool can grow to before the minimum fee rate starts rising
+pub const MAX_MEMPOOL_SIZE: usize = 300_000_000;
+
+/// Keeps the fee rate and size of every mempool transaction to know which fee rate a
+/// transaction needs to stay in a full mempool.
+#[derive(Debug, Default)]
+pub struct MempoolMinFee {
+    entries: HashMap<[u8; 32], (u64, usize)>,
+    total_size: usize,
+    max_size: usize,
+    evicted_fee_rate: u64, // highest fee rate evicted since the mempool was last half empty
+}
+
+impl MempoolMinFee {
+    /// Creates a tracker for a mempool limited to `max_size` bytes
+    pub fn new(max_size: usize) -> MempoolMinFee {
+        MempoolMinFee {
+            entries: HashMap::new(),
+            total_size: 0,
+            max_size,
+            evicted_fee_rate: 0,
+        }
+    }
+
+    /// Records a transaction with its fee rate (sat/kvB) and size in bytes
+    pub fn add(&mut self, txid: [u8; 32], fee_rate: u64, size: usize) {
+        if let Some((_, previous_size)) = self.entries.insert(txid, (fee_rate, size)) {
+            self.total_size -= previous_size;
+        }
+        self.total_size += size;
+    }
+
+    /// Returns the fee rate (sat/kvB) and size of a transaction in the mempool
+    pub fn get(&self, txid: &[u8; 32]) -> Option<(u64, usize)> {
+        self.entries.get(txid).copied()
+    }
+
+    /// Forgets a transaction that left the mempool
+    pub fn remove(&mut self, txid: &[u8; 32]) {
+        if let Some((_, size)) = self.entries.remove(txid) {
+            self.total_size -= size;
+        }
+        if self.total_size <= self.max_size / 2 {
+            self.evicted_fee_rate = 0;
+        }
+    }
+
+    /// Forgets the cheapest transactions until the mempool fits its maximum size, except the
+    /// protected ones. Returns the ids of the evicted transactions
+    pub fn evict(&mut self, protected: impl Fn(&[u8; 32]) -> bool) -> Vec<[u8; 32]> {
+        let mut entries: Vec<([u8; 32], u64)> = self
+            .entries
+            .iter()
+            .filter(|(txid, _)| !protected(txid))
+            .map(|(txid, (fee_rate, _))| (*txid, *fee_rate))
+            .collect();
+        entries.sort_by_key(|(_, fee_rate)| *fee_rate);
+        let mut evicted = vec![];
+        for (txid, fee_rate) in entries {
+            if self.total_size <= self.max_size {
+                break;
+            }
+            if let Some((_, size)) = self.entries.remove(&txid) {
+                self.total_size -= size;
+            }
+            self.evicted_fee_rate = self.evicted_fee_rate.max(fee_rate);
+            evicted.push(txid);
+        }
+        evicted
+    }
+
+    /// Returns the minimum fee rate (sat/kvB) a transaction needs to enter the mempool.
+    /// While there's room it's the relay minimum, once full it's just above the
+    /// cheapest transactions that would be evicted.
+    pub fn min_fee_rate(&self) -> u64 {
+        // Transactions as cheap as the evicted ones would be evicted again
+        let floor = match self.evicted_fee_rate {
+            0 => MIN_RELAY_FEE_RATE,
+            evicted => evicted + MIN_RELAY_FEE_RATE,
+        };
+        if self.total_size <= self.max_size {
+            return floor;
+        }
+        let mut entries: Vec<&(u64, usize)> = self.entries.values().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.0));
+
+        let mut size = 0;
+        for (fee_rate, tx_size) in entries {
+            size += tx_size;
+            if size > self.max_size {
+                return (fee_rate + MIN_RELAY_FEE_RATE).max(floor);
+            }
+        }
+        floor
+    }
+
+    /// Returns the amount of transactions tracked
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod min_fee_tests {
+    use super::*;
+
+    #[test]
+    fn test_min_fee_rate_with_room() {
+        let mut min_fee = MempoolMinFee::new(1000);
+        min_fee.add([1; 32], 5000, 500);
+        assert_eq!(min_fee.min_fee_rate(), MIN_RELAY_FEE_RATE);
+    }
+
+    #[test]
+    fn test_min_fee_rate_when_full() {
+        let mut min_fee = MempoolMinFee::new(1000);
+        min_fee.add([1; 32], 5000, 600);
+        min_fee.add([2; 32], 3000, 300);
+        min_fee.add([3; 32], 2000, 300);
+        assert_eq!(min_fee.min_fee_rate(), 2000 + MIN_RELAY_FEE_RATE);
+
+        min_fee.remove(&[1; 32]);
+        assert_eq!(min_fee.min_fee_rate(), MIN_RELAY_FEE_RATE);
+    }
+
+    #[test]
+    fn test_evict_cheapest_transactions() {
+        let mut min_fee = MempoolMinFee::new(1000);
+        min_fee.add([1; 32], 5000, 600);
+        min_fee.add([2; 32], 3000, 300);
+        min_fee.add([3; 32], 2000, 300);
+        min_fee.add([4; 32], 1000, 300);
+        assert_eq!(
+            min_fee.evict(|txid| *txid == [4; 32]),
+            vec![[3; 32], [2; 32]]
+        );
+        assert_eq!(min_fee.len(), 2);
+        assert_eq!(min_fee.min_fee_rate(), 3000 + MIN_RELAY_FEE_RATE);
+
+        min_fee.remove(&[1; 32]);
+        assert_eq!(min_fee.min_fee_rate(), MIN_RELAY_FEE_RATE);
+        min_fee.remove(&[4; 32]);
+        assert!(min_fee.is_empty());
+    }
+}
@@ -0,0 +1,4 @@
+pub mod fee_estimator;
+pub mod min_fee;
+pub mod package;
+pub mod tx_relay;
@@ -0,0 +1,111 @@
+use crate::message_structs::tx_message::TXMessage;
+use std::error::Error;
+
+/// A parent transaction and a child spending one of its outputs, evaluated together
+/// so a high fee child can pay for a parent that alone is below the mempool minimum (CPFP).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Package {
+    parent: TXMessage,
+    child: TXMessage,
+}
+
+impl Package {
+    /// Creates a 1-parent-1-child package.
+    /// # Errors
+    /// Returns an error if the child doesn't spend an output of the parent
+    /// or if the parent spends an output of the child
+    pub fn new(parent: TXMessage, child: TXMessage) -> Result<Package, Box<dyn Error>> {
+        let parent_id = parent.get_id();
+        let child_id = child.get_id();
+        if parent_id == child_id {
+            return Err("Parent and child are the same transaction".into());
+        }
+        if !Self::spends_from(&child, parent_id) {
+            return Err("Child doesn't spend any output of the parent".into());
+        }
+        if Self::spends_from(&parent, child_id) {
+            return Err("Parent can't spend outputs of the child".into());
+        }
+        Ok(Package { parent, child })
+    }
+
+    /// Returns true if any input of the transaction spends an output of `txid`
+    pub fn spends_from(tx: &TXMessage, txid: [u8; 32]) -> bool {
+        tx.get_input()
+            .iter()
+            .any(|input| input.get_outpoint().get_hash() == txid)
+    }
+
+    /// Returns the combined size of both transactions in vbytes
+    pub fn vsize(&self) -> usize {
+        self.parent.vsize() + self.child.vsize()
+    }
+
+    /// Returns the fee rate of the package in sat/kvB given the fee paid by each transaction
+    pub fn fee_rate(&self, parent_fee: u64, child_fee: u64) -> u64 {
+        (parent_fee + child_fee) * 1000 / self.vsize().max(1) as u64
+    }
+
+    pub fn get_parent(&self) -> &TXMessage {
+        &self.parent
+    }
+
+    pub fn get_child(&self) -> &TXMessage {
+        &self.child
+    }
+}
+
+#[cfg(test)]
+mod package_tests {
+    use super::*;
+    use crate::message_structs::{
+        compact_size::CompactSize, input::Input, outpoint::Outpoint, output::Output,
+    };
+    use crate::utils::array_tools::reverse_array;
+
+    fn tx_spending(txid: [u8; 32], value: i64) -> TXMessage {
+        let input = Input::new(
+            Outpoint::new(reverse_array(&txid), 0),
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            0xffffffff,
+        );
+        let output = Output::new(
+            value,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![0x51],
+        );
+        TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![input],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![output],
+            0,
+        )
+    }
+
+    #[test]
+    fn test_new_package() {
+        let parent = tx_spending([1; 32], 1000);
+        let child = tx_spending(parent.get_id(), 500);
+        assert!(Package::new(parent, child).is_ok());
+    }
+
+    #[test]
+    fn test_unrelated_transactions_are_not_a_package() {
+        let parent = tx_spending([1; 32], 1000);
+        let child = tx_spending([2; 32], 500);
+        assert!(Package::new(parent, child).is_err());
+    }
+
+    #[test]
+    fn test_package_fee_rate() {
+        let parent = tx_spending([1; 32], 1000);
+        let child = tx_spending(parent.get_id(), 500);
+        let size = (parent.vsize() + child.vsize()) as u64;
+        let package = Package::new(parent, child).unwrap();
+
+        assert_eq!(package.fee_rate(0, size * 5), 5000);
+    }
+}
@@ -0,0 +1,790 @@
+use super::min_fee::{MempoolMinFee, MAX_MEMPOOL_SIZE};
+use super::package::Package;
+use crate::message_structs::compact_size::CompactSize;
+use crate::message_structs::fee_filter::FeeFilter;
+use crate::message_structs::inv::Inv;
+use crate::message_structs::inv_or_get_data_message::InvOrGetDataMessage;
+use crate::message_structs::tx_message::TXMessage;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type Mempool = Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>;
+/// Held while writing a whole message to a peer, its stream is shared by several threads
+pub type PeerWriteLock = Arc<Mutex<()>>;
+
+/// Average time between two inv announcements to the same peer, in milliseconds
+const TRICKLE_INTERVAL_MS: u64 = 5000;
+/// Maximum amount of transactions announced to a peer on each trickle
+pub const MAX_INV_PER_TRICKLE: usize = 35;
+/// Our own unconfirmed transactions are announced again every this many trickles (~10 minutes)
+const REBROADCAST_TRICKLES: u32 = 120;
+/// Inventory type for transactions
+const MSG_TX: u32 = 1;
+/// Maximum amount of below minimum fee transactions kept waiting for a child to pay for them
+const MAX_LOW_FEE_TRANSACTIONS: usize = 100;
+/// Fee rate (sat/kvB) a replacement pays for its own relay on top of the fees it replaces (BIP125)
+const INCREMENTAL_RELAY_FEE_RATE: u64 = 1000;
+/// Maximum amount of transactions a replacement takes out of the mempool, descendants included
+const MAX_REPLACED: usize = 100;
+/// Transactions with an input sequence up to it signal they can be replaced (BIP125)
+const MAX_REPLACEABLE_SEQUENCE: u32 = 0xfffffffd;
+
+/// Relay state of a connected peer
+struct PeerRelay {
+    stream: TcpStream,
+    write_lock: PeerWriteLock,
+    fee_filter: u64,
+    sent_fee_filter: Option<u64>,
+    known: HashSet<[u8; 32]>,
+    pending: Vec<[u8; 32]>,
+}
+
+/// Announces the transactions in our mempool to every connected peer through inv messages.
+/// Announcements are queued per peer and sent in small shuffled batches (trickling),
+/// so peers can't easily infer which transactions originated here.
+pub struct TxRelay {
+    peers: HashMap<String, PeerRelay>,
+    own_transactions: HashMap<[u8; 32], u64>,
+    mempool: Mempool,
+    min_fee: MempoolMinFee,
+    low_fee_transactions: HashMap<[u8; 32], (TXMessage, u64)>,
+    spent_outpoints: HashMap<Vec<u8>, [u8; 32]>, // serialized outpoint to the mempool tx spending it
+}
+
+impl TxRelay {
+    /// Creates a relay that serves the transactions stored in the given mempool
+    pub fn new(mempool: Mempool) -> TxRelay {
+        TxRelay {
+            peers: HashMap::new(),
+            own_transactions: HashMap::new(),
+            mempool,
+            min_fee: MempoolMinFee::new(MAX_MEMPOOL_SIZE),
+            low_fee_transactions: HashMap::new(),
+            spent_outpoints: HashMap::new(),
+        }
+    }
+
+    /// Starts announcing transactions to a newly connected peer and sends it our fee filter
+    pub fn add_peer(&mut self, address: String, stream: TcpStream) {
+        self.peers.insert(
+            address,
+            PeerRelay {
+                stream,
+                write_lock: Arc::new(Mutex::new(())),
+                fee_filter: 0,
+                sent_fee_filter: None,
+                known: HashSet::new(),
+                pending: vec![],
+            },
+        );
+        self.send_fee_filters();
+    }
+
+    /// Stops announcing transactions to a disconnected peer
+    pub fn remove_peer(&mut self, address: &str) {
+        self.peers.remove(address);
+    }
+
+    /// Returns the lock the threads writing to the peer hold while sending a message,
+    /// a new one if the peer isn't relayed to
+    pub fn write_lock(&self, address: &str) -> PeerWriteLock {
+        match self.peers.get(address) {
+            Some(peer) => Arc::clone(&peer.write_lock),
+            None => Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Returns the amount of peers transactions are being relayed to
+    pub fn peers_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Sets the minimum fee rate (sat/kvB) of the transactions announced to a peer
+    pub fn set_fee_filter(&mut self, address: &str, fee_rate: u64) {
+        if let Some(peer) = self.peers.get_mut(address) {
+            peer.fee_filter = fee_rate;
+        }
+    }
+
+    /// Records that a peer already has a transaction, so it's not announced back to it
+    pub fn mark_known(&mut self, address: &str, txid: [u8; 32]) {
+        if let Some(peer) = self.peers.get_mut(address) {
+            peer.known.insert(txid);
+            peer.pending.retain(|pending| *pending != txid);
+        }
+    }
+
+    /// Queues the announcement of a transaction with the given fee rate (sat/kvB) for every peer
+    /// that doesn't know it yet and whose fee filter allows it
+    pub fn announce(&mut self, txid: [u8; 32], fee_rate: u64) {
+        for peer in self.peers.values_mut() {
+            if peer.known.contains(&txid) || peer.pending.contains(&txid) {
+                continue;
+            }
+            if fee_rate < peer.fee_filter {
+                continue;
+            }
+            peer.pending.push(txid);
+        }
+    }
+
+    /// Records the fee rate (sat/kvB) and size of a transaction that entered the mempool
+    pub fn transaction_added(&mut self, txid: [u8; 32], fee_rate: u64, size: usize) {
+        self.min_fee.add(txid, fee_rate, size);
+    }
+
+    /// Adds a transaction received from a peer paying `fee` satoshis to the mempool. If it spends
+    /// the same outputs as mempool transactions it only replaces them following BIP125.
+    /// The cheapest transactions are evicted if the mempool gets full, returns false if
+    /// it was rejected or evicted itself
+    pub fn add_to_mempool(&mut self, tx: &TXMessage, fee: u64) -> bool {
+        let fee_rate = fee * 1000 / tx.vsize().max(1) as u64;
+        if !self.can_replace(tx, fee, fee_rate) {
+            return false;
+        }
+        self.insert(tx, fee_rate);
+        self.evict();
+        self.in_mempool(&tx.get_id())
+    }
+
+    fn in_mempool(&self, txid: &[u8; 32]) -> bool {
+        match self.mempool.lock() {
+            Ok(mempool) => mempool.contains_key(txid),
+            Err(_) => false,
+        }
+    }
+
+    fn mempool_transaction(&self, txid: &[u8; 32]) -> Option<TXMessage> {
+        let serialized = match self.mempool.lock() {
+            Ok(mempool) => mempool.get(txid).cloned(),
+            Err(_) => None,
+        };
+        TXMessage::deserialize(&mut serialized?).ok()
+    }
+
+    /// Returns the mempool transactions the transaction conflicts with
+    fn conflicts_of(&self, tx: &TXMessage) -> Vec<[u8; 32]> {
+        let txid = tx.get_id();
+        self.spends_of(tx)
+            .into_iter()
+            .filter(|conflict| *conflict != txid)
+            .collect()
+    }
+
+    /// Returns true if the transaction can replace the mempool transactions it conflicts with
+    /// (BIP125): all of them signal it, it pays a higher fee rate than each of them and its fee
+    /// covers the fees of every transaction it takes out plus the relay of its own size.
+    /// Our own transactions are never replaced by a peer's, only our wallet can sign their inputs
+    fn can_replace(&self, tx: &TXMessage, fee: u64, fee_rate: u64) -> bool {
+        let conflicts = self.conflicts_of(tx);
+        for conflict in conflicts.iter() {
+            if self.own_transactions.contains_key(conflict) {
+                return false;
+            }
+            let signals = self.mempool_transaction(conflict).is_some_and(|conflict| {
+                conflict
+                    .get_input()
+                    .iter()
+                    .any(|input| input.get_sequence_number() <= MAX_REPLACEABLE_SEQUENCE)
+            });
+            match self.min_fee.get(conflict) {
+                Some((conflict_fee_rate, _)) if signals && fee_rate > conflict_fee_rate => {}
+                _ => return false,
+            }
+        }
+        let replaced = self.with_descendants(&conflicts);
+        if replaced.len() > MAX_REPLACED {
+            return false;
+        }
+        let replaced_fees: u64 = replaced
+            .iter()
+            .filter_map(|txid| self.min_fee.get(txid))
+            .map(|(fee_rate, size)| (fee_rate * size as u64).div_ceil(1000))
+            .sum();
+        fee >= replaced_fees + INCREMENTAL_RELAY_FEE_RATE * tx.vsize() as u64 / 1000
+    }
+
+    /// Returns the transactions with every mempool transaction descending from them
+    fn with_descendants(&self, txids: &[[u8; 32]]) -> Vec<[u8; 32]> {
+        let mut found = txids.to_vec();
+        let mut next = 0;
+        while next < found.len() {
+            let txid = found[next];
+            next += 1;
+            let outputs = match self.mempool_transaction(&txid) {
+                Some(tx) => tx.get_output().len() as u32,
+                None => continue,
+            };
+            for index in 0..outputs {
+                let mut outpoint = txid.to_vec();
+                outpoint.extend_from_slice(&index.to_le_bytes());
+                if let Some(child) = self.spent_outpoints.get(&outpoint) {
+                    if !found.contains(child) {
+                        found.push(*child);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Inserts the transaction in the mempool after removing the ones it conflicts with
+    fn insert(&mut self, tx: &TXMessage, fee_rate: u64) {
+        let txid = tx.get_id();
+        let conflicts = self.conflicts_of(tx);
+        self.remove_transactions(&conflicts);
+        if let Ok(mut mempool) = self.mempool.lock() {
+            mempool.insert(txid, tx.serialize());
+        }
+        for input in tx.get_input() {
+            self.spent_outpoints
+                .insert(input.get_outpoint().serialize(), txid);
+        }
+        self.transaction_added(txid, fee_rate, tx.vsize());
+    }
+
+    /// Returns the mempool transactions spending any of the outputs the transaction spends
+    fn spends_of(&self, tx: &TXMessage) -> Vec<[u8; 32]> {
+        let mut spends: Vec<[u8; 32]> = tx
+            .get_input()
+            .iter()
+            .filter_map(|input| self.spent_outpoints.get(&input.get_outpoint().serialize()))
+            .copied()
+            .collect();
+        spends.dedup();
+        spends
+    }
+
+    /// Evicts the cheapest transactions other than our own while the mempool is full
+    fn evict(&mut self) {
+        let own = &self.own_transactions;
+        let evicted = self.min_fee.evict(|txid| own.contains_key(txid));
+        self.remove_transactions(&evicted);
+    }
+
+    /// Removes replaced or evicted transactions from the mempool, with the transactions
+    /// spending their outputs as those can't be mined anymore
+    fn remove_transactions(&mut self, txids: &[[u8; 32]]) {
+        let mut removed = txids.to_vec();
+        while let Some(txid) = removed.pop() {
+            let tx = match self.mempool.lock() {
+                Ok(mut mempool) => mempool.remove(&txid),
+                Err(_) => None,
+            };
+            self.transactions_confirmed(&[txid]);
+            let tx = match tx.map(|mut bytes| TXMessage::deserialize(&mut bytes)) {
+                Some(Ok(tx)) => tx,
+                _ => continue,
+            };
+            for input in tx.get_input() {
+                let outpoint = input.get_outpoint().serialize();
+                if self.spent_outpoints.get(&outpoint) == Some(&txid) {
+                    self.spent_outpoints.remove(&outpoint);
+                }
+            }
+            for index in 0..tx.get_output().len() as u32 {
+                let mut outpoint = txid.to_vec();
+                outpoint.extend_from_slice(&index.to_le_bytes());
+                if let Some(child) = self.spent_outpoints.remove(&outpoint) {
+                    removed.push(child);
+                }
+            }
+        }
+    }
+
+    /// Returns the fee rate (sat/kvB) a transaction needs to be accepted in our mempool
+    pub fn min_fee_rate(&self) -> u64 {
+        self.min_fee.min_fee_rate()
+    }
+
+    /// Returns true if a transaction with the given fee rate (sat/kvB) can enter the mempool on its own
+    pub fn accepts_fee_rate(&self, fee_rate: u64) -> bool {
+        fee_rate >= self.min_fee_rate()
+    }
+
+    /// Keeps a transaction rejected for its low fee, so a child can later get it accepted as a package
+    pub fn add_low_fee_transaction(&mut self, tx: TXMessage, fee: u64) {
+        if self.low_fee_transactions.len() >= MAX_LOW_FEE_TRANSACTIONS {
+            if let Some(txid) = self.low_fee_transactions.keys().next().copied() {
+                self.low_fee_transactions.remove(&txid);
+            }
+        }
+        self.low_fee_transactions.insert(tx.get_id(), (tx, fee));
+    }
+
+    /// Returns the low fee transaction spent by the given child, with its fee
+    pub fn low_fee_parent(&self, child: &TXMessage) -> Option<(TXMessage, u64)> {
+        self.low_fee_transactions
+            .iter()
+            .find(|(txid, _)| Package::spends_from(child, **txid))
+            .map(|(_, (parent, fee))| (parent.clone(), *fee))
+    }
+
+    /// Evaluates a parent and child by their combined fee rate and, if it's enough,
+    /// adds both to the mempool and announces them. Own packages are rebroadcast until confirmed
+    /// # Errors
+    /// Returns an error if the package fee rate is below the mempool minimum or a peer's package
+    /// conflicts with mempool transactions
+    pub fn submit_package(
+        &mut self,
+        package: &Package,
+        fees: (u64, u64),
+        own: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let fee_rate = package.fee_rate(fees.0, fees.1);
+        if !self.accepts_fee_rate(fee_rate) {
+            return Err("Package fee rate is below the mempool minimum".into());
+        }
+        if !own
+            && [package.get_parent(), package.get_child()]
+                .iter()
+                .any(|tx| !self.conflicts_of(tx).is_empty())
+        {
+            return Err("Package conflicts with mempool transactions".into());
+        }
+        for tx in [package.get_parent(), package.get_child()] {
+            let txid = tx.get_id();
+            self.insert(tx, fee_rate);
+            self.low_fee_transactions.remove(&txid);
+            if own {
+                self.own_transactions.insert(txid, fee_rate);
+            }
+        }
+        self.evict();
+        for tx in [package.get_parent(), package.get_child()] {
+            if self.in_mempool(&tx.get_id()) {
+                self.announce(tx.get_id(), fee_rate);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends our minimum fee rate to every peer that doesn't have the current value yet
+    pub fn send_fee_filters(&mut self) {
+        let fee_rate = self.min_fee_rate();
+        let fee_filter = FeeFilter::new(fee_rate);
+        for peer in self.peers.values_mut() {
+            if peer.sent_fee_filter == Some(fee_rate) {
+                continue;
+            }
+            // Busy peers get it on the next trickle, waiting could deadlock with their writer
+            let _writing = match peer.write_lock.try_lock() {
+                Ok(writing) => writing,
+                Err(_) => continue,
+            };
+            if fee_filter.send(&peer.stream).is_ok() {
+                peer.sent_fee_filter = Some(fee_rate);
+            }
+        }
+    }
+
+    /// Adds a transaction created by our wallet to the mempool and announces it.
+    /// It will be announced again periodically until it's confirmed.
+    /// Returns true if it's in the mempool
+    pub fn add_own_transaction(&mut self, tx: &TXMessage, fee_rate: u64) -> bool {
+        let txid = tx.get_id();
+        self.own_transactions.insert(txid, fee_rate);
+        self.insert(tx, fee_rate);
+        self.evict();
+        self.announce(txid, fee_rate);
+        self.in_mempool(&txid)
+    }
+
+    /// Replaces an own transaction with another one spending the same inputs at a higher fee (RBF).
+    /// The replaced transaction leaves the mempool and stops being announced
+    pub fn replace_own_transaction(&mut self, replaced: [u8; 32], tx: &TXMessage, fee_rate: u64) {
+        self.remove_transactions(&[replaced]);
+        self.add_own_transaction(tx, fee_rate);
+    }
+
+    /// Stops announcing own transactions the wallet abandoned, they leave the mempool
+    /// with the transactions spending them
+    pub fn abandon_own_transactions(&mut self, txids: &[[u8; 32]]) {
+        self.remove_transactions(txids);
+    }
+
+    /// Adds a child created by our wallet to pay for its unconfirmed parent (CPFP), both are
+    /// announced again with the fee rate of the package given the fee paid by each transaction
+    pub fn add_own_child(&mut self, parent: &TXMessage, child: &TXMessage, fees: (u64, u64)) {
+        let vsize = (parent.vsize() + child.vsize()).max(1) as u64;
+        let fee_rate = (fees.0 + fees.1) * 1000 / vsize;
+        for tx in [parent, child] {
+            for peer in self.peers.values_mut() {
+                peer.known.remove(&tx.get_id());
+            }
+            self.add_own_transaction(tx, fee_rate);
+        }
+    }
+
+    /// Returns the ids of our own transactions still waiting for confirmation
+    pub fn own_unconfirmed(&self) -> Vec<[u8; 32]> {
+        self.own_transactions.keys().copied().collect()
+    }
+
+    /// Stops relaying the transactions included in a block
+    pub fn transactions_confirmed(&mut self, txids: &[[u8; 32]]) {
+        for txid in txids {
+            self.own_transactions.remove(txid);
+            self.min_fee.remove(txid);
+            self.low_fee_transactions.remove(txid);
+            for peer in self.peers.values_mut() {
+                peer.known.remove(txid);
+                peer.pending.retain(|pending| pending != txid);
+            }
+        }
+    }
+
+    /// Stops relaying the transactions of a new block and removes from the mempool the ones
+    /// spending the same outputs, which can't be mined anymore
+    pub fn block_connected(&mut self, transactions: &[TXMessage]) {
+        let txids: Vec<[u8; 32]> = transactions.iter().map(|tx| tx.get_id()).collect();
+        let mut conflicts = vec![];
+        for tx in transactions {
+            for input in tx.get_input() {
+                match self
+                    .spent_outpoints
+                    .remove(&input.get_outpoint().serialize())
+                {
+                    Some(spender) if !txids.contains(&spender) => conflicts.push(spender),
+                    _ => {}
+                }
+            }
+        }
+        self.transactions_confirmed(&txids);
+        self.remove_transactions(&conflicts);
+    }
+
+    /// Announces our unconfirmed transactions again to every peer
+    pub fn rebroadcast(&mut self) {
+        let own: Vec<([u8; 32], u64)> = self
+            .own_transactions
+            .iter()
+            .map(|(txid, fee_rate)| (*txid, *fee_rate))
+            .collect();
+        for (txid, fee_rate) in own {
+            for peer in self.peers.values_mut() {
+                peer.known.remove(&txid);
+            }
+            self.announce(txid, fee_rate);
+        }
+    }
+
+    /// Returns the amount of announcements waiting to be sent to a peer
+    pub fn pending_for(&self, address: &str) -> usize {
+        match self.peers.get(address) {
+            Some(peer) => peer.pending.len(),
+            None => 0,
+        }
+    }
+
+    /// Sends a shuffled batch of the pending announcements to each peer that isn't being
+    /// written to by another thread. Peers whose stream fails are dropped
+    pub fn flush(&mut self) {
+        let mut disconnected = vec![];
+        let mut rng = rand::thread_rng();
+        for (address, peer) in self.peers.iter_mut() {
+            if peer.pending.is_empty() {
+                continue;
+            }
+            let _writing = match peer.write_lock.try_lock() {
+                Ok(writing) => writing,
+                Err(_) => continue,
+            };
+            peer.pending.shuffle(&mut rng);
+            let amount = peer.pending.len().min(MAX_INV_PER_TRICKLE);
+            let batch: Vec<[u8; 32]> = peer.pending.drain(0..amount).collect();
+
+            let inventory: Vec<Inv> = batch.iter().map(|txid| Inv::new(MSG_TX, *txid)).collect();
+            let inv = InvOrGetDataMessage::new(
+                CompactSize::from_usize_to_compact_size(inventory.len()),
+                inventory,
+            );
+            if inv.send_inv(&peer.stream).is_err() {
+                disconnected.push(address.clone());
+                continue;
+            }
+            peer.known.extend(batch);
+        }
+        for address in disconnected {
+            self.peers.remove(&address);
+        }
+    }
+
+    /// Starts the thread that periodically trickles announcements and rebroadcasts our transactions
+    pub fn start(relay: Arc<Mutex<TxRelay>>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut trickles = 0;
+            loop {
+                // Randomized interval so announcements timing can't be used to find the origin
+                let interval = rand::thread_rng()
+                    .gen_range(TRICKLE_INTERVAL_MS / 2..TRICKLE_INTERVAL_MS * 3 / 2);
+                thread::sleep(Duration::from_millis(interval));
+
+                let mut relay = match relay.lock() {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+                trickles += 1;
+                if trickles >= REBROADCAST_TRICKLES {
+                    relay.rebroadcast();
+                    trickles = 0;
+                }
+                relay.flush();
+                relay.send_fee_filters();
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tx_relay_tests {
+    use super::*;
+    use crate::message_structs::{input::Input, outpoint::Outpoint, output::Output};
+    use crate::node::mempool::min_fee::MIN_RELAY_FEE_RATE;
+    use crate::utils::array_tools::reverse_array;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn connected_relay() -> (TxRelay, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (remote, _) = listener.accept().unwrap();
+        let mut relay = TxRelay::new(Arc::new(Mutex::new(HashMap::new())));
+        relay.add_peer("peer".to_string(), stream);
+        (relay, remote)
+    }
+
+    #[test]
+    fn test_announce_queues_for_peer() {
+        let (mut relay, _remote) = connected_relay();
+        relay.announce([1; 32], 1000);
+        relay.announce([1; 32], 1000);
+        assert_eq!(relay.pending_for("peer"), 1);
+    }
+
+    #[test]
+    fn test_announce_skips_known_transactions() {
+        let (mut relay, _remote) = connected_relay();
+        relay.mark_known("peer", [1; 32]);
+        relay.announce([1; 32], 1000);
+        assert_eq!(relay.pending_for("peer"), 0);
+    }
+
+    #[test]
+    fn test_announce_honors_fee_filter() {
+        let (mut relay, _remote) = connected_relay();
+        relay.set_fee_filter("peer", 2000);
+        relay.announce([1; 32], 1000);
+        relay.announce([2; 32], 2000);
+        assert_eq!(relay.pending_for("peer"), 1);
+    }
+
+    #[test]
+    fn test_fee_filter_sent_on_connection() {
+        let (_relay, mut remote) = connected_relay();
+        let mut message = [0u8; 32];
+        remote.read_exact(&mut message).unwrap();
+        assert_eq!(&message[4..13], b"feefilter");
+        assert_eq!(message[24..32], MIN_RELAY_FEE_RATE.to_le_bytes());
+    }
+
+    #[test]
+    fn test_flush_sends_inv_in_batches() {
+        let (mut relay, mut remote) = connected_relay();
+        for n in 0..(MAX_INV_PER_TRICKLE + 5) {
+            relay.announce([n as u8; 32], 1000);
+        }
+        relay.flush();
+        assert_eq!(relay.pending_for("peer"), 5);
+
+        // Nothing is written while another thread writes to the peer
+        let write_lock = relay.write_lock("peer");
+        let writing = write_lock.lock().unwrap();
+        relay.flush();
+        assert_eq!(relay.pending_for("peer"), 5);
+        drop(writing);
+
+        let mut fee_filter = [0u8; 32];
+        remote.read_exact(&mut fee_filter).unwrap();
+        let mut header = [0u8; 24];
+        remote.read_exact(&mut header).unwrap();
+        assert_eq!(&header[4..7], b"inv");
+        let mut count = [0u8; 1];
+        remote.read_exact(&mut count).unwrap();
+        assert_eq!(count[0] as usize, MAX_INV_PER_TRICKLE);
+    }
+
+    #[test]
+    fn test_submit_package() {
+        let (mut relay, _remote) = connected_relay();
+        let parent = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            0,
+        );
+        let child = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Input::new(
+                Outpoint::new(reverse_array(&parent.get_id()), 0),
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                0xffffffff,
+            )],
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            0,
+        );
+        relay.add_low_fee_transaction(parent.clone(), 0);
+        assert_eq!(relay.low_fee_parent(&child), Some((parent.clone(), 0)));
+
+        let package = Package::new(parent.clone(), child.clone()).unwrap();
+        assert!(relay.submit_package(&package, (0, 1), false).is_err());
+        assert!(relay.submit_package(&package, (0, 1000), true).is_ok());
+
+        assert!(relay.mempool.lock().unwrap().contains_key(&parent.get_id()));
+        assert!(relay.mempool.lock().unwrap().contains_key(&child.get_id()));
+        assert_eq!(relay.low_fee_parent(&child), None);
+        assert_eq!(relay.own_unconfirmed().len(), 2);
+    }
+
+    #[test]
+    fn test_own_transactions_until_confirmed() {
+        let (mut relay, _remote) = connected_relay();
+        let tx = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            0,
+        );
+        relay.add_own_transaction(&tx, 1000);
+        assert!(relay.mempool.lock().unwrap().contains_key(&tx.get_id()));
+        relay.flush();
+
+        relay.rebroadcast();
+        assert_eq!(relay.pending_for("peer"), 1);
+
+        relay.transactions_confirmed(&[tx.get_id()]);
+        assert!(relay.own_unconfirmed().is_empty());
+        assert_eq!(relay.pending_for("peer"), 0);
+    }
+
+    #[test]
+    fn test_replace_own_transaction_and_add_child() {
+        let (mut relay, _remote) = connected_relay();
+        let tx_with_locktime = |locktime| {
+            TXMessage::new(
+                1,
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                locktime,
+            )
+        };
+        let (original, replacement) = (tx_with_locktime(0), tx_with_locktime(1));
+        relay.add_own_transaction(&original, 1000);
+        relay.flush();
+        relay.replace_own_transaction(original.get_id(), &replacement, 2000);
+        assert!(!relay
+            .mempool
+            .lock()
+            .unwrap()
+            .contains_key(&original.get_id()));
+        assert_eq!(relay.own_unconfirmed(), vec![replacement.get_id()]);
+        assert_eq!(relay.pending_for("peer"), 1);
+
+        // The parent is announced again with the child
+        relay.flush();
+        let child = tx_with_locktime(2);
+        relay.add_own_child(&replacement, &child, (0, 1000));
+        assert!(relay.mempool.lock().unwrap().contains_key(&child.get_id()));
+        assert_eq!(relay.own_unconfirmed().len(), 2);
+        assert_eq!(relay.pending_for("peer"), 2);
+    }
+
+    /// Returns a transaction spending the first output of `hash`
+    fn spending(hash: [u8; 32], sequence: u32, locktime: u32) -> TXMessage {
+        TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Input::new(
+                Outpoint::new(reverse_array(&hash), 0),
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                sequence,
+            )],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Output::new(
+                1000,
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+            )],
+            locktime,
+        )
+    }
+
+    fn in_mempool(relay: &TxRelay, tx: &TXMessage) -> bool {
+        relay.mempool.lock().unwrap().contains_key(&tx.get_id())
+    }
+
+    #[test]
+    fn test_conflicting_transactions_leave_the_mempool() {
+        let (mut relay, _remote) = connected_relay();
+        // Each transaction takes 60 vbytes
+        let original = spending([1; 32], 0xfffffffd, 0);
+        let child = spending(original.get_id(), 0xffffffff, 0);
+        assert!(relay.add_to_mempool(&original, 60));
+        assert!(relay.add_to_mempool(&child, 60));
+
+        // The replacement takes the original out along with its child
+        let replacement = spending([1; 32], 0xffffffff, 1);
+        assert!(relay.add_to_mempool(&replacement, 200));
+        assert!(!in_mempool(&relay, &original));
+        assert!(!in_mempool(&relay, &child));
+        assert!(in_mempool(&relay, &replacement));
+        assert_eq!(relay.min_fee.len(), 1);
+
+        // A block spending the same output makes it invalid
+        relay.block_connected(&[spending([1; 32], 0xffffffff, 2)]);
+        assert!(!in_mempool(&relay, &replacement));
+        assert!(relay.min_fee.is_empty());
+        assert!(relay.spent_outpoints.is_empty());
+    }
+
+    #[test]
+    fn test_replacements_follow_bip125() {
+        let (mut relay, _remote) = connected_relay();
+        let final_tx = spending([1; 32], 0xffffffff, 0);
+        assert!(relay.add_to_mempool(&final_tx, 100));
+        let original = spending([2; 32], 0xfffffffd, 0);
+        assert!(relay.add_to_mempool(&original, 100));
+
+        // Transactions that don't signal can't be replaced
+        assert!(!relay.add_to_mempool(&spending([1; 32], 0xffffffff, 1), 1000));
+        assert!(in_mempool(&relay, &final_tx));
+
+        // Replacements pay a higher fee rate and the replaced fees plus their own relay
+        for fee in [90, 150] {
+            let replacement = spending([2; 32], 0xffffffff, fee);
+            assert!(!relay.add_to_mempool(&replacement, fee as u64));
+            assert!(!in_mempool(&relay, &replacement));
+            assert!(in_mempool(&relay, &original));
+        }
+        let replacement = spending([2; 32], 0xffffffff, 1);
+        assert!(relay.add_to_mempool(&replacement, 160));
+        assert!(!in_mempool(&relay, &original));
+        assert!(in_mempool(&relay, &replacement));
+    }
+}
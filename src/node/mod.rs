@@ -1,6 +1,7 @@
 pub mod bitnode;
 pub mod connection_manager;
 pub mod interface;
+pub mod mempool;
 pub mod peer_discovery;
 pub mod storage_engine;
 pub mod utxo_collector;
@@ -36,10 +36,14 @@ impl UtxoCollector {
         // Save all new outputs that come inside the tx
         for (index, output) in tx.get_output().iter().enumerate() {
             let bitcoin_address = bitcoin_address_in_b58_output(&output.get_script());
+            // Outputs without an address, like bare multisig ones, can't be looked up
+            if bitcoin_address.is_empty() {
+                continue;
+            }
             if let std::collections::hash_map::Entry::Vacant(e) =
                 self.utxos.entry(bitcoin_address.clone())
             {
-                let outpoint = Outpoint::new(tx.get_id(), 0);
+                let outpoint = Outpoint::new(tx.get_id(), index as u32);
                 let values_push = (outpoint, output.clone());
                 e.insert(vec![values_push]);
             //println!("address match")
@@ -54,19 +58,14 @@ impl UtxoCollector {
             }
         }
 
-        // Foreach input, check if there's any outpoint that matches the ones we have. If so, remove it from utxos
+        // Foreach input, remove the outpoint it spends from the utxos of every address. The
+        // spending script doesn't always show the address, like P2SH multisig or sh(wpkh) ones
         for input in tx.get_input() {
-            let bitcoin_address = bitcoin_address_in_b58_input(&input.get_script());
-
-            if self.utxos.contains_key(&bitcoin_address) {
-                if let Some(x) = self.utxos.get_mut(&bitcoin_address) {
-                    let outpoint = input.get_outpoint();
-                    let index = match x.iter().position(|x| x.0 == outpoint) {
-                        Some(x) => x,
-                        None => continue,
-                    };
-                    x.remove(index);
-                }
+            // Utxos keep the id of their transaction in internal byte order
+            let outpoint = input.get_outpoint();
+            let outpoint = Outpoint::new(outpoint.get_hash(), outpoint.get_index());
+            for utxos in self.utxos.values_mut() {
+                utxos.retain(|(utxo_outpoint, _)| *utxo_outpoint != outpoint);
             }
         }
 
@@ -92,6 +91,28 @@ impl UtxoCollector {
         &mut self.utxos
     }
 
+    /// Returns true if any output paying to the script was seen
+    pub fn has_script(&self, script: &[u8]) -> bool {
+        self.utxos
+            .contains_key(&bitcoin_address_in_b58_output(script))
+    }
+
+    /// Returns the utxos stored for the addresses of the scripts, None if none of them was ever paid
+    pub fn utxos_for_scripts(&self, scripts: &[Vec<u8>]) -> Option<Vec<(Outpoint, Output)>> {
+        let mut found = false;
+        let mut utxos = vec![];
+        for script in scripts {
+            if let Some(address_utxos) = self.utxos.get(&bitcoin_address_in_b58_output(script)) {
+                found = true;
+                utxos.extend_from_slice(address_utxos);
+            }
+        }
+        match found {
+            true => Some(utxos),
+            false => None,
+        }
+    }
+
     pub fn _get_utxos_for_address(&self, _address: &str) -> Vec<(Outpoint, Output)> {
         unimplemented!("get_utxos_for_address not implemented")
     }
@@ -136,3 +157,64 @@ impl UtxoCollector {
         }
     }
 }
+
+#[cfg(test)]
+mod utxo_collector_tests {
+    use super::*;
+    use crate::message_structs::{compact_size::CompactSize, input::Input};
+    use crate::utils::array_tools::reverse_array;
+    use crate::utils::script_tools::{p2sh_script, push_data};
+
+    fn transaction(inputs: Vec<Input>, scripts: &[Vec<u8>]) -> TXMessage {
+        let outputs: Vec<Output> = scripts
+            .iter()
+            .map(|script| {
+                Output::new(
+                    1_000,
+                    CompactSize::from_usize_to_compact_size(script.len()),
+                    script.clone(),
+                )
+            })
+            .collect();
+        TXMessage::new(
+            2,
+            CompactSize::from_usize_to_compact_size(inputs.len()),
+            inputs,
+            CompactSize::from_usize_to_compact_size(outputs.len()),
+            outputs,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_p2sh_spends_are_removed() {
+        let redeem_script = vec![0x51];
+        let script = p2sh_script(&redeem_script);
+        let address = bitcoin_address_in_b58_output(&script);
+        let funding = transaction(vec![], &[script.clone(), script]);
+        let mut collector = UtxoCollector::new();
+        collector.add_utxo(funding.clone());
+        let indexes: Vec<u32> = collector.utxos[&address]
+            .iter()
+            .map(|(outpoint, _)| outpoint.get_index())
+            .collect();
+        assert_eq!(indexes, vec![0, 1]);
+
+        // The outpoint comes from the wire, where the id isn't reversed
+        let script_sig = push_data(&redeem_script);
+        let input = Input::new(
+            Outpoint::new(reverse_array(&funding.get_id()), 1),
+            CompactSize::from_usize_to_compact_size(script_sig.len()),
+            script_sig,
+            u32::MAX,
+        );
+        collector.add_utxo(transaction(vec![input], &[]));
+        assert_eq!(
+            collector.utxos[&address],
+            vec![(
+                Outpoint::new(funding.get_id(), 0),
+                funding.get_output()[0].clone()
+            )]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::message_structs::block_headers::BlockHeader;
 use crate::message_structs::block_message::BlockMessage;
-use bitcoin_hashes::{sha256, sha256d, Hash};
+use bitcoin_hashes::{sha256, sha256d, Hash, HashEngine};
 
 /// Receives a vector. Returns a Hash.
 /// For some reason Satoshi decided thath the hash should be return as big endian in Sha256dHash, so here we are.
@@ -41,6 +41,16 @@ pub fn vec_calculate_simple_hash_array_le(serialized: &[u8]) -> Option<[u8; 32]>
     hash_array.ok()
 }
 
+/// BIP340 tagged hash: SHA256(SHA256(tag) || SHA256(tag) || data)
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes()).to_byte_array();
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash);
+    engine.input(&tag_hash);
+    engine.input(data);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
 /// Receives a BlockHeader, return a [u8;32], if possible
 /// This function returns the hash in big endian
 pub fn header_calculate_doublehash_be(header: &BlockHeader) -> sha256d::Hash {
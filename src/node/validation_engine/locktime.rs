@@ -0,0 +1,581 @@
+use crate::message_structs::tx_message::TXMessage;
+use std::collections::HashMap;
+
+/// nLockTime values below this are block heights, from it on they are unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// An input with this sequence doesn't enforce any locktime
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+/// BIP68: when set the sequence is not a relative locktime
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// BIP68: when set the relative locktime is in units of 512 seconds, otherwise in blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+/// Amount of blocks used to calculate the median time past
+pub const MEDIAN_TIME_SPAN: u32 = 11;
+/// Headers kept in the index. Relative locktimes can't be longer than 65535 blocks
+/// (or 65535 * 512 seconds), so coins confirmed before them always satisfy them.
+pub const INDEXED_HEADERS: usize = 70_000;
+
+const OP_0: u8 = 0x00;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_1NEGATE: u8 = 0x4f;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_IF: u8 = 0x63;
+const OP_NOTIF: u8 = 0x64;
+const OP_ELSE: u8 = 0x67;
+const OP_ENDIF: u8 = 0x68;
+const OP_DROP: u8 = 0x75;
+const OP_EQUAL: u8 = 0x87;
+const OP_HASH160: u8 = 0xa9;
+const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+
+/// Height and time of the recent headers and the height at which transactions were confirmed,
+/// needed to evaluate absolute and relative locktimes.
+#[derive(Debug, Default)]
+pub struct ChainTimes {
+    heights: HashMap<[u8; 32], u32>,
+    times: HashMap<u32, u32>,
+    coin_heights: HashMap<[u8; 32], u32>,
+    unplaced_blocks: HashMap<[u8; 32], Vec<[u8; 32]>>,
+}
+
+impl ChainTimes {
+    pub fn new() -> ChainTimes {
+        ChainTimes::default()
+    }
+
+    /// Indexes a header by its hash
+    pub fn add_header(&mut self, hash: [u8; 32], height: u32, time: u32) {
+        self.heights.insert(hash, height);
+        self.times.insert(height, time);
+        if let Some(txids) = self.unplaced_blocks.remove(&hash) {
+            self.add_block(hash, &txids);
+        }
+    }
+
+    /// Returns the height of an indexed header
+    pub fn height_of(&self, hash: &[u8; 32]) -> Option<u32> {
+        self.heights.get(hash).copied()
+    }
+
+    /// Records the txs confirmed by a block. If its header isn't indexed yet
+    /// they are placed once it is.
+    pub fn add_block(&mut self, hash: [u8; 32], txids: &[[u8; 32]]) {
+        match self.height_of(&hash) {
+            Some(height) => {
+                for txid in txids {
+                    self.coin_heights.insert(*txid, height);
+                }
+            }
+            None => {
+                self.unplaced_blocks.insert(hash, txids.to_vec());
+            }
+        }
+    }
+
+    /// Returns the height of the block that confirmed a tx
+    pub fn coin_height(&self, txid: &[u8; 32]) -> Option<u32> {
+        self.coin_heights.get(txid).copied()
+    }
+
+    /// Returns the median of the times of the last 11 blocks up to `height` (BIP113)
+    pub fn median_time_past(&self, height: u32) -> Option<u32> {
+        let first = height.saturating_sub(MEDIAN_TIME_SPAN - 1);
+        let mut times: Vec<u32> = (first..=height)
+            .filter_map(|height| self.times.get(&height).copied())
+            .collect();
+        if times.is_empty() {
+            return None;
+        }
+        times.sort();
+        Some(times[times.len() / 2])
+    }
+
+    /// Returns true if the tx can be included in a block at `height`: its nLockTime is final
+    /// and the BIP68 relative locktimes of its inputs are satisfied.
+    /// `pending` are the txids of the outputs it spends that aren't confirmed before `height`.
+    /// Coins whose confirmation is unknown are older than any relative locktime.
+    pub fn is_final(&self, tx: &TXMessage, height: u32, pending: &[[u8; 32]]) -> bool {
+        let median_time_past = self.median_time_past(height.saturating_sub(1)).unwrap_or(0);
+        if !is_final_tx(tx, height, median_time_past) {
+            return false;
+        }
+        let coins: Vec<Option<(u32, u32)>> = tx
+            .get_input()
+            .iter()
+            .map(|input| {
+                let hash = input.get_outpoint().get_hash();
+                let coin_height = if pending.contains(&hash) {
+                    height
+                } else {
+                    self.coin_height(&hash)?
+                };
+                // BIP68 measures time from the median time past of the block before the coin's block
+                let coin_time = self
+                    .median_time_past(coin_height.saturating_sub(1))
+                    .unwrap_or(0);
+                Some((coin_height, coin_time))
+            })
+            .collect();
+        let locks = sequence_locks(tx, &coins);
+        check_sequence_locks(locks, height, median_time_past)
+    }
+}
+
+/// Returns true if the absolute locktime (nLockTime) of the tx is satisfied at `height`,
+/// comparing time based locktimes against the median time past of the previous block
+pub fn is_final_tx(tx: &TXMessage, height: u32, median_time_past: u32) -> bool {
+    let lock_time = tx.time;
+    if lock_time == 0 {
+        return true;
+    }
+    let limit = if lock_time < LOCKTIME_THRESHOLD {
+        height
+    } else {
+        median_time_past
+    };
+    if lock_time < limit {
+        return true;
+    }
+    tx.get_input()
+        .iter()
+        .all(|input| input.get_sequence_number() == SEQUENCE_FINAL)
+}
+
+/// Returns the last height and time (-1 when there's none) at which the BIP68 relative
+/// locktimes of the tx are still unsatisfied.
+/// `coins` has, for every input, the height and median time past before the block that
+/// confirmed the spent output, or None if the output is too old to matter
+pub fn sequence_locks(tx: &TXMessage, coins: &[Option<(u32, u32)>]) -> (i64, i64) {
+    let mut min_height: i64 = -1;
+    let mut min_time: i64 = -1;
+    if tx.get_version() < 2 {
+        return (min_height, min_time);
+    }
+    for (input, coin) in tx.get_input().iter().zip(coins) {
+        let sequence = input.get_sequence_number();
+        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            continue;
+        }
+        let (coin_height, coin_time) = match coin {
+            Some(coin) => *coin,
+            None => continue,
+        };
+        let value = (sequence & SEQUENCE_LOCKTIME_MASK) as i64;
+        if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            let time = coin_time as i64 + (value << SEQUENCE_LOCKTIME_GRANULARITY) - 1;
+            min_time = min_time.max(time);
+        } else {
+            min_height = min_height.max(coin_height as i64 + value - 1);
+        }
+    }
+    (min_height, min_time)
+}
+
+/// Returns true if a block at `height` whose previous block has `median_time_past`
+/// satisfies the locks returned by `sequence_locks`
+pub fn check_sequence_locks(locks: (i64, i64), height: u32, median_time_past: u32) -> bool {
+    locks.0 < height as i64 && locks.1 < median_time_past as i64
+}
+
+/// BIP112: returns true if the input `index` of the tx satisfies an OP_CHECKSEQUENCEVERIFY
+/// requiring `required`
+pub fn check_sequence_verify(tx: &TXMessage, index: usize, required: i64) -> bool {
+    if required < 0 {
+        return false;
+    }
+    let required = required as u64;
+    if required & SEQUENCE_LOCKTIME_DISABLE_FLAG as u64 != 0 {
+        return true;
+    }
+    if tx.get_version() < 2 {
+        return false;
+    }
+    let sequence = match tx.get_input().get(index) {
+        Some(input) => input.get_sequence_number(),
+        None => return false,
+    };
+    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+        return false;
+    }
+    let mask = (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) as u64;
+    let required = required & mask;
+    let sequence = (sequence & (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK)) as u64;
+    let type_flag = SEQUENCE_LOCKTIME_TYPE_FLAG as u64;
+    if (required & type_flag) != (sequence & type_flag) {
+        return false;
+    }
+    required <= sequence
+}
+
+/// Returns true if the input `index` satisfies every OP_CHECKSEQUENCEVERIFY executed when
+/// spending `script`, the script of the output it spends. For P2SH and P2WSH outputs the
+/// redeem or witness script is the one run
+pub fn check_script_sequence_locks(tx: &TXMessage, index: usize, script: &[u8]) -> bool {
+    let script_sig = match tx.get_input().get(index) {
+        Some(input) => input.get_script(),
+        None => return false,
+    };
+    let (script, stack) = executed_script(script, &script_sig, tx.get_witness(index));
+    csv_requirements(&script, stack)
+        .iter()
+        .all(|required| check_sequence_verify(tx, index, *required))
+}
+
+/// Stack of a script being run. Only the items on top of the last operation that isn't
+/// evaluated here are known
+#[derive(Debug)]
+struct ScriptStack {
+    items: Vec<Vec<u8>>,
+    complete: bool,
+}
+
+impl ScriptStack {
+    fn new(items: Vec<Vec<u8>>) -> ScriptStack {
+        ScriptStack {
+            items,
+            complete: true,
+        }
+    }
+
+    /// Returns the item on top, None if it isn't known or the stack is empty
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        self.items.pop()
+    }
+
+    /// Forgets every item, after an operation whose result isn't evaluated
+    fn forget(&mut self) {
+        self.items.clear();
+        self.complete = false;
+    }
+}
+
+/// Returns the script run when spending an output with the given script signature and
+/// witness, with the stack it starts with
+fn executed_script(
+    script: &[u8],
+    script_sig: &[u8],
+    witness: Vec<Vec<u8>>,
+) -> (Vec<u8>, ScriptStack) {
+    let mut stack = pushed_items(script_sig);
+    let mut script = script.to_vec();
+    if is_p2sh(&script) {
+        script = match stack.pop() {
+            Some(redeem_script) => redeem_script,
+            None => return (vec![], stack),
+        };
+    }
+    if is_p2wsh(&script) {
+        let mut witness = witness;
+        script = witness.pop().unwrap_or_default();
+        stack = ScriptStack::new(witness);
+    }
+    (script, stack)
+}
+
+fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23 && script[0] == OP_HASH160 && script[1] == 0x14 && script[22] == OP_EQUAL
+}
+
+fn is_p2wsh(script: &[u8]) -> bool {
+    script.len() == 34 && script[0] == OP_0 && script[1] == 0x20
+}
+
+/// Returns the items pushed by a script signature, unknown if it does anything else
+fn pushed_items(script_sig: &[u8]) -> ScriptStack {
+    let mut stack = ScriptStack::new(vec![]);
+    let mut i = 0;
+    while let Some((_, data)) = next_operation(script_sig, &mut i) {
+        match data {
+            Some(data) => stack.items.push(data),
+            None => stack.forget(),
+        }
+    }
+    stack
+}
+
+/// Returns the arguments of each OP_CHECKSEQUENCEVERIFY executed when running the script
+/// on the stack. Only the branches known to be taken are checked: those whose condition
+/// is computed by operations not evaluated here could be skipped by a valid spend
+fn csv_requirements(script: &[u8], mut stack: ScriptStack) -> Vec<i64> {
+    let mut requirements = vec![];
+    // Whether each open branch is executed, None if it isn't known
+    let mut branches: Vec<Option<bool>> = vec![];
+    let mut i = 0;
+    while let Some((opcode, data)) = next_operation(script, &mut i) {
+        let skipped = branches.contains(&Some(false));
+        let executed = branches.iter().all(|branch| *branch == Some(true));
+        match opcode {
+            OP_IF | OP_NOTIF => {
+                let branch = match (skipped, executed) {
+                    (true, _) => Some(false),
+                    (false, true) => stack
+                        .pop()
+                        .map(|condition| cast_to_bool(&condition) == (opcode == OP_IF)),
+                    (false, false) => None,
+                };
+                if branch.is_none() {
+                    stack.forget();
+                }
+                branches.push(branch);
+            }
+            OP_ELSE => {
+                if let Some(branch) = branches.last_mut() {
+                    *branch = branch.map(|taken| !taken);
+                }
+            }
+            OP_ENDIF => {
+                branches.pop();
+            }
+            _ if skipped => {}
+            _ if !executed => stack.forget(),
+            OP_DROP => {
+                stack.pop();
+            }
+            OP_CHECKSEQUENCEVERIFY => match stack.items.last() {
+                // A CSV on an empty stack or with an invalid number fails, -1 never passes
+                Some(required) => requirements.push(script_number(required).unwrap_or(-1)),
+                None if stack.complete => requirements.push(-1),
+                None => {}
+            },
+            _ => match data {
+                Some(data) => stack.items.push(data),
+                None => stack.forget(),
+            },
+        }
+    }
+    requirements
+}
+
+/// Reads the operation at `i` and moves past it. Returns the opcode and the data it pushes
+/// if it is a push operation
+fn next_operation(script: &[u8], i: &mut usize) -> Option<(u8, Option<Vec<u8>>)> {
+    let opcode = *script.get(*i)?;
+    *i += 1;
+    let data = match opcode {
+        OP_0 => Some(vec![]),
+        0x01..=0x4b => {
+            let end = (*i + opcode as usize).min(script.len());
+            let data = script[*i..end].to_vec();
+            *i = end;
+            Some(data)
+        }
+        0x4c..=OP_PUSHDATA4 => {
+            let length_size = 1 << (opcode - 0x4c);
+            let mut length = 0;
+            for (n, byte) in script.iter().skip(*i).take(length_size).enumerate() {
+                length |= (*byte as usize) << (8 * n);
+            }
+            let start = (*i + length_size).min(script.len());
+            let end = (start + length).min(script.len());
+            *i = end;
+            Some(script[start..end].to_vec())
+        }
+        OP_1NEGATE => Some(vec![0x81]),
+        OP_1..=OP_16 => Some(vec![opcode - OP_1 + 1]),
+        _ => None,
+    };
+    Some((opcode, data))
+}
+
+/// Returns false for zero, negative zero and empty items, true for anything else
+fn cast_to_bool(item: &[u8]) -> bool {
+    match item.split_last() {
+        Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || (*last != 0 && *last != 0x80),
+        None => false,
+    }
+}
+
+/// Decodes a minimally encoded script number of up to 5 bytes, empty is zero
+fn script_number(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() {
+        return Some(0);
+    }
+    if bytes.len() > 5 {
+        return None;
+    }
+    let mut number: i64 = 0;
+    for (n, byte) in bytes.iter().enumerate() {
+        number |= (*byte as i64) << (8 * n);
+    }
+    let last = bytes[bytes.len() - 1];
+    if last & 0x80 != 0 {
+        number &= !(0x80_i64 << (8 * (bytes.len() - 1)));
+        number = -number;
+    }
+    Some(number)
+}
+
+#[cfg(test)]
+mod locktime_tests {
+    use super::*;
+    use crate::message_structs::{
+        compact_size::CompactSize, input::Input, outpoint::Outpoint, output::Output,
+    };
+
+    fn tx_with(version: i32, sequence: u32, lock_time: u32) -> TXMessage {
+        let input = Input::new(
+            Outpoint::new([1; 32], 0),
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            sequence,
+        );
+        let output = Output::new(1000, CompactSize::from_usize_to_compact_size(1), vec![0x51]);
+        TXMessage::new(
+            version,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![input],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![output],
+            lock_time,
+        )
+    }
+
+    #[test]
+    fn test_height_locktime() {
+        let tx = tx_with(1, 0, 100);
+        assert!(!is_final_tx(&tx, 100, 0));
+        assert!(is_final_tx(&tx, 101, 0));
+    }
+
+    #[test]
+    fn test_time_locktime_uses_median_time_past() {
+        let tx = tx_with(1, 0, 1_700_000_000);
+        assert!(!is_final_tx(&tx, 1_000_000, 1_700_000_000));
+        assert!(is_final_tx(&tx, 1, 1_700_000_001));
+    }
+
+    #[test]
+    fn test_final_sequences_disable_locktime() {
+        let tx = tx_with(1, SEQUENCE_FINAL, 100);
+        assert!(is_final_tx(&tx, 1, 0));
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let mut chain = ChainTimes::new();
+        for height in 1..=11 {
+            chain.add_header([height as u8; 32], height, 1000 + height * 10);
+        }
+        chain.add_header([12; 32], 12, 2000);
+        assert_eq!(chain.median_time_past(11), Some(1060));
+        assert_eq!(chain.median_time_past(12), Some(1070));
+    }
+
+    #[test]
+    fn test_relative_height_lock() {
+        let tx = tx_with(2, 10, 0);
+        let locks = sequence_locks(&tx, &[Some((100, 0))]);
+        assert!(!check_sequence_locks(locks, 109, 0));
+        assert!(check_sequence_locks(locks, 110, 0));
+    }
+
+    #[test]
+    fn test_relative_time_lock() {
+        let tx = tx_with(2, SEQUENCE_LOCKTIME_TYPE_FLAG | 2, 0);
+        let locks = sequence_locks(&tx, &[Some((100, 1_000_000))]);
+        assert!(!check_sequence_locks(locks, 200, 1_001_023));
+        assert!(check_sequence_locks(locks, 200, 1_001_024));
+    }
+
+    #[test]
+    fn test_relative_lock_ignored_for_version_1() {
+        let tx = tx_with(1, 10, 0);
+        let locks = sequence_locks(&tx, &[Some((100, 0))]);
+        assert_eq!(locks, (-1, -1));
+    }
+
+    #[test]
+    fn test_chain_times_places_blocks_and_checks_finality() {
+        let mut chain = ChainTimes::new();
+        chain.add_block([100; 32], &[[1; 32]]);
+        for height in 1..=110 {
+            chain.add_header([height as u8; 32], height, height);
+        }
+        let tx = tx_with(2, 10, 0);
+        assert_eq!(chain.coin_height(&[1; 32]), Some(100));
+        assert!(!chain.is_final(&tx, 109, &[]));
+        assert!(chain.is_final(&tx, 110, &[]));
+        assert!(!chain.is_final(&tx, 110, &[[1; 32]]));
+    }
+
+    #[test]
+    fn test_check_sequence_verify() {
+        let script = vec![0x01, 0x0a, OP_CHECKSEQUENCEVERIFY, 0x75, 0x51];
+        assert_eq!(
+            csv_requirements(&script, ScriptStack::new(vec![])),
+            vec![10]
+        );
+        assert!(check_script_sequence_locks(&tx_with(2, 10, 0), 0, &script));
+        assert!(!check_script_sequence_locks(&tx_with(2, 9, 0), 0, &script));
+        assert!(!check_script_sequence_locks(&tx_with(1, 10, 0), 0, &script));
+        let time_sequence = SEQUENCE_LOCKTIME_TYPE_FLAG | 10;
+        assert!(!check_script_sequence_locks(
+            &tx_with(2, time_sequence, 0),
+            0,
+            &script
+        ));
+    }
+
+    #[test]
+    fn test_sequence_locks_of_the_executed_branch() {
+        // OP_IF <key> OP_CHECKSIG OP_ELSE 10 OP_CSV OP_DROP <key> OP_CHECKSIG OP_ENDIF
+        let mut script = vec![OP_IF, 0x21];
+        script.extend_from_slice(&[2; 33]);
+        script.extend_from_slice(&[0xac, OP_ELSE, 0x01, 0x0a, OP_CHECKSEQUENCEVERIFY, OP_DROP]);
+        script.push(0x21);
+        script.extend_from_slice(&[3; 33]);
+        script.extend_from_slice(&[0xac, OP_ENDIF]);
+        let spend = |condition: Vec<u8>| ScriptStack::new(vec![vec![0x30; 71], condition]);
+        assert!(csv_requirements(&script, spend(vec![1])).is_empty());
+        assert_eq!(csv_requirements(&script, spend(vec![])), vec![10]);
+
+        // A condition computed by the script isn't known, the branch isn't checked
+        let mut hash_condition = vec![0x76, OP_HASH160, 0x14];
+        hash_condition.extend_from_slice(&[4; 20]);
+        hash_condition.push(OP_EQUAL);
+        hash_condition.extend_from_slice(&script);
+        assert!(csv_requirements(&hash_condition, spend(vec![])).is_empty());
+    }
+
+    #[test]
+    fn test_sequence_locks_of_p2sh_and_p2wsh_spends() {
+        let witness_script = vec![0x01, 0x0a, OP_CHECKSEQUENCEVERIFY, OP_DROP, 0x51];
+        let mut p2wsh = vec![OP_0, 0x20];
+        p2wsh.extend_from_slice(&[5; 32]);
+        let mut p2sh = vec![OP_HASH160, 0x14];
+        p2sh.extend_from_slice(&[6; 20]);
+        p2sh.push(OP_EQUAL);
+
+        let witness_spend = |sequence| {
+            let mut tx = tx_with(2, sequence, 0);
+            tx.set_witness(0, vec![witness_script.clone()]);
+            tx
+        };
+        assert!(check_script_sequence_locks(&witness_spend(10), 0, &p2wsh));
+        assert!(!check_script_sequence_locks(&witness_spend(9), 0, &p2wsh));
+
+        let script_sig_spend = |sequence| {
+            let mut script_sig = vec![witness_script.len() as u8];
+            script_sig.extend_from_slice(&witness_script);
+            let input = Input::new(
+                Outpoint::new([1; 32], 0),
+                CompactSize::from_usize_to_compact_size(script_sig.len()),
+                script_sig,
+                sequence,
+            );
+            TXMessage::new(
+                2,
+                CompactSize::from_usize_to_compact_size(1),
+                vec![input],
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                0,
+            )
+        };
+        assert!(check_script_sequence_locks(&script_sig_spend(10), 0, &p2sh));
+        assert!(!check_script_sequence_locks(&script_sig_spend(9), 0, &p2sh));
+    }
+}
@@ -1,3 +1,5 @@
 pub mod hashes;
+pub mod locktime;
 pub mod merkles;
+pub mod taproot;
 pub mod validations;
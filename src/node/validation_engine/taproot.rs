@@ -0,0 +1,184 @@
+use crate::message_structs::output::Output;
+use crate::message_structs::tx_message::{TXMessage, TAPROOT_SIGHASH_DEFAULT};
+use crate::node::validation_engine::hashes::tagged_hash;
+use crate::utils::script_tools::is_p2tr;
+use rand::RngCore;
+use secp256k1::{schnorr, KeyPair, Message, PublicKey, Scalar, Secp256k1, XOnlyPublicKey};
+use std::error::Error;
+
+/// First byte of the annex, an optional last witness element of taproot spends (BIP341)
+const ANNEX_TAG: u8 = 0x50;
+const SCHNORR_SIGNATURE_SIZE: usize = 64;
+
+/// BIP341 tweak of an internal key committing to no script tree
+fn tap_tweak(internal_key: &XOnlyPublicKey) -> Result<Scalar, Box<dyn Error>> {
+    let tweak = tagged_hash("TapTweak", &internal_key.serialize());
+    Ok(Scalar::from_be_bytes(tweak)?)
+}
+
+/// Returns the x-only output key of a key path only taproot output of the public key
+/// # Errors
+/// Returns an error if the public key is invalid
+pub fn taproot_output_key(public_key: &[u8]) -> Result<[u8; 32], Box<dyn Error>> {
+    let secp = Secp256k1::verification_only();
+    let (internal_key, _) = PublicKey::from_slice(public_key)?.x_only_public_key();
+    let tweak = tap_tweak(&internal_key)?;
+    let (output_key, _) = internal_key.add_tweak(&secp, &tweak)?;
+    Ok(output_key.serialize())
+}
+
+/// Returns the tweaked keypair that signs for the taproot output key of the private key
+/// # Errors
+/// Returns an error if the private key is invalid
+pub fn tweak_keypair(private_key: &[u8]) -> Result<KeyPair, Box<dyn Error>> {
+    let secp = Secp256k1::new();
+    let keypair = KeyPair::from_seckey_slice(&secp, private_key)?;
+    let (internal_key, _) = keypair.x_only_public_key();
+    let tweak = tap_tweak(&internal_key)?;
+    Ok(keypair.add_xonly_tweak(&secp, &tweak)?)
+}
+
+/// BIP340 signature of a signature hash, with random auxiliary data
+pub fn sign_schnorr(keypair: &KeyPair, sig_hash: &[u8; 32]) -> Vec<u8> {
+    let secp = Secp256k1::signing_only();
+    let message = match Message::from_slice(sig_hash) {
+        Ok(message) => message,
+        Err(_) => return vec![],
+    };
+    let mut aux_rand = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut aux_rand);
+    let signature = secp.sign_schnorr_with_aux_rand(&message, keypair, &aux_rand);
+    let bytes: &[u8; SCHNORR_SIGNATURE_SIZE] = signature.as_ref();
+    bytes.to_vec()
+}
+
+/// Verifies a BIP340 signature of the message under the x-only public key
+pub fn verify_schnorr(signature: &[u8], message: &[u8], public_key: &[u8]) -> bool {
+    let secp = Secp256k1::verification_only();
+    let (signature, message, public_key) = match (
+        schnorr::Signature::from_slice(signature),
+        Message::from_slice(message),
+        XOnlyPublicKey::from_slice(public_key),
+    ) {
+        (Ok(signature), Ok(message), Ok(public_key)) => (signature, message, public_key),
+        _ => return false,
+    };
+    secp.verify_schnorr(&signature, &message, &public_key)
+        .is_ok()
+}
+
+/// Splits the annex off a taproot witness, it is the last element if there are
+/// at least two and it starts with ANNEX_TAG
+fn split_annex(mut witness: Vec<Vec<u8>>) -> (Vec<Vec<u8>>, Option<Vec<u8>>) {
+    if witness.len() > 1 && witness.last().and_then(|item| item.first()) == Some(&ANNEX_TAG) {
+        let annex = witness.pop();
+        return (witness, annex);
+    }
+    (witness, None)
+}
+
+/// Checks the key path spend of the input `index`, which spends the P2TR output
+/// `prevouts[index]`. `prevouts` are the outputs spent by every input
+pub fn check_key_path_spend(tx: &TXMessage, index: usize, prevouts: &[Output]) -> bool {
+    let (witness, annex) = split_annex(tx.get_witness(index));
+    let signature = match witness.as_slice() {
+        [signature] => signature,
+        _ => return false,
+    };
+    let (signature, hash_type) = match signature.len() {
+        SCHNORR_SIGNATURE_SIZE => (&signature[..], TAPROOT_SIGHASH_DEFAULT),
+        65 if signature[64] != TAPROOT_SIGHASH_DEFAULT => (&signature[..64], signature[64]),
+        _ => return false,
+    };
+    let sig_hash = match tx.taproot_sig_hash(index, prevouts, hash_type, annex.as_deref()) {
+        Some(sig_hash) => sig_hash,
+        None => return false,
+    };
+    match prevouts.get(index) {
+        Some(prevout) if is_p2tr(&prevout.get_script()) => {
+            verify_schnorr(signature, &sig_hash, &prevout.get_script()[2..])
+        }
+        _ => false,
+    }
+}
+
+/// Checks the key path spends of the P2TR outputs spent by the transaction.
+/// `prevouts` are the outputs spent by each input, None if they aren't known.
+/// Inputs whose witness was stripped and script path spends can't be checked and are accepted
+pub fn check_taproot_spends(tx: &TXMessage, prevouts: &[Option<Output>]) -> bool {
+    let prevouts: Vec<Output> = match prevouts.iter().cloned().collect() {
+        Some(prevouts) => prevouts,
+        None => return true,
+    };
+    for (index, prevout) in prevouts.iter().enumerate() {
+        if !is_p2tr(&prevout.get_script()) {
+            continue;
+        }
+        let (witness, _) = split_annex(tx.get_witness(index));
+        if witness.len() != 1 {
+            continue;
+        }
+        if !check_key_path_spend(tx, index, &prevouts) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod taproot_tests {
+    use super::*;
+    use crate::utils::array_tools::{cast_str_to_bytes_vec, u8_vec_to_hex_string};
+
+    // BIP340 test vector 0
+    #[test]
+    fn test_verify_schnorr_signature() {
+        let public_key = cast_str_to_bytes_vec(
+            "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+        )
+        .unwrap();
+        let signature = cast_str_to_bytes_vec(
+            "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0",
+        )
+        .unwrap();
+        let message = [0u8; 32];
+        assert!(verify_schnorr(&signature, &message, &public_key));
+        assert!(!verify_schnorr(&signature, &[1u8; 32], &public_key));
+    }
+
+    // BIP341 wallet test vector of a key path only output
+    #[test]
+    fn test_taproot_output_key() {
+        let mut public_key = vec![0x02];
+        public_key.extend(
+            cast_str_to_bytes_vec(
+                "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            u8_vec_to_hex_string(&taproot_output_key(&public_key).unwrap()),
+            "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"
+        );
+    }
+
+    #[test]
+    fn test_tweaked_keypair_signs_for_the_output_key() {
+        let private_key = [7u8; 32];
+        let secp = Secp256k1::new();
+        let public_key = KeyPair::from_seckey_slice(&secp, &private_key)
+            .unwrap()
+            .public_key()
+            .serialize();
+        let keypair = tweak_keypair(&private_key).unwrap();
+        let output_key = taproot_output_key(&public_key).unwrap();
+        assert_eq!(keypair.x_only_public_key().0.serialize(), output_key);
+
+        let sig_hash = [3u8; 32];
+        let signature = sign_schnorr(&keypair, &sig_hash);
+        assert!(verify_schnorr(&signature, &sig_hash, &output_key));
+        let mut tampered = signature.clone();
+        tampered[10] ^= 1;
+        assert!(!verify_schnorr(&tampered, &sig_hash, &output_key));
+    }
+}
@@ -0,0 +1,692 @@
+use super::descriptor::SpendingScripts;
+use crate::message_structs::{compact_size::CompactSize, outpoint::Outpoint, output::Output};
+use crate::utils::script_tools::{is_p2sh, is_p2tr, is_p2wpkh, is_p2wsh, multisig_keys, push_data};
+use rand::{seq::SliceRandom, Rng};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Smallest change output that isn't dust, in satoshis
+pub const DUST_LIMIT: i64 = 546;
+/// Fee rate (sat/vB) the change is expected to be spent at
+pub const LONG_TERM_FEE_RATE: u64 = 10;
+/// Branches branch-and-bound explores before giving up
+const BNB_MAX_TRIES: usize = 100_000;
+/// Random subsets knapsack tries
+const KNAPSACK_ITERATIONS: usize = 1000;
+/// Outpoint and sequence of an input
+const INPUT_OVERHEAD: usize = 40;
+/// Push of a DER signature with its sighash byte
+const SIGNATURE_PUSH: usize = 73;
+/// Push of a compressed public key
+const PUBKEY_PUSH: usize = 34;
+
+/// Size in vbytes of the input spending the script once signed. P2SH and P2WSH scripts
+/// whose redeem or witness script isn't known are estimated as 2-of-3 multisig
+pub fn input_vsize(script: &[u8]) -> usize {
+    if is_p2wpkh(script) {
+        68
+    } else if is_p2tr(script) {
+        58
+    } else if is_p2wsh(script) {
+        105
+    } else if is_p2sh(script) {
+        297
+    } else {
+        148
+    }
+}
+
+/// Size in vbytes of the input spending the script once signed, P2SH and P2WSH inputs
+/// are sized from the redeem and witness scripts behind their hash
+pub fn spending_vsize(script: &[u8], spending_scripts: &SpendingScripts) -> usize {
+    let (script_sig, witness) = match spending_scripts {
+        // sh(wpkh): the script sig pushes the P2WPKH script, the witness has the signature and key
+        (Some(redeem_script), None) if is_p2sh(script) && is_p2wpkh(redeem_script) => (
+            push_data(redeem_script).len(),
+            1 + SIGNATURE_PUSH + PUBKEY_PUSH,
+        ),
+        (Some(redeem_script), None) if is_p2sh(script) => match multisig_keys(redeem_script) {
+            // OP_0 (CHECKMULTISIG bug), the signatures and the redeem script
+            Some((required, _)) => (
+                1 + required * SIGNATURE_PUSH + push_data(redeem_script).len(),
+                0,
+            ),
+            None => return input_vsize(script),
+        },
+        (None, Some(witness_script)) if is_p2wsh(script) => match multisig_keys(witness_script) {
+            // Items count, empty item, the signatures and the witness script
+            Some((required, _)) => (
+                0,
+                CompactSize::from_usize_to_compact_size(required + 2).size()
+                    + 1
+                    + required * SIGNATURE_PUSH
+                    + CompactSize::from_usize_to_compact_size(witness_script.len()).size()
+                    + witness_script.len(),
+            ),
+            None => return input_vsize(script),
+        },
+        _ => return input_vsize(script),
+    };
+    let base =
+        INPUT_OVERHEAD + CompactSize::from_usize_to_compact_size(script_sig).size() + script_sig;
+    (base * 4 + witness).div_ceil(4)
+}
+
+/// Size in vbytes of an output paying to the script
+pub fn output_vsize(script: &[u8]) -> usize {
+    // value + script length + script
+    8 + 1 + script.len()
+}
+
+/// Utxo that may be spent, with what it adds to the transaction once the fee of spending it is paid
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub utxo: (Outpoint, Output),
+    pub effective_value: i64,
+    index: usize,       // position among the utxos
+    fee: i64,           // fee of spending it now
+    long_term_fee: i64, // fee of spending it at the long term fee rate
+}
+
+/// What the selected utxos must pay for and the fee rates (sat/vB) of spending them
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectionParams {
+    pub target: i64, // amount sent plus the fee of the transaction without inputs or change
+    pub fee_rate: u64,
+    pub long_term_fee_rate: u64, // expected fee rate when the change is spent
+    pub change_fee: i64,         // fee of adding the change output
+    pub cost_of_change: i64,     // fee of adding the change output and spending it later
+    pub min_change: i64,         // smaller change goes to the fee
+    pub input_vsizes: HashMap<Vec<u8>, usize>, // sizes of the inputs spending the scripts, if known
+}
+
+impl SelectionParams {
+    /// Selection of the utxos paying `amount` at `fee_rate`, `base_vsize` is the size of the
+    /// transaction without inputs or change and the change goes to `change_script`.
+    /// Inputs spending the scripts of `input_vsizes` take the size it has for them
+    pub fn new(
+        amount: i64,
+        base_vsize: usize,
+        fee_rate: u64,
+        long_term_fee_rate: u64,
+        change_script: &[u8],
+        input_vsizes: &HashMap<Vec<u8>, usize>,
+    ) -> SelectionParams {
+        let change_fee = (output_vsize(change_script) as u64 * fee_rate) as i64;
+        let change_vsize = Self::vsize_in(input_vsizes, change_script);
+        let spend_change_fee = (change_vsize as u64 * long_term_fee_rate) as i64;
+        SelectionParams {
+            target: amount + (base_vsize as u64 * fee_rate) as i64,
+            fee_rate,
+            long_term_fee_rate,
+            change_fee,
+            cost_of_change: change_fee + spend_change_fee,
+            min_change: DUST_LIMIT,
+            input_vsizes: input_vsizes.clone(),
+        }
+    }
+
+    /// Selection of utxos covering a target that already has the whole fee, spending more
+    /// inputs costs nothing and any excess goes back as change
+    pub fn fixed_fee(target: i64) -> SelectionParams {
+        SelectionParams {
+            target,
+            fee_rate: 0,
+            long_term_fee_rate: 0,
+            change_fee: 0,
+            cost_of_change: 0,
+            min_change: 1,
+            input_vsizes: HashMap::new(),
+        }
+    }
+
+    /// Size in vbytes of the input spending the script
+    pub fn input_vsize(&self, script: &[u8]) -> usize {
+        Self::vsize_in(&self.input_vsizes, script)
+    }
+
+    fn vsize_in(input_vsizes: &HashMap<Vec<u8>, usize>, script: &[u8]) -> usize {
+        match input_vsizes.get(script) {
+            Some(vsize) => *vsize,
+            None => input_vsize(script),
+        }
+    }
+
+    /// Utxos worth spending at the fee rate, those costing more than their value are left out
+    pub fn candidates(&self, utxos: &[(Outpoint, Output)]) -> Vec<Candidate> {
+        self.all_candidates(utxos)
+            .into_iter()
+            .filter(|candidate| candidate.effective_value > 0)
+            .collect()
+    }
+
+    fn all_candidates(&self, utxos: &[(Outpoint, Output)]) -> Vec<Candidate> {
+        utxos
+            .iter()
+            .enumerate()
+            .map(|(index, (outpoint, output))| {
+                let vsize = self.input_vsize(&output.get_script()) as u64;
+                let fee = (vsize * self.fee_rate) as i64;
+                Candidate {
+                    utxo: (*outpoint, output.clone()),
+                    effective_value: output.value - fee,
+                    index,
+                    fee,
+                    long_term_fee: (vsize * self.long_term_fee_rate) as i64,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Utxos the user chose to spend or to keep unspent
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoinControl {
+    selected: Vec<Outpoint>, // if there are any, transactions spend all of them and nothing else
+    frozen: Vec<Outpoint>,   // never spent
+}
+
+impl CoinControl {
+    /// Spends the outpoint in the next transactions, unfreezing it
+    pub fn select(&mut self, outpoint: Outpoint) {
+        self.frozen.retain(|frozen| *frozen != outpoint);
+        if !self.is_selected(&outpoint) {
+            self.selected.push(outpoint);
+        }
+    }
+
+    /// Keeps the outpoint unspent, unselecting it
+    pub fn freeze(&mut self, outpoint: Outpoint) {
+        self.selected.retain(|selected| *selected != outpoint);
+        if !self.is_frozen(&outpoint) {
+            self.frozen.push(outpoint);
+        }
+    }
+
+    /// Leaves the outpoint to coin selection
+    pub fn release(&mut self, outpoint: &Outpoint) {
+        self.selected.retain(|selected| selected != outpoint);
+        self.frozen.retain(|frozen| frozen != outpoint);
+    }
+
+    pub fn is_selected(&self, outpoint: &Outpoint) -> bool {
+        self.selected.contains(outpoint)
+    }
+
+    pub fn is_frozen(&self, outpoint: &Outpoint) -> bool {
+        self.frozen.contains(outpoint)
+    }
+
+    /// True if the transactions must spend the selected utxos
+    pub fn has_selection(&self) -> bool {
+        !self.selected.is_empty()
+    }
+
+    /// Utxos the transactions may spend: the selected ones if there are any, never the frozen
+    pub fn spendable(&self, utxos: &[(Outpoint, Output)]) -> Vec<(Outpoint, Output)> {
+        utxos
+            .iter()
+            .filter(|(outpoint, _)| !self.has_selection() || self.is_selected(outpoint))
+            .filter(|(outpoint, _)| !self.is_frozen(outpoint))
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets the outpoints that aren't among the utxos anymore
+    pub fn retain_utxos(&mut self, utxos: &[(Outpoint, Output)]) {
+        let is_utxo = |outpoint: &Outpoint| utxos.iter().any(|(utxo, _)| utxo == outpoint);
+        self.selected.retain(is_utxo);
+        self.frozen.retain(is_utxo);
+    }
+}
+
+/// Utxos chosen to be spent, the change to create and how wasteful the choice is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub utxos: Vec<(Outpoint, Output)>,
+    pub change: i64, // 0 if there's no change output, the excess goes to the fee
+    pub waste: i64,
+}
+
+impl Selection {
+    /// Change and waste of spending the candidates, None if they don't reach the target.
+    /// The waste is what paying now costs over paying at the long term fee rate, plus the
+    /// cost of the change, or the excess given to the fee when there's none
+    pub fn new(candidates: &[Candidate], params: &SelectionParams) -> Option<Selection> {
+        let value: i64 = candidates.iter().map(|c| c.effective_value).sum();
+        let excess = value - params.target;
+        if excess < 0 || candidates.is_empty() {
+            return None;
+        }
+        let timing_waste: i64 = candidates.iter().map(|c| c.fee - c.long_term_fee).sum();
+        let (change, waste) = match excess - params.change_fee {
+            change if change >= params.min_change => (change, timing_waste + params.cost_of_change),
+            _ => (0, timing_waste + excess),
+        };
+        // Spent in the order the utxos were given
+        let mut ordered: Vec<&Candidate> = candidates.iter().collect();
+        ordered.sort_by_key(|c| c.index);
+        Some(Selection {
+            utxos: ordered.into_iter().map(|c| c.utxo.clone()).collect(),
+            change,
+            waste,
+        })
+    }
+}
+
+/// Strategy choosing which candidates to spend
+pub trait CoinSelection {
+    /// Returns the candidates to spend, None if the strategy finds no solution
+    fn select(&self, candidates: &[Candidate], params: &SelectionParams) -> Option<Vec<Candidate>>;
+}
+
+/// Depth first search of a changeless solution whose excess is below the cost of change,
+/// keeping the one with the least waste
+pub struct BranchAndBound;
+/// Randomly builds subsets of the smaller candidates looking for the one closest to the
+/// target with change, or takes the smallest candidate covering it alone
+pub struct Knapsack;
+/// Spends the largest candidates first
+pub struct LargestFirst;
+/// Spends candidates in random order until the target with change is reached
+pub struct SingleRandomDraw;
+
+/// State of the branch-and-bound search
+struct BnbSearch<'a> {
+    sorted: &'a [Candidate],
+    params: &'a SelectionParams,
+    selected: Vec<usize>,
+    best: Option<(i64, Vec<usize>)>,
+    tries: usize,
+}
+
+impl BnbSearch<'_> {
+    fn search(&mut self, index: usize, value: i64, waste: i64, remaining: i64) {
+        if self.tries == 0 {
+            return;
+        }
+        self.tries -= 1;
+        let params = self.params;
+        if value > params.target + params.cost_of_change || value + remaining < params.target {
+            return;
+        }
+        // More inputs only add waste when the fee rate is above the long term one
+        if let Some((best_waste, _)) = &self.best {
+            if params.fee_rate > params.long_term_fee_rate && waste > *best_waste {
+                return;
+            }
+        }
+        if value >= params.target {
+            let waste = waste + value - params.target;
+            if self.best.as_ref().is_none_or(|(best, _)| waste < *best) {
+                self.best = Some((waste, self.selected.clone()));
+            }
+            return;
+        }
+        let candidate = match self.sorted.get(index) {
+            Some(candidate) => candidate,
+            None => return,
+        };
+        let remaining = remaining - candidate.effective_value;
+        self.selected.push(index);
+        self.search(
+            index + 1,
+            value + candidate.effective_value,
+            waste + candidate.fee - candidate.long_term_fee,
+            remaining,
+        );
+        self.selected.pop();
+        // Leaving this one out, equivalent candidates after it would give the same solutions
+        let mut next = index + 1;
+        let mut remaining = remaining;
+        while let Some(equivalent) = self.sorted.get(next) {
+            if equivalent.effective_value != candidate.effective_value
+                || equivalent.fee != candidate.fee
+            {
+                break;
+            }
+            remaining -= equivalent.effective_value;
+            next += 1;
+        }
+        self.search(next, value, waste, remaining);
+    }
+}
+
+impl CoinSelection for BranchAndBound {
+    fn select(&self, candidates: &[Candidate], params: &SelectionParams) -> Option<Vec<Candidate>> {
+        let mut sorted = candidates.to_vec();
+        sorted.sort_by_key(|c| Reverse(c.effective_value));
+        let mut search = BnbSearch {
+            sorted: &sorted,
+            params,
+            selected: vec![],
+            best: None,
+            tries: BNB_MAX_TRIES,
+        };
+        let total = sorted.iter().map(|c| c.effective_value).sum();
+        search.search(0, 0, 0, total);
+        let (_, selected) = search.best?;
+        Some(selected.into_iter().map(|i| sorted[i].clone()).collect())
+    }
+}
+
+impl CoinSelection for Knapsack {
+    fn select(&self, candidates: &[Candidate], params: &SelectionParams) -> Option<Vec<Candidate>> {
+        let target = params.target + params.change_fee + params.min_change;
+        if let Some(exact) = candidates
+            .iter()
+            .find(|c| c.effective_value == params.target)
+        {
+            return Some(vec![exact.clone()]);
+        }
+        let (mut smaller, larger): (Vec<Candidate>, Vec<Candidate>) = candidates
+            .iter()
+            .cloned()
+            .partition(|c| c.effective_value < target);
+        let lowest_larger = larger.into_iter().min_by_key(|c| c.effective_value);
+        let smaller_total: i64 = smaller.iter().map(|c| c.effective_value).sum();
+        if smaller_total == params.target {
+            return Some(smaller);
+        }
+        if smaller_total < target {
+            return match lowest_larger {
+                Some(candidate) => Some(vec![candidate]),
+                None if smaller_total >= params.target => Some(smaller),
+                None => None,
+            };
+        }
+        smaller.sort_by_key(|c| Reverse(c.effective_value));
+        let (best_total, best) = Self::best_subset(&smaller, target);
+        match lowest_larger {
+            Some(candidate) if candidate.effective_value <= best_total => Some(vec![candidate]),
+            _ => Some(
+                smaller
+                    .into_iter()
+                    .zip(best)
+                    .filter_map(|(candidate, included)| included.then_some(candidate))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl Knapsack {
+    /// Random subsets reaching the target, returns the smallest total found and its candidates
+    fn best_subset(sorted: &[Candidate], target: i64) -> (i64, Vec<bool>) {
+        let mut rng = rand::thread_rng();
+        let mut best = vec![true; sorted.len()];
+        let mut best_total: i64 = sorted.iter().map(|c| c.effective_value).sum();
+        for _ in 0..KNAPSACK_ITERATIONS {
+            if best_total == target {
+                break;
+            }
+            let mut included = vec![false; sorted.len()];
+            let mut total = 0;
+            let mut reached = false;
+            // The first pass includes candidates randomly, the second the ones left out
+            for pass in 0..2 {
+                if reached {
+                    break;
+                }
+                for (index, candidate) in sorted.iter().enumerate() {
+                    let include = match pass {
+                        0 => rng.gen_bool(0.5),
+                        _ => !included[index],
+                    };
+                    if !include || included[index] {
+                        continue;
+                    }
+                    total += candidate.effective_value;
+                    included[index] = true;
+                    if total >= target {
+                        reached = true;
+                        if total < best_total {
+                            best_total = total;
+                            best = included.clone();
+                        }
+                        total -= candidate.effective_value;
+                        included[index] = false;
+                    }
+                }
+            }
+        }
+        (best_total, best)
+    }
+}
+
+impl CoinSelection for LargestFirst {
+    fn select(&self, candidates: &[Candidate], params: &SelectionParams) -> Option<Vec<Candidate>> {
+        let mut sorted = candidates.to_vec();
+        sorted.sort_by_key(|c| Reverse(c.effective_value));
+        take_until(sorted, params.target)
+    }
+}
+
+impl CoinSelection for SingleRandomDraw {
+    fn select(&self, candidates: &[Candidate], params: &SelectionParams) -> Option<Vec<Candidate>> {
+        let mut shuffled = candidates.to_vec();
+        shuffled.shuffle(&mut rand::thread_rng());
+        let with_change = params.target + params.change_fee + params.min_change;
+        take_until(shuffled.clone(), with_change).or_else(|| take_until(shuffled, params.target))
+    }
+}
+
+/// Takes candidates in order until their value reaches the target
+fn take_until(candidates: Vec<Candidate>, target: i64) -> Option<Vec<Candidate>> {
+    let mut selected = vec![];
+    let mut value = 0;
+    for candidate in candidates {
+        value += candidate.effective_value;
+        selected.push(candidate);
+        if value >= target {
+            return Some(selected);
+        }
+    }
+    None
+}
+
+/// Every strategy, in order of preference when their waste is the same
+pub fn default_strategies() -> Vec<Box<dyn CoinSelection>> {
+    vec![
+        Box::new(BranchAndBound),
+        Box::new(Knapsack),
+        Box::new(SingleRandomDraw),
+        Box::new(LargestFirst),
+    ]
+}
+
+/// Runs every default strategy and returns the selection with the least waste
+pub fn select_coins(utxos: &[(Outpoint, Output)], params: &SelectionParams) -> Option<Selection> {
+    select_coins_with(utxos, params, &default_strategies())
+}
+
+/// Runs the strategies and returns the selection with the least waste, the one with
+/// fewer inputs on a tie. None if the utxos can't reach the target
+pub fn select_coins_with(
+    utxos: &[(Outpoint, Output)],
+    params: &SelectionParams,
+    strategies: &[Box<dyn CoinSelection>],
+) -> Option<Selection> {
+    let candidates = params.candidates(utxos);
+    strategies
+        .iter()
+        .filter_map(|strategy| strategy.select(&candidates, params))
+        .filter_map(|selected| Selection::new(&selected, params))
+        .min_by_key(|selection| (selection.waste, selection.utxos.len()))
+}
+
+/// Selection spending every utxo, even those costing more than their value. None if they
+/// don't reach the target
+pub fn select_all(utxos: &[(Outpoint, Output)], params: &SelectionParams) -> Option<Selection> {
+    Selection::new(&params.all_candidates(utxos), params)
+}
+
+#[cfg(test)]
+mod coin_selection_tests {
+    use super::*;
+    use crate::utils::script_tools::{multisig_script, p2sh_script, p2wpkh_script, p2wsh_script};
+
+    fn p2wpkh_utxo(value: i64, index: u32) -> (Outpoint, Output) {
+        let mut script = vec![0x00, 0x14];
+        script.extend([index as u8; 20]);
+        (
+            Outpoint::new([1u8; 32], index),
+            Output::new(value, CompactSize::from_usize_to_compact_size(22), script),
+        )
+    }
+
+    fn params(amount: i64) -> SelectionParams {
+        SelectionParams::new(
+            amount,
+            41,
+            10,
+            5,
+            &[
+                0x00, 0x14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            &HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn test_branch_and_bound_finds_changeless_solution() {
+        let params = params(50_000);
+        // 680 is the fee of spending a p2wpkh input at 10 sat/vB
+        let exact = params.target + 680;
+        let utxos = vec![
+            p2wpkh_utxo(10_000, 0),
+            p2wpkh_utxo(exact - 20_000, 1),
+            p2wpkh_utxo(200_000, 2),
+            p2wpkh_utxo(20_680, 3),
+        ];
+
+        let selected = BranchAndBound
+            .select(&params.candidates(&utxos), &params)
+            .unwrap();
+        let selection = Selection::new(&selected, &params).unwrap();
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.utxos.len(), 2);
+        // Only the cost of paying above the long term fee rate
+        assert_eq!(selection.waste, 2 * 68 * 5);
+
+        // It's preferred over the strategies creating change
+        assert_eq!(select_coins(&utxos, &params), Some(selection));
+    }
+
+    #[test]
+    fn test_selection_waste_with_and_without_change() {
+        let params = params(10_000);
+        let utxo = p2wpkh_utxo(100_000, 0);
+        let candidates = params.candidates(&[utxo]);
+
+        let selection = Selection::new(&candidates, &params).unwrap();
+        let excess = 100_000 - 680 - params.target;
+        assert_eq!(selection.change, excess - params.change_fee);
+        assert_eq!(selection.waste, 68 * 5 + params.cost_of_change);
+
+        // Change below the dust limit goes to the fee and is counted as waste
+        let params = SelectionParams {
+            target: 100_000 - 680 - 300,
+            ..params
+        };
+        let selection = Selection::new(&candidates, &params).unwrap();
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.waste, 68 * 5 + 300);
+    }
+
+    #[test]
+    fn test_candidates_pay_their_own_fee() {
+        let params = params(1_000);
+        let utxos = vec![p2wpkh_utxo(600, 0), p2wpkh_utxo(5_000, 1)];
+
+        // Spending 600 costs 680, so it's never offered
+        let candidates = params.candidates(&utxos);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].effective_value, 5_000 - 680);
+        assert!(select_coins(&[utxos[0].clone()], &params).is_none());
+    }
+
+    #[test]
+    fn test_strategies_reach_the_target() {
+        let params = params(70_000);
+        let utxos: Vec<(Outpoint, Output)> = (0..12)
+            .map(|i| p2wpkh_utxo(3_000 + 7_919 * i as i64, i))
+            .collect();
+        let candidates = params.candidates(&utxos);
+
+        let largest = LargestFirst.select(&candidates, &params).unwrap();
+        assert_eq!(largest[0].utxo, utxos[11]);
+
+        for strategy in default_strategies() {
+            for _ in 0..20 {
+                if let Some(selected) = strategy.select(&candidates, &params) {
+                    assert!(Selection::new(&selected, &params).is_some());
+                }
+            }
+        }
+        assert!(Knapsack.select(&candidates, &params).is_some());
+        assert!(SingleRandomDraw.select(&candidates, &params).is_some());
+
+        // Not enough funds
+        assert!(select_coins(&utxos[..2], &params).is_none());
+    }
+
+    #[test]
+    fn test_coin_control_spends_selected_and_keeps_frozen() {
+        let utxos = vec![
+            p2wpkh_utxo(600, 0),
+            p2wpkh_utxo(50_000, 1),
+            p2wpkh_utxo(30_000, 2),
+        ];
+        let mut coin_control = CoinControl::default();
+        coin_control.freeze(utxos[1].0);
+        assert_eq!(
+            coin_control.spendable(&utxos),
+            vec![utxos[0].clone(), utxos[2].clone()]
+        );
+
+        // Selected utxos are spent even if they cost more than their value
+        coin_control.select(utxos[0].0);
+        coin_control.select(utxos[2].0);
+        let spendable = coin_control.spendable(&utxos);
+        assert_eq!(spendable.len(), 2);
+        let selection = select_all(&spendable, &params(20_000)).unwrap();
+        assert_eq!(selection.utxos, spendable);
+        assert!(select_all(&spendable, &params(40_000)).is_none());
+
+        // Selecting a frozen utxo unfreezes it
+        coin_control.select(utxos[1].0);
+        assert!(!coin_control.is_frozen(&utxos[1].0));
+        coin_control.release(&utxos[1].0);
+        coin_control.retain_utxos(&utxos[1..]);
+        assert!(!coin_control.is_selected(&utxos[0].0));
+        assert_eq!(coin_control.spendable(&utxos), vec![utxos[2].clone()]);
+    }
+
+    #[test]
+    fn test_spending_vsize_of_script_hashes() {
+        let keys: Vec<Vec<u8>> = (2..5u8).map(|byte| vec![byte; 33]).collect();
+        let multisig = multisig_script(2, &keys).unwrap();
+        let p2wpkh = p2wpkh_script(&[1u8; 20]);
+        assert_eq!(
+            spending_vsize(&p2sh_script(&p2wpkh), &(Some(p2wpkh), None)),
+            91
+        );
+        let p2sh = p2sh_script(&multisig);
+        assert_eq!(spending_vsize(&p2sh, &(Some(multisig.clone()), None)), 297);
+        let p2wsh = p2wsh_script(&multisig);
+        assert_eq!(spending_vsize(&p2wsh, &(None, Some(multisig))), 105);
+
+        // 1-of-1 scripts are far smaller than the 2-of-3 estimate
+        let single = multisig_script(1, &keys[..1]).unwrap();
+        assert_eq!(
+            spending_vsize(&p2sh_script(&single), &(Some(single.clone()), None)),
+            153
+        );
+        assert_eq!(
+            spending_vsize(&p2wsh_script(&single), &(None, Some(single))),
+            70
+        );
+        assert_eq!(spending_vsize(&p2sh, &(None, None)), input_vsize(&p2sh));
+    }
+}
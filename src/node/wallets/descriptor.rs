@@ -0,0 +1,616 @@
+use super::hd_keys::{parse_path, ExtendedPrivKey, ExtendedPubKey, HARDENED};
+use crate::node::validation_engine::taproot::taproot_output_key;
+use crate::utils::array_tools::{cast_str_to_bytes_vec, u8_vec_to_hex_string};
+use crate::utils::script_tools::{
+    bitcoin_address_in_b58_output, hash160, multisig_script, p2pkh_script, p2sh_script,
+    p2tr_script, p2wpkh_script, p2wsh_script, script_for_address,
+};
+use secp256k1::{PublicKey, XOnlyPublicKey};
+use std::error::Error;
+use std::fmt;
+
+/// Characters a descriptor can have, their position is used by the checksum (BIP380)
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u64; 5] = [
+    0xf5dee51989,
+    0xa9fdca3312,
+    0x1bab10e32d,
+    0x3706b1677a,
+    0x644d626ffd,
+];
+const CHECKSUM_LEN: usize = 8;
+
+/// Public key of a descriptor, given directly or as an extended key
+#[derive(Debug, Clone, PartialEq)]
+enum KeySource {
+    Single(Vec<u8>), // compressed, or x-only inside tr()
+    Extended(ExtendedPubKey),
+}
+
+/// Key expression of a descriptor (BIP380): an optional origin, the key and, for extended
+/// keys, the normal derivation path after it, ending in `*` if the descriptor is ranged
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescriptorKey {
+    origin: Option<([u8; 4], Vec<u32>)>, // master fingerprint and path of the key
+    source: KeySource,
+    path: Vec<u32>,
+    wildcard: bool,
+}
+
+/// Multisig script of `threshold` of `keys`, sorted when derived if `sorted` (BIP383)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Multisig {
+    threshold: usize,
+    keys: Vec<DescriptorKey>,
+    sorted: bool,
+}
+
+/// Redeem script and witness script behind the hash of an output
+pub type SpendingScripts = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// Output descriptor (BIP380-386) describing the output scripts of a wallet
+#[derive(Debug, Clone, PartialEq)]
+pub enum Descriptor {
+    Pkh(DescriptorKey),    // P2PKH
+    Wpkh(DescriptorKey),   // P2WPKH
+    ShWpkh(DescriptorKey), // P2WPKH nested in P2SH
+    Tr(DescriptorKey),     // P2TR key path, without script tree
+    Multi(Multisig),       // bare multisig
+    ShMulti(Multisig),     // P2SH multisig
+    WshMulti(Multisig),    // P2WSH multisig
+    Addr(String),          // script of an address (BIP385)
+}
+
+impl DescriptorKey {
+    /// Parses a key expression, x-only keys are only allowed inside tr()
+    fn parse(expression: &str, x_only: bool) -> Result<DescriptorKey, Box<dyn Error>> {
+        let (origin, key) = match expression.strip_prefix('[') {
+            Some(rest) => match rest.split_once(']') {
+                Some((origin, key)) => (Some(Self::parse_origin(origin)?), key),
+                None => return Err("Unclosed key origin".into()),
+            },
+            None => (None, expression),
+        };
+        let mut steps: Vec<&str> = key.split('/').collect();
+        let encoded = steps.remove(0);
+
+        if let Ok(xpub) = ExtendedPubKey::decode(encoded) {
+            let wildcard = match steps.last() {
+                Some(&"*") => {
+                    steps.pop();
+                    true
+                }
+                Some(step) if step.starts_with('*') => {
+                    return Err("Hardened wildcards need a private key".into())
+                }
+                _ => false,
+            };
+            let path = parse_path(&steps.join("/"))?;
+            if path.iter().any(|index| *index >= HARDENED) {
+                return Err("Hardened steps need a private key".into());
+            }
+            return Ok(DescriptorKey {
+                origin,
+                source: KeySource::Extended(xpub),
+                path,
+                wildcard,
+            });
+        }
+        if ExtendedPrivKey::decode(encoded).is_ok() {
+            return Err("Private keys in descriptors are not supported".into());
+        }
+        if !steps.is_empty() {
+            return Err("Only extended keys can be derived".into());
+        }
+        let public_key = cast_str_to_bytes_vec(encoded)?;
+        let valid = match public_key.len() {
+            33 => PublicKey::from_slice(&public_key).is_ok(),
+            32 => x_only && XOnlyPublicKey::from_slice(&public_key).is_ok(),
+            _ => false,
+        };
+        if !valid {
+            return Err(format!("Invalid public key {}", encoded).into());
+        }
+        Ok(DescriptorKey {
+            origin,
+            source: KeySource::Single(public_key),
+            path: vec![],
+            wildcard: false,
+        })
+    }
+
+    /// Parses `fingerprint/path` of a key origin
+    fn parse_origin(origin: &str) -> Result<([u8; 4], Vec<u32>), Box<dyn Error>> {
+        let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+        let fingerprint = cast_str_to_bytes_vec(fingerprint)?;
+        if fingerprint.len() != 4 {
+            return Err("The key origin fingerprint must have 4 bytes".into());
+        }
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&fingerprint);
+        Ok((bytes, parse_path(path)?))
+    }
+
+    /// Returns the public key at `index`, which is only used by ranged keys
+    fn derive(&self, index: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let xpub = match &self.source {
+            KeySource::Single(public_key) => return Ok(public_key.clone()),
+            KeySource::Extended(xpub) => xpub,
+        };
+        let mut key = xpub.clone();
+        for step in &self.path {
+            key = key.derive_child(*step)?;
+        }
+        if self.wildcard {
+            key = key.derive_child(index)?;
+        }
+        Ok(key.get_public_key().to_vec())
+    }
+}
+
+impl fmt::Display for DescriptorKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((fingerprint, path)) = &self.origin {
+            write!(
+                f,
+                "[{}{}]",
+                u8_vec_to_hex_string(fingerprint),
+                format_path(path)
+            )?;
+        }
+        match &self.source {
+            KeySource::Single(public_key) => write!(f, "{}", u8_vec_to_hex_string(public_key))?,
+            KeySource::Extended(xpub) => write!(f, "{}{}", xpub.encode(), format_path(&self.path))?,
+        }
+        if self.wildcard {
+            write!(f, "/*")?;
+        }
+        Ok(())
+    }
+}
+
+impl Multisig {
+    /// Parses the arguments of multi() or sortedmulti(): the threshold and the keys
+    fn parse(arguments: &str, sorted: bool) -> Result<Multisig, Box<dyn Error>> {
+        let mut arguments = arguments.split(',');
+        let threshold = match arguments.next() {
+            Some(threshold) => threshold.parse()?,
+            None => return Err("Missing multisig threshold".into()),
+        };
+        let mut keys = vec![];
+        for key in arguments {
+            keys.push(DescriptorKey::parse(key, false)?);
+        }
+        let multisig = Multisig {
+            threshold,
+            keys,
+            sorted,
+        };
+        // Checks the threshold and the amount of keys
+        multisig.script_at(0)?;
+        Ok(multisig)
+    }
+
+    fn script_at(&self, index: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut public_keys = vec![];
+        for key in &self.keys {
+            public_keys.push(key.derive(index)?);
+        }
+        if self.sorted {
+            public_keys.sort();
+        }
+        multisig_script(self.threshold, &public_keys)
+    }
+}
+
+impl fmt::Display for Multisig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.sorted {
+            true => "sortedmulti",
+            false => "multi",
+        };
+        write!(f, "{}({}", name, self.threshold)?;
+        for key in &self.keys {
+            write!(f, ",{}", key)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Descriptor {
+    /// Parses a descriptor, checking its checksum if it has one
+    /// # Errors
+    /// Returns an error if the checksum is wrong or the descriptor is invalid or unsupported
+    pub fn parse(descriptor: &str) -> Result<Descriptor, Box<dyn Error>> {
+        let descriptor = descriptor.trim();
+        let body = match descriptor.split_once('#') {
+            Some((body, checksum)) => {
+                if descriptor_checksum(body)? != checksum {
+                    return Err("Invalid descriptor checksum".into());
+                }
+                body
+            }
+            None => descriptor,
+        };
+        let (name, arguments) = split_function(body)?;
+        let descriptor = match name {
+            "pkh" => Descriptor::Pkh(DescriptorKey::parse(arguments, false)?),
+            "wpkh" => Descriptor::Wpkh(DescriptorKey::parse(arguments, false)?),
+            "tr" if arguments.contains(',') => {
+                return Err("Taproot script trees are not supported".into())
+            }
+            "tr" => Descriptor::Tr(DescriptorKey::parse(arguments, true)?),
+            "multi" => Descriptor::Multi(Multisig::parse(arguments, false)?),
+            "sortedmulti" => Descriptor::Multi(Multisig::parse(arguments, true)?),
+            "addr" => {
+                script_for_address(arguments)?;
+                Descriptor::Addr(arguments.to_string())
+            }
+            "sh" => match split_function(arguments)? {
+                ("wpkh", key) => Descriptor::ShWpkh(DescriptorKey::parse(key, false)?),
+                ("multi", arguments) => Descriptor::ShMulti(Multisig::parse(arguments, false)?),
+                ("sortedmulti", arguments) => {
+                    Descriptor::ShMulti(Multisig::parse(arguments, true)?)
+                }
+                _ => return Err("Unsupported sh() descriptor".into()),
+            },
+            "wsh" => match split_function(arguments)? {
+                ("multi", arguments) => Descriptor::WshMulti(Multisig::parse(arguments, false)?),
+                ("sortedmulti", arguments) => {
+                    Descriptor::WshMulti(Multisig::parse(arguments, true)?)
+                }
+                _ => return Err("Unsupported wsh() descriptor".into()),
+            },
+            _ => return Err(format!("Unsupported descriptor {}()", name).into()),
+        };
+        Ok(descriptor)
+    }
+
+    /// Descriptors of a `threshold` of `keys` multisig, P2WSH if `segwit` or else P2SH. The keys
+    /// are sorted (sortedmulti) so every participant gets the same scripts. Extended public keys
+    /// receive on `/0/*` and take the change on `/1/*`, there's no change descriptor if no key is extended
+    /// # Errors
+    /// Returns an error if a key or the threshold are invalid
+    pub fn multisig(
+        threshold: usize,
+        keys: &[&str],
+        segwit: bool,
+    ) -> Result<(Descriptor, Option<Descriptor>), Box<dyn Error>> {
+        let is_extended = |key: &str| {
+            let key = key.rsplit(']').next().unwrap_or(key);
+            ExtendedPubKey::decode(key.split('/').next().unwrap_or(key)).is_ok()
+        };
+        let chain_descriptor = |chain: u32| {
+            let keys: Vec<String> = keys
+                .iter()
+                .map(|key| match is_extended(key) {
+                    true => format!("{}/{}/*", key, chain),
+                    false => key.to_string(),
+                })
+                .collect();
+            let multisig = format!("sortedmulti({},{})", threshold, keys.join(","));
+            match segwit {
+                true => Descriptor::parse(&format!("wsh({})", multisig)),
+                false => Descriptor::parse(&format!("sh({})", multisig)),
+            }
+        };
+        let receive = chain_descriptor(0)?;
+        match keys.iter().any(|key| is_extended(key)) {
+            true => Ok((receive, Some(chain_descriptor(1)?))),
+            false => Ok((receive, None)),
+        }
+    }
+
+    /// Redeem script and witness script needed to spend the output at `index`,
+    /// for the descriptors whose output has the hash of a script
+    /// # Errors
+    /// Returns an error if the keys can't be derived
+    pub fn spending_scripts_at(&self, index: u32) -> Result<SpendingScripts, Box<dyn Error>> {
+        let scripts = match self {
+            Descriptor::ShWpkh(key) => (Some(p2wpkh_script(&hash160(&key.derive(index)?))), None),
+            Descriptor::ShMulti(multisig) => (Some(multisig.script_at(index)?), None),
+            Descriptor::WshMulti(multisig) => (None, Some(multisig.script_at(index)?)),
+            _ => (None, None),
+        };
+        Ok(scripts)
+    }
+
+    /// Returns true if the descriptor has keys derived by index (`/*`)
+    pub fn is_ranged(&self) -> bool {
+        match self {
+            Descriptor::Pkh(key)
+            | Descriptor::Wpkh(key)
+            | Descriptor::ShWpkh(key)
+            | Descriptor::Tr(key) => key.wildcard,
+            Descriptor::Multi(multisig)
+            | Descriptor::ShMulti(multisig)
+            | Descriptor::WshMulti(multisig) => multisig.keys.iter().any(|key| key.wildcard),
+            Descriptor::Addr(_) => false,
+        }
+    }
+
+    /// Returns the output script at `index`, non ranged descriptors have a single one
+    /// # Errors
+    /// Returns an error if the keys can't be derived
+    pub fn script_at(&self, index: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let script = match self {
+            Descriptor::Pkh(key) => p2pkh_script(&hash160(&key.derive(index)?)),
+            Descriptor::Wpkh(key) => p2wpkh_script(&hash160(&key.derive(index)?)),
+            Descriptor::ShWpkh(key) => p2sh_script(&p2wpkh_script(&hash160(&key.derive(index)?))),
+            Descriptor::Tr(key) => {
+                let mut public_key = key.derive(index)?;
+                if public_key.len() == 32 {
+                    // x-only keys are the even one (BIP340)
+                    public_key.insert(0, 0x02);
+                }
+                p2tr_script(&taproot_output_key(&public_key)?)
+            }
+            Descriptor::Multi(multisig) => multisig.script_at(index)?,
+            Descriptor::ShMulti(multisig) => p2sh_script(&multisig.script_at(index)?),
+            Descriptor::WshMulti(multisig) => p2wsh_script(&multisig.script_at(index)?),
+            Descriptor::Addr(address) => script_for_address(address)?,
+        };
+        Ok(script)
+    }
+
+    /// Returns the testnet address at `index`
+    /// # Errors
+    /// Returns an error for bare multisig descriptors, which have no address
+    pub fn address_at(&self, index: u32) -> Result<String, Box<dyn Error>> {
+        if let Descriptor::Multi(_) = self {
+            return Err("Bare multisig descriptors have no address".into());
+        }
+        Ok(bitcoin_address_in_b58_output(&self.script_at(index)?))
+    }
+
+    /// Returns the descriptor with its checksum, to be imported elsewhere
+    pub fn export(&self) -> String {
+        let descriptor = self.to_string();
+        match descriptor_checksum(&descriptor) {
+            Ok(checksum) => format!("{}#{}", descriptor, checksum),
+            Err(_) => descriptor,
+        }
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Descriptor::Pkh(key) => write!(f, "pkh({})", key),
+            Descriptor::Wpkh(key) => write!(f, "wpkh({})", key),
+            Descriptor::ShWpkh(key) => write!(f, "sh(wpkh({}))", key),
+            Descriptor::Tr(key) => write!(f, "tr({})", key),
+            Descriptor::Multi(multisig) => write!(f, "{}", multisig),
+            Descriptor::ShMulti(multisig) => write!(f, "sh({})", multisig),
+            Descriptor::WshMulti(multisig) => write!(f, "wsh({})", multisig),
+            Descriptor::Addr(address) => write!(f, "addr({})", address),
+        }
+    }
+}
+
+/// Splits `name(arguments)` in its name and arguments
+fn split_function(expression: &str) -> Result<(&str, &str), Box<dyn Error>> {
+    match expression.split_once('(') {
+        Some((name, rest)) => match rest.strip_suffix(')') {
+            Some(arguments) => Ok((name, arguments)),
+            None => Err("Unbalanced parentheses in descriptor".into()),
+        },
+        None => Err("Invalid descriptor".into()),
+    }
+}
+
+/// Formats child numbers as `/0/1'`
+fn format_path(path: &[u32]) -> String {
+    path.iter()
+        .map(|index| match *index >= HARDENED {
+            true => format!("/{}'", index - HARDENED),
+            false => format!("/{}", index),
+        })
+        .collect()
+}
+
+/// Checksum of a descriptor without its `#checksum` (BIP380)
+/// # Errors
+/// Returns an error if the descriptor has characters outside INPUT_CHARSET
+pub fn descriptor_checksum(descriptor: &str) -> Result<String, Box<dyn Error>> {
+    let mut symbols = vec![];
+    let mut groups = vec![];
+    for c in descriptor.chars() {
+        let position = match INPUT_CHARSET.find(c) {
+            Some(position) => position as u64,
+            None => return Err(format!("Invalid descriptor character {}", c).into()),
+        };
+        symbols.push(position & 31);
+        groups.push(position >> 5);
+        if groups.len() == 3 {
+            symbols.push(groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups.as_slice() {
+        [first] => symbols.push(*first),
+        [first, second] => symbols.push(first * 3 + second),
+        _ => {}
+    }
+    symbols.extend_from_slice(&[0; CHECKSUM_LEN]);
+    let checksum = polymod(&symbols) ^ 1;
+    Ok((0..CHECKSUM_LEN)
+        .map(|i| CHECKSUM_CHARSET[((checksum >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect())
+}
+
+fn polymod(symbols: &[u64]) -> u64 {
+    let mut checksum: u64 = 1;
+    for symbol in symbols {
+        let top = checksum >> 35;
+        checksum = ((checksum & 0x7ffffffff) << 5) ^ symbol;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+#[cfg(test)]
+mod descriptor_tests {
+    use super::*;
+    use crate::node::wallets::hd_account::HdAccount;
+    use crate::utils::array_tools::cast_str_to_bytes_vec;
+
+    const KEY_2: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    const KEY_3: &str = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+
+    fn script_of(descriptor: &str) -> String {
+        u8_vec_to_hex_string(&Descriptor::parse(descriptor).unwrap().script_at(0).unwrap())
+    }
+
+    #[test]
+    fn test_descriptor_checksum() {
+        // BIP380 and BIP381 examples
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        let descriptor = format!("pkh({})", KEY_2);
+        assert_eq!(descriptor_checksum(&descriptor).unwrap(), "8fhd9pwu");
+        assert!(Descriptor::parse(&format!("{}#8fhd9pwu", descriptor)).is_ok());
+        assert!(Descriptor::parse(&format!("{}#8fhd9pwv", descriptor)).is_err());
+        assert_eq!(
+            Descriptor::parse(&descriptor).unwrap().export(),
+            format!("{}#8fhd9pwu", descriptor)
+        );
+    }
+
+    #[test]
+    fn test_single_key_descriptors() {
+        assert_eq!(
+            script_of(&format!("pkh({})", KEY_2)),
+            "76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac"
+        );
+        assert_eq!(
+            script_of(&format!("wpkh({})", KEY_2)),
+            "001406afd46bcdfd22ef94ac122aa11f241244a37ecc"
+        );
+        assert_eq!(
+            script_of(&format!("sh(wpkh({}))", KEY_2)),
+            "a914978a0121f9a24de65a13bab0c43c3a48be074eae87"
+        );
+        assert_eq!(
+            script_of(&format!("tr({})", KEY_2)),
+            "5120cafd90c7026f0b6ab98df89490d02732881f2f4b5900856358dddff4679c2ffb"
+        );
+        // The x-only key gives the same output
+        assert_eq!(
+            script_of(&format!("tr({})", &KEY_2[2..])),
+            "5120cafd90c7026f0b6ab98df89490d02732881f2f4b5900856358dddff4679c2ffb"
+        );
+        assert!(Descriptor::parse(&format!("pkh({})", &KEY_2[2..])).is_err());
+
+        let address = Descriptor::parse(&format!("pkh({})", KEY_2))
+            .unwrap()
+            .address_at(0)
+            .unwrap();
+        let addr = Descriptor::parse(&format!("addr({})", address)).unwrap();
+        assert!(!addr.is_ranged());
+        assert_eq!(
+            u8_vec_to_hex_string(&addr.script_at(0).unwrap()),
+            "76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac"
+        );
+        assert!(Descriptor::parse("addr(notanaddress)").is_err());
+    }
+
+    #[test]
+    fn test_multisig_descriptors() {
+        let multi = format!("multi(2,{},{})", KEY_3, KEY_2);
+        assert_eq!(
+            script_of(&multi),
+            format!("522102{}2102{}52ae", &KEY_3[2..], &KEY_2[2..])
+        );
+        assert_eq!(
+            script_of(&format!("sortedmulti(2,{},{})", KEY_3, KEY_2)),
+            format!("522102{}2102{}52ae", &KEY_2[2..], &KEY_3[2..])
+        );
+        assert_eq!(
+            script_of(&format!("sh(sortedmulti(2,{},{}))", KEY_3, KEY_2)),
+            "a9149af9b52da033d663b8eef59d586c7a4e01cf021687"
+        );
+        assert_eq!(
+            script_of(&format!("wsh({})", multi)),
+            "0020a90b0565f93e7c0d71454f4e497658f03aa889f1ba62a0a3d890d784040c2b81"
+        );
+        assert!(Descriptor::parse(&multi).unwrap().address_at(0).is_err());
+        assert!(Descriptor::parse(&format!("wsh({})", multi))
+            .unwrap()
+            .address_at(0)
+            .unwrap()
+            .starts_with("tb1q"));
+        assert!(Descriptor::parse(&format!("multi(3,{},{})", KEY_3, KEY_2)).is_err());
+        assert!(Descriptor::parse(&format!("multi(0,{})", KEY_3)).is_err());
+    }
+
+    #[test]
+    fn test_multisig_wallet_descriptors() {
+        let account = HdAccount::from_seed(&[1u8; 32]).unwrap();
+        let xpub = account.get_xpub();
+        let (receive, change) = Descriptor::multisig(2, &[KEY_3, &xpub, KEY_2], true).unwrap();
+        assert_eq!(
+            receive.to_string(),
+            format!("wsh(sortedmulti(2,{},{}/0/*,{}))", KEY_3, xpub, KEY_2)
+        );
+        let change = change.unwrap();
+        assert!(change.to_string().contains("/1/*"));
+
+        // The witness script is the sorted multisig of the derived keys
+        let key = account.derive_keys(1, 4).unwrap().get_pubkey().clone();
+        let mut public_keys = vec![
+            cast_str_to_bytes_vec(KEY_2).unwrap(),
+            cast_str_to_bytes_vec(KEY_3).unwrap(),
+            key,
+        ];
+        public_keys.sort();
+        let witness_script = multisig_script(2, &public_keys).unwrap();
+        assert_eq!(
+            change.spending_scripts_at(4).unwrap(),
+            (None, Some(witness_script.clone()))
+        );
+        assert_eq!(change.script_at(4).unwrap(), p2wsh_script(&witness_script));
+
+        let (receive, change) = Descriptor::multisig(1, &[KEY_2, KEY_3], false).unwrap();
+        assert!(change.is_none());
+        let redeem_script = receive.spending_scripts_at(0).unwrap().0.unwrap();
+        assert_eq!(receive.script_at(0).unwrap(), p2sh_script(&redeem_script));
+        assert!(Descriptor::multisig(3, &[KEY_2, KEY_3], false).is_err());
+        assert!(Descriptor::multisig(1, &[KEY_2, "nokey"], true).is_err());
+    }
+
+    #[test]
+    fn test_ranged_descriptors() {
+        let seed = cast_str_to_bytes_vec("000102030405060708090a0b0c0d0e0f").unwrap();
+        let account = HdAccount::from_seed(&seed).unwrap();
+        let descriptor = format!("pkh([d34db33f/44'/1'/0']{}/0/*)", account.get_xpub());
+        let parsed = Descriptor::parse(&descriptor).unwrap();
+        assert!(parsed.is_ranged());
+        assert_eq!(parsed.to_string(), descriptor);
+        let keys = account.derive_keys(0, 3).unwrap();
+        assert_eq!(parsed.script_at(3).unwrap(), keys.get_script());
+        assert_eq!(parsed.address_at(3).unwrap(), keys.get_address());
+
+        let taproot = Descriptor::parse(&format!("tr({}/1/*)", account.get_xpub())).unwrap();
+        let keys = account.derive_keys(1, 2).unwrap();
+        assert_eq!(taproot.script_at(2).unwrap(), keys.get_taproot_script());
+
+        // Public keys can't derive hardened children
+        assert!(Descriptor::parse(&format!("pkh({}/0'/*)", account.get_xpub())).is_err());
+        assert!(Descriptor::parse(&format!("pkh({}/0/*')", account.get_xpub())).is_err());
+        assert!(Descriptor::parse(&format!("pkh({})", account.get_xprv())).is_err());
+    }
+
+    #[test]
+    fn test_invalid_descriptors() {
+        assert!(Descriptor::parse("pkh(").is_err());
+        assert!(Descriptor::parse(&format!("sh(pkh({}))", KEY_2)).is_err());
+        assert!(Descriptor::parse(&format!("wsh(wpkh({}))", KEY_2)).is_err());
+        assert!(Descriptor::parse(&format!("tr({},pk({}))", KEY_2, KEY_3)).is_err());
+        assert!(Descriptor::parse(&format!("combo({})", KEY_2)).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+use super::descriptor::{Descriptor, SpendingScripts};
+use super::hd_account::{CHANGE_CHAIN, GAP_LIMIT, RECEIVE_CHAIN};
+use crate::utils::array_tools::u8_vec_to_hex_string;
+use crate::utils::script_tools::bitcoin_address_in_b58_output;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Scripts derived from one descriptor. Like the key chains of a HD account the first
+/// `issued` were handed out and GAP_LIMIT are watched after the last `used` one.
+/// Non ranged descriptors have a single script
+#[derive(Clone)]
+struct ScriptChain {
+    descriptor: Descriptor,
+    scripts: Vec<Vec<u8>>,
+    issued: u32,
+    used: u32,
+}
+
+impl ScriptChain {
+    fn new(descriptor: Descriptor, issued: u32) -> ScriptChain {
+        ScriptChain {
+            descriptor,
+            scripts: vec![],
+            issued,
+            used: 0,
+        }
+    }
+
+    /// Amount of scripts that must be derived
+    fn window(&self) -> u32 {
+        match self.descriptor.is_ranged() {
+            true => self.issued.max(self.used + GAP_LIMIT),
+            false => 1,
+        }
+    }
+}
+
+/// Watch only account defined by a receive descriptor, an optional change descriptor
+/// and any amount of other non ranged descriptors (like watched addresses).
+/// The change goes to the receive descriptor if there's no change one
+#[derive(Clone)]
+pub struct DescriptorAccount {
+    receive: ScriptChain,
+    change: Option<ScriptChain>,
+    others: Vec<ScriptChain>,               // chain OTHERS_CHAIN + position
+    addresses: HashMap<String, (u32, u32)>, // address of every derived script -> (chain, index)
+}
+
+/// First chain number of the other descriptors
+const OTHERS_CHAIN: u32 = 2;
+
+impl DescriptorAccount {
+    /// Creates the account, `receive` and `change` are the amount of scripts already issued.
+    /// The scripts of the other descriptors are always shown as issued
+    /// # Errors
+    /// Returns an error if the scripts can't be derived or a descriptor is a bare multisig
+    pub fn new(
+        receive_descriptor: Descriptor,
+        change_descriptor: Option<Descriptor>,
+        other_descriptors: Vec<Descriptor>,
+        receive: u32,
+        change: u32,
+    ) -> Result<DescriptorAccount, Box<dyn Error>> {
+        let bare_multisig = [Some(&receive_descriptor), change_descriptor.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(other_descriptors.iter())
+            .any(|descriptor| matches!(descriptor, Descriptor::Multi(_)));
+        if bare_multisig {
+            return Err("Bare multisig outputs have no address to watch, use sh() or wsh()".into());
+        }
+        let mut account = DescriptorAccount {
+            receive: ScriptChain::new(receive_descriptor, receive),
+            change: change_descriptor.map(|descriptor| ScriptChain::new(descriptor, change)),
+            others: other_descriptors
+                .into_iter()
+                .map(|descriptor| ScriptChain::new(descriptor, 1))
+                .collect(),
+            addresses: HashMap::new(),
+        };
+        account.fill_window()?;
+        Ok(account)
+    }
+
+    /// Parses descriptors separated by whitespace. The first ranged one is the receive descriptor
+    /// and the second the change one, the first descriptor receives if none is ranged.
+    /// The rest are only watched
+    /// # Errors
+    /// Returns an error if there are no descriptors, more than two ranged ones or any is invalid
+    pub fn parse(
+        descriptors: &str,
+        receive: u32,
+        change: u32,
+    ) -> Result<DescriptorAccount, Box<dyn Error>> {
+        let descriptors = descriptors
+            .split_whitespace()
+            .map(Descriptor::parse)
+            .collect::<Result<Vec<Descriptor>, Box<dyn Error>>>()?;
+        let (mut ranged, mut others): (Vec<Descriptor>, Vec<Descriptor>) = descriptors
+            .into_iter()
+            .partition(|descriptor| descriptor.is_ranged());
+        if ranged.len() > 2 {
+            return Err("Expected a receive descriptor and an optional change one".into());
+        }
+        if ranged.is_empty() && !others.is_empty() {
+            ranged.push(others.remove(0));
+        }
+        let mut ranged = ranged.into_iter();
+        let receive_descriptor = match ranged.next() {
+            Some(descriptor) => descriptor,
+            None => return Err("Expected a receive descriptor and an optional change one".into()),
+        };
+        Self::new(receive_descriptor, ranged.next(), others, receive, change)
+    }
+
+    /// Every chain number of the account
+    fn chains(&self) -> Vec<u32> {
+        let mut chains = vec![RECEIVE_CHAIN, CHANGE_CHAIN];
+        chains.extend((0..self.others.len() as u32).map(|position| OTHERS_CHAIN + position));
+        chains
+    }
+
+    /// Derives the scripts missing in the window of each chain
+    fn fill_window(&mut self) -> Result<(), Box<dyn Error>> {
+        for chain in self.chains() {
+            let script_chain = match self.script_chain_mut(chain) {
+                Some(script_chain) => script_chain,
+                None => continue,
+            };
+            let mut derived = vec![];
+            while (script_chain.scripts.len() as u32) < script_chain.window() {
+                let index = script_chain.scripts.len() as u32;
+                let script = script_chain.descriptor.script_at(index)?;
+                derived.push((bitcoin_address_in_b58_output(&script), index));
+                script_chain.scripts.push(script);
+            }
+            for (address, index) in derived {
+                self.addresses.insert(address, (chain, index));
+            }
+        }
+        Ok(())
+    }
+
+    fn script_chain(&self, chain: u32) -> Option<&ScriptChain> {
+        match chain {
+            RECEIVE_CHAIN => Some(&self.receive),
+            CHANGE_CHAIN => self.change.as_ref(),
+            _ => self.others.get((chain - OTHERS_CHAIN) as usize),
+        }
+    }
+
+    fn script_chain_mut(&mut self, chain: u32) -> Option<&mut ScriptChain> {
+        match chain {
+            RECEIVE_CHAIN => Some(&mut self.receive),
+            CHANGE_CHAIN => self.change.as_mut(),
+            _ => self.others.get_mut((chain - OTHERS_CHAIN) as usize),
+        }
+    }
+
+    /// Address shown for the script `index` of `chain`, the script in hex if it has no address
+    fn address_of(&self, chain: u32, index: u32) -> String {
+        let script_chain = match self.script_chain(chain) {
+            Some(script_chain) => script_chain,
+            None => return String::new(),
+        };
+        match script_chain.descriptor.address_at(index) {
+            Ok(address) => address,
+            Err(_) => match script_chain.scripts.get(index as usize) {
+                Some(script) => u8_vec_to_hex_string(script),
+                None => String::new(),
+            },
+        }
+    }
+
+    /// Issues the next receive address, the only one of non ranged descriptors
+    /// # Errors
+    /// Returns an error if the descriptor isn't ranged and its address was issued
+    pub fn next_receive_address(&mut self) -> Result<String, Box<dyn Error>> {
+        if !self.receive.descriptor.is_ranged() && self.receive.issued > 0 {
+            return Err("The descriptor isn't ranged, it has a single address".into());
+        }
+        self.receive.issued += 1;
+        self.fill_window()?;
+        Ok(self.address_of(RECEIVE_CHAIN, self.receive.issued - 1))
+    }
+
+    /// Issues the next change script, from the receive descriptor if there's no change one
+    /// # Errors
+    /// Returns an error if the script can't be derived
+    pub fn next_change_script(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let chain = match self.change {
+            Some(_) => CHANGE_CHAIN,
+            None => RECEIVE_CHAIN,
+        };
+        if let Some(script_chain) = self.script_chain_mut(chain) {
+            if script_chain.descriptor.is_ranged() {
+                script_chain.issued += 1;
+            }
+        }
+        self.fill_window()?;
+        match self.script_chain(chain) {
+            Some(script_chain) => {
+                let index = script_chain.issued.max(1) - 1;
+                Ok(script_chain.scripts[index as usize].clone())
+            }
+            None => Err("The account has no descriptors".into()),
+        }
+    }
+
+    /// Last receive address issued, the first one if there's none yet
+    pub fn receive_address(&self) -> String {
+        self.address_of(RECEIVE_CHAIN, self.receive.issued.max(1) - 1)
+    }
+
+    /// Marks the scripts found used by `is_used`, moving the windows while new scripts are
+    /// found. The last receive script issued is kept unused. Returns true if anything changed
+    /// # Errors
+    /// Returns an error if the scripts can't be derived
+    pub fn discover(&mut self, is_used: impl Fn(&[u8]) -> bool) -> Result<bool, Box<dyn Error>> {
+        let before = (self.addresses.len(), self.get_issued());
+        let mut checked = 0;
+        while checked < self.addresses.len() {
+            checked = self.addresses.len();
+            for chain in self.chains() {
+                if let Some(script_chain) = self.script_chain_mut(chain) {
+                    let used = script_chain
+                        .scripts
+                        .iter()
+                        .rposition(|script| is_used(script))
+                        .map_or(0, |index| index as u32 + 1);
+                    script_chain.used = script_chain.used.max(used);
+                    script_chain.issued = script_chain.issued.max(script_chain.used);
+                }
+            }
+            self.fill_window()?;
+        }
+        if self.receive.descriptor.is_ranged()
+            && (self.receive.issued == 0 || self.receive.used >= self.receive.issued)
+        {
+            self.next_receive_address()?;
+        }
+        Ok(before != (self.addresses.len(), self.get_issued()))
+    }
+
+    /// Returns true if the address is one of the watched scripts
+    pub fn is_mine(&self, address: &str) -> bool {
+        self.addresses.contains_key(address)
+    }
+
+    /// Returns the receive addresses issued, at least the first one, and those of the other descriptors
+    pub fn issued_addresses(&self) -> Vec<String> {
+        let mut addresses: Vec<String> = (0..self.receive.issued.max(1))
+            .map(|index| self.address_of(RECEIVE_CHAIN, index))
+            .collect();
+        for position in 0..self.others.len() as u32 {
+            addresses.push(self.address_of(OTHERS_CHAIN + position, 0));
+        }
+        addresses
+    }
+
+    /// Redeem script and witness script of a watched script, needed by signers of
+    /// P2SH and P2WSH outputs. None if the script isn't watched
+    pub fn spending_scripts(&self, script: &[u8]) -> Option<SpendingScripts> {
+        let (chain, index) = self.addresses.get(&bitcoin_address_in_b58_output(script))?;
+        let script_chain = self.script_chain(*chain)?;
+        script_chain.descriptor.spending_scripts_at(*index).ok()
+    }
+
+    /// Returns every watched address
+    pub fn watched_addresses(&self) -> Vec<String> {
+        self.addresses.keys().cloned().collect()
+    }
+
+    /// Returns every watched script: the issued ones and the lookahead of both chains
+    pub fn watched_scripts(&self) -> Vec<Vec<u8>> {
+        let mut scripts = self.receive.scripts.clone();
+        if let Some(change) = &self.change {
+            scripts.extend(change.scripts.iter().cloned());
+        }
+        for other in &self.others {
+            scripts.extend(other.scripts.iter().cloned());
+        }
+        scripts
+    }
+
+    /// Returns the descriptors of the account with their checksums
+    pub fn export(&self) -> Vec<String> {
+        let mut descriptors = vec![self.receive.descriptor.export()];
+        if let Some(change) = &self.change {
+            descriptors.push(change.descriptor.export());
+        }
+        for other in &self.others {
+            descriptors.push(other.descriptor.export());
+        }
+        descriptors
+    }
+
+    /// Amount of scripts issued on the receive and change chains
+    pub fn get_issued(&self) -> (u32, u32) {
+        let change = self.change.as_ref().map_or(0, |change| change.issued);
+        (self.receive.issued, change)
+    }
+}
+
+#[cfg(test)]
+mod descriptor_account_tests {
+    use super::*;
+    use crate::node::wallets::hd_account::HdAccount;
+    use crate::utils::array_tools::cast_str_to_bytes_vec;
+
+    fn account() -> (HdAccount, DescriptorAccount) {
+        let seed = cast_str_to_bytes_vec("000102030405060708090a0b0c0d0e0f").unwrap();
+        let hd_account = HdAccount::from_seed(&seed).unwrap();
+        let descriptors = format!(
+            "wpkh({}/0/*) wpkh({}/1/*)",
+            hd_account.get_xpub(),
+            hd_account.get_xpub()
+        );
+        let account = DescriptorAccount::parse(&descriptors, 0, 0).unwrap();
+        (hd_account, account)
+    }
+
+    #[test]
+    fn test_descriptor_account_window() {
+        let (hd_account, mut account) = account();
+        assert_eq!(account.watched_scripts().len(), 2 * GAP_LIMIT as usize);
+        let lookahead = hd_account
+            .derive_keys(RECEIVE_CHAIN, GAP_LIMIT - 1)
+            .unwrap();
+        let outside = hd_account.derive_keys(RECEIVE_CHAIN, GAP_LIMIT).unwrap();
+        assert!(account.is_mine(&lookahead.get_segwit_address()));
+        assert!(!account.is_mine(&outside.get_segwit_address()));
+        assert!(!account.is_mine(&lookahead.get_address()));
+
+        // A payment to the last watched script moves the window
+        let used = lookahead.get_segwit_script();
+        assert!(account.discover(|script| script == used).unwrap());
+        assert!(account.is_mine(&outside.get_segwit_address()));
+        assert_eq!(account.get_issued(), (GAP_LIMIT + 1, 0));
+        assert_eq!(
+            account.receive_address(),
+            hd_account
+                .derive_keys(RECEIVE_CHAIN, GAP_LIMIT)
+                .unwrap()
+                .get_segwit_address()
+        );
+        assert_eq!(
+            account.next_change_script().unwrap(),
+            hd_account
+                .derive_keys(CHANGE_CHAIN, 0)
+                .unwrap()
+                .get_segwit_script()
+        );
+        assert_eq!(account.export().len(), 2);
+    }
+
+    #[test]
+    fn test_non_ranged_descriptor_account() {
+        let key = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let mut account = DescriptorAccount::parse(&format!("pkh({})", key), 0, 0).unwrap();
+        assert_eq!(account.watched_scripts().len(), 1);
+        let address = account.next_receive_address().unwrap();
+        assert_eq!(account.receive_address(), address);
+        assert!(account.is_mine(&address));
+        assert!(account.next_receive_address().is_err());
+        assert_eq!(account.issued_addresses(), vec![address]);
+        assert!(DescriptorAccount::parse("", 0, 0).is_err());
+        let bare_multisig = format!("multi(1,{})", key);
+        assert!(DescriptorAccount::parse(&bare_multisig, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_descriptor_account_watching_addresses() {
+        let (hd_account, _) = account();
+        let first = hd_account.derive_keys(RECEIVE_CHAIN, 0).unwrap();
+        let second = hd_account.derive_keys(RECEIVE_CHAIN, 1).unwrap();
+        let descriptors = format!(
+            "addr({}) addr({})",
+            first.get_address(),
+            second.get_taproot_address()
+        );
+        let account = DescriptorAccount::parse(&descriptors, 0, 0).unwrap();
+        assert_eq!(
+            account.issued_addresses(),
+            vec![first.get_address(), second.get_taproot_address()]
+        );
+        assert!(account.is_mine(&second.get_taproot_address()));
+        assert_eq!(account.watched_scripts().len(), 2);
+        assert_eq!(account.export().len(), 2);
+
+        // A ranged descriptor receives while the addresses are only watched
+        let descriptors = format!("{} wpkh({}/0/*)", descriptors, hd_account.get_xpub());
+        let account = DescriptorAccount::parse(&descriptors, 1, 0).unwrap();
+        assert_eq!(account.receive_address(), first.get_segwit_address());
+        assert_eq!(account.issued_addresses().len(), 3);
+        assert!(account.is_mine(&first.get_address()));
+    }
+}
@@ -0,0 +1,315 @@
+use crate::utils::array_tools::{cast_str_to_bytes_vec, u8_vec_to_hex_string};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::RngCore;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// scrypt cost (N = 2^15, 32 MiB of memory with r = 8)
+pub const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// Seconds the wallets stay unlocked when no timeout is given
+pub const UNLOCK_TIMEOUT_SECS: u64 = 300;
+/// First field of the wallets file header when the keys are encrypted
+pub const ENCRYPTED_HEADER: &str = "encrypted";
+/// Line starting the content of a wallets file encrypted as a whole, followed by it in hex
+pub const SEALED_HEADER: &str = "sealed";
+/// Known text encrypted in the header to check the passphrase without touching the keys
+const CHECK_TEXT: &[u8] = b"rusteze wallets";
+
+/// Symmetric key derived from the passphrase with scrypt, used with ChaCha20-Poly1305
+struct WalletCipher {
+    key: [u8; 32],
+}
+
+impl Drop for WalletCipher {
+    fn drop(&mut self) {
+        self.key = [0; 32];
+    }
+}
+
+impl WalletCipher {
+    /// Derives the key of the private keys and the key of the wallets files. scrypt gives
+    /// the same first 32 bytes whatever the length asked, so the keys key is the one
+    /// derived before the files were encrypted
+    fn derive(
+        passphrase: &str,
+        salt: &[u8],
+        log_n: u8,
+    ) -> Result<(WalletCipher, WalletCipher), Box<dyn Error>> {
+        let params = match scrypt::Params::new(log_n, SCRYPT_R, SCRYPT_P, 64) {
+            Ok(params) => params,
+            Err(_) => return Err("Invalid scrypt parameters".into()),
+        };
+        let mut derived = [0u8; 64];
+        if scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut derived).is_err() {
+            return Err("Error deriving the wallets key".into());
+        }
+        let (mut key, mut file_key) = ([0u8; 32], [0u8; 32]);
+        key.copy_from_slice(&derived[..32]);
+        file_key.copy_from_slice(&derived[32..]);
+        derived.fill(0);
+        Ok((WalletCipher { key }, WalletCipher { key: file_key }))
+    }
+
+    /// Returns the random nonce followed by the ciphertext and its tag
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.key));
+        let ciphertext = match cipher.encrypt(Nonce::from_slice(&nonce), plaintext) {
+            Ok(ciphertext) => ciphertext,
+            Err(_) => return Err("Error encrypting".into()),
+        };
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if data.len() < NONCE_LEN {
+            return Err("Encrypted data too short".into());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.key));
+        match cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+            Ok(plaintext) => Ok(plaintext),
+            Err(_) => Err("Wrong passphrase or corrupted data".into()),
+        }
+    }
+}
+
+/// Passphrase protection of the wallets keys. While locked only the salt and the
+/// passphrase check are kept, unlocking derives the key again for a limited time.
+/// The key of the wallets files is kept after locking so they can still be saved,
+/// it can't decrypt the private keys
+pub struct WalletEncryption {
+    salt: Vec<u8>,
+    log_n: u8,
+    check: Vec<u8>,
+    cipher: Option<WalletCipher>,
+    file_cipher: Option<WalletCipher>,
+    unlocked_until: Option<Instant>,
+}
+
+impl WalletEncryption {
+    /// Creates the encryption for a new passphrase with a random salt, unlocked for `timeout`
+    pub fn new(
+        passphrase: &str,
+        log_n: u8,
+        timeout: Duration,
+    ) -> Result<WalletEncryption, Box<dyn Error>> {
+        if passphrase.is_empty() {
+            return Err("The passphrase can't be empty".into());
+        }
+        let mut salt = vec![0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let (cipher, file_cipher) = WalletCipher::derive(passphrase, &salt, log_n)?;
+        let check = cipher.encrypt(CHECK_TEXT)?;
+        Ok(WalletEncryption {
+            salt,
+            log_n,
+            check,
+            cipher: Some(cipher),
+            file_cipher: Some(file_cipher),
+            unlocked_until: Some(Instant::now() + timeout),
+        })
+    }
+
+    /// Reads the header line of the wallets file: `encrypted,salt,log_n,check`
+    pub fn from_header(line: &str) -> Option<WalletEncryption> {
+        let parts: Vec<&str> = line.trim().split(',').collect();
+        if parts.len() != 4 || parts[0] != ENCRYPTED_HEADER {
+            return None;
+        }
+        Some(WalletEncryption {
+            salt: cast_str_to_bytes_vec(parts[1]).ok()?,
+            log_n: parts[2].parse().ok()?,
+            check: cast_str_to_bytes_vec(parts[3]).ok()?,
+            cipher: None,
+            file_cipher: None,
+            unlocked_until: None,
+        })
+    }
+
+    /// Returns the header line stored at the start of the wallets file
+    pub fn header(&self) -> String {
+        format!(
+            "{},{},{},{}",
+            ENCRYPTED_HEADER,
+            u8_vec_to_hex_string(&self.salt),
+            self.log_n,
+            u8_vec_to_hex_string(&self.check)
+        )
+    }
+
+    /// Derives the key from the passphrase and keeps it for `timeout`
+    /// # Errors
+    /// Returns an error if the passphrase is wrong
+    pub fn unlock(&mut self, passphrase: &str, timeout: Duration) -> Result<(), Box<dyn Error>> {
+        let (cipher, file_cipher) = WalletCipher::derive(passphrase, &self.salt, self.log_n)?;
+        if cipher.decrypt(&self.check).is_err() {
+            return Err("Wrong passphrase".into());
+        }
+        self.cipher = Some(cipher);
+        self.file_cipher = Some(file_cipher);
+        self.unlocked_until = Some(Instant::now() + timeout);
+        Ok(())
+    }
+
+    /// Forgets the derived key of the private keys
+    pub fn lock(&mut self) {
+        self.cipher = None;
+        self.unlocked_until = None;
+    }
+
+    /// Returns true if the key is available and the timeout hasn't expired
+    pub fn is_unlocked(&self) -> bool {
+        match self.unlocked_until {
+            Some(until) => self.cipher.is_some() && Instant::now() < until,
+            None => false,
+        }
+    }
+
+    /// Returns true if the wallets were unlocked and the timeout expired
+    pub fn expired(&self) -> bool {
+        self.cipher.is_some() && !self.is_unlocked()
+    }
+
+    /// # Errors
+    /// Returns an error if the wallets are locked
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        match &self.cipher {
+            Some(cipher) if self.is_unlocked() => cipher.encrypt(plaintext),
+            _ => Err("Wallets are locked".into()),
+        }
+    }
+
+    /// # Errors
+    /// Returns an error if the wallets are locked or the data was not encrypted with this key
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        match &self.cipher {
+            Some(cipher) if self.is_unlocked() => cipher.decrypt(data),
+            _ => Err("Wallets are locked".into()),
+        }
+    }
+
+    /// Returns true if the passphrase was given since the wallets were opened,
+    /// so the wallets files can be encrypted
+    pub fn has_file_key(&self) -> bool {
+        self.file_cipher.is_some()
+    }
+
+    /// Encrypts the whole content of a wallets file: the sealed header and the content in hex
+    /// # Errors
+    /// Returns an error if the passphrase wasn't given since the wallets were opened
+    pub fn seal(&self, lines: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        let data = match &self.file_cipher {
+            Some(file_cipher) => file_cipher.encrypt(lines.join("\n").as_bytes())?,
+            None => return Err("The wallets were never unlocked".into()),
+        };
+        Ok(vec![SEALED_HEADER.to_string(), u8_vec_to_hex_string(&data)])
+    }
+
+    /// Decrypts the content of a sealed wallets file, files written before they were sealed
+    /// are returned as they are
+    /// # Errors
+    /// Returns an error if the passphrase wasn't given or the file was sealed with another one
+    pub fn unseal(&self, lines: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        if !is_sealed(lines) {
+            return Ok(lines.to_vec());
+        }
+        let data = match lines.get(1) {
+            Some(data) => cast_str_to_bytes_vec(data)?,
+            None => return Err("Sealed file without content".into()),
+        };
+        let plaintext = match &self.file_cipher {
+            Some(file_cipher) => file_cipher.decrypt(&data)?,
+            None => return Err("The wallets were never unlocked".into()),
+        };
+        Ok(String::from_utf8(plaintext)?
+            .lines()
+            .map(|line| line.to_string())
+            .collect())
+    }
+}
+
+/// Returns true if the lines of a wallets file are encrypted as a whole
+pub fn is_sealed(lines: &[String]) -> bool {
+    lines.first().is_some_and(|line| line == SEALED_HEADER)
+}
+
+#[cfg(test)]
+mod encryption_tests {
+    use super::*;
+
+    // Cheap cost so the tests run fast
+    const TEST_LOG_N: u8 = 4;
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let encryption = WalletEncryption::new("passphrase", TEST_LOG_N, TIMEOUT).unwrap();
+        let data = encryption.encrypt(b"private key").unwrap();
+        assert_ne!(data, b"private key".to_vec());
+        assert_eq!(encryption.decrypt(&data).unwrap(), b"private key".to_vec());
+    }
+
+    #[test]
+    fn test_unlock_from_header() {
+        let encryption = WalletEncryption::new("passphrase", TEST_LOG_N, TIMEOUT).unwrap();
+        let data = encryption.encrypt(b"private key").unwrap();
+
+        let mut opened = WalletEncryption::from_header(&encryption.header()).unwrap();
+        assert!(!opened.is_unlocked());
+        assert!(opened.decrypt(&data).is_err());
+        assert!(opened.unlock("wrong", TIMEOUT).is_err());
+        opened.unlock("passphrase", TIMEOUT).unwrap();
+        assert_eq!(opened.decrypt(&data).unwrap(), b"private key".to_vec());
+
+        opened.lock();
+        assert!(opened.decrypt(&data).is_err());
+    }
+
+    #[test]
+    fn test_seal_and_unseal_files() {
+        let encryption = WalletEncryption::new("passphrase", TEST_LOG_N, TIMEOUT).unwrap();
+        let lines = vec!["0,wallet".to_string(), "1,other".to_string()];
+        let sealed = encryption.seal(&lines).unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!sealed[1].contains("wallet"));
+
+        // The files stay readable after locking, only with the passphrase once reopened
+        let mut opened = WalletEncryption::from_header(&encryption.header()).unwrap();
+        assert!(opened.unseal(&sealed).is_err());
+        opened.unlock("passphrase", TIMEOUT).unwrap();
+        opened.lock();
+        assert!(!opened.is_unlocked());
+        assert_eq!(opened.unseal(&sealed).unwrap(), lines);
+        assert_eq!(opened.unseal(&lines).unwrap(), lines);
+        let other = WalletEncryption::new("other", TEST_LOG_N, TIMEOUT).unwrap();
+        assert!(other.unseal(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_tampered_data_is_rejected() {
+        let encryption = WalletEncryption::new("passphrase", TEST_LOG_N, TIMEOUT).unwrap();
+        let mut data = encryption.encrypt(b"private key").unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(encryption.decrypt(&data).is_err());
+    }
+
+    #[test]
+    fn test_timeout_expires() {
+        let encryption = WalletEncryption::new("passphrase", TEST_LOG_N, Duration::ZERO).unwrap();
+        assert!(!encryption.is_unlocked());
+        assert!(encryption.expired());
+        assert!(encryption.encrypt(b"private key").is_err());
+    }
+}
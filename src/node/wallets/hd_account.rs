@@ -0,0 +1,411 @@
+use super::hd_keys::{ExtendedPrivKey, ExtendedPubKey, Network};
+use super::keys_handler::KeysHandler;
+use crate::utils::array_tools::u8_array_to_hex_string;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// BIP44 account used by the HD wallets (coin type 1, testnet)
+pub const ACCOUNT_PATH: &str = "m/44'/1'/0'";
+/// Depth of the keys at ACCOUNT_PATH
+const ACCOUNT_DEPTH: u8 = 3;
+pub const RECEIVE_CHAIN: u32 = 0;
+pub const CHANGE_CHAIN: u32 = 1;
+/// Unused addresses watched after the last used one of each chain
+pub const GAP_LIMIT: u32 = 20;
+
+/// Keys derived on one chain of the account. The first `issued` were handed out,
+/// the first `used` (at least) received funds, and the rest up to GAP_LIMIT after
+/// the last used one are watched as lookahead
+#[derive(Clone)]
+struct KeyChain {
+    keys: Vec<KeysHandler>,
+    issued: u32,
+    used: u32,
+}
+
+impl KeyChain {
+    fn new(issued: u32) -> KeyChain {
+        KeyChain {
+            keys: vec![],
+            issued,
+            used: 0,
+        }
+    }
+
+    /// Amount of keys that must be derived: the issued ones and the lookahead after the last used
+    fn window(&self) -> u32 {
+        self.issued.max(self.used + GAP_LIMIT)
+    }
+}
+
+/// Account of a HD wallet: derives the receive (`account/0/i`) and change (`account/1/i`) keys.
+/// Without the private account key (locked or watch only) it still derives the addresses.
+pub struct HdAccount {
+    xpub: ExtendedPubKey,
+    xprv: Option<ExtendedPrivKey>,
+    receive: KeyChain,
+    change: KeyChain,
+    addresses: HashMap<String, (u32, u32)>, // P2PKH and P2WPKH address -> (chain, index) of every derived key
+}
+
+impl Clone for HdAccount {
+    fn clone(&self) -> Self {
+        HdAccount {
+            xpub: self.xpub.clone(),
+            xprv: self.xprv.clone(),
+            receive: self.receive.clone(),
+            change: self.change.clone(),
+            addresses: self.addresses.clone(),
+        }
+    }
+}
+
+impl HdAccount {
+    /// Creates the account of a new seed
+    /// # Errors
+    /// Returns an error if the seed is invalid
+    pub fn from_seed(seed: &[u8]) -> Result<HdAccount, Box<dyn Error>> {
+        let master = ExtendedPrivKey::from_seed(seed, Network::Testnet)?;
+        Self::from_xprv(master, 0, 0)
+    }
+
+    /// Creates the account from an extended private key, a master key is derived to ACCOUNT_PATH.
+    /// `receive` and `change` are the amount of keys already issued on each chain
+    /// # Errors
+    /// Returns an error if the key is neither a master nor an account key
+    pub fn from_xprv(
+        xprv: ExtendedPrivKey,
+        receive: u32,
+        change: u32,
+    ) -> Result<HdAccount, Box<dyn Error>> {
+        let account = match xprv.get_depth() {
+            0 => xprv.derive_path(ACCOUNT_PATH)?,
+            ACCOUNT_DEPTH => xprv,
+            _ => return Err("The extended key must be a master or account key".into()),
+        };
+        Self::build(account.to_extended_public(), Some(account), receive, change)
+    }
+
+    /// Creates a locked account that only derives public keys
+    /// # Errors
+    /// Returns an error if the keys can't be derived
+    pub fn from_xpub(
+        xpub: ExtendedPubKey,
+        receive: u32,
+        change: u32,
+    ) -> Result<HdAccount, Box<dyn Error>> {
+        Self::build(xpub, None, receive, change)
+    }
+
+    fn build(
+        xpub: ExtendedPubKey,
+        xprv: Option<ExtendedPrivKey>,
+        receive: u32,
+        change: u32,
+    ) -> Result<HdAccount, Box<dyn Error>> {
+        let mut hd_account = HdAccount {
+            xpub,
+            xprv,
+            receive: KeyChain::new(receive),
+            change: KeyChain::new(change),
+            addresses: HashMap::new(),
+        };
+        hd_account.fill_window()?;
+        Ok(hd_account)
+    }
+
+    /// Derives the keys missing in the window of each chain
+    fn fill_window(&mut self) -> Result<(), Box<dyn Error>> {
+        for chain in [RECEIVE_CHAIN, CHANGE_CHAIN] {
+            loop {
+                let key_chain = self.key_chain(chain);
+                let index = key_chain.keys.len() as u32;
+                if index >= key_chain.window() {
+                    break;
+                }
+                let keys = self.derive_keys(chain, index)?;
+                for address in keys.get_all_addresses() {
+                    self.addresses.insert(address, (chain, index));
+                }
+                self.key_chain_mut(chain).keys.push(keys);
+            }
+        }
+        Ok(())
+    }
+
+    fn key_chain(&self, chain: u32) -> &KeyChain {
+        match chain {
+            RECEIVE_CHAIN => &self.receive,
+            _ => &self.change,
+        }
+    }
+
+    fn key_chain_mut(&mut self, chain: u32) -> &mut KeyChain {
+        match chain {
+            RECEIVE_CHAIN => &mut self.receive,
+            _ => &mut self.change,
+        }
+    }
+
+    /// Derives the key `index` of `chain`, with its private key if the account is unlocked
+    /// # Errors
+    /// Returns an error if the key can't be derived
+    pub fn derive_keys(&self, chain: u32, index: u32) -> Result<KeysHandler, Box<dyn Error>> {
+        if let Some(xprv) = &self.xprv {
+            let child = xprv.derive_child(chain)?.derive_child(index)?;
+            return match KeysHandler::new(&u8_array_to_hex_string(&child.get_private_key())) {
+                Some(keys_handler) => Ok(keys_handler),
+                None => Err("Invalid derived key".into()),
+            };
+        }
+        let child = self.xpub.derive_child(chain)?.derive_child(index)?;
+        Ok(KeysHandler::from_public_key(
+            child.get_public_key().to_vec(),
+        ))
+    }
+
+    /// Issues the next key of `chain`, extending the window if needed
+    fn next_keys(&mut self, chain: u32) -> Result<KeysHandler, Box<dyn Error>> {
+        self.key_chain_mut(chain).issued += 1;
+        self.fill_window()?;
+        let key_chain = self.key_chain(chain);
+        Ok(key_chain.keys[key_chain.issued as usize - 1].clone())
+    }
+
+    /// Issues a fresh receive key
+    /// # Errors
+    /// Returns an error if the key can't be derived
+    pub fn next_receive_keys(&mut self) -> Result<KeysHandler, Box<dyn Error>> {
+        self.next_keys(RECEIVE_CHAIN)
+    }
+
+    /// Issues a fresh change key
+    /// # Errors
+    /// Returns an error if the key can't be derived
+    pub fn next_change_keys(&mut self) -> Result<KeysHandler, Box<dyn Error>> {
+        self.next_keys(CHANGE_CHAIN)
+    }
+
+    /// Marks the key of a watched address as used and moves its chain window, so there
+    /// are always GAP_LIMIT unused addresses watched after it. Returns false if it isn't ours
+    /// # Errors
+    /// Returns an error if the new lookahead keys can't be derived
+    pub fn mark_used(&mut self, address: &str) -> Result<bool, Box<dyn Error>> {
+        let (chain, index) = match self.addresses.get(address) {
+            Some(position) => *position,
+            None => return Ok(false),
+        };
+        let key_chain = self.key_chain_mut(chain);
+        key_chain.used = key_chain.used.max(index + 1);
+        key_chain.issued = key_chain.issued.max(key_chain.used);
+        self.fill_window()?;
+        Ok(true)
+    }
+
+    /// Marks every watched address found used by `is_used`, repeating while the windows move,
+    /// so the addresses of a restored wallet are found. The last receive key issued is kept
+    /// unused. Returns true if anything changed
+    /// # Errors
+    /// Returns an error if the keys can't be derived
+    pub fn discover(&mut self, is_used: impl Fn(&str) -> bool) -> Result<bool, Box<dyn Error>> {
+        let before = (self.addresses.len(), self.get_issued());
+        let mut checked = 0;
+        while checked < self.addresses.len() {
+            checked = self.addresses.len();
+            let used: Vec<String> = self
+                .addresses
+                .keys()
+                .filter(|address| is_used(address))
+                .cloned()
+                .collect();
+            for address in used {
+                self.mark_used(&address)?;
+            }
+        }
+        if self.receive.issued == 0 || self.receive.used >= self.receive.issued {
+            self.next_receive_keys()?;
+        }
+        Ok(before != (self.addresses.len(), self.get_issued()))
+    }
+
+    /// Returns true if the address belongs to any watched key, issued or lookahead
+    pub fn is_mine(&self, address: &str) -> bool {
+        self.addresses.contains_key(address)
+    }
+
+    /// Returns every issued key, receive keys first
+    pub fn issued_keys(&self) -> Vec<KeysHandler> {
+        let mut keys = self.receive.keys[..self.receive.issued as usize].to_vec();
+        keys.extend_from_slice(&self.change.keys[..self.change.issued as usize]);
+        keys
+    }
+
+    /// Returns every watched key: the issued ones and the lookahead of both chains
+    pub fn watched_keys(&self) -> Vec<KeysHandler> {
+        let mut keys = self.receive.keys.clone();
+        keys.extend(self.change.keys.iter().cloned());
+        keys
+    }
+
+    /// Returns every watched address, legacy, segwit and taproot
+    pub fn watched_addresses(&self) -> Vec<String> {
+        self.addresses.keys().cloned().collect()
+    }
+
+    /// Last receive key issued, None if there's none yet
+    pub fn last_receive_keys(&self) -> Option<KeysHandler> {
+        match self.receive.issued {
+            0 => None,
+            issued => self.receive.keys.get(issued as usize - 1).cloned(),
+        }
+    }
+
+    /// Forgets the account private key and the private keys derived from it
+    pub fn lock(&mut self) {
+        self.xprv = None;
+        for keys in self.receive.keys.iter_mut() {
+            keys.lock();
+        }
+        for keys in self.change.keys.iter_mut() {
+            keys.lock();
+        }
+    }
+
+    /// Restores the account private key from its encoding, returns false if it doesn't belong to the account
+    pub fn unlock(&mut self, encoded_xprv: &str) -> bool {
+        let xprv = match ExtendedPrivKey::decode(encoded_xprv) {
+            Ok(xprv) => xprv,
+            Err(_) => return false,
+        };
+        if xprv.to_extended_public() != self.xpub {
+            return false;
+        }
+        self.xprv = Some(xprv);
+        self.receive.keys.clear();
+        self.change.keys.clear();
+        self.fill_window().is_ok()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.xprv.is_none()
+    }
+
+    /// Encoded account private key (tprv), empty if the account is locked
+    pub fn get_xprv(&self) -> String {
+        match &self.xprv {
+            Some(xprv) => xprv.encode(),
+            None => String::new(),
+        }
+    }
+
+    /// Encoded account public key (tpub)
+    pub fn get_xpub(&self) -> String {
+        self.xpub.encode()
+    }
+
+    /// Amount of keys issued on the receive and change chains
+    pub fn get_issued(&self) -> (u32, u32) {
+        (self.receive.issued, self.change.issued)
+    }
+}
+
+#[cfg(test)]
+mod hd_account_tests {
+    use super::*;
+    use crate::utils::array_tools::cast_str_to_bytes_vec;
+
+    fn account() -> HdAccount {
+        let seed = cast_str_to_bytes_vec("000102030405060708090a0b0c0d0e0f").unwrap();
+        HdAccount::from_seed(&seed).unwrap()
+    }
+
+    #[test]
+    fn test_fresh_addresses() {
+        let mut account = account();
+        let first = account.next_receive_keys().unwrap();
+        let second = account.next_receive_keys().unwrap();
+        let change = account.next_change_keys().unwrap();
+        assert_ne!(first.get_address(), second.get_address());
+        assert_ne!(second.get_address(), change.get_address());
+        assert!(first.get_address().starts_with('m') || first.get_address().starts_with('n'));
+        assert_eq!(account.get_issued(), (2, 1));
+        assert_eq!(account.issued_keys().len(), 3);
+    }
+
+    #[test]
+    fn test_watch_only_derives_same_addresses() {
+        let mut account = account();
+        let receive = account.next_receive_keys().unwrap();
+        let xpub = ExtendedPubKey::decode(&account.get_xpub()).unwrap();
+        let watch = HdAccount::from_xpub(xpub, 1, 0).unwrap();
+        let watched = watch.last_receive_keys().unwrap();
+        assert_eq!(watched.get_address(), receive.get_address());
+        assert!(!watched.has_private_key());
+    }
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let mut account = account();
+        account.next_receive_keys().unwrap();
+        let xprv = account.get_xprv();
+        account.lock();
+        assert!(account.is_locked());
+        assert!(!account.last_receive_keys().unwrap().has_private_key());
+        assert!(!account.unlock(&account_of_other_seed()));
+        assert!(account.unlock(&xprv));
+        assert!(account.last_receive_keys().unwrap().has_private_key());
+    }
+
+    #[test]
+    fn test_lookahead_window() {
+        let mut account = account();
+        assert_eq!(account.watched_keys().len(), 2 * GAP_LIMIT as usize);
+        let lookahead = account.derive_keys(RECEIVE_CHAIN, GAP_LIMIT - 1).unwrap();
+        let outside = account.derive_keys(RECEIVE_CHAIN, GAP_LIMIT).unwrap();
+        assert!(account.is_mine(&lookahead.get_address()));
+        assert!(account.is_mine(&lookahead.get_segwit_address()));
+        assert!(account.is_mine(&lookahead.get_taproot_address()));
+        assert!(!account.is_mine(&outside.get_address()));
+
+        // Using the last watched address moves the window
+        assert!(account.mark_used(&lookahead.get_segwit_address()).unwrap());
+        assert!(account.is_mine(&outside.get_address()));
+        assert_eq!(account.get_issued(), (GAP_LIMIT, 0));
+        assert_eq!(account.watched_keys().len(), 3 * GAP_LIMIT as usize);
+        assert!(!account
+            .mark_used("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_discover_used_addresses() {
+        let original = account();
+        // Gaps smaller than the limit, each address is only watched after the previous one is found
+        let mut used: Vec<String> = [5, 5 + GAP_LIMIT - 1, 5 + 2 * GAP_LIMIT - 2]
+            .iter()
+            .map(|index| {
+                original
+                    .derive_keys(RECEIVE_CHAIN, *index)
+                    .unwrap()
+                    .get_address()
+            })
+            .collect();
+        used.push(original.derive_keys(CHANGE_CHAIN, 3).unwrap().get_address());
+
+        let mut restored = account();
+        assert!(!restored.is_mine(&used[2]));
+        assert!(restored
+            .discover(|address| used.contains(&address.to_string()))
+            .unwrap());
+        // The used receive addresses and a fresh one after them
+        assert_eq!(restored.get_issued(), (5 + 2 * GAP_LIMIT, 4));
+        assert!(restored.is_mine(&used[2]));
+        assert!(!restored
+            .discover(|address| used.contains(&address.to_string()))
+            .unwrap());
+    }
+
+    fn account_of_other_seed() -> String {
+        HdAccount::from_seed(&[7u8; 32]).unwrap().get_xprv()
+    }
+}
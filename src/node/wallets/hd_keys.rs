@@ -0,0 +1,447 @@
+use bitcoin_hashes::hmac::{Hmac, HmacEngine};
+use bitcoin_hashes::{hash160, sha256d, sha512, Hash, HashEngine};
+use secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
+use std::error::Error;
+
+/// Child numbers from this one on are hardened
+pub const HARDENED: u32 = 1 << 31;
+
+const MAINNET_PRIVATE_VERSION: [u8; 4] = [0x04, 0x88, 0xad, 0xe4]; // xprv
+const MAINNET_PUBLIC_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e]; // xpub
+const TESTNET_PRIVATE_VERSION: [u8; 4] = [0x04, 0x35, 0x83, 0x94]; // tprv
+const TESTNET_PUBLIC_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf]; // tpub
+const SERIALIZED_LEN: usize = 78;
+const MAX_DEPTH_ERROR: &str = "Keys at depth 255 can't have children";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+/// BIP32 extended private key
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedPrivKey {
+    network: Network,
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: u32,
+    chain_code: [u8; 32],
+    private_key: [u8; 32],
+}
+
+/// BIP32 extended public key, only derives normal (non hardened) children
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedPubKey {
+    network: Network,
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: u32,
+    chain_code: [u8; 32],
+    public_key: [u8; 33],
+}
+
+impl ExtendedPrivKey {
+    /// Creates the master key of a seed of 16 to 64 bytes
+    /// # Errors
+    /// Returns an error if the seed length is invalid or it produces an invalid key
+    pub fn from_seed(seed: &[u8], network: Network) -> Result<ExtendedPrivKey, Box<dyn Error>> {
+        if seed.len() < 16 || seed.len() > 64 {
+            return Err("The seed must have between 16 and 64 bytes".into());
+        }
+        let (key, chain_code) = hmac_sha512(b"Bitcoin seed", seed);
+        SecretKey::from_slice(&key)?;
+        Ok(ExtendedPrivKey {
+            network,
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+            chain_code,
+            private_key: key,
+        })
+    }
+
+    /// Derives the child `index`, hardened if `index` >= HARDENED
+    /// # Errors
+    /// Returns an error if the key is at depth 255 or in the (very unlikely) case
+    /// the child key is invalid
+    pub fn derive_child(&self, index: u32) -> Result<ExtendedPrivKey, Box<dyn Error>> {
+        let mut data = Vec::with_capacity(37);
+        if index >= HARDENED {
+            data.push(0);
+            data.extend_from_slice(&self.private_key);
+        } else {
+            data.extend_from_slice(&self.public_key());
+        }
+        data.extend_from_slice(&index.to_be_bytes());
+        let (tweak, chain_code) = hmac_sha512(&self.chain_code, &data);
+
+        let tweak = Scalar::from_be_bytes(tweak)?;
+        let private_key = SecretKey::from_slice(&self.private_key)?.add_tweak(&tweak)?;
+        Ok(ExtendedPrivKey {
+            network: self.network,
+            depth: self.depth.checked_add(1).ok_or(MAX_DEPTH_ERROR)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            private_key: private_key.secret_bytes(),
+        })
+    }
+
+    /// Derives a path like `m/44'/1'/0'/0/5`, `h` can also be used for hardened indexes
+    /// # Errors
+    /// Returns an error if the path is invalid
+    pub fn derive_path(&self, path: &str) -> Result<ExtendedPrivKey, Box<dyn Error>> {
+        let mut key = self.clone();
+        for index in parse_path(path)? {
+            key = key.derive_child(index)?;
+        }
+        Ok(key)
+    }
+
+    /// Returns the compressed public key
+    pub fn public_key(&self) -> [u8; 33] {
+        let secp = Secp256k1::new();
+        match SecretKey::from_slice(&self.private_key) {
+            Ok(secret_key) => PublicKey::from_secret_key(&secp, &secret_key).serialize(),
+            Err(_) => [0; 33],
+        }
+    }
+
+    pub fn to_extended_public(&self) -> ExtendedPubKey {
+        ExtendedPubKey {
+            network: self.network,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: self.public_key(),
+        }
+    }
+
+    /// First 4 bytes of the hash160 of the public key
+    pub fn fingerprint(&self) -> [u8; 4] {
+        fingerprint(&self.public_key())
+    }
+
+    pub fn get_private_key(&self) -> [u8; 32] {
+        self.private_key
+    }
+
+    pub fn get_network(&self) -> Network {
+        self.network
+    }
+
+    pub fn get_depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Encodes the key as xprv (tprv in testnet)
+    pub fn encode(&self) -> String {
+        let version = match self.network {
+            Network::Mainnet => MAINNET_PRIVATE_VERSION,
+            Network::Testnet => TESTNET_PRIVATE_VERSION,
+        };
+        let mut key = vec![0];
+        key.extend_from_slice(&self.private_key);
+        serialize(
+            version,
+            (
+                self.depth,
+                self.parent_fingerprint,
+                self.child_number,
+                self.chain_code,
+            ),
+            &key,
+        )
+    }
+
+    /// # Errors
+    /// Returns an error if the string isn't a valid xprv or tprv
+    pub fn decode(encoded: &str) -> Result<ExtendedPrivKey, Box<dyn Error>> {
+        let (version, (depth, parent_fingerprint, child_number, chain_code), key) =
+            deserialize(encoded)?;
+        let network = match version {
+            MAINNET_PRIVATE_VERSION => Network::Mainnet,
+            TESTNET_PRIVATE_VERSION => Network::Testnet,
+            _ => return Err("Not an extended private key".into()),
+        };
+        if key[0] != 0 {
+            return Err("Invalid extended private key".into());
+        }
+        let mut private_key = [0u8; 32];
+        private_key.copy_from_slice(&key[1..]);
+        SecretKey::from_slice(&private_key)?;
+        Ok(ExtendedPrivKey {
+            network,
+            depth,
+            parent_fingerprint,
+            child_number,
+            chain_code,
+            private_key,
+        })
+    }
+}
+
+impl ExtendedPubKey {
+    /// Derives the normal child `index`
+    /// # Errors
+    /// Returns an error if `index` is hardened or the key is at depth 255
+    pub fn derive_child(&self, index: u32) -> Result<ExtendedPubKey, Box<dyn Error>> {
+        if index >= HARDENED {
+            return Err("Hardened children can't be derived from a public key".into());
+        }
+        let mut data = self.public_key.to_vec();
+        data.extend_from_slice(&index.to_be_bytes());
+        let (tweak, chain_code) = hmac_sha512(&self.chain_code, &data);
+
+        let secp = Secp256k1::new();
+        let tweak = Scalar::from_be_bytes(tweak)?;
+        let public_key = PublicKey::from_slice(&self.public_key)?.add_exp_tweak(&secp, &tweak)?;
+        Ok(ExtendedPubKey {
+            network: self.network,
+            depth: self.depth.checked_add(1).ok_or(MAX_DEPTH_ERROR)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            public_key: public_key.serialize(),
+        })
+    }
+
+    /// Derives a path of normal indexes like `m/0/5`, relative to this key
+    /// # Errors
+    /// Returns an error if the path is invalid or has hardened indexes
+    pub fn derive_path(&self, path: &str) -> Result<ExtendedPubKey, Box<dyn Error>> {
+        let mut key = self.clone();
+        for index in parse_path(path)? {
+            key = key.derive_child(index)?;
+        }
+        Ok(key)
+    }
+
+    pub fn fingerprint(&self) -> [u8; 4] {
+        fingerprint(&self.public_key)
+    }
+
+    pub fn get_public_key(&self) -> [u8; 33] {
+        self.public_key
+    }
+
+    pub fn get_network(&self) -> Network {
+        self.network
+    }
+
+    pub fn get_depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Encodes the key as xpub (tpub in testnet)
+    pub fn encode(&self) -> String {
+        let version = match self.network {
+            Network::Mainnet => MAINNET_PUBLIC_VERSION,
+            Network::Testnet => TESTNET_PUBLIC_VERSION,
+        };
+        serialize(
+            version,
+            (
+                self.depth,
+                self.parent_fingerprint,
+                self.child_number,
+                self.chain_code,
+            ),
+            &self.public_key,
+        )
+    }
+
+    /// # Errors
+    /// Returns an error if the string isn't a valid xpub or tpub
+    pub fn decode(encoded: &str) -> Result<ExtendedPubKey, Box<dyn Error>> {
+        let (version, (depth, parent_fingerprint, child_number, chain_code), key) =
+            deserialize(encoded)?;
+        let network = match version {
+            MAINNET_PUBLIC_VERSION => Network::Mainnet,
+            TESTNET_PUBLIC_VERSION => Network::Testnet,
+            _ => return Err("Not an extended public key".into()),
+        };
+        PublicKey::from_slice(&key)?;
+        Ok(ExtendedPubKey {
+            network,
+            depth,
+            parent_fingerprint,
+            child_number,
+            chain_code,
+            public_key: key,
+        })
+    }
+}
+
+/// Parses a derivation path like `m/44'/1'/0'/0/5` into child numbers
+/// # Errors
+/// Returns an error if any index is not a number or is empty, like in `m//0`
+pub fn parse_path(path: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+    let mut indexes = vec![];
+    let path = path.trim();
+    if path.is_empty() {
+        return Ok(indexes);
+    }
+    for (position, part) in path.split('/').enumerate() {
+        if position == 0 && part == "m" {
+            continue;
+        }
+        if part.is_empty() {
+            return Err(format!("Empty index in the path {}", path).into());
+        }
+        let (number, hardened) = match part.strip_suffix(['\'', 'h', 'H']) {
+            Some(number) => (number, true),
+            None => (part, false),
+        };
+        let index: u32 = number.parse()?;
+        if index >= HARDENED {
+            return Err("Index out of range".into());
+        }
+        indexes.push(if hardened { index + HARDENED } else { index });
+    }
+    Ok(indexes)
+}
+
+/// Splits HMAC-SHA512(key, data) in its left (key material) and right (chain code) halves
+fn hmac_sha512(key: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut engine = HmacEngine::<sha512::Hash>::new(key);
+    engine.input(data);
+    let hash = Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+    let mut left = [0u8; 32];
+    let mut right = [0u8; 32];
+    left.copy_from_slice(&hash[..32]);
+    right.copy_from_slice(&hash[32..]);
+    (left, right)
+}
+
+fn fingerprint(public_key: &[u8]) -> [u8; 4] {
+    let hash = hash160::Hash::hash(public_key).to_byte_array();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Depth, parent fingerprint, child number and chain code of a serialized key
+type KeyMetadata = (u8, [u8; 4], u32, [u8; 32]);
+
+fn serialize(version: [u8; 4], metadata: KeyMetadata, key: &[u8]) -> String {
+    let (depth, parent_fingerprint, child_number, chain_code) = metadata;
+    let mut data = version.to_vec();
+    data.push(depth);
+    data.extend_from_slice(&parent_fingerprint);
+    data.extend_from_slice(&child_number.to_be_bytes());
+    data.extend_from_slice(&chain_code);
+    data.extend_from_slice(key);
+    let checksum = sha256d::Hash::hash(&data).to_byte_array();
+    data.extend_from_slice(&checksum[..4]);
+    bs58::encode(data).into_string()
+}
+
+/// Version, metadata and key data (private keys are prefixed with 0)
+type SerializedKey = ([u8; 4], KeyMetadata, [u8; 33]);
+
+fn deserialize(encoded: &str) -> Result<SerializedKey, Box<dyn Error>> {
+    let data = bs58::decode(encoded.trim()).into_vec()?;
+    if data.len() != SERIALIZED_LEN + 4 {
+        return Err("Invalid extended key length".into());
+    }
+    let (payload, checksum) = data.split_at(SERIALIZED_LEN);
+    if sha256d::Hash::hash(payload).to_byte_array()[..4] != *checksum {
+        return Err("Invalid extended key checksum".into());
+    }
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&payload[0..4]);
+    let mut parent_fingerprint = [0u8; 4];
+    parent_fingerprint.copy_from_slice(&payload[5..9]);
+    let mut child_number = [0u8; 4];
+    child_number.copy_from_slice(&payload[9..13]);
+    let mut chain_code = [0u8; 32];
+    chain_code.copy_from_slice(&payload[13..45]);
+    let mut key = [0u8; 33];
+    key.copy_from_slice(&payload[45..78]);
+    Ok((
+        version,
+        (
+            payload[4],
+            parent_fingerprint,
+            u32::from_be_bytes(child_number),
+            chain_code,
+        ),
+        key,
+    ))
+}
+
+#[cfg(test)]
+mod hd_keys_tests {
+    use super::*;
+    use crate::utils::array_tools::cast_str_to_bytes_vec;
+
+    // BIP32 test vector 1
+    fn master() -> ExtendedPrivKey {
+        let seed = cast_str_to_bytes_vec("000102030405060708090a0b0c0d0e0f").unwrap();
+        ExtendedPrivKey::from_seed(&seed, Network::Mainnet).unwrap()
+    }
+
+    #[test]
+    fn test_master_key() {
+        let master = master();
+        assert_eq!(master.encode(), "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi");
+        assert_eq!(master.to_extended_public().encode(), "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8");
+    }
+
+    #[test]
+    fn test_hardened_and_normal_derivation() {
+        let master = master();
+        let child = master.derive_path("m/0'").unwrap();
+        assert_eq!(child.encode(), "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7");
+        let grandchild = master.derive_path("m/0h/1").unwrap();
+        assert_eq!(grandchild.encode(), "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs");
+        assert_eq!(grandchild.to_extended_public().encode(), "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ");
+
+        let mut deepest = master.clone();
+        deepest.depth = u8::MAX;
+        assert!(deepest.derive_child(0).is_err());
+        assert!(deepest.to_extended_public().derive_child(0).is_err());
+    }
+
+    #[test]
+    fn test_public_derivation_matches_private() {
+        let child = master().derive_path("m/0'").unwrap();
+        let from_public = child.to_extended_public().derive_child(1).unwrap();
+        assert_eq!(
+            from_public,
+            child.derive_child(1).unwrap().to_extended_public()
+        );
+        assert!(child.to_extended_public().derive_child(HARDENED).is_err());
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let key = master().derive_path("m/44'/1'/0'").unwrap();
+        let testnet = ExtendedPrivKey {
+            network: Network::Testnet,
+            ..key
+        };
+        let encoded = testnet.encode();
+        assert!(encoded.starts_with("tprv"));
+        assert_eq!(ExtendedPrivKey::decode(&encoded).unwrap(), testnet);
+
+        let public = testnet.to_extended_public();
+        assert!(public.encode().starts_with("tpub"));
+        assert_eq!(ExtendedPubKey::decode(&public.encode()).unwrap(), public);
+        assert!(ExtendedPrivKey::decode(&public.encode()).is_err());
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path("m/44'/1h/0/7").unwrap(),
+            vec![44 + HARDENED, 1 + HARDENED, 0, 7]
+        );
+        assert!(parse_path("m/a").is_err());
+        assert!(parse_path("m//0").is_err());
+        assert!(parse_path("m/0/").is_err());
+        assert_eq!(parse_path("m").unwrap(), vec![]);
+        assert_eq!(parse_path("").unwrap(), vec![]);
+    }
+}
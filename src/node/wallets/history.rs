@@ -0,0 +1,283 @@
+use crate::utils::array_tools::{cast_str_to_fixed_bytes, txid_to_hex, u8_array_to_hex_string};
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File the history of every wallet is saved to, each line starts with the id of its wallet
+pub const HISTORY_PATH: &str = "./storage/history.txt";
+/// Header of the history exported to CSV
+const CSV_HEADER: &str = "txid,amount,fee,height,timestamp,confirmations,label";
+
+/// Hash, height and time of a block
+pub type BlockPosition = ([u8; 32], u32, u32);
+
+/// Transaction of a wallet: the net amount it received (negative if it spent), the fee
+/// if the wallet paid it, the block that confirmed it and the label chosen by the user.
+/// The timestamp is the time of the block or, while unconfirmed, when it was first seen
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub txid: [u8; 32],
+    pub amount: i64,
+    pub fee: Option<i64>,
+    pub block: Option<([u8; 32], u32)>,
+    pub timestamp: u32,
+    pub label: String,
+}
+
+impl HistoryEntry {
+    /// Returns the height of the block that confirmed the transaction
+    pub fn height(&self) -> Option<u32> {
+        self.block.map(|(_, height)| height)
+    }
+
+    /// Returns the amount of blocks from the one that confirmed the transaction to the tip,
+    /// 0 while it's unconfirmed
+    pub fn confirmations(&self, tip: u32) -> u32 {
+        match self.height() {
+            Some(height) if tip >= height => tip - height + 1,
+            _ => 0,
+        }
+    }
+
+    /// Returns the entry as a line of the history file, the label goes last as it may have commas
+    fn to_line(&self) -> String {
+        let (block_hash, height) = match self.block {
+            Some((hash, height)) => (u8_array_to_hex_string(&hash), height.to_string()),
+            None => (String::new(), String::new()),
+        };
+        format!(
+            "{},{},{},{},{},{},{}",
+            u8_array_to_hex_string(&self.txid),
+            self.amount,
+            optional_to_string(self.fee),
+            block_hash,
+            height,
+            self.timestamp,
+            self.label
+        )
+    }
+
+    /// Parses a line written by to_line
+    fn from_line(line: &str) -> Option<HistoryEntry> {
+        let parts: Vec<&str> = line.splitn(7, ',').collect();
+        if parts.len() != 7 {
+            return None;
+        }
+        let block = match (parts[3], parts[4]) {
+            ("", "") => None,
+            (hash, height) => Some((cast_str_to_fixed_bytes(hash).ok()?, height.parse().ok()?)),
+        };
+        Some(HistoryEntry {
+            txid: cast_str_to_fixed_bytes(parts[0]).ok()?,
+            amount: parts[1].parse().ok()?,
+            fee: match parts[2] {
+                "" => None,
+                fee => Some(fee.parse().ok()?),
+            },
+            block,
+            timestamp: parts[5].parse().ok()?,
+            label: parts[6].to_string(),
+        })
+    }
+}
+
+/// Ledger of the transactions that pay to or spend from a wallet, in the order they were seen
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransactionHistory {
+    entries: Vec<HistoryEntry>,
+}
+
+impl TransactionHistory {
+    pub fn new() -> TransactionHistory {
+        TransactionHistory::default()
+    }
+
+    /// Adds the entry, replacing the one of the same transaction.
+    /// The label of the replaced entry is kept if the new one has none
+    pub fn add(&mut self, mut entry: HistoryEntry) {
+        match self.entries.iter_mut().find(|old| old.txid == entry.txid) {
+            Some(old) => {
+                if entry.label.is_empty() {
+                    entry.label = old.label.clone();
+                }
+                *old = entry;
+            }
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Forgets a transaction, for one replaced by another paying a higher fee
+    pub fn remove(&mut self, txid: &[u8; 32]) -> Option<HistoryEntry> {
+        let index = self.entries.iter().position(|entry| entry.txid == *txid)?;
+        Some(self.entries.remove(index))
+    }
+
+    pub fn get(&self, txid: &[u8; 32]) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|entry| entry.txid == *txid)
+    }
+
+    pub fn get_entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Marks a transaction as confirmed by the block, returns false if it isn't in the history
+    pub fn confirm(&mut self, txid: &[u8; 32], (hash, height, time): BlockPosition) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.txid == *txid) {
+            Some(entry) => {
+                entry.block = Some((hash, height));
+                entry.timestamp = time;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A block connected at `height`: transactions confirmed at that height or above
+    /// by other blocks were in blocks reorganized out of the chain, so they are unconfirmed again
+    pub fn connect_block(&mut self, hash: [u8; 32], height: u32) {
+        for entry in self.entries.iter_mut() {
+            if let Some((block_hash, block_height)) = entry.block {
+                if block_height >= height && block_hash != hash {
+                    entry.block = None;
+                }
+            }
+        }
+    }
+
+    /// Sets the label of a transaction
+    /// # Errors
+    /// Returns an error if the transaction isn't in the history or the label has line breaks
+    pub fn set_label(&mut self, txid: &[u8; 32], label: &str) -> Result<(), Box<dyn Error>> {
+        if label.contains(['\n', '\r']) {
+            return Err("The label can't have line breaks".into());
+        }
+        match self.entries.iter_mut().find(|entry| entry.txid == *txid) {
+            Some(entry) => {
+                entry.label = label.to_string();
+                Ok(())
+            }
+            None => Err("The transaction isn't in the history of the wallet".into()),
+        }
+    }
+
+    /// Returns the history in CSV, with the confirmations counted up to the tip
+    pub fn to_csv(&self, tip: u32) -> String {
+        let mut csv = format!("{}\n", CSV_HEADER);
+        for entry in self.entries.iter() {
+            let label = entry.label.replace('"', "\"\"");
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},\"{}\"\n",
+                txid_to_hex(&entry.txid),
+                entry.amount,
+                optional_to_string(entry.fee),
+                optional_to_string(entry.height()),
+                entry.timestamp,
+                entry.confirmations(tip),
+                label
+            ));
+        }
+        csv
+    }
+
+    /// Returns the lines the history is saved as
+    pub fn get_file_lines(&self) -> Vec<String> {
+        self.entries.iter().map(HistoryEntry::to_line).collect()
+    }
+
+    /// Adds the entry saved in a line of the history file, returns false if it's malformed
+    pub fn open_line(&mut self, line: &str) -> bool {
+        match HistoryEntry::from_line(line) {
+            Some(entry) => {
+                self.add(entry);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Seconds since the epoch, for the entries of transactions seen before they are confirmed
+pub fn current_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+fn optional_to_string<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+
+    fn entry(byte: u8, amount: i64, label: &str) -> HistoryEntry {
+        HistoryEntry {
+            txid: [byte; 32],
+            amount,
+            fee: None,
+            block: None,
+            timestamp: 1_700_000_000,
+            label: label.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_history_confirmations_and_reorgs() {
+        let mut history = TransactionHistory::new();
+        history.add(entry(1, -5_000, "rent"));
+        history.add(entry(2, 7_000, ""));
+        assert!(history.confirm(&[1; 32], ([10; 32], 100, 1_700_000_600)));
+        assert!(history.confirm(&[2; 32], ([11; 32], 101, 1_700_001_200)));
+        assert!(!history.confirm(&[3; 32], ([11; 32], 101, 1_700_001_200)));
+        assert_eq!(history.get(&[1; 32]).unwrap().confirmations(105), 6);
+        assert_eq!(history.get(&[1; 32]).unwrap().timestamp, 1_700_000_600);
+
+        // Another block at height 101 reorganizes out the one confirming the second transaction
+        history.connect_block([12; 32], 101);
+        assert_eq!(history.get(&[1; 32]).unwrap().height(), Some(100));
+        assert_eq!(history.get(&[2; 32]).unwrap().confirmations(105), 0);
+
+        // Seeing it again keeps the label
+        history.add(entry(1, -5_000, ""));
+        assert_eq!(history.get(&[1; 32]).unwrap().label, "rent");
+        assert!(history.remove(&[1; 32]).is_some());
+        assert_eq!(history.get_entries().len(), 1);
+    }
+
+    #[test]
+    fn test_history_labels_file_lines_and_csv() {
+        let mut history = TransactionHistory::new();
+        history.add(entry(1, -5_000, ""));
+        history.add(HistoryEntry {
+            fee: Some(300),
+            ..entry(2, -9_000, "")
+        });
+        history.confirm(&[2; 32], ([10; 32], 100, 1_700_000_600));
+        history.set_label(&[1; 32], "rent, \"May\"").unwrap();
+        assert!(history.set_label(&[3; 32], "nope").is_err());
+        assert!(history.set_label(&[1; 32], "two\nlines").is_err());
+
+        let mut opened = TransactionHistory::new();
+        for line in history.get_file_lines() {
+            assert!(opened.open_line(&line));
+        }
+        assert_eq!(opened, history);
+        assert!(!opened.open_line("not,a,history,line"));
+
+        let csv = history.to_csv(100);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            format!(
+                "{},-5000,,,1700000000,0,\"rent, \"\"May\"\"\"",
+                "01".repeat(32)
+            )
+        );
+        assert_eq!(
+            lines[2],
+            format!("{},-9000,300,100,1700000600,1,\"\"", "02".repeat(32))
+        );
+    }
+}
@@ -1,8 +1,14 @@
+use super::hd_keys::Network;
 use crate::node::validation_engine::hashes::vec_calculate_simple_hash_array_le;
+use crate::node::validation_engine::taproot::taproot_output_key;
 use crate::utils::array_tools::cast_array_to_string;
 use crate::utils::array_tools::cast_str_to_bytes_vec;
 use crate::utils::array_tools::cast_str_to_fixed_bytes;
 use crate::utils::array_tools::u8_array_to_hex_string;
+use crate::utils::array_tools::u8_vec_to_hex_string;
+use crate::utils::script_tools::{
+    p2pkh_script, p2tr_script, p2wpkh_script, segwit_address_of_hash, taproot_address_of_key,
+};
 use bitcoin_hashes::ripemd160;
 
 use bitcoin_hashes::sha256d;
@@ -10,9 +16,47 @@ use bitcoin_hashes::Hash;
 use secp256k1::{PublicKey, Secp256k1, SecretKey};
 use std::error::Error;
 
+const MAINNET_WIF_VERSION: u8 = 0x80;
+const TESTNET_WIF_VERSION: u8 = 0xef;
+/// Length of an uncompressed public key, compressed ones have 33 bytes
+const UNCOMPRESSED_LEN: usize = 65;
+/// Suffix of the private keys in WIF whose public key is compressed
+const COMPRESSED_FLAG: u8 = 0x01;
+
+/// Kinds of output a key can receive to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressType {
+    Legacy,  // P2PKH, base58
+    SegWit,  // P2WPKH, bech32
+    Taproot, // P2TR key path, bech32m
+}
+
+impl AddressType {
+    /// Name of the address type in the wallets file
+    pub fn tag(&self) -> &'static str {
+        match self {
+            AddressType::Legacy => "legacy",
+            AddressType::SegWit => "segwit",
+            AddressType::Taproot => "taproot",
+        }
+    }
+
+    /// Returns the address type named `tag` in the wallets file
+    pub fn from_tag(tag: &str) -> Option<AddressType> {
+        [
+            AddressType::Legacy,
+            AddressType::SegWit,
+            AddressType::Taproot,
+        ]
+        .into_iter()
+        .find(|address_type| address_type.tag() == tag)
+    }
+}
+
 pub struct KeysHandler {
-    private_key: [u8; 32],
+    private_key: Option<[u8; 32]>, // None while the wallet is locked
     pub public_key: Vec<u8>,
+    compressed: bool, // uncompressed keys only come from WIF imports
 }
 
 impl Clone for KeysHandler {
@@ -20,18 +64,17 @@ impl Clone for KeysHandler {
         KeysHandler {
             private_key: self.private_key,
             public_key: self.public_key.clone(),
+            compressed: self.compressed,
         }
     }
 }
 
 impl KeysHandler {
+    /// Creates the KeysHandler of a private key in hex or in WIF, compressed or not
     pub fn new(priv_key: &str) -> Option<KeysHandler> {
-        let private_key = match cast_str_to_fixed_bytes(priv_key) {
-            Ok(private_key) => private_key,
-            Err(_) => return None,
-        };
+        let (private_key, compressed) = Self::parse_private_key(priv_key)?;
 
-        let public_key = match Self::calculate_pubkey_vec(&private_key) {
+        let public_key = match Self::calculate_pubkey_vec(&private_key, compressed) {
             Some(public_key) => public_key,
             None => return None,
         };
@@ -39,13 +82,107 @@ impl KeysHandler {
         println!("Keys Handler ok");
 
         Some(KeysHandler {
-            private_key,
+            private_key: Some(private_key),
             public_key,
+            compressed,
         })
     }
 
+    /// Creates a locked KeysHandler that only knows the public key
+    pub fn from_public_key(public_key: Vec<u8>) -> KeysHandler {
+        KeysHandler {
+            private_key: None,
+            compressed: public_key.len() != UNCOMPRESSED_LEN,
+            public_key,
+        }
+    }
+
+    /// Decodes a private key in WIF, returns it with whether its public key is compressed
+    /// and the network it was encoded for
+    /// # Errors
+    /// Returns an error if it isn't valid base58check or doesn't have a WIF version and length
+    pub fn decode_wif(wif: &str) -> Result<([u8; 32], bool, Network), Box<dyn Error>> {
+        let data = bs58::decode(wif.trim()).into_vec()?;
+        if data.len() < 4 {
+            return Err("Invalid WIF length".into());
+        }
+        let (payload, checksum) = data.split_at(data.len() - 4);
+        if sha256d::Hash::hash(payload).to_byte_array()[..4] != *checksum {
+            return Err("Invalid WIF checksum".into());
+        }
+        let network = match payload.first() {
+            Some(&MAINNET_WIF_VERSION) => Network::Mainnet,
+            Some(&TESTNET_WIF_VERSION) => Network::Testnet,
+            _ => return Err("Invalid WIF version".into()),
+        };
+        let compressed = match payload.len() {
+            33 => false,
+            34 if payload[33] == COMPRESSED_FLAG => true,
+            _ => return Err("Invalid WIF length".into()),
+        };
+        let private_key: [u8; 32] = payload[1..33].try_into()?;
+        SecretKey::from_slice(&private_key)?;
+        Ok((private_key, compressed, network))
+    }
+
+    /// Returns the private key in WIF for the network, None if the wallet is locked
+    pub fn get_wif(&self, network: Network) -> Option<String> {
+        let private_key = self.private_key?;
+        let mut data = match network {
+            Network::Mainnet => vec![MAINNET_WIF_VERSION],
+            Network::Testnet => vec![TESTNET_WIF_VERSION],
+        };
+        data.extend_from_slice(&private_key);
+        if self.compressed {
+            data.push(COMPRESSED_FLAG);
+        }
+        let checksum = sha256d::Hash::hash(&data).to_byte_array();
+        data.extend_from_slice(&checksum[..4]);
+        Some(bs58::encode(data).into_string())
+    }
+
+    /// Returns true unless the key was imported from an uncompressed WIF
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Parses a private key in hex, whose public key is compressed, or in WIF
+    fn parse_private_key(priv_key: &str) -> Option<([u8; 32], bool)> {
+        if let Ok(private_key) = cast_str_to_fixed_bytes(priv_key) {
+            return Some((private_key, true));
+        }
+        let (private_key, compressed, _) = Self::decode_wif(priv_key).ok()?;
+        Some((private_key, compressed))
+    }
+
+    /// Forgets the private key
+    pub fn lock(&mut self) {
+        self.private_key = None;
+    }
+
+    /// Restores the private key, returns false if it doesn't match the public key
+    pub fn unlock(&mut self, priv_key: &str) -> bool {
+        let private_key = match Self::parse_private_key(priv_key) {
+            Some((private_key, _)) => private_key,
+            None => return false,
+        };
+        if !Self::keys_are_valid(&self.public_key, &private_key) {
+            return false;
+        }
+        self.private_key = Some(private_key);
+        true
+    }
+
+    pub fn has_private_key(&self) -> bool {
+        self.private_key.is_some()
+    }
+
+    /// Returns the private key, empty if the wallet is locked
     pub fn get_private_key(&self) -> &[u8] {
-        &self.private_key
+        match &self.private_key {
+            Some(private_key) => private_key,
+            None => &[],
+        }
     }
 
     /// retuns the address in string format
@@ -57,6 +194,74 @@ impl KeysHandler {
         }
     }
 
+    /// Returns the native segwit (P2WPKH) testnet address of the public key,
+    /// empty if it's uncompressed since segwit outputs can't be spent with those
+    pub fn get_segwit_address(&self) -> String {
+        match Self::calculate_pubkey_hash(&self.public_key) {
+            Some(public_key_hash) if self.compressed => segwit_address_of_hash(&public_key_hash),
+            _ => String::new(),
+        }
+    }
+
+    /// Returns the P2WPKH output script paying to the public key, empty if it's uncompressed
+    pub fn get_segwit_script(&self) -> Vec<u8> {
+        match Self::calculate_pubkey_hash(&self.public_key) {
+            Some(public_key_hash) if self.compressed => p2wpkh_script(&public_key_hash),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the P2PKH output script paying to the public key
+    pub fn get_script(&self) -> Vec<u8> {
+        match Self::calculate_pubkey_hash(&self.public_key) {
+            Some(public_key_hash) => p2pkh_script(&public_key_hash),
+            None => vec![],
+        }
+    }
+
+    /// Returns the taproot (P2TR) testnet address of the public key, without script tree
+    pub fn get_taproot_address(&self) -> String {
+        match taproot_output_key(&self.public_key) {
+            Ok(output_key) => taproot_address_of_key(&output_key),
+            Err(_) => String::new(),
+        }
+    }
+
+    /// Returns the P2TR output script paying to the tweaked public key
+    pub fn get_taproot_script(&self) -> Vec<u8> {
+        match taproot_output_key(&self.public_key) {
+            Ok(output_key) => p2tr_script(&output_key),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Returns the address of the given type
+    pub fn address_of(&self, address_type: AddressType) -> String {
+        match address_type {
+            AddressType::Legacy => self.get_address(),
+            AddressType::SegWit => self.get_segwit_address(),
+            AddressType::Taproot => self.get_taproot_address(),
+        }
+    }
+
+    /// Returns the output script of the given type
+    pub fn script_of(&self, address_type: AddressType) -> Vec<u8> {
+        match address_type {
+            AddressType::Legacy => self.get_script(),
+            AddressType::SegWit => self.get_segwit_script(),
+            AddressType::Taproot => self.get_taproot_script(),
+        }
+    }
+
+    /// Returns the addresses of every type, the key can receive to any of them
+    pub fn get_all_addresses(&self) -> Vec<String> {
+        vec![
+            self.get_address(),
+            self.get_segwit_address(),
+            self.get_taproot_address(),
+        ]
+    }
+
     /// retuns the address in vec format
     pub fn get_address_vec(&self) -> Vec<u8> {
         if let Ok(address) = Self::encode_pubkey_address(&self.public_key) {
@@ -68,7 +273,8 @@ impl KeysHandler {
 
     /// returns true if the public key corresponds to the private key given
     pub fn keys_are_valid(public_key: &[u8], private_key: &[u8]) -> bool {
-        let derived_public_key = Self::calculate_pubkey_vec(private_key);
+        let compressed = public_key.len() != UNCOMPRESSED_LEN;
+        let derived_public_key = Self::calculate_pubkey_vec(private_key, compressed);
         match derived_public_key {
             Some(derived_public_key) => derived_public_key == public_key,
             None => false,
@@ -80,13 +286,20 @@ impl KeysHandler {
         &self.public_key
     }
 
+    /// Returns the private key as stored in the wallets file: in hex, or in testnet WIF
+    /// if the public key is uncompressed since hex keys are read as compressed.
+    /// Empty if the wallet is locked
     pub fn get_privkey(&self) -> String {
-        u8_array_to_hex_string(&self.private_key)
+        match &self.private_key {
+            Some(_) if !self.compressed => self.get_wif(Network::Testnet).unwrap_or_default(),
+            Some(private_key) => u8_array_to_hex_string(private_key),
+            None => String::new(),
+        }
     }
 
     /// Returns the pubkey string
     pub fn get_pubkey_string(&self) -> String {
-        Self::build_pubkey(&self.private_key).to_string()
+        u8_vec_to_hex_string(&self.public_key)
     }
 
     /// Builds the pub key from the private key
@@ -98,8 +311,11 @@ impl KeysHandler {
     }
 
     /// Calculates the public key vector from the private key
-    fn calculate_pubkey_vec(private_key: &[u8]) -> Option<Vec<u8>> {
+    fn calculate_pubkey_vec(private_key: &[u8], compressed: bool) -> Option<Vec<u8>> {
         let public_key = Self::build_pubkey(private_key);
+        if !compressed {
+            return Some(public_key.serialize_uncompressed().to_vec());
+        }
 
         match cast_str_to_bytes_vec(&public_key.to_string()) {
             Ok(public_key_bytes) => Some(public_key_bytes.to_vec()),
@@ -309,6 +525,105 @@ mod transaction_creation_tests {
         assert_eq!(encoded_address, expected_encoded_address);
     }
 
+    #[test]
+    fn test_lock_and_unlock() {
+        let mut keys_handler = setup();
+        let private_key = keys_handler.get_privkey();
+        let mut locked = KeysHandler::from_public_key(keys_handler.get_pubkey().clone());
+        keys_handler.lock();
+
+        assert!(!keys_handler.has_private_key());
+        assert!(keys_handler.get_private_key().is_empty());
+        assert_eq!(locked.get_address(), "mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc");
+        assert!(!locked.unlock("18e14a7b6a307f426a94f8114701e7c8e774e7f9a47e2c2035db29a206321725"));
+        assert!(locked.unlock(&private_key));
+        assert_eq!(locked.get_privkey(), private_key);
+    }
+
+    #[test]
+    fn test_wif_import_and_export() {
+        let private_key = "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d";
+        let uncompressed = custom_setup("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ");
+        assert!(!uncompressed.is_compressed());
+        assert_eq!(uncompressed.get_pubkey().len(), 65);
+        assert_eq!(
+            uncompressed.get_address(),
+            "mvgbzkCSgKbYgaeG38auUzR7otscEGi8U7"
+        );
+        assert!(uncompressed.get_segwit_address().is_empty());
+        assert_eq!(
+            uncompressed.get_wif(Network::Testnet).unwrap(),
+            "91gGn1HgSap6CbU12F6z3pJri26xzp7Ay1VW6NHCoEayNXwRpu2"
+        );
+        // Reopened from the wallets file it stays uncompressed
+        let reopened = custom_setup(&uncompressed.get_privkey());
+        assert_eq!(reopened.get_pubkey(), uncompressed.get_pubkey());
+
+        let compressed = custom_setup("cMzLdeGd5vEqxB8B6VFQoRopQ3sLAAvEzDAoQgvX54xwofSWj1fx");
+        assert_eq!(compressed.get_privkey(), private_key);
+        assert_eq!(
+            compressed.get_pubkey(),
+            custom_setup(private_key).get_pubkey()
+        );
+        assert_eq!(
+            compressed.get_wif(Network::Mainnet).unwrap(),
+            "KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617"
+        );
+        assert_eq!(
+            KeysHandler::decode_wif("KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617")
+                .unwrap(),
+            (
+                cast_str_to_fixed_bytes(private_key).unwrap(),
+                true,
+                Network::Mainnet
+            )
+        );
+        assert!(
+            KeysHandler::decode_wif("KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98618")
+                .is_err()
+        );
+
+        let mut locked = KeysHandler::from_public_key(uncompressed.get_pubkey().clone());
+        assert!(locked.get_wif(Network::Testnet).is_none());
+        assert!(locked.unlock("91gGn1HgSap6CbU12F6z3pJri26xzp7Ay1VW6NHCoEayNXwRpu2"));
+        assert_eq!(locked.get_address(), uncompressed.get_address());
+    }
+
+    #[test]
+    fn test_get_segwit_address() {
+        // Generator point public key, BIP173 example
+        let keys_handler =
+            custom_setup("0000000000000000000000000000000000000000000000000000000000000001");
+
+        assert_eq!(
+            keys_handler.get_segwit_address(),
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+        );
+        assert_eq!(
+            keys_handler.get_segwit_script(),
+            cast_str_to_bytes_vec("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_taproot_address() {
+        let keys_handler =
+            custom_setup("0000000000000000000000000000000000000000000000000000000000000001");
+
+        assert_eq!(
+            keys_handler.get_taproot_address(),
+            "tb1pmfr3p9j00pfxjh0zmgp99y8zftmd3s5pmedqhyptwy6lm87hf5ssk79hv2"
+        );
+        assert_eq!(
+            keys_handler.script_of(AddressType::Taproot),
+            cast_str_to_bytes_vec(
+                "5120da4710964f7852695de2da025290e24af6d8c281de5a0b902b7135fd9fd74d21"
+            )
+            .unwrap()
+        );
+        assert_eq!(keys_handler.get_all_addresses().len(), 3);
+    }
+
     #[test]
     fn test_get_tx_address_success() {
         let keys_handler = setup();
@@ -0,0 +1,365 @@
+use super::keys_handler::KeysHandler;
+use crate::message_structs::{
+    compact_size::CompactSize,
+    input::Input,
+    outpoint::Outpoint,
+    output::Output,
+    tx_message::{TXMessage, TAPROOT_SIGHASH_DEFAULT},
+};
+use crate::node::validation_engine::hashes::tagged_hash;
+use crate::node::validation_engine::taproot::{check_key_path_spend, sign_schnorr, tweak_keypair};
+use crate::utils::array_tools::reverse_array;
+use crate::utils::base64::{decode_base64, encode_base64};
+use crate::utils::script_tools::{
+    hash160, is_p2pkh, is_p2tr, is_p2wpkh, p2pkh_script, p2sh_script, p2wpkh_script, push_data,
+    script_for_address,
+};
+use bitcoin_hashes::{sha256d, Hash};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId, Signature};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use std::error::Error;
+
+/// Prefix of the messages hashed by BIP137 signatures
+const MESSAGE_MAGIC: &str = "Bitcoin Signed Message:\n";
+const BIP322_TAG: &str = "BIP0322-signed-message";
+const COMPACT_SIGNATURE_LEN: usize = 65;
+/// BIP137 headers are 27 + the recovery id, plus 4 for compressed keys, 8 for
+/// P2SH-P2WPKH and 12 for P2WPKH addresses
+const HEADER_BASE: u8 = 27;
+const HEADER_COMPRESSED: u8 = 31;
+const HEADER_MAX: u8 = 42;
+const SIGHASH_ALL: u8 = 0x01;
+const OP_0: u8 = 0x00;
+const OP_RETURN: u8 = 0x6a;
+
+/// Double sha256 of the message after the magic prefix, both with their length (BIP137)
+pub fn message_hash(message: &str) -> [u8; 32] {
+    let mut data = vec![];
+    for part in [MESSAGE_MAGIC.as_bytes(), message.as_bytes()] {
+        data.extend(CompactSize::from_usize_to_compact_size(part.len()).serialize());
+        data.extend_from_slice(part);
+    }
+    sha256d::Hash::hash(&data).to_byte_array()
+}
+
+/// Signs the message for an address of the keys: a BIP137 compact signature for P2PKH
+/// addresses and a BIP322 simple signature for P2WPKH and P2TR ones, encoded in base64
+/// # Errors
+/// Returns an error if the keys have no private key or the address type can't sign messages
+pub fn sign_message(
+    keys_handler: &KeysHandler,
+    script: &[u8],
+    message: &str,
+) -> Result<String, Box<dyn Error>> {
+    if !keys_handler.has_private_key() {
+        return Err("The key has no private key to sign with".into());
+    }
+    if is_p2pkh(script) {
+        sign_compact(keys_handler, message)
+    } else if is_p2wpkh(script) || is_p2tr(script) {
+        sign_simple(keys_handler, script, message)
+    } else {
+        Err("Messages can only be signed for P2PKH, P2WPKH or P2TR addresses".into())
+    }
+}
+
+/// Verifies a BIP137 or BIP322 simple signature of the message by the address
+/// # Errors
+/// Returns an error if the address or the signature can't be decoded
+pub fn verify_message(
+    address: &str,
+    message: &str,
+    signature: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let script = script_for_address(address)?;
+    verify_script_signature(&script, message, signature)
+}
+
+/// Verifies a BIP137 or BIP322 simple signature of the message by the output script
+/// # Errors
+/// Returns an error if the signature can't be decoded
+pub fn verify_script_signature(
+    script: &[u8],
+    message: &str,
+    signature: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let signature = decode_base64(signature)?;
+    match signature.first() {
+        Some(header)
+            if signature.len() == COMPACT_SIGNATURE_LEN
+                && (HEADER_BASE..=HEADER_MAX).contains(header) =>
+        {
+            verify_compact(script, message, &signature)
+        }
+        _ => verify_simple(script, message, signature),
+    }
+}
+
+/// BIP137 signature: header byte and recoverable ECDSA signature of the message hash
+fn sign_compact(keys_handler: &KeysHandler, message: &str) -> Result<String, Box<dyn Error>> {
+    let secp = Secp256k1::signing_only();
+    let secret_key = SecretKey::from_slice(keys_handler.get_private_key())?;
+    let hash = Message::from_slice(&message_hash(message))?;
+    let (recovery_id, bytes) = secp
+        .sign_ecdsa_recoverable(&hash, &secret_key)
+        .serialize_compact();
+    let header = match keys_handler.is_compressed() {
+        true => HEADER_COMPRESSED,
+        false => HEADER_BASE,
+    };
+    let mut signature = vec![header + recovery_id.to_i32() as u8];
+    signature.extend_from_slice(&bytes);
+    Ok(encode_base64(&signature))
+}
+
+/// Recovers the signing key and checks the script pays to it. Signatures of compressed
+/// keys are accepted for any of their single key scripts, as not every wallet sets the
+/// header of segwit addresses
+fn verify_compact(script: &[u8], message: &str, signature: &[u8]) -> Result<bool, Box<dyn Error>> {
+    let secp = Secp256k1::verification_only();
+    let header = signature[0];
+    let recovery_id = RecoveryId::from_i32(((header - HEADER_BASE) % 4) as i32)?;
+    let recoverable = RecoverableSignature::from_compact(&signature[1..], recovery_id)?;
+    let hash = Message::from_slice(&message_hash(message))?;
+    let public_key = match secp.recover_ecdsa(&hash, &recoverable) {
+        Ok(public_key) => public_key,
+        Err(_) => return Ok(false),
+    };
+    if header < HEADER_COMPRESSED {
+        return Ok(p2pkh_script(&hash160(&public_key.serialize_uncompressed())) == script);
+    }
+    let key_hash = hash160(&public_key.serialize());
+    let scripts = [
+        p2pkh_script(&key_hash),
+        p2sh_script(&p2wpkh_script(&key_hash)),
+        p2wpkh_script(&key_hash),
+    ];
+    Ok(scripts.iter().any(|candidate| candidate == script))
+}
+
+/// BIP322 virtual transaction whose only output pays to the script and commits to the message
+fn to_spend(script: &[u8], message: &str) -> TXMessage {
+    let mut script_sig = vec![OP_0];
+    script_sig.extend(push_data(&tagged_hash(BIP322_TAG, message.as_bytes())));
+    let input = Input::new(
+        Outpoint::new([0; 32], u32::MAX),
+        CompactSize::from_usize_to_compact_size(script_sig.len()),
+        script_sig,
+        0,
+    );
+    let output = Output::new(
+        0,
+        CompactSize::from_usize_to_compact_size(script.len()),
+        script.to_vec(),
+    );
+    TXMessage::new(
+        0,
+        CompactSize::from_usize_to_compact_size(1),
+        vec![input],
+        CompactSize::from_usize_to_compact_size(1),
+        vec![output],
+        0,
+    )
+}
+
+/// BIP322 virtual transaction spending `to_spend` to an OP_RETURN output, the signature
+/// is its witness
+fn to_sign(to_spend: &TXMessage) -> TXMessage {
+    let input = Input::new(
+        Outpoint::new(reverse_array(&to_spend.get_id()), 0),
+        CompactSize::from_usize_to_compact_size(0),
+        vec![],
+        0,
+    );
+    let output = Output::new(
+        0,
+        CompactSize::from_usize_to_compact_size(1),
+        vec![OP_RETURN],
+    );
+    TXMessage::new(
+        0,
+        CompactSize::from_usize_to_compact_size(1),
+        vec![input],
+        CompactSize::from_usize_to_compact_size(1),
+        vec![output],
+        0,
+    )
+}
+
+/// BIP322 simple signature: the witness of `to_sign`, serialized like in transactions
+fn sign_simple(
+    keys_handler: &KeysHandler,
+    script: &[u8],
+    message: &str,
+) -> Result<String, Box<dyn Error>> {
+    let to_spend = to_spend(script, message);
+    let to_sign = to_sign(&to_spend);
+    let private_key = keys_handler.get_private_key();
+    let witness = if is_p2tr(script) {
+        let sig_hash = to_sign
+            .taproot_sig_hash(0, &to_spend.get_output(), TAPROOT_SIGHASH_DEFAULT, None)
+            .ok_or("Invalid signature hash")?;
+        vec![sign_schnorr(&tweak_keypair(private_key)?, &sig_hash)]
+    } else {
+        let sig_hash = to_sign.segwit_sig_hash(0, &p2pkh_script(&script[2..]), 0);
+        let secp = Secp256k1::signing_only();
+        let signature = secp.sign_ecdsa(
+            &Message::from_slice(&sig_hash)?,
+            &SecretKey::from_slice(private_key)?,
+        );
+        let mut signature = signature.serialize_der().to_vec();
+        signature.push(SIGHASH_ALL);
+        vec![signature, keys_handler.get_pubkey().clone()]
+    };
+    let mut serialized = CompactSize::from_usize_to_compact_size(witness.len()).serialize();
+    for item in witness {
+        serialized.extend(CompactSize::from_usize_to_compact_size(item.len()).serialize());
+        serialized.extend(item);
+    }
+    Ok(encode_base64(&serialized))
+}
+
+/// Rebuilds `to_sign` with the witness of the signature and checks it spends `to_spend`
+fn verify_simple(
+    script: &[u8],
+    message: &str,
+    mut signature: Vec<u8>,
+) -> Result<bool, Box<dyn Error>> {
+    let item_count = read_size(&mut signature)?;
+    let mut witness = vec![];
+    for _ in 0..item_count {
+        let item_len = read_size(&mut signature)?;
+        if item_len > signature.len() {
+            return Err("Witness item out of bounds".into());
+        }
+        witness.push(signature.drain(..item_len).collect::<Vec<u8>>());
+    }
+    if !signature.is_empty() {
+        return Err("Unexpected bytes after the witness".into());
+    }
+    let to_spend = to_spend(script, message);
+    let mut to_sign = to_sign(&to_spend);
+    if is_p2tr(script) {
+        to_sign.set_witness(0, witness);
+        return Ok(check_key_path_spend(&to_sign, 0, &to_spend.get_output()));
+    }
+    if !is_p2wpkh(script) {
+        return Err("Only P2WPKH and P2TR addresses have BIP322 simple signatures".into());
+    }
+    let (signature, public_key) = match witness.as_slice() {
+        [signature, public_key] => (signature, public_key),
+        _ => return Ok(false),
+    };
+    if hash160(public_key) != script[2..] || signature.last() != Some(&SIGHASH_ALL) {
+        return Ok(false);
+    }
+    let sig_hash = to_sign.segwit_sig_hash(0, &p2pkh_script(&script[2..]), 0);
+    let secp = Secp256k1::verification_only();
+    Ok(
+        match (
+            Signature::from_der(&signature[..signature.len() - 1]),
+            PublicKey::from_slice(public_key),
+        ) {
+            (Ok(signature), Ok(public_key)) => secp
+                .verify_ecdsa(&Message::from_slice(&sig_hash)?, &signature, &public_key)
+                .is_ok(),
+            _ => false,
+        },
+    )
+}
+
+/// Reads a compact size off the front of the payload, checking it is all there
+fn read_size(payload: &mut Vec<u8>) -> Result<usize, Box<dyn Error>> {
+    let needed = match payload.first() {
+        Some(0xFD) => 3,
+        Some(0xFE) => 5,
+        Some(0xFF) => 9,
+        Some(_) => 1,
+        None => 0,
+    };
+    if needed == 0 || payload.len() < needed {
+        return Err("Truncated signature".into());
+    }
+    Ok(CompactSize::deserialize(payload).get_number())
+}
+
+#[cfg(test)]
+mod message_signing_tests {
+    use super::*;
+    use crate::utils::bech32::decode_segwit_address;
+    use crate::utils::script_tools::p2tr_script;
+
+    #[test]
+    fn test_bip322_virtual_transactions() {
+        // BIP322 test vectors, the to_spend txids are shown in display order
+        let script = p2wpkh_script(
+            &decode_segwit_address("bc", "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")
+                .unwrap()
+                .1,
+        );
+        for (message, txid) in [
+            (
+                "",
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+            ),
+            (
+                "Hello World",
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+            ),
+        ] {
+            let id = reverse_array(&to_spend(&script, message).get_id());
+            let hex: String = id.iter().map(|byte| format!("{:02x}", byte)).collect();
+            assert_eq!(hex, txid);
+        }
+
+        let signature = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert!(verify_script_signature(&script, "Hello World", signature).unwrap());
+        assert!(!verify_script_signature(&script, "Hello World!", signature).unwrap());
+
+        // Taproot key path spend signed with SIGHASH_ALL
+        let script = p2tr_script(
+            &decode_segwit_address(
+                "bc",
+                "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3",
+            )
+            .unwrap()
+            .1,
+        );
+        let signature = "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==";
+        assert!(verify_script_signature(&script, "Hello World", signature).unwrap());
+        assert!(!verify_script_signature(&script, "Hello World!", signature).unwrap());
+    }
+
+    #[test]
+    fn test_sign_and_verify_messages() {
+        let keys_handler =
+            KeysHandler::new("5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd")
+                .unwrap();
+        let message = "I own this address";
+        for address in keys_handler.get_all_addresses() {
+            let script = script_for_address(&address).unwrap();
+            let signature = sign_message(&keys_handler, &script, message).unwrap();
+            assert!(verify_message(&address, message, &signature).unwrap());
+            assert!(!verify_message(&address, "Someone else's", &signature).unwrap());
+        }
+
+        // A compact signature of the key doesn't verify for another key's address
+        let other =
+            KeysHandler::new("cMzLdeGd5vEqxB8B6VFQoRopQ3sLAAvEzDAoQgvX54xwofSWj1fx").unwrap();
+        let signature = sign_message(&keys_handler, &keys_handler.get_script(), message).unwrap();
+        assert_eq!(
+            decode_base64(&signature).unwrap().len(),
+            COMPACT_SIGNATURE_LEN
+        );
+        assert!(!verify_message(&other.get_address(), message, &signature).unwrap());
+
+        let uncompressed =
+            KeysHandler::new("91gGn1HgSap6CbU12F6z3pJri26xzp7Ay1VW6NHCoEayNXwRpu2").unwrap();
+        let signature = sign_message(&uncompressed, &uncompressed.get_script(), message).unwrap();
+        assert!(verify_message(&uncompressed.get_address(), message, &signature).unwrap());
+
+        let watch_only = KeysHandler::from_public_key(keys_handler.get_pubkey().clone());
+        assert!(sign_message(&watch_only, &keys_handler.get_script(), message).is_err());
+        assert!(verify_message(&keys_handler.get_address(), message, "not base64!").is_err());
+    }
+}
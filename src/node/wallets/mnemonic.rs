@@ -0,0 +1,224 @@
+use bitcoin_hashes::hmac::{Hmac, HmacEngine};
+use bitcoin_hashes::{sha256, sha512, Hash, HashEngine};
+use rand::RngCore;
+use std::error::Error;
+use std::sync::OnceLock;
+
+/// BIP39 English wordlist, one word per line in alphabetical order
+const ENGLISH_WORDLIST: &str = include_str!("bip39_english.txt");
+const WORDLIST_LEN: usize = 2048;
+const BITS_PER_WORD: usize = 11;
+const PBKDF2_ROUNDS: u32 = 2048;
+/// Valid amounts of words: 12, 15, 18, 21 and 24 (128 to 256 bits of entropy)
+const MIN_WORDS: usize = 12;
+const MAX_WORDS: usize = 24;
+
+/// BIP39 mnemonic sentence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mnemonic {
+    words: Vec<&'static str>,
+}
+
+impl Mnemonic {
+    /// Creates a mnemonic of `word_count` words from random entropy
+    /// # Errors
+    /// Returns an error if `word_count` isn't 12, 15, 18, 21 or 24
+    pub fn generate(word_count: usize) -> Result<Mnemonic, Box<dyn Error>> {
+        if !valid_word_count(word_count) {
+            return Err("The mnemonic must have 12, 15, 18, 21 or 24 words".into());
+        }
+        let mut entropy = vec![0u8; word_count * 4 / 3];
+        rand::thread_rng().fill_bytes(&mut entropy);
+        Self::from_entropy(&entropy)
+    }
+
+    /// Encodes the entropy (16 to 32 bytes, multiple of 4) followed by its checksum in words
+    /// # Errors
+    /// Returns an error if the entropy length is invalid
+    pub fn from_entropy(entropy: &[u8]) -> Result<Mnemonic, Box<dyn Error>> {
+        if entropy.len() < 16 || entropy.len() > 32 || !entropy.len().is_multiple_of(4) {
+            return Err("The entropy must have 16, 20, 24, 28 or 32 bytes".into());
+        }
+        let mut bits = to_bits(entropy);
+        let checksum_len = entropy.len() / 4;
+        bits.extend(to_bits(&checksum(entropy)).into_iter().take(checksum_len));
+
+        let wordlist = wordlist();
+        let words = bits
+            .chunks(BITS_PER_WORD)
+            .map(|chunk| wordlist[from_bits(chunk)])
+            .collect();
+        Ok(Mnemonic { words })
+    }
+
+    /// Parses a mnemonic checking its words and checksum, case and extra spaces are ignored
+    /// # Errors
+    /// Returns an error if a word isn't in the wordlist, the amount of words or the checksum are wrong
+    pub fn parse(phrase: &str) -> Result<Mnemonic, Box<dyn Error>> {
+        let wordlist = wordlist();
+        let mut bits = vec![];
+        let mut words = vec![];
+        for word in phrase.split_whitespace() {
+            let index = match wordlist.binary_search(&word.to_lowercase().as_str()) {
+                Ok(index) => index,
+                Err(_) => return Err(format!("Unknown word: {}", word).into()),
+            };
+            words.push(wordlist[index]);
+            let index_bytes = (index as u16).to_be_bytes();
+            bits.extend(to_bits(&index_bytes).into_iter().skip(16 - BITS_PER_WORD));
+        }
+        if !valid_word_count(words.len()) {
+            return Err("The mnemonic must have 12, 15, 18, 21 or 24 words".into());
+        }
+
+        let checksum_len = words.len() / 3;
+        let (entropy_bits, checksum_bits) = bits.split_at(bits.len() - checksum_len);
+        let entropy: Vec<u8> = entropy_bits.chunks(8).map(|b| from_bits(b) as u8).collect();
+        let expected: Vec<bool> = to_bits(&checksum(&entropy))
+            .into_iter()
+            .take(checksum_len)
+            .collect();
+        if expected != checksum_bits {
+            return Err("Invalid mnemonic checksum".into());
+        }
+        Ok(Mnemonic { words })
+    }
+
+    /// Derives the 64 bytes seed with PBKDF2-HMAC-SHA512, the passphrase is optional (empty).
+    /// Words are ASCII; non ASCII passphrases are used as typed (no NFKD normalization)
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        let salt = format!("mnemonic{}", passphrase);
+        pbkdf2_hmac_sha512(self.phrase().as_bytes(), salt.as_bytes(), PBKDF2_ROUNDS)
+    }
+
+    /// Returns the words separated by spaces
+    pub fn phrase(&self) -> String {
+        self.words.join(" ")
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.words.len()
+    }
+}
+
+/// Returns the wordlist, split into words the first time it's needed
+fn wordlist() -> &'static [&'static str] {
+    static WORDLIST: OnceLock<Vec<&'static str>> = OnceLock::new();
+    WORDLIST.get_or_init(|| {
+        let wordlist: Vec<&'static str> = ENGLISH_WORDLIST.lines().collect();
+        debug_assert_eq!(wordlist.len(), WORDLIST_LEN);
+        wordlist
+    })
+}
+
+fn valid_word_count(word_count: usize) -> bool {
+    (MIN_WORDS..=MAX_WORDS).contains(&word_count) && word_count.is_multiple_of(3)
+}
+
+fn checksum(entropy: &[u8]) -> [u8; 32] {
+    sha256::Hash::hash(entropy).to_byte_array()
+}
+
+/// Bits of the bytes, most significant first
+fn to_bits(bytes: &[u8]) -> Vec<bool> {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+        .collect()
+}
+
+fn from_bits(bits: &[bool]) -> usize {
+    bits.iter()
+        .fold(0, |value, bit| (value << 1) | usize::from(*bit))
+}
+
+/// PBKDF2 with HMAC-SHA512 producing a single 64 bytes block
+fn pbkdf2_hmac_sha512(password: &[u8], salt: &[u8], rounds: u32) -> [u8; 64] {
+    let hmac = |data: &[u8]| {
+        let mut engine = HmacEngine::<sha512::Hash>::new(password);
+        engine.input(data);
+        Hmac::<sha512::Hash>::from_engine(engine).to_byte_array()
+    };
+    let mut block = salt.to_vec();
+    block.extend_from_slice(&1u32.to_be_bytes());
+    let mut u = hmac(&block);
+    let mut result = u;
+    for _ in 1..rounds {
+        u = hmac(&u);
+        for (r, x) in result.iter_mut().zip(u.iter()) {
+            *r ^= x;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod mnemonic_tests {
+    use super::*;
+    use crate::utils::array_tools::{cast_str_to_bytes_vec, u8_vec_to_hex_string};
+
+    #[test]
+    fn test_wordlist() {
+        let wordlist = wordlist();
+        assert_eq!(wordlist.len(), WORDLIST_LEN);
+        assert_eq!(wordlist[0], "abandon");
+        assert_eq!(wordlist[WORDLIST_LEN - 1], "zoo");
+        assert!(wordlist.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    // BIP39 test vectors (passphrase "TREZOR")
+    #[test]
+    fn test_from_entropy_and_seed() {
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+                "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            ),
+            (
+                "68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+                "hamster diagram private dutch cause delay private meat slide toddler razor book happy fancy gospel tennis maple dilemma loan word shrug inflict delay length",
+                "64c87cde7e12ecf6704ab95bb1408bef047c22db4cc7491c4271d170a1b213d20b385bc1588d9c7b38f1b39d415665b8a9030c9ec653d75e65f847d8fc1fc440",
+            ),
+        ];
+        for (entropy, phrase, seed) in vectors {
+            let entropy = cast_str_to_bytes_vec(entropy).unwrap();
+            let mnemonic = Mnemonic::from_entropy(&entropy).unwrap();
+            assert_eq!(mnemonic.phrase(), phrase);
+            assert_eq!(Mnemonic::parse(phrase).unwrap(), mnemonic);
+            assert_eq!(u8_vec_to_hex_string(&mnemonic.to_seed("TREZOR")), seed);
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let mnemonic = Mnemonic::generate(24).unwrap();
+        assert_eq!(mnemonic.word_count(), 24);
+        assert_eq!(Mnemonic::parse(&mnemonic.phrase()).unwrap(), mnemonic);
+        assert_eq!(Mnemonic::generate(12).unwrap().word_count(), 12);
+        assert!(Mnemonic::generate(13).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_mnemonics() {
+        // Last word changed, wrong checksum
+        assert!(Mnemonic::parse(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon"
+        )
+        .is_err());
+        assert!(Mnemonic::parse("abandon abandon about").is_err());
+        assert!(Mnemonic::parse(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon bitcoin"
+        )
+        .is_err());
+        assert!(Mnemonic::parse(
+            "  Abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon ABOUT "
+        )
+        .is_ok());
+    }
+}
@@ -1,4 +1,18 @@
+pub mod coin_selection;
+pub mod descriptor;
+pub mod descriptor_account;
+pub mod encryption;
+pub mod hd_account;
+pub mod hd_keys;
+pub mod history;
 pub mod keys_handler;
+pub mod message_signing;
+pub mod mnemonic;
+pub mod payment_request;
+pub mod payments;
+pub mod pending;
+pub mod psbt;
+pub mod rescan;
 pub mod transactions_handler;
 pub mod wallet;
 pub mod wallet_handler;
@@ -0,0 +1,329 @@
+use super::payments::MAX_MONEY;
+use crate::message_structs::tx_message::TXMessage;
+use crate::utils::array_tools::{cast_str_to_fixed_bytes, u8_array_to_hex_string};
+use crate::utils::script_tools::script_for_address;
+use std::error::Error;
+
+/// File the payment requests of every wallet are saved to, each line starts with the id of its wallet
+pub const PAYMENT_REQUESTS_PATH: &str = "./storage/payment_requests.txt";
+const URI_SCHEME: &str = "bitcoin:";
+const SATS_PER_BTC: i64 = 100_000_000;
+const BTC_DECIMALS: usize = 8;
+/// Parameters starting with it must be understood to pay the URI (BIP21)
+const REQUIRED_PREFIX: &str = "req-";
+
+/// BIP21 payment URI: `bitcoin:<address>?amount=<BTC>&label=<label>&message=<message>`.
+/// The amount is kept in satoshis
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PaymentUri {
+    pub address: String,
+    pub amount: Option<i64>,
+    pub label: String,
+    pub message: String,
+}
+
+impl PaymentUri {
+    /// Parses a BIP21 URI, the scheme is case insensitive and unknown optional parameters are ignored
+    /// # Errors
+    /// Returns an error if it isn't a bitcoin URI, the address or the amount are invalid
+    /// or it has a required parameter that isn't supported
+    pub fn parse(uri: &str) -> Result<PaymentUri, Box<dyn Error>> {
+        let uri = uri.trim();
+        let scheme = uri.get(..URI_SCHEME.len()).unwrap_or_default();
+        if !scheme.eq_ignore_ascii_case(URI_SCHEME) {
+            return Err("Payment URIs start with bitcoin:".into());
+        }
+        let (address, query) = match uri[URI_SCHEME.len()..].split_once('?') {
+            Some((address, query)) => (address, query),
+            None => (&uri[URI_SCHEME.len()..], ""),
+        };
+        if script_for_address(address).is_err() {
+            return Err(format!("Invalid address in the payment URI: {}", address).into());
+        }
+        let mut payment_uri = PaymentUri {
+            address: address.to_string(),
+            ..Default::default()
+        };
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = percent_decode(value)?;
+            match key {
+                "amount" => payment_uri.amount = Some(parse_btc_amount(&value)?),
+                "label" => payment_uri.label = value,
+                "message" => payment_uri.message = value,
+                key if key.starts_with(REQUIRED_PREFIX) => {
+                    return Err(format!("Unsupported payment URI parameter {}", key).into())
+                }
+                _ => {}
+            }
+        }
+        Ok(payment_uri)
+    }
+
+    /// Returns the URI, parameters are left out if empty
+    pub fn to_uri(&self) -> String {
+        let mut parameters = vec![];
+        if let Some(amount) = self.amount {
+            parameters.push(format!("amount={}", format_btc_amount(amount)));
+        }
+        for (key, value) in [("label", &self.label), ("message", &self.message)] {
+            if !value.is_empty() {
+                parameters.push(format!("{}={}", key, percent_encode(value)));
+            }
+        }
+        match parameters.is_empty() {
+            true => format!("{}{}", URI_SCHEME, self.address),
+            false => format!("{}{}?{}", URI_SCHEME, self.address, parameters.join("&")),
+        }
+    }
+}
+
+/// Payment asked for with a fresh address of a wallet, tracked until a transaction pays it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentRequest {
+    pub uri: PaymentUri,
+    pub created: u32,
+    pub paid_by: Option<[u8; 32]>,
+}
+
+impl PaymentRequest {
+    pub fn new(uri: PaymentUri, created: u32) -> PaymentRequest {
+        PaymentRequest {
+            uri,
+            created,
+            paid_by: None,
+        }
+    }
+
+    pub fn is_paid(&self) -> bool {
+        self.paid_by.is_some()
+    }
+
+    /// Marks the request paid if the transaction pays its address at least the amount asked,
+    /// any amount if it has none. Outputs in `settled` already paid another request and are
+    /// skipped, the ones paying this request are added. Returns true if it was paid by this transaction
+    pub fn check_payment(&mut self, transaction: &TXMessage, settled: &mut Vec<usize>) -> bool {
+        if self.is_paid() {
+            return false;
+        }
+        let script = match script_for_address(&self.uri.address) {
+            Ok(script) => script,
+            Err(_) => return false,
+        };
+        let paying: Vec<(usize, i64)> = transaction
+            .get_output()
+            .iter()
+            .enumerate()
+            .filter(|(index, output)| output.get_script() == script && !settled.contains(index))
+            .map(|(index, output)| (index, output.value))
+            .collect();
+        let received: i64 = paying.iter().map(|(_, value)| value).sum();
+        if received == 0 || received < self.uri.amount.unwrap_or_default() {
+            return false;
+        }
+        settled.extend(paying.iter().map(|(index, _)| index));
+        self.paid_by = Some(transaction.get_id());
+        true
+    }
+
+    /// Returns the request as a line of the requests file, the URI goes last
+    pub fn to_line(&self) -> String {
+        let paid_by = self
+            .paid_by
+            .map(|txid| u8_array_to_hex_string(&txid))
+            .unwrap_or_default();
+        format!("{},{},{}", self.created, paid_by, self.uri.to_uri())
+    }
+
+    /// Parses a line written by to_line
+    pub fn from_line(line: &str) -> Option<PaymentRequest> {
+        let mut parts = line.splitn(3, ',');
+        let created = parts.next()?.parse().ok()?;
+        let paid_by = match parts.next()? {
+            "" => None,
+            txid => Some(cast_str_to_fixed_bytes(txid).ok()?),
+        };
+        Some(PaymentRequest {
+            uri: PaymentUri::parse(parts.next()?).ok()?,
+            created,
+            paid_by,
+        })
+    }
+}
+
+/// Parses an amount in BTC with up to 8 decimals, as written in payment URIs, into satoshis
+/// # Errors
+/// Returns an error if it isn't a positive decimal number, has more than 8 decimals
+/// or is more than MAX_MONEY
+pub fn parse_btc_amount(amount: &str) -> Result<i64, Box<dyn Error>> {
+    let invalid = || format!("Invalid amount {}", amount);
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let digits = |text: &str| text.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty())
+        || !digits(whole)
+        || !digits(fraction)
+        || fraction.len() > BTC_DECIMALS
+    {
+        return Err(invalid().into());
+    }
+    let whole: i64 = match whole {
+        "" => 0,
+        whole => whole.parse().map_err(|_| invalid())?,
+    };
+    let fraction: i64 = format!("{:0<width$}", fraction, width = BTC_DECIMALS)
+        .parse()
+        .map_err(|_| invalid())?;
+    let sats = whole
+        .checked_mul(SATS_PER_BTC)
+        .and_then(|sats| sats.checked_add(fraction))
+        .ok_or_else(invalid)?;
+    if sats == 0 || sats > MAX_MONEY {
+        return Err(invalid().into());
+    }
+    Ok(sats)
+}
+
+/// Writes satoshis as BTC without trailing zeros
+pub fn format_btc_amount(sats: i64) -> String {
+    let fraction = format!("{:08}", sats % SATS_PER_BTC);
+    let fraction = fraction.trim_end_matches('0');
+    match fraction.is_empty() {
+        true => (sats / SATS_PER_BTC).to_string(),
+        false => format!("{}.{}", sats / SATS_PER_BTC, fraction),
+    }
+}
+
+/// Percent encodes everything but the unreserved characters of RFC 3986
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decodes the percent encoded bytes of the text, which must be UTF-8
+fn percent_decode(text: &str) -> Result<String, Box<dyn Error>> {
+    let bytes = text.as_bytes();
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = text
+                .get(index + 1..index + 3)
+                .ok_or("Invalid percent encoding")?;
+            decoded.push(u8::from_str_radix(hex, 16)?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
+
+#[cfg(test)]
+mod payment_request_tests {
+    use super::*;
+    use crate::message_structs::{compact_size::CompactSize, output::Output};
+
+    const ADDRESS: &str = "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun";
+
+    #[test]
+    fn test_payment_uri() {
+        let uri = PaymentUri::parse(&format!(
+            "BITCOIN:{}?amount=0.0005&label=Luke-Jr&message=Donation%20for%20project%20xyz&x=1",
+            ADDRESS
+        ))
+        .unwrap();
+        assert_eq!(
+            uri,
+            PaymentUri {
+                address: ADDRESS.to_string(),
+                amount: Some(50_000),
+                label: "Luke-Jr".to_string(),
+                message: "Donation for project xyz".to_string(),
+            }
+        );
+        assert_eq!(
+            uri.to_uri(),
+            format!(
+                "bitcoin:{}?amount=0.0005&label=Luke-Jr&message=Donation%20for%20project%20xyz",
+                ADDRESS
+            )
+        );
+        assert_eq!(PaymentUri::parse(&uri.to_uri()).unwrap(), uri);
+        assert_eq!(
+            PaymentUri::parse(&format!("bitcoin:{}", ADDRESS))
+                .unwrap()
+                .to_uri(),
+            format!("bitcoin:{}", ADDRESS)
+        );
+
+        assert!(PaymentUri::parse(ADDRESS).is_err());
+        assert!(PaymentUri::parse("bitcoin:notanaddress").is_err());
+        assert!(PaymentUri::parse(&format!(
+            "bitcoin:{}?req-somethingyoudontunderstand=50",
+            ADDRESS
+        ))
+        .is_err());
+        assert!(PaymentUri::parse(&format!("bitcoin:{}?amount=1e-3", ADDRESS)).is_err());
+
+        assert_eq!(parse_btc_amount("20.3").unwrap(), 2_030_000_000);
+        assert_eq!(parse_btc_amount(".00000001").unwrap(), 1);
+        assert!(parse_btc_amount("0.000000001").is_err());
+        assert!(parse_btc_amount("0").is_err());
+        assert_eq!(parse_btc_amount("21000000").unwrap(), MAX_MONEY);
+        assert!(parse_btc_amount("21000000.00000001").is_err());
+        assert_eq!(format_btc_amount(2_030_000_000), "20.3");
+        assert_eq!(format_btc_amount(100_000_000), "1");
+    }
+
+    #[test]
+    fn test_payment_request_is_paid_and_saved() {
+        let uri = PaymentUri {
+            address: ADDRESS.to_string(),
+            amount: Some(10_000),
+            label: "Invoice, 7".to_string(),
+            message: String::new(),
+        };
+        let mut request = PaymentRequest::new(uri, 1_700_000_000);
+        let script = script_for_address(ADDRESS).unwrap();
+        let paying = |value: i64| {
+            let output = Output::new(
+                value,
+                CompactSize::from_usize_to_compact_size(script.len()),
+                script.clone(),
+            );
+            TXMessage::new(
+                2,
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                CompactSize::from_usize_to_compact_size(1),
+                vec![output],
+                0,
+            )
+        };
+        assert!(!request.check_payment(&paying(9_999), &mut vec![]));
+        let opened = PaymentRequest::from_line(&request.to_line()).unwrap();
+        assert_eq!(opened, request);
+
+        // An output settles only one request
+        let mut other = request.clone();
+        let transaction = paying(10_000);
+        let mut settled = vec![];
+        assert!(request.check_payment(&transaction, &mut settled));
+        assert_eq!(settled, vec![0]);
+        assert!(!other.check_payment(&transaction, &mut settled));
+        assert!(!request.check_payment(&paying(20_000), &mut vec![]));
+        assert_eq!(request.paid_by, Some(transaction.get_id()));
+        let opened = PaymentRequest::from_line(&request.to_line()).unwrap();
+        assert_eq!(opened, request);
+        assert!(PaymentRequest::from_line("1700000000,,mnJvq7").is_none());
+    }
+}
@@ -0,0 +1,92 @@
+use std::error::Error;
+
+/// Payment of a batch transaction: address, label and amount in satoshis
+pub type Payment = (String, String, i64);
+
+/// Satoshis that will ever exist, no amount can be above it
+pub const MAX_MONEY: i64 = 21_000_000 * 100_000_000;
+
+/// Parses payments from CSV lines of `address,amount[,label]`. Blank lines,
+/// lines starting with `#` and a header line whose amount isn't a number are skipped
+/// # Errors
+/// Returns an error naming the line if it hasn't an address and a positive amount up to
+/// MAX_MONEY, or if the amounts add up to more than MAX_MONEY
+pub fn parse_payments_csv(csv: &str) -> Result<Vec<Payment>, Box<dyn Error>> {
+    let mut payments = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, ',').map(|field| field.trim());
+        let address = fields.next().unwrap_or_default();
+        let amount = fields.next().unwrap_or_default();
+        let label = fields.next().unwrap_or_default().trim_matches('"');
+        let amount = match amount.parse::<i64>() {
+            Ok(amount) if amount > 0 && amount <= MAX_MONEY && !address.is_empty() => amount,
+            Err(_) if index == 0 => continue,
+            _ => return Err(format!("Invalid payment in line {}: {}", index + 1, line).into()),
+        };
+        payments.push((address.to_string(), label.to_string(), amount));
+    }
+    if payments.is_empty() {
+        return Err("There are no payments in the file".into());
+    }
+    if total_amount(&payments).is_none() {
+        return Err("The payments add up to more than 21 million bitcoin".into());
+    }
+    Ok(payments)
+}
+
+/// Total amount paid by the payments, None if it's above MAX_MONEY
+pub fn total_amount(payments: &[Payment]) -> Option<i64> {
+    checked_total(payments.iter().map(|(_, _, amount)| *amount))
+}
+
+/// Adds the amounts, None if the total overflows or is above MAX_MONEY
+pub fn checked_total(amounts: impl IntoIterator<Item = i64>) -> Option<i64> {
+    amounts
+        .into_iter()
+        .try_fold(0i64, |total, amount| total.checked_add(amount))
+        .filter(|total| *total <= MAX_MONEY)
+}
+
+#[cfg(test)]
+mod payments_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_payments_csv() {
+        let csv = "address,amount,label\n\
+            mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun, 10000, \"Alice\"\n\
+            \n\
+            # Paid next month\n\
+            mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc,25000\n";
+        let payments = parse_payments_csv(csv).unwrap();
+        assert_eq!(
+            payments,
+            vec![
+                (
+                    "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                    "Alice".to_string(),
+                    10_000
+                ),
+                (
+                    "mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc".to_string(),
+                    String::new(),
+                    25_000
+                ),
+            ]
+        );
+        assert_eq!(total_amount(&payments), Some(35_000));
+
+        assert!(parse_payments_csv("address,amount\n").is_err());
+        assert!(parse_payments_csv("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun,1000\nmw2D,ten").is_err());
+        assert!(parse_payments_csv("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun,-5").is_err());
+        let too_much = format!("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun,{}", MAX_MONEY + 1);
+        assert!(parse_payments_csv(&too_much).is_err());
+        let half = format!("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun,{}\n", MAX_MONEY / 2 + 1);
+        assert!(parse_payments_csv(&half.repeat(2)).is_err());
+        assert_eq!(checked_total([i64::MAX, 1]), None);
+    }
+}
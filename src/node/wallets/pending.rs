@@ -0,0 +1,212 @@
+use super::history::current_time;
+use super::psbt::parse_transaction;
+use super::transactions_handler::P2PKH;
+use crate::message_structs::{
+    compact_size::CompactSize, outpoint::Outpoint, output::Output, tx_message::TXMessage,
+};
+use crate::utils::array_tools::{cast_str_to_bytes_vec, u8_vec_to_hex_string};
+
+/// File the pending transactions of every wallet are saved to, each line starts with the id of its wallet
+pub const PENDING_PATH: &str = "./storage/pending.txt";
+/// Seconds a sent transaction waits for a confirmation before its utxos can be spent again,
+/// nodes drop transactions from their mempools after two weeks
+const PENDING_EXPIRY: u32 = 14 * 24 * 60 * 60;
+
+/// Transaction sent by the wallet that isn't confirmed yet, kept with the utxos it spends
+/// so its fee can be bumped by replacing it (RBF) or by spending its change (CPFP)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingTransaction {
+    transaction: TXMessage,
+    spent: Vec<(Outpoint, Output)>,
+    change_index: Option<usize>,
+    sent: u32, // seconds since the epoch
+}
+
+impl PendingTransaction {
+    /// Creates the pending transaction, change_index is the position of its change output if any
+    pub fn new(
+        transaction: TXMessage,
+        spent: Vec<(Outpoint, Output)>,
+        change_index: Option<usize>,
+    ) -> PendingTransaction {
+        PendingTransaction {
+            transaction,
+            spent,
+            change_index,
+            sent: current_time(),
+        }
+    }
+
+    pub fn get_transaction(&self) -> &TXMessage {
+        &self.transaction
+    }
+
+    pub fn get_spent(&self) -> &[(Outpoint, Output)] {
+        &self.spent
+    }
+
+    pub fn get_id(&self) -> [u8; 32] {
+        self.transaction.get_id()
+    }
+
+    /// Returns the fee the transaction pays
+    pub fn fee(&self) -> i64 {
+        P2PKH::fee_paid(&self.spent, &self.transaction)
+    }
+
+    /// Returns the fee rate of the transaction in sat/vB, rounded up
+    pub fn fee_rate(&self) -> u64 {
+        let vsize = self.transaction.vsize().max(1) as u64;
+        (self.fee().max(0) as u64).div_ceil(vsize)
+    }
+
+    /// Returns the script and amount of every output that isn't the change
+    pub fn recipients(&self) -> Vec<(Vec<u8>, i64)> {
+        self.transaction
+            .get_output()
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| Some(*index) != self.change_index)
+            .map(|(_, output)| (output.get_script(), output.value))
+            .collect()
+    }
+
+    /// Returns the change output with its outpoint, None if the transaction has no change
+    pub fn change(&self) -> Option<(Outpoint, Output)> {
+        let index = self.change_index?;
+        let output = self.transaction.get_output().get(index)?.clone();
+        Some((Outpoint::new(self.get_id(), index as u32), output))
+    }
+
+    /// Returns true if the transaction spends the outpoint
+    pub fn spends(&self, outpoint: &Outpoint) -> bool {
+        self.spent.iter().any(|(spent, _)| spent == outpoint)
+    }
+
+    /// Returns true if the transaction was sent too long ago to still be confirmed
+    pub fn is_expired(&self, now: u32) -> bool {
+        now.saturating_sub(self.sent) > PENDING_EXPIRY
+    }
+
+    /// Returns the transaction as a line of the pending file: the change index, the time
+    /// it was sent, the transaction and each spent utxo as txid:index:value:script
+    pub fn to_line(&self) -> String {
+        let change_index = self
+            .change_index
+            .map(|index| index.to_string())
+            .unwrap_or_default();
+        let spent: Vec<String> = self
+            .spent
+            .iter()
+            .map(|(outpoint, output)| {
+                format!(
+                    "{}:{}:{}",
+                    outpoint,
+                    output.value,
+                    u8_vec_to_hex_string(&output.get_script())
+                )
+            })
+            .collect();
+        format!(
+            "{},{},{},{}",
+            change_index,
+            self.sent,
+            u8_vec_to_hex_string(&self.transaction.serialize()),
+            spent.join(";")
+        )
+    }
+
+    /// Parses a line written by to_line
+    pub fn from_line(line: &str) -> Option<PendingTransaction> {
+        let mut parts = line.split(',');
+        let change_index = match parts.next()? {
+            "" => None,
+            index => Some(index.parse().ok()?),
+        };
+        let sent = parts.next()?.parse().ok()?;
+        let transaction = parse_transaction(&cast_str_to_bytes_vec(parts.next()?).ok()?).ok()?;
+        let mut spent = vec![];
+        for utxo in parts.next()?.split(';') {
+            let mut fields = utxo.split(':');
+            let outpoint = format!("{}:{}", fields.next()?, fields.next()?);
+            let value = fields.next()?.parse().ok()?;
+            let script = cast_str_to_bytes_vec(fields.next()?).ok()?;
+            let output = Output::new(
+                value,
+                CompactSize::from_usize_to_compact_size(script.len()),
+                script,
+            );
+            spent.push((Outpoint::parse(&outpoint).ok()?, output));
+        }
+        if parts.next().is_some() || spent.len() != transaction.get_input().len() {
+            return None;
+        }
+        Some(PendingTransaction {
+            transaction,
+            spent,
+            change_index,
+            sent,
+        })
+    }
+}
+
+#[cfg(test)]
+mod pending_tests {
+    use super::*;
+    use crate::message_structs::{compact_size::CompactSize, input::Input};
+    use crate::utils::script_tools::p2pkh_script;
+
+    fn output(value: i64, byte: u8) -> Output {
+        let script = p2pkh_script(&[byte; 20]);
+        Output::new(
+            value,
+            CompactSize::from_usize_to_compact_size(script.len()),
+            script,
+        )
+    }
+
+    #[test]
+    fn test_pending_transaction() {
+        let spent = vec![(Outpoint::new([1; 32], 0), output(20_000, 1))];
+        let input = Input::new(
+            spent[0].0,
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            0xfffffffd,
+        );
+        let transaction = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![input],
+            CompactSize::from_usize_to_compact_size(2),
+            vec![output(15_000, 2), output(4_000, 3)],
+            0,
+        );
+        let pending = PendingTransaction::new(transaction.clone(), spent.clone(), Some(1));
+
+        assert_eq!(pending.fee(), 1_000);
+        assert_eq!(
+            pending.fee_rate(),
+            1_000u64.div_ceil(transaction.vsize() as u64)
+        );
+        assert_eq!(pending.recipients(), vec![(p2pkh_script(&[2; 20]), 15_000)]);
+        assert_eq!(
+            pending.change(),
+            Some((Outpoint::new(transaction.get_id(), 1), output(4_000, 3)))
+        );
+        assert!(pending.spends(&spent[0].0));
+        assert!(!pending.spends(&Outpoint::new([1; 32], 1)));
+
+        let opened = PendingTransaction::from_line(&pending.to_line()).unwrap();
+        assert_eq!(opened, pending);
+        assert!(!pending.is_expired(pending.sent + PENDING_EXPIRY));
+        assert!(pending.is_expired(pending.sent + PENDING_EXPIRY + 1));
+
+        let no_change = PendingTransaction::new(transaction, spent, None);
+        assert_eq!(no_change.recipients().len(), 2);
+        assert_eq!(no_change.change(), None);
+        let opened = PendingTransaction::from_line(&no_change.to_line()).unwrap();
+        assert_eq!(opened, no_change);
+        assert!(PendingTransaction::from_line("1,1700000000,0100,").is_none());
+    }
+}
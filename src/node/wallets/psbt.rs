@@ -0,0 +1,985 @@
+use super::keys_handler::KeysHandler;
+use super::transactions_handler::P2PKH;
+use crate::message_structs::compact_size::CompactSize;
+use crate::message_structs::outpoint::Outpoint;
+use crate::message_structs::output::Output;
+use crate::message_structs::tx_message::{TXMessage, TAPROOT_SIGHASH_DEFAULT};
+use crate::node::validation_engine::taproot::{sign_schnorr, tweak_keypair};
+use crate::utils::base64::{decode_base64, encode_base64};
+use crate::utils::script_tools::{
+    hash160, is_p2pkh, is_p2sh, is_p2tr, is_p2wpkh, is_p2wsh, multisig_keys, p2pkh_script,
+    p2sh_script, p2wpkh_script, p2wsh_script, push_data,
+};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// "psbt" followed by 0xff
+const MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xff];
+const SEPARATOR: u8 = 0x00;
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const IN_NON_WITNESS_UTXO: u8 = 0x00;
+const IN_WITNESS_UTXO: u8 = 0x01;
+const IN_PARTIAL_SIG: u8 = 0x02;
+const IN_SIGHASH_TYPE: u8 = 0x03;
+const IN_REDEEM_SCRIPT: u8 = 0x04;
+const IN_WITNESS_SCRIPT: u8 = 0x05;
+const IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+const IN_TAP_KEY_SIG: u8 = 0x13;
+const SIGHASH_ALL: u8 = 0x01;
+const OP_0: u8 = 0x00;
+
+/// Key-value pairs of a PSBT map, the key includes its type
+type Map = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Data a PSBT (BIP174) holds for an input, the pairs it doesn't know are kept as they are
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PsbtInput {
+    pub non_witness_utxo: Option<TXMessage>,
+    pub witness_utxo: Option<Output>,
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>, // public key -> signature with its hash type
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    pub final_script_sig: Option<Vec<u8>>,
+    pub final_script_witness: Option<Vec<Vec<u8>>>,
+    pub tap_key_sig: Option<Vec<u8>>,
+    unknown: Map,
+}
+
+/// Partially signed bitcoin transaction (BIP174): an unsigned transaction with what
+/// signers need to sign its inputs and the signatures gathered so far
+#[derive(Debug, Clone, PartialEq)]
+pub struct Psbt {
+    unsigned_tx: TXMessage,
+    global: Map, // global pairs besides the unsigned transaction
+    inputs: Vec<PsbtInput>,
+    outputs: Vec<Map>, // output pairs are kept as they are
+}
+
+impl PsbtInput {
+    /// Returns true if the input has its final scripts
+    pub fn is_final(&self) -> bool {
+        self.final_script_sig.is_some() || self.final_script_witness.is_some()
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut map = vec![];
+        if let Some(transaction) = &self.non_witness_utxo {
+            write_pair(
+                &mut map,
+                &[IN_NON_WITNESS_UTXO],
+                &transaction.serialize_without_witness(),
+            );
+        }
+        if let Some(output) = &self.witness_utxo {
+            write_pair(&mut map, &[IN_WITNESS_UTXO], &output.serialize());
+        }
+        for (public_key, signature) in &self.partial_sigs {
+            let mut key = vec![IN_PARTIAL_SIG];
+            key.extend_from_slice(public_key);
+            write_pair(&mut map, &key, signature);
+        }
+        if let Some(sighash_type) = self.sighash_type {
+            write_pair(&mut map, &[IN_SIGHASH_TYPE], &sighash_type.to_le_bytes());
+        }
+        if let Some(script) = &self.redeem_script {
+            write_pair(&mut map, &[IN_REDEEM_SCRIPT], script);
+        }
+        if let Some(script) = &self.witness_script {
+            write_pair(&mut map, &[IN_WITNESS_SCRIPT], script);
+        }
+        if let Some(script) = &self.final_script_sig {
+            write_pair(&mut map, &[IN_FINAL_SCRIPTSIG], script);
+        }
+        if let Some(witness) = &self.final_script_witness {
+            write_pair(
+                &mut map,
+                &[IN_FINAL_SCRIPTWITNESS],
+                &serialize_witness(witness),
+            );
+        }
+        if let Some(signature) = &self.tap_key_sig {
+            write_pair(&mut map, &[IN_TAP_KEY_SIG], signature);
+        }
+        for (key, value) in &self.unknown {
+            write_pair(&mut map, key, value);
+        }
+        map.push(SEPARATOR);
+        map
+    }
+
+    fn deserialize(pairs: Map) -> Result<PsbtInput, Box<dyn Error>> {
+        let mut input = PsbtInput::default();
+        for (key, value) in pairs {
+            match (key[0], key.len()) {
+                (IN_NON_WITNESS_UTXO, 1) => {
+                    input.non_witness_utxo = Some(parse_transaction(&value)?)
+                }
+                (IN_WITNESS_UTXO, 1) => input.witness_utxo = Some(parse_output(&value)?),
+                (IN_PARTIAL_SIG, 34) | (IN_PARTIAL_SIG, 66) => {
+                    input.partial_sigs.insert(key[1..].to_vec(), value);
+                }
+                (IN_SIGHASH_TYPE, 1) => match <[u8; 4]>::try_from(value.as_slice()) {
+                    Ok(bytes) => input.sighash_type = Some(u32::from_le_bytes(bytes)),
+                    Err(_) => return Err("Invalid PSBT sighash type".into()),
+                },
+                (IN_REDEEM_SCRIPT, 1) => input.redeem_script = Some(value),
+                (IN_WITNESS_SCRIPT, 1) => input.witness_script = Some(value),
+                (IN_FINAL_SCRIPTSIG, 1) => input.final_script_sig = Some(value),
+                (IN_FINAL_SCRIPTWITNESS, 1) => {
+                    input.final_script_witness = Some(parse_witness(&value)?)
+                }
+                (IN_TAP_KEY_SIG, 1) if value.len() == 64 || value.len() == 65 => {
+                    input.tap_key_sig = Some(value)
+                }
+                _ => {
+                    input.unknown.insert(key, value);
+                }
+            }
+        }
+        Ok(input)
+    }
+
+    /// Adds what the other input has and this one doesn't
+    fn combine(&mut self, other: &PsbtInput) {
+        if self.non_witness_utxo.is_none() {
+            self.non_witness_utxo = other.non_witness_utxo.clone();
+        }
+        if self.witness_utxo.is_none() {
+            self.witness_utxo = other.witness_utxo.clone();
+        }
+        for (public_key, signature) in &other.partial_sigs {
+            self.partial_sigs
+                .entry(public_key.clone())
+                .or_insert_with(|| signature.clone());
+        }
+        self.sighash_type = self.sighash_type.or(other.sighash_type);
+        if self.redeem_script.is_none() {
+            self.redeem_script = other.redeem_script.clone();
+        }
+        if self.witness_script.is_none() {
+            self.witness_script = other.witness_script.clone();
+        }
+        if !self.is_final() {
+            self.final_script_sig = other.final_script_sig.clone();
+            self.final_script_witness = other.final_script_witness.clone();
+        }
+        if self.tap_key_sig.is_none() {
+            self.tap_key_sig = other.tap_key_sig.clone();
+        }
+        for (key, value) in &other.unknown {
+            self.unknown
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Signature and public key of the partial signature whose key hashes to `pubkey_hash`
+    fn signature_for_hash(&self, pubkey_hash: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.partial_sigs
+            .iter()
+            .find(|(public_key, _)| hash160(public_key) == pubkey_hash)
+            .map(|(public_key, signature)| (signature.clone(), public_key.clone()))
+    }
+
+    /// Final signature script and witness spending `script`, None if signatures are missing
+    fn final_scripts(&self, script: &[u8]) -> Option<(Vec<u8>, Vec<Vec<u8>>)> {
+        if is_p2tr(script) {
+            return Some((vec![], vec![self.tap_key_sig.clone()?]));
+        }
+        if is_p2pkh(script) {
+            let (signature, public_key) = self.signature_for_hash(&script[3..23])?;
+            let mut script_sig = push_data(&signature);
+            script_sig.extend(push_data(&public_key));
+            return Some((script_sig, vec![]));
+        }
+        if is_p2wpkh(script) {
+            let (signature, public_key) = self.signature_for_hash(&script[2..])?;
+            return Some((vec![], vec![signature, public_key]));
+        }
+        if let Some(witness_script) = &self.witness_script {
+            if is_p2wsh(script) && p2wsh_script(witness_script) == script {
+                // OP_CHECKMULTISIG pops an extra empty item
+                let mut witness = vec![vec![]];
+                witness.extend(self.multisig_signatures(witness_script)?);
+                witness.push(witness_script.clone());
+                return Some((vec![], witness));
+            }
+        }
+        let redeem_script = self.redeem_script.as_ref()?;
+        if !is_p2sh(script) || p2sh_script(redeem_script) != script {
+            return None;
+        }
+        if is_p2wpkh(redeem_script) {
+            let (signature, public_key) = self.signature_for_hash(&redeem_script[2..])?;
+            return Some((push_data(redeem_script), vec![signature, public_key]));
+        }
+        let mut script_sig = vec![OP_0];
+        for signature in self.multisig_signatures(redeem_script)? {
+            script_sig.extend(push_data(&signature));
+        }
+        script_sig.extend(push_data(redeem_script));
+        Some((script_sig, vec![]))
+    }
+
+    /// Signatures of the multisig script in the order of its keys, None if there are
+    /// less than its threshold
+    fn multisig_signatures(&self, script: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let (threshold, public_keys) = multisig_keys(script)?;
+        let signatures: Vec<Vec<u8>> = public_keys
+            .iter()
+            .filter_map(|public_key| self.partial_sigs.get(public_key).cloned())
+            .take(threshold)
+            .collect();
+        match signatures.len() == threshold {
+            true => Some(signatures),
+            false => None,
+        }
+    }
+
+    /// Removes what was only needed to sign, once the input is final
+    fn clear_signing_data(&mut self) {
+        self.partial_sigs.clear();
+        self.sighash_type = None;
+        self.redeem_script = None;
+        self.witness_script = None;
+        self.tap_key_sig = None;
+    }
+}
+
+impl Psbt {
+    /// Creates the PSBT of an unsigned transaction, `prevouts` are the outputs its inputs spend
+    /// with the transaction that created each one, which inputs not spending a witness program
+    /// must have (BIP174)
+    /// # Errors
+    /// Returns an error if the transaction has signatures, a prevout is missing or a previous
+    /// transaction is missing or doesn't create the prevout
+    pub fn new(
+        unsigned_tx: TXMessage,
+        prevouts: &[(Output, Option<TXMessage>)],
+    ) -> Result<Psbt, Box<dyn Error>> {
+        if unsigned_tx.input_list.len() != prevouts.len() {
+            return Err("Every input needs the output it spends".into());
+        }
+        if unsigned_tx.has_witness()
+            || unsigned_tx
+                .input_list
+                .iter()
+                .any(|input| !input.get_script().is_empty())
+        {
+            return Err("The transaction is already signed".into());
+        }
+        let mut inputs = vec![];
+        for (index, (prevout, previous_tx)) in prevouts.iter().enumerate() {
+            let outpoint = unsigned_tx.input_list[index].get_outpoint();
+            if let Some(previous_tx) = previous_tx {
+                if Outpoint::new(previous_tx.get_id(), outpoint.get_index()) != outpoint
+                    || previous_tx.get_output().get(outpoint.get_index() as usize) != Some(prevout)
+                {
+                    return Err(format!(
+                        "The previous transaction of input {} doesn't match",
+                        index
+                    )
+                    .into());
+                }
+            }
+            let script = prevout.get_script();
+            let witness_program = is_p2wpkh(&script) || is_p2wsh(&script) || is_p2tr(&script);
+            if !witness_program && previous_tx.is_none() {
+                return Err(format!("Input {} needs the transaction it spends", index).into());
+            }
+            inputs.push(PsbtInput {
+                witness_utxo: witness_program.then(|| prevout.clone()),
+                non_witness_utxo: previous_tx.clone(),
+                ..Default::default()
+            });
+        }
+        let outputs = vec![Map::new(); unsigned_tx.get_output().len()];
+        Ok(Psbt {
+            unsigned_tx,
+            global: Map::new(),
+            inputs,
+            outputs,
+        })
+    }
+
+    /// Serializes the PSBT in its binary format
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut psbt = MAGIC.to_vec();
+        write_pair(
+            &mut psbt,
+            &[GLOBAL_UNSIGNED_TX],
+            &self.unsigned_tx.serialize_without_witness(),
+        );
+        for (key, value) in &self.global {
+            write_pair(&mut psbt, key, value);
+        }
+        psbt.push(SEPARATOR);
+        for input in &self.inputs {
+            psbt.extend(input.serialize());
+        }
+        for output in &self.outputs {
+            for (key, value) in output {
+                write_pair(&mut psbt, key, value);
+            }
+            psbt.push(SEPARATOR);
+        }
+        psbt
+    }
+
+    /// Parses a PSBT in its binary format
+    /// # Errors
+    /// Returns an error if it is malformed or has no unsigned transaction
+    pub fn deserialize(bytes: &[u8]) -> Result<Psbt, Box<dyn Error>> {
+        let mut reader = Reader::new(bytes);
+        if reader.read(MAGIC.len())? != MAGIC {
+            return Err("Invalid PSBT magic".into());
+        }
+        let mut global = reader.read_map()?;
+        let unsigned_tx = match global.remove(&vec![GLOBAL_UNSIGNED_TX]) {
+            Some(transaction) => parse_transaction(&transaction)?,
+            None => return Err("The PSBT has no unsigned transaction".into()),
+        };
+        if unsigned_tx.has_witness()
+            || unsigned_tx
+                .input_list
+                .iter()
+                .any(|input| !input.get_script().is_empty())
+        {
+            return Err("The PSBT transaction has signatures".into());
+        }
+        let mut inputs = vec![];
+        for _ in 0..unsigned_tx.input_list.len() {
+            inputs.push(PsbtInput::deserialize(reader.read_map()?)?);
+        }
+        let mut outputs = vec![];
+        for _ in 0..unsigned_tx.get_output().len() {
+            outputs.push(reader.read_map()?);
+        }
+        if !reader.is_empty() {
+            return Err("Unexpected data after the PSBT".into());
+        }
+        Ok(Psbt {
+            unsigned_tx,
+            global,
+            inputs,
+            outputs,
+        })
+    }
+
+    /// Encodes the PSBT in base64, the usual way to share it
+    pub fn to_base64(&self) -> String {
+        encode_base64(&self.serialize())
+    }
+
+    /// Parses a base64 PSBT
+    /// # Errors
+    /// Returns an error if it isn't valid base64 or a valid PSBT
+    pub fn from_base64(encoded: &str) -> Result<Psbt, Box<dyn Error>> {
+        Self::deserialize(&decode_base64(encoded)?)
+    }
+
+    pub fn get_unsigned_tx(&self) -> &TXMessage {
+        &self.unsigned_tx
+    }
+
+    pub fn get_inputs(&self) -> &[PsbtInput] {
+        &self.inputs
+    }
+
+    /// Adds the redeem script and witness script signers need to spend the input
+    pub fn set_spending_scripts(
+        &mut self,
+        index: usize,
+        redeem_script: Option<Vec<u8>>,
+        witness_script: Option<Vec<u8>>,
+    ) {
+        if let Some(input) = self.inputs.get_mut(index) {
+            input.redeem_script = redeem_script.or(input.redeem_script.take());
+            input.witness_script = witness_script.or(input.witness_script.take());
+        }
+    }
+
+    /// Output spent by each input, None if the PSBT doesn't have it
+    fn prevouts(&self) -> Vec<Option<Output>> {
+        self.inputs
+            .iter()
+            .zip(self.unsigned_tx.input_list.iter())
+            .map(|(input, tx_input)| {
+                if let Some(output) = &input.witness_utxo {
+                    return Some(output.clone());
+                }
+                let previous_tx = input.non_witness_utxo.as_ref()?;
+                let outpoint = tx_input.get_outpoint();
+                if Outpoint::new(previous_tx.get_id(), outpoint.get_index()) != outpoint {
+                    return None;
+                }
+                previous_tx
+                    .get_output()
+                    .get(outpoint.get_index() as usize)
+                    .cloned()
+            })
+            .collect()
+    }
+
+    /// Fee paid by the transaction, None if a prevout is missing
+    pub fn fee(&self) -> Option<i64> {
+        let prevouts: Option<Vec<Output>> = self.prevouts().into_iter().collect();
+        let inputs: i64 = prevouts?.iter().map(|output| output.value).sum();
+        Some(inputs - self.unsigned_tx.get_output_amounts().iter().sum::<i64>())
+    }
+
+    /// ECDSA signature hash of the input for the public key, with the redeem script the input
+    /// needs, None if the key can't spend the P2PKH, P2WPKH or P2SH-P2WPKH script or isn't
+    /// one of the multisig keys of its redeem or witness script
+    fn ecdsa_sig_hash(
+        &self,
+        index: usize,
+        prevout: &Output,
+        public_key: &[u8],
+    ) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+        let script = prevout.get_script();
+        let has_key = |multisig: &[u8]| {
+            multisig_keys(multisig)
+                .is_some_and(|(_, keys)| keys.iter().any(|key| key == public_key))
+        };
+        let input = &self.inputs[index];
+        if let Some(witness_script) = &input.witness_script {
+            if script == p2wsh_script(witness_script) && has_key(witness_script) {
+                let sig_hash =
+                    self.unsigned_tx
+                        .segwit_sig_hash(index, witness_script, prevout.value);
+                return Some((sig_hash, None));
+            }
+        }
+        if let Some(redeem_script) = &input.redeem_script {
+            if script == p2sh_script(redeem_script) && has_key(redeem_script) {
+                let mut transaction = self.unsigned_tx.clone();
+                transaction.input_list[index].update_script(redeem_script.clone());
+                return Some((transaction.sig_hash(index as u32), None));
+            }
+        }
+        let pubkey_hash = hash160(public_key);
+        if script == p2pkh_script(&pubkey_hash) {
+            let mut transaction = self.unsigned_tx.clone();
+            transaction.input_list[index].update_script(script);
+            return Some((transaction.sig_hash(index as u32), None));
+        }
+        let witness_program = p2wpkh_script(&pubkey_hash);
+        let script_code = p2pkh_script(&pubkey_hash);
+        let sig_hash = self
+            .unsigned_tx
+            .segwit_sig_hash(index, &script_code, prevout.value);
+        if script == witness_program {
+            return Some((sig_hash, None));
+        }
+        if script == p2sh_script(&witness_program) {
+            return Some((sig_hash, Some(witness_program)));
+        }
+        None
+    }
+
+    /// Signs every input not final whose script belongs to one of the keys, with SIGHASH_ALL
+    /// (the default hash type for taproot). Returns the amount of signatures added
+    /// # Errors
+    /// Returns an error if a key can't sign
+    pub fn sign(&mut self, keys: &[KeysHandler]) -> Result<usize, Box<dyn Error>> {
+        let prevouts = self.prevouts();
+        let all_prevouts: Option<Vec<Output>> = prevouts.iter().cloned().collect();
+        let mut signatures = 0;
+        for (index, prevout) in prevouts.iter().enumerate() {
+            let prevout = match prevout {
+                Some(prevout) if !self.inputs[index].is_final() => prevout,
+                _ => continue,
+            };
+            let script = prevout.get_script();
+            for keys_handler in keys.iter().filter(|keys| keys.has_private_key()) {
+                if is_p2tr(&script) {
+                    if keys_handler.get_taproot_script() != script
+                        || self.inputs[index].tap_key_sig.is_some()
+                        || self.inputs[index].sighash_type.unwrap_or(0) != 0
+                    {
+                        continue;
+                    }
+                    let sig_hash = all_prevouts.as_ref().and_then(|all_prevouts| {
+                        self.unsigned_tx.taproot_sig_hash(
+                            index,
+                            all_prevouts,
+                            TAPROOT_SIGHASH_DEFAULT,
+                            None,
+                        )
+                    });
+                    if let Some(sig_hash) = sig_hash {
+                        let keypair = tweak_keypair(keys_handler.get_private_key())?;
+                        self.inputs[index].tap_key_sig = Some(sign_schnorr(&keypair, &sig_hash));
+                        signatures += 1;
+                    }
+                    continue;
+                }
+                if self.inputs[index].sighash_type.unwrap_or(1) != SIGHASH_ALL as u32 {
+                    continue;
+                }
+                let public_key = keys_handler.get_pubkey().clone();
+                if self.inputs[index].partial_sigs.contains_key(&public_key) {
+                    continue;
+                }
+                let (sig_hash, redeem_script) =
+                    match self.ecdsa_sig_hash(index, prevout, &public_key) {
+                        Some(sig_hash) => sig_hash,
+                        None => continue,
+                    };
+                let mut signature =
+                    P2PKH::generate_ecdsa_signature(keys_handler.get_private_key(), &sig_hash);
+                signature.push(SIGHASH_ALL);
+                let input = &mut self.inputs[index];
+                input.partial_sigs.insert(public_key, signature);
+                if redeem_script.is_some() {
+                    input.redeem_script = redeem_script;
+                }
+                signatures += 1;
+            }
+        }
+        Ok(signatures)
+    }
+
+    /// Adds the signatures and data of another PSBT of the same transaction
+    /// # Errors
+    /// Returns an error if the PSBTs are of different transactions
+    pub fn combine(&mut self, other: &Psbt) -> Result<(), Box<dyn Error>> {
+        if self.unsigned_tx != other.unsigned_tx {
+            return Err("The PSBTs are of different transactions".into());
+        }
+        for (key, value) in &other.global {
+            self.global
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        for (input, other_input) in self.inputs.iter_mut().zip(other.inputs.iter()) {
+            input.combine(other_input);
+        }
+        for (output, other_output) in self.outputs.iter_mut().zip(other.outputs.iter()) {
+            for (key, value) in other_output {
+                output.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the final scripts of every input and extracts the signed transaction
+    /// # Errors
+    /// Returns an error if an input lacks its prevout or signatures
+    pub fn finalize(&mut self) -> Result<TXMessage, Box<dyn Error>> {
+        let prevouts = self.prevouts();
+        for (index, input) in self.inputs.iter_mut().enumerate() {
+            if input.is_final() {
+                continue;
+            }
+            let script = match &prevouts[index] {
+                Some(prevout) => prevout.get_script(),
+                None => return Err(format!("Input {} has no utxo", index).into()),
+            };
+            let (script_sig, witness) = match input.final_scripts(&script) {
+                Some(final_scripts) => final_scripts,
+                None => return Err(format!("Input {} isn't signed", index).into()),
+            };
+            input.final_script_sig = Some(script_sig);
+            if !witness.is_empty() {
+                input.final_script_witness = Some(witness);
+            }
+            input.clear_signing_data();
+        }
+        self.extract()
+    }
+
+    /// Returns the signed transaction of a finalized PSBT
+    /// # Errors
+    /// Returns an error if an input isn't final
+    pub fn extract(&self) -> Result<TXMessage, Box<dyn Error>> {
+        let mut transaction = self.unsigned_tx.clone();
+        for (index, input) in self.inputs.iter().enumerate() {
+            if !input.is_final() {
+                return Err(format!("Input {} isn't final", index).into());
+            }
+            if let Some(script_sig) = &input.final_script_sig {
+                transaction.input_list[index].update_script(script_sig.clone());
+            }
+            if let Some(witness) = &input.final_script_witness {
+                transaction.set_witness(index, witness.clone());
+            }
+        }
+        Ok(transaction)
+    }
+}
+
+fn write_pair(map: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    map.extend(CompactSize::from_usize_to_compact_size(key.len()).serialize());
+    map.extend_from_slice(key);
+    map.extend(CompactSize::from_usize_to_compact_size(value.len()).serialize());
+    map.extend_from_slice(value);
+}
+
+fn serialize_witness(witness: &[Vec<u8>]) -> Vec<u8> {
+    let mut serialized = CompactSize::from_usize_to_compact_size(witness.len()).serialize();
+    for item in witness {
+        serialized.extend(CompactSize::from_usize_to_compact_size(item.len()).serialize());
+        serialized.extend_from_slice(item);
+    }
+    serialized
+}
+
+/// Reads PSBT data checking its bounds, imported PSBTs may be malformed
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if len > self.data.len() - self.position {
+            return Err("Unexpected end of PSBT data".into());
+        }
+        self.position += len;
+        Ok(&self.data[self.position - len..self.position])
+    }
+
+    fn read_compact(&mut self) -> Result<usize, Box<dyn Error>> {
+        let number = match self.read(1)?[0] {
+            0xfd => u16::from_le_bytes([self.read(1)?[0], self.read(1)?[0]]) as u64,
+            0xfe => {
+                let bytes = self.read(4)?;
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64
+            }
+            0xff => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(self.read(8)?);
+                u64::from_le_bytes(bytes)
+            }
+            number => number as u64,
+        };
+        Ok(number as usize)
+    }
+
+    /// Reads key-value pairs up to the separator
+    fn read_map(&mut self) -> Result<Map, Box<dyn Error>> {
+        let mut map = Map::new();
+        loop {
+            let key_len = self.read_compact()?;
+            if key_len == 0 {
+                return Ok(map);
+            }
+            let key = self.read(key_len)?.to_vec();
+            let value_len = self.read_compact()?;
+            let value = self.read(value_len)?.to_vec();
+            if map.insert(key, value).is_some() {
+                return Err("Duplicated PSBT key".into());
+            }
+        }
+    }
+}
+
+/// Parses a transaction checking its bounds first, TXMessage::deserialize expects valid data
+pub fn parse_transaction(bytes: &[u8]) -> Result<TXMessage, Box<dyn Error>> {
+    let mut reader = Reader::new(bytes);
+    reader.read(4)?;
+    let segwit = bytes.len() > 5 && bytes[4] == 0 && bytes[5] == 1;
+    if segwit {
+        reader.read(2)?;
+    }
+    let inputs = reader.read_compact()?;
+    if inputs == 0 {
+        return Err("The transaction has no inputs".into());
+    }
+    for _ in 0..inputs {
+        reader.read(36)?;
+        let script_len = reader.read_compact()?;
+        reader.read(script_len)?;
+        reader.read(4)?;
+    }
+    for _ in 0..reader.read_compact()? {
+        reader.read(8)?;
+        let script_len = reader.read_compact()?;
+        reader.read(script_len)?;
+    }
+    if segwit {
+        for _ in 0..inputs {
+            for _ in 0..reader.read_compact()? {
+                let item_len = reader.read_compact()?;
+                reader.read(item_len)?;
+            }
+        }
+    }
+    reader.read(4)?;
+    if !reader.is_empty() {
+        return Err("Unexpected data after the transaction".into());
+    }
+    TXMessage::deserialize(&mut bytes.to_vec())
+}
+
+fn parse_output(bytes: &[u8]) -> Result<Output, Box<dyn Error>> {
+    let mut reader = Reader::new(bytes);
+    let mut value = [0u8; 8];
+    value.copy_from_slice(reader.read(8)?);
+    let script_len = reader.read_compact()?;
+    let script = reader.read(script_len)?.to_vec();
+    if !reader.is_empty() {
+        return Err("Invalid PSBT utxo".into());
+    }
+    Ok(Output::new(
+        i64::from_le_bytes(value),
+        CompactSize::from_usize_to_compact_size(script_len),
+        script,
+    ))
+}
+
+fn parse_witness(bytes: &[u8]) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let mut reader = Reader::new(bytes);
+    let mut witness = vec![];
+    for _ in 0..reader.read_compact()? {
+        let item_len = reader.read_compact()?;
+        witness.push(reader.read(item_len)?.to_vec());
+    }
+    if !reader.is_empty() {
+        return Err("Invalid PSBT witness".into());
+    }
+    Ok(witness)
+}
+
+#[cfg(test)]
+mod psbt_tests {
+    use super::*;
+    use crate::message_structs::input::Input;
+    use crate::node::validation_engine::taproot::check_taproot_spends;
+    use crate::utils::script_tools::multisig_script;
+    use secp256k1::{ecdsa::Signature, Message, Secp256k1};
+
+    fn keys() -> (KeysHandler, KeysHandler) {
+        (
+            KeysHandler::new("5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd")
+                .unwrap(),
+            KeysHandler::new("18e14a7b6a307f426a94f8114701e7c8e774e7f9a47e2c2035db29a206321725")
+                .unwrap(),
+        )
+    }
+
+    /// Transaction creating the output, as its only one
+    fn funding(output: &Output) -> TXMessage {
+        // An input keeps it from being read back as a segwit transaction
+        let input = Input::new(
+            Outpoint::new([0; 32], u32::MAX),
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            u32::MAX,
+        );
+        TXMessage::new(
+            2,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![input],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![output.clone()],
+            0,
+        )
+    }
+
+    fn utxos(scripts: Vec<(i64, Vec<u8>)>) -> Vec<(Outpoint, Output)> {
+        scripts
+            .into_iter()
+            .map(|(value, script)| {
+                let output = Output::new(
+                    value,
+                    CompactSize::from_usize_to_compact_size(script.len()),
+                    script,
+                );
+                (Outpoint::new(funding(&output).get_id(), 0), output)
+            })
+            .collect()
+    }
+
+    fn psbt_for(utxos: &[(Outpoint, Output)], recipient: &[u8], change: &[u8]) -> Psbt {
+        let (transaction, used) =
+            P2PKH::create_unsigned_transaction(utxos, recipient, change, 8_000, 1).unwrap();
+        let prevouts: Vec<(Output, Option<TXMessage>)> = used
+            .into_iter()
+            .map(|(_, output)| (output.clone(), Some(funding(&output))))
+            .collect();
+        Psbt::new(transaction, &prevouts).unwrap()
+    }
+
+    #[test]
+    fn test_psbt_sign_and_finalize_matches_signed_transaction() {
+        let (first, second) = keys();
+        let utxos = utxos(vec![
+            (4_000, first.get_script()),
+            (6_000, second.get_segwit_script()),
+        ]);
+        let recipient = second.get_script();
+        let mut psbt = psbt_for(&utxos, &recipient, &first.get_segwit_script());
+
+        // Serialization round trips in binary and base64
+        let encoded = psbt.to_base64();
+        assert!(encoded.starts_with("cHNidP8B"));
+        assert_eq!(Psbt::from_base64(&encoded).unwrap(), psbt);
+        assert_eq!(Psbt::deserialize(&psbt.serialize()).unwrap(), psbt);
+        // 292 vbytes at 1 sat/vB
+        assert_eq!(psbt.fee(), Some(292));
+
+        // Watch-only keys add no signatures
+        let watch_only = KeysHandler::from_public_key(first.get_pubkey().clone());
+        assert_eq!(psbt.sign(&[watch_only]).unwrap(), 0);
+        assert_eq!(psbt.sign(&[first.clone(), second.clone()]).unwrap(), 2);
+        let signed = Psbt::from_base64(&psbt.to_base64()).unwrap();
+        assert_eq!(signed.get_inputs()[1].partial_sigs.len(), 1);
+
+        // ECDSA signatures are deterministic, so it matches signing the transaction directly
+        let (expected, _) = P2PKH::create_transaction_with_keys(
+            &[first.clone(), second],
+            &utxos,
+            &recipient,
+            &first.get_segwit_script(),
+            8_000,
+            1,
+        )
+        .unwrap();
+        let transaction = psbt.finalize().unwrap();
+        assert_eq!(transaction, expected);
+        assert!(psbt.get_inputs().iter().all(PsbtInput::is_final));
+        assert!(psbt.get_inputs()[0].partial_sigs.is_empty());
+        assert_eq!(psbt.extract().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_psbt_combine_partial_signatures() {
+        let (first, second) = keys();
+        let utxos = utxos(vec![
+            (4_000, first.get_taproot_script()),
+            (6_000, p2sh_script(&second.get_segwit_script())),
+        ]);
+        let prevouts: Vec<Option<Output>> = utxos
+            .iter()
+            .map(|(_, output)| Some(output.clone()))
+            .collect();
+        let mut psbt = psbt_for(&utxos, &second.get_taproot_script(), &first.get_script());
+        let mut other = psbt.clone();
+
+        // Each signer signs its own input
+        assert_eq!(psbt.sign(std::slice::from_ref(&first)).unwrap(), 1);
+        assert_eq!(other.sign(std::slice::from_ref(&second)).unwrap(), 1);
+        assert!(psbt.clone().finalize().is_err());
+        assert!(psbt.extract().is_err());
+        assert_eq!(
+            other.get_inputs()[1].redeem_script,
+            Some(second.get_segwit_script())
+        );
+
+        psbt.combine(&other).unwrap();
+        let transaction = psbt.finalize().unwrap();
+        assert_eq!(transaction.get_witness(0).len(), 1);
+        assert_eq!(
+            transaction.get_input()[1].get_script(),
+            push_data(&second.get_segwit_script())
+        );
+        assert_eq!(transaction.get_witness(1)[1], *second.get_pubkey());
+        assert!(check_taproot_spends(&transaction, &prevouts));
+    }
+
+    #[test]
+    fn test_psbt_multisig_signatures() {
+        let (first, second) = keys();
+        let third =
+            KeysHandler::new("0000000000000000000000000000000000000000000000000000000000000003")
+                .unwrap();
+        let mut public_keys: Vec<Vec<u8>> = [&first, &second, &third]
+            .iter()
+            .map(|keys| keys.get_pubkey().clone())
+            .collect();
+        public_keys.sort();
+        let multisig = multisig_script(2, &public_keys).unwrap();
+        let utxos = utxos(vec![
+            (4_000, p2wsh_script(&multisig)),
+            (6_000, p2sh_script(&multisig)),
+        ]);
+        let mut psbt = psbt_for(&utxos, &first.get_script(), &p2wsh_script(&multisig));
+
+        // Without the scripts the keys can't tell the inputs are theirs
+        assert_eq!(psbt.clone().sign(std::slice::from_ref(&first)).unwrap(), 0);
+        psbt.set_spending_scripts(0, None, Some(multisig.clone()));
+        psbt.set_spending_scripts(1, Some(multisig.clone()), None);
+        let mut other = psbt.clone();
+        assert_eq!(psbt.sign(std::slice::from_ref(&first)).unwrap(), 2);
+        assert!(psbt.clone().finalize().is_err());
+        assert_eq!(other.sign(&[third.clone(), second.clone()]).unwrap(), 4);
+        psbt.combine(&Psbt::from_base64(&other.to_base64()).unwrap())
+            .unwrap();
+        let transaction = psbt.finalize().unwrap();
+
+        // Two signatures in the order of the keys, after the empty item for OP_CHECKMULTISIG
+        let witness = transaction.get_witness(0);
+        assert_eq!(witness.len(), 4);
+        assert!(witness[0].is_empty());
+        assert_eq!(witness[3], multisig);
+        let secp = Secp256k1::verification_only();
+        let sig_hash =
+            Message::from_slice(&transaction.segwit_sig_hash(0, &multisig, 4_000)).unwrap();
+        let signers: Vec<&Vec<u8>> = public_keys
+            .iter()
+            .filter(|key| {
+                witness[1..3].iter().any(|signature| {
+                    let signature = Signature::from_der(&signature[..signature.len() - 1]).unwrap();
+                    let key = secp256k1::PublicKey::from_slice(key).unwrap();
+                    secp.verify_ecdsa(&sig_hash, &signature, &key).is_ok()
+                })
+            })
+            .collect();
+        assert_eq!(signers.len(), 2);
+
+        let script_sig = transaction.get_input()[1].get_script();
+        assert_eq!(script_sig[0], OP_0);
+        assert!(script_sig.ends_with(&push_data(&multisig)));
+        assert!(transaction.get_witness(1).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_psbts() {
+        let (first, second) = keys();
+        let utxos = utxos(vec![(10_000, first.get_script())]);
+        let mut psbt = psbt_for(&utxos, &second.get_script(), &first.get_script());
+        let serialized = psbt.serialize();
+
+        assert!(Psbt::deserialize(&serialized[1..]).is_err());
+        assert!(Psbt::deserialize(&serialized[..serialized.len() - 1]).is_err());
+        let mut extra = serialized.clone();
+        extra.push(0);
+        assert!(Psbt::deserialize(&extra).is_err());
+        assert!(Psbt::from_base64("cHNidP8B*").is_err());
+        assert!(Psbt::new(psbt.get_unsigned_tx().clone(), &[]).is_err());
+        // Legacy inputs need the whole transaction they spend, and it must create the prevout
+        let prevout = utxos[0].1.clone();
+        let unsigned_tx = psbt.get_unsigned_tx().clone();
+        assert!(Psbt::new(unsigned_tx.clone(), &[(prevout.clone(), None)]).is_err());
+        let mut other_output = prevout.clone();
+        other_output.value += 1;
+        let other_tx = funding(&other_output);
+        assert!(Psbt::new(unsigned_tx.clone(), &[(prevout.clone(), Some(other_tx))]).is_err());
+        assert!(Psbt::new(unsigned_tx, &[(prevout.clone(), Some(funding(&prevout)))]).is_ok());
+
+        // Script lengths near usize::MAX are rejected instead of overflowing
+        let mut huge_script = vec![2, 0, 0, 0, 1];
+        huge_script.extend_from_slice(&[0; 36]);
+        huge_script.push(0xff);
+        huge_script.extend_from_slice(&[0xff; 8]);
+        assert!(parse_transaction(&huge_script).is_err());
+
+        // PSBTs of other transactions can't be combined
+        let other = psbt_for(&utxos, &first.get_script(), &first.get_script());
+        assert!(psbt.combine(&other).is_err());
+        assert!(psbt.finalize().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::message_structs::block_headers::BlockHeader;
+use chrono::NaiveDate;
+use std::error::Error;
+
+/// File the birthday of every wallet is saved to, after the id of the wallet
+pub const BIRTHDAYS_PATH: &str = "./storage/birthdays.txt";
+/// Blocks may be timestamped up to two hours before the ones preceding them
+pub const BLOCK_TIME_DRIFT: u32 = 2 * 60 * 60;
+
+/// Block a wallet rescan starts from, chosen by the user as a height or a date
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RescanStart {
+    Height(u32),
+    Time(u32),
+}
+
+impl RescanStart {
+    /// Parses a block height or a date as YYYY-MM-DD, which starts at 00:00 UTC
+    /// # Errors
+    /// Returns an error if the text is neither a height nor a date
+    pub fn parse(text: &str) -> Result<RescanStart, Box<dyn Error>> {
+        let text = text.trim();
+        if let Ok(height) = text.parse::<u32>() {
+            return Ok(RescanStart::Height(height));
+        }
+        let date = match NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => {
+                return Err("The rescan starts from a block height or a YYYY-MM-DD date".into())
+            }
+        };
+        let time = date.and_hms_opt(0, 0, 0).ok_or("Invalid date")?.timestamp();
+        Ok(RescanStart::Time(u32::try_from(time)?))
+    }
+
+    /// Returns the index of the first serialized header of the chain the rescan starts from,
+    /// the header at index n has height n + 1, and the birthday of a wallet rescanned from it.
+    /// None if the chain doesn't reach it
+    pub fn first_header(&self, headers: &[Vec<u8>]) -> Option<(usize, u32)> {
+        let time_of = |serialized: &Vec<u8>| {
+            BlockHeader::deserialize(&mut serialized.clone()).map(|header| header.time)
+        };
+        match *self {
+            RescanStart::Height(height) => {
+                let index = height.max(1) as usize - 1;
+                let time = time_of(headers.get(index)?).ok()?;
+                // Blocks right after it may have an earlier time
+                Some((index, time.saturating_sub(BLOCK_TIME_DRIFT)))
+            }
+            RescanStart::Time(time) => {
+                // Header times only go up over the chain, give or take the drift
+                let index =
+                    headers.partition_point(|header| time_of(header).map_or(true, |t| t < time));
+                headers.get(index)?;
+                Some((index, time))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod rescan_tests {
+    use super::*;
+
+    #[test]
+    fn test_rescan_start() {
+        let headers: Vec<Vec<u8>> = [1_700_000_000, 1_700_000_600, 1_700_086_400]
+            .iter()
+            .map(|time| BlockHeader::new(1, [0; 32], [0; 32], *time, 0, 0).serialize())
+            .collect();
+        assert_eq!(RescanStart::parse(" 2 ").unwrap(), RescanStart::Height(2));
+        assert_eq!(
+            RescanStart::parse("2023-11-15").unwrap(),
+            RescanStart::Time(1_700_006_400)
+        );
+        assert!(RescanStart::parse("15/11/2023").is_err());
+
+        assert_eq!(
+            RescanStart::Height(2).first_header(&headers),
+            Some((1, 1_700_000_600 - BLOCK_TIME_DRIFT))
+        );
+        assert_eq!(
+            RescanStart::Height(0).first_header(&headers),
+            Some((0, 1_700_000_000 - BLOCK_TIME_DRIFT))
+        );
+        assert_eq!(RescanStart::Height(4).first_header(&headers), None);
+        assert_eq!(
+            RescanStart::Time(1_700_006_400).first_header(&headers),
+            Some((2, 1_700_006_400))
+        );
+        assert_eq!(
+            RescanStart::Time(1_800_000_000).first_header(&headers),
+            None
+        );
+    }
+}
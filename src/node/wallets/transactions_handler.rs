@@ -1,15 +1,39 @@
+use super::coin_selection::{
+    input_vsize, output_vsize, select_all, select_coins, CoinControl, Selection, SelectionParams,
+    DUST_LIMIT, LONG_TERM_FEE_RATE,
+};
 use super::keys_handler::KeysHandler;
+use super::payments::checked_total;
+use super::pending::PendingTransaction;
 use crate::message_structs::{
-    compact_size::CompactSize, input::Input, outpoint::Outpoint, output::Output,
-    tx_message::TXMessage,
+    compact_size::CompactSize,
+    input::Input,
+    outpoint::Outpoint,
+    output::Output,
+    tx_message::{TXMessage, TAPROOT_SIGHASH_DEFAULT},
 };
+use crate::node::validation_engine::taproot::{sign_schnorr, tweak_keypair};
 use crate::utils::array_tools::u8_vec_to_hex_string;
-use crate::utils::script_tools::from_adderss_to_vec;
+use crate::utils::script_tools::{is_p2pkh, is_p2tr, is_p2wpkh, p2pkh_script};
 use secp256k1::{ecdsa::Signature, Message, Secp256k1, SecretKey};
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::error::Error as Err;
-type TransactionResult = Result<(TXMessage, Vec<(Outpoint, Output)>), Box<dyn Err>>;
-
-/// P2PKH Transaction Handler
+pub type TransactionResult = Result<(TXMessage, Vec<(Outpoint, Output)>), Box<dyn Err>>;
+
+/// Highest fee rate (sat/vB) a transaction may be built with
+pub const MAX_FEE_RATE: u64 = 1_000;
+/// Highest fee (satoshis) a transaction may pay, 0.1 BTC
+pub const MAX_FEE: i64 = 10_000_000;
+/// Version, locktime and input and output counts
+const TRANSACTION_OVERHEAD: usize = 10;
+/// Sequence of the inputs of our transactions, signals they can be replaced (BIP125)
+pub const RBF_SEQUENCE: u32 = 0xfffffffd;
+/// Fee rate (sat/vB) a replacement must pay over the fee of the transactions it replaces
+const INCREMENTAL_FEE_RATE: i64 = 1;
+
+/// P2PKH Transaction Handler, also spends P2WPKH outputs signing them with BIP143
+/// and P2TR outputs through their key path (BIP341)
 pub struct P2PKH {}
 
 impl Default for P2PKH {
@@ -30,40 +54,177 @@ impl P2PKH {
         P2PKH {}
     }
 
-    /// Receives the keys_handler, a recipient_address, amount and fee; and returns the corresponding P2PKH Transaction
+    /// Estimates the size in vbytes of a signed P2PKH transaction with the given amount of inputs and outputs
+    pub fn estimate_size(inputs: usize, outputs: usize) -> usize {
+        // version + locktime + counts, signed input with compressed pubkey, P2PKH output
+        10 + 148 * inputs + 34 * outputs
+    }
+
+    /// Estimates the size in vbytes the transaction spending the previous outputs will have once signed
+    pub fn estimate_vsize(previous_outputs: &[Output], outputs: &[Output]) -> usize {
+        let inputs: usize = previous_outputs
+            .iter()
+            .map(|output| input_vsize(&output.get_script()))
+            .sum();
+        let outputs: usize = outputs
+            .iter()
+            .map(|output| output_vsize(&output.get_script()))
+            .sum();
+        // The segwit marker and flag add half a vbyte, rounded up
+        let segwit = previous_outputs
+            .iter()
+            .any(|output| !is_p2pkh(&output.get_script()));
+        TRANSACTION_OVERHEAD + segwit as usize + inputs + outputs
+    }
+
+    /// Returns the fee the transaction spending the utxos pays
+    pub fn fee_paid(utxos: &[(Outpoint, Output)], transaction: &TXMessage) -> i64 {
+        let input_value: i64 = utxos.iter().map(|(_, output)| output.value).sum();
+        input_value - transaction.get_output_amounts().iter().sum::<i64>()
+    }
+
+    /// Receives the keys_handler, a recipient output script, amount and fee rate in sat/vB; and returns
+    /// the corresponding Transaction, the change goes back to the P2PKH address of the keys
     pub fn create_transaction(
         keys_handler: &KeysHandler,
         utxos: &[(Outpoint, Output)],
-        recipient_address: &[u8],
+        recipient_script: &[u8],
         amount: i64,
-        fee: i64,
+        fee_rate: u64,
+    ) -> TransactionResult {
+        Self::create_transaction_with_keys(
+            std::slice::from_ref(keys_handler),
+            utxos,
+            recipient_script,
+            &keys_handler.get_script(),
+            amount,
+            fee_rate,
+        )
+    }
+
+    /// Same as create_transaction but the utxos may belong to any of the keys (HD wallets),
+    /// each input is signed with the key of the script it spends and the change goes to change_script
+    pub fn create_transaction_with_keys(
+        keys: &[KeysHandler],
+        utxos: &[(Outpoint, Output)],
+        recipient_script: &[u8],
+        change_script: &[u8],
+        amount: i64,
+        fee_rate: u64,
     ) -> TransactionResult {
         println!("wallet P2PKH CREATE TRANSACTION");
-        // Setup
-        let sender_pubkey = keys_handler.get_pubkey();
-        let sender_address = keys_handler.get_address();
+        if keys.is_empty() {
+            return Err("No keys to sign the transaction".into());
+        }
+        let (mut transaction, utxos) = Self::create_unsigned_transaction(
+            utxos,
+            recipient_script,
+            change_script,
+            amount,
+            fee_rate,
+        )?;
 
+        // Sign transaction
+        let previous_outputs: Vec<Output> =
+            utxos.iter().map(|(_, output)| output.clone()).collect();
+        Self::sign_transaction(&mut transaction, keys, &previous_outputs);
+
+        println!("wallet P2PKH CREATE TRANSACTION 4: {:?}", transaction);
         println!(
-            "Our Address:{:?}",
-            bs58::encode(sender_address.clone()).into_string()
+            "tx in hex: {:?}",
+            u8_vec_to_hex_string(&transaction.serialize())
         );
+        // Signed_transaction
+        Ok((transaction, utxos))
+    }
+
+    /// Builds the transaction spending the utxos needed without signing it, its inputs have
+    /// empty scripts and witnesses so it can be signed elsewhere. The fee is the fee rate
+    /// times the estimated size of the signed transaction, plus the change if it would be dust
+    /// # Errors
+    /// Returns an error if the utxos can't pay the amount and the fee, or the fee is absurd
+    pub fn create_unsigned_transaction(
+        utxos: &[(Outpoint, Output)],
+        recipient_script: &[u8],
+        change_script: &[u8],
+        amount: i64,
+        fee_rate: u64,
+    ) -> TransactionResult {
+        Self::create_controlled_transaction(
+            utxos,
+            recipient_script,
+            change_script,
+            amount,
+            fee_rate,
+            &CoinControl::default(),
+        )
+    }
+
+    /// Same as create_unsigned_transaction but spending only the utxos coin control allows,
+    /// all the selected ones if the user selected any
+    pub fn create_controlled_transaction(
+        utxos: &[(Outpoint, Output)],
+        recipient_script: &[u8],
+        change_script: &[u8],
+        amount: i64,
+        fee_rate: u64,
+        coin_control: &CoinControl,
+    ) -> TransactionResult {
+        Self::create_batch_transaction(
+            utxos,
+            &[(recipient_script.to_vec(), amount)],
+            change_script,
+            fee_rate,
+            coin_control,
+            &HashMap::new(),
+        )
+    }
+
+    /// Builds an unsigned transaction paying every recipient (output script and amount)
+    /// in the given order, with a single change output after them if there is change.
+    /// Inputs spending the scripts of `input_vsizes` are estimated with the size it has for them
+    /// # Errors
+    /// Returns an error if there are no recipients, an amount is dust, the utxos can't pay
+    /// the amounts and the fee, or the fee is absurd
+    pub fn create_batch_transaction(
+        utxos: &[(Outpoint, Output)],
+        recipients: &[(Vec<u8>, i64)],
+        change_script: &[u8],
+        fee_rate: u64,
+        coin_control: &CoinControl,
+        input_vsizes: &HashMap<Vec<u8>, usize>,
+    ) -> TransactionResult {
+        let sender_pubkey = &[];
+        if recipients.is_empty() {
+            return Err("The transaction must pay at least one recipient".into());
+        }
+        if recipients.iter().any(|(_, amount)| *amount < DUST_LIMIT) {
+            return Err(format!("The amount must be at least {} satoshis", DUST_LIMIT).into());
+        }
+        if checked_total(recipients.iter().map(|(_, amount)| *amount)).is_none() {
+            return Err("The amounts add up to more than 21 million bitcoin".into());
+        }
+        if fee_rate > MAX_FEE_RATE {
+            return Err(format!("The fee rate can't be above {} sat/vB", MAX_FEE_RATE).into());
+        }
 
-        let (utxos, change) = Self::get_utxos_needed(utxos, amount + fee, sender_pubkey);
+        let Selection { utxos, change, .. } = Self::get_utxos_needed(
+            utxos,
+            recipients,
+            fee_rate,
+            change_script,
+            coin_control,
+            input_vsizes,
+        )?;
 
         // Version
         let version = 1;
 
         // Inputs and Inputs count
-        let (inputs, scripts_used) = Self::create_transaction_inputs(&utxos, sender_pubkey);
+        let inputs = Self::create_transaction_inputs(&utxos, sender_pubkey);
         let inputs_count = CompactSize::from_usize_to_compact_size(inputs.len());
 
-        let outputs = Self::create_transaction_outputs(
-            amount,
-            fee,
-            change as i64,
-            &from_adderss_to_vec(&sender_address).unwrap(),
-            recipient_address,
-        );
+        let outputs = Self::create_transaction_outputs(recipients, change, change_script);
         let outputs_count = CompactSize::from_usize_to_compact_size(outputs.len());
 
         println!("wallet P2PKH CREATE TRANSACTION 2");
@@ -72,7 +233,7 @@ impl P2PKH {
         println!("wallet P2PKH CREATE TRANSACTION 3");
 
         // Create transaction
-        let mut transaction = TXMessage::new(
+        let transaction = TXMessage::new(
             version,
             inputs_count,
             inputs,
@@ -81,50 +242,246 @@ impl P2PKH {
             locktime,
         );
 
-        // Sign transaction
-        Self::sign_transaction(&mut transaction, keys_handler, scripts_used);
+        let fee = Self::fee_paid(&utxos, &transaction);
+        if fee < 0 {
+            return Err("The outputs spend more than the inputs".into());
+        }
+        if fee > MAX_FEE {
+            return Err(format!("The fee of {} satoshis is too high", fee).into());
+        }
 
-        // Change inputs' script
-        // for input in transaction.input_list.iter_mut() {
-        //     input.update_script(signature_script.remove(0));
+        Ok((transaction, utxos))
+    }
 
-        // }
+    /// Builds an unsigned replacement of the pending transaction (BIP125) paying its recipients
+    /// at a higher fee rate. It spends the same inputs and the change pays the extra fee,
+    /// if that isn't enough the largest of the other utxos are added
+    /// # Errors
+    /// Returns an error if the fee rate isn't above the one of the transaction
+    /// or the utxos can't pay the new fee
+    pub fn create_replacement(
+        pending: &PendingTransaction,
+        utxos: &[(Outpoint, Output)],
+        change_script: &[u8],
+        fee_rate: u64,
+        input_vsizes: &HashMap<Vec<u8>, usize>,
+    ) -> TransactionResult {
+        if fee_rate <= pending.fee_rate() {
+            return Err(format!(
+                "The fee rate must be above the {} sat/vB the transaction pays",
+                pending.fee_rate()
+            )
+            .into());
+        }
+        if fee_rate > MAX_FEE_RATE {
+            return Err(format!("The fee rate can't be above {} sat/vB", MAX_FEE_RATE).into());
+        }
+        let recipients = pending.recipients();
+        let mut inputs = pending.get_spent().to_vec();
+        let mut others: Vec<&(Outpoint, Output)> = utxos
+            .iter()
+            .filter(|(outpoint, _)| !pending.spends(outpoint))
+            .collect();
+        others.sort_by_key(|(_, output)| Reverse(output.value));
+        let mut others = others.into_iter();
+        loop {
+            let mut coin_control = CoinControl::default();
+            for (outpoint, _) in inputs.iter() {
+                coin_control.select(*outpoint);
+            }
+            if let Ok((transaction, spent)) = Self::create_batch_transaction(
+                &inputs,
+                &recipients,
+                change_script,
+                fee_rate,
+                &coin_control,
+                input_vsizes,
+            ) {
+                let previous_outputs: Vec<Output> =
+                    spent.iter().map(|(_, output)| output.clone()).collect();
+                let vsize = Self::estimate_vsize(&previous_outputs, &transaction.get_output());
+                let min_fee = pending.fee() + INCREMENTAL_FEE_RATE * vsize as i64;
+                if Self::fee_paid(&spent, &transaction) >= min_fee {
+                    return Ok((transaction, spent));
+                }
+            }
+            match others.next() {
+                Some(utxo) => inputs.push(utxo.clone()),
+                None => return Err("Not enough balance to pay the new fee".into()),
+            }
+        }
+    }
 
-        println!("wallet P2PKH CREATE TRANSACTION 4: {:?}", transaction);
-        println!(
-            "tx in hex: {:?}",
-            u8_vec_to_hex_string(&transaction.serialize())
+    /// Builds an unsigned child spending the change of the pending parent to change_script (CPFP).
+    /// Its fee takes the fee rate of parent and child together to fee_rate, if the change
+    /// can't pay it the largest of the other utxos are added
+    /// # Errors
+    /// Returns an error if the parent has no change, already pays the fee rate
+    /// or the utxos can't pay the fee
+    pub fn create_child(
+        parent: &PendingTransaction,
+        utxos: &[(Outpoint, Output)],
+        change_script: &[u8],
+        fee_rate: u64,
+    ) -> TransactionResult {
+        let change = match parent.change() {
+            Some(change) => change,
+            None => return Err("The transaction has no change output to spend".into()),
+        };
+        if fee_rate <= parent.fee_rate() {
+            return Err(
+                format!("The transaction already pays {} sat/vB", parent.fee_rate()).into(),
+            );
+        }
+        if fee_rate > MAX_FEE_RATE {
+            return Err(format!("The fee rate can't be above {} sat/vB", MAX_FEE_RATE).into());
+        }
+        let mut others: Vec<&(Outpoint, Output)> = utxos
+            .iter()
+            .filter(|(outpoint, _)| !parent.spends(outpoint))
+            .collect();
+        others.sort_by_key(|(_, output)| Reverse(output.value));
+        let mut others = others.into_iter();
+        let mut inputs = vec![change];
+        let parent_vsize = parent.get_transaction().vsize() as i64;
+        let fee_rate = fee_rate as i64;
+        loop {
+            let previous_outputs: Vec<Output> =
+                inputs.iter().map(|(_, output)| output.clone()).collect();
+            let child_output = Output::new(
+                0,
+                CompactSize::from_usize_to_compact_size(change_script.len()),
+                change_script.to_vec(),
+            );
+            let vsize = Self::estimate_vsize(&previous_outputs, &[child_output]) as i64;
+            let fee = (fee_rate * (parent_vsize + vsize) - parent.fee()).max(vsize);
+            if fee > MAX_FEE {
+                return Err(format!("The fee of {} satoshis is too high", fee).into());
+            }
+            let value = previous_outputs
+                .iter()
+                .map(|output| output.value)
+                .sum::<i64>()
+                - fee;
+            if value >= DUST_LIMIT {
+                let transaction_inputs = Self::create_transaction_inputs(&inputs, &[]);
+                let outputs = Self::create_transaction_outputs(&[], value, change_script);
+                let transaction = TXMessage::new(
+                    1,
+                    CompactSize::from_usize_to_compact_size(transaction_inputs.len()),
+                    transaction_inputs,
+                    CompactSize::from_usize_to_compact_size(outputs.len()),
+                    outputs,
+                    0,
+                );
+                return Ok((transaction, inputs));
+            }
+            match others.next() {
+                Some(utxo) => inputs.push(utxo.clone()),
+                None => return Err("Not enough balance to pay for the transaction".into()),
+            }
+        }
+    }
+
+    /// Builds and signs a transaction spending every utxo of the key to recipient_script,
+    /// the fee at fee_rate is taken from the swept amount. Returns it with the fee it pays
+    /// # Errors
+    /// Returns an error if there are no utxos, the fee rate is too high or they can't pay the fee
+    pub fn create_sweep(
+        keys_handler: &KeysHandler,
+        utxos: &[(Outpoint, Output)],
+        recipient_script: &[u8],
+        fee_rate: u64,
+    ) -> Result<(TXMessage, i64), Box<dyn Err>> {
+        if utxos.is_empty() {
+            return Err("The key has no utxos to sweep".into());
+        }
+        if fee_rate > MAX_FEE_RATE {
+            return Err(format!("The fee rate can't be above {} sat/vB", MAX_FEE_RATE).into());
+        }
+        let previous_outputs: Vec<Output> =
+            utxos.iter().map(|(_, output)| output.clone()).collect();
+        let output = Output::new(
+            0,
+            CompactSize::from_usize_to_compact_size(recipient_script.len()),
+            recipient_script.to_vec(),
         );
-        // Signed_transaction
-        Ok((transaction, utxos))
+        let mut vsize = Self::estimate_vsize(&previous_outputs, &[output]);
+        if !keys_handler.is_compressed() {
+            // Legacy inputs are estimated with compressed public keys, 32 bytes shorter
+            let legacy_inputs = previous_outputs
+                .iter()
+                .filter(|output| is_p2pkh(&output.get_script()))
+                .count();
+            vsize += 32 * legacy_inputs;
+        }
+        let fee = fee_rate as i64 * vsize as i64;
+        if fee > MAX_FEE {
+            return Err(format!("The fee of {} satoshis is too high", fee).into());
+        }
+        let value = previous_outputs
+            .iter()
+            .map(|output| output.value)
+            .sum::<i64>()
+            - fee;
+        if value < DUST_LIMIT {
+            return Err("The utxos of the key can't pay the fee".into());
+        }
+        let inputs = Self::create_transaction_inputs(utxos, &[]);
+        let outputs = Self::create_transaction_outputs(&[], value, recipient_script);
+        let mut transaction = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(inputs.len()),
+            inputs,
+            CompactSize::from_usize_to_compact_size(outputs.len()),
+            outputs,
+            0,
+        );
+        Self::sign_transaction(
+            &mut transaction,
+            std::slice::from_ref(keys_handler),
+            &previous_outputs,
+        );
+        Ok((transaction, fee))
     }
 
-    /// Returns the minimum number of UTXOs needed to cover the amount + fee and the value of the change, if any
+    /// Returns the UTXOs chosen by coin selection to cover the amounts paid to the recipients
+    /// and the fee of the transaction at the fee rate, and the value of the change, 0 if it
+    /// would be dust. Only the utxos coin control allows are spent
     fn get_utxos_needed(
         utxos: &[(Outpoint, Output)],
-        value: i64,
-        _sender_pubkey: &[u8],
-    ) -> (Vec<(Outpoint, Output)>, u32) {
-        // Sort utxos by ascending order of value
-        let mut sorted_utxos = utxos.to_owned();
-        sorted_utxos.sort_by(|(_, output1), (_, output2)| output1.value.cmp(&output2.value));
-
-        // Get the minimum number of UTXOs needed to cover the amount + fee
-        let mut needed_utxos = Vec::new();
-        let mut total_value = 0;
-
-        for (outpoint, output) in sorted_utxos {
-            total_value += output.value;
-            needed_utxos.push((
-                Outpoint::new(outpoint.get_hash(), outpoint.get_index()),
-                output.clone(),
-            ));
-            if total_value >= value {
-                break;
-            }
+        recipients: &[(Vec<u8>, i64)],
+        fee_rate: u64,
+        change_script: &[u8],
+        coin_control: &CoinControl,
+        input_vsizes: &HashMap<Vec<u8>, usize>,
+    ) -> Result<Selection, Box<dyn Err>> {
+        // The inputs and change are accounted for by coin selection
+        let outputs_vsize: usize = recipients
+            .iter()
+            .map(|(script, _)| output_vsize(script))
+            .sum();
+        let amount: i64 = recipients.iter().map(|(_, amount)| amount).sum();
+        // Outputs counts above 252 take more than the byte in the overhead
+        let count_vsize = CompactSize::from_usize_to_compact_size(recipients.len() + 1).size() - 1;
+        let base_vsize = TRANSACTION_OVERHEAD + 1 + count_vsize + outputs_vsize;
+        let params = SelectionParams::new(
+            amount,
+            base_vsize,
+            fee_rate,
+            LONG_TERM_FEE_RATE,
+            change_script,
+            input_vsizes,
+        );
+        let utxos = coin_control.spendable(utxos);
+        let selection = match coin_control.has_selection() {
+            true => select_all(&utxos, &params),
+            false => select_coins(&utxos, &params),
+        };
+        match selection {
+            Some(selection) if selection.change >= 0 => Ok(selection),
+            _ => Err("Not enough balance to pay the amount and the fee".into()),
         }
-
-        (needed_utxos, (total_value - value) as u32)
     }
 
     /// Returns the transactino inputs for the utxos passed as arguments.
@@ -132,55 +489,51 @@ impl P2PKH {
     fn create_transaction_inputs(
         utxos: &[(Outpoint, Output)],
         _sender_pubkey: &[u8],
-    ) -> (Vec<Input>, Vec<Vec<u8>>) {
+    ) -> Vec<Input> {
         let mut inputs = Vec::new();
-        let mut _i = 0;
-        let mut scripts = vec![];
 
-        for (_i, (outpoint, output)) in utxos.iter().enumerate() {
+        for (outpoint, _) in utxos.iter() {
             let previous_tx = outpoint;
-            scripts.push(output.get_script());
             // Create script and script_lenght
             let script = Vec::new();
             let script_length = CompactSize::from_usize_to_compact_size(0);
 
-            let sequence = 0xffffffff;
+            let sequence = RBF_SEQUENCE;
 
             let input = Input::new(*previous_tx, script_length, script, sequence);
             inputs.push(input);
         }
-        (inputs, scripts)
+        inputs
     }
 
+    /// Returns an output for each recipient, followed by the change output unless the change is 0
     fn create_transaction_outputs(
-        value: i64,
-        _fee: i64,
+        recipients: &[(Vec<u8>, i64)],
         change: i64,
-        sender_address: &[u8],
-        recipient_address: &[u8],
+        change_script: &[u8],
     ) -> Vec<Output> {
-        // If you want to spend less money than the amount available in a UTXO (Unspent Transaction Output), you need to create two outputs in your transaction:
-        // Output for the recipient: This output contains the desired amount of money you want to send to the recipient. It specifies the recipient's address and the amount.
+        // If you want to spend less money than the amount available in a UTXO (Unspent Transaction Output), you need to create the outputs in your transaction:
+        // Outputs for the recipients: Each output contains the desired amount of money you want to send to a recipient. It specifies the recipient's address and the amount.
         // Change output: This output sends the remaining amount back to yourself. It ensures that the total input amount matches the total output amount in the transaction.
 
         let mut outputs = Vec::new();
 
-        // Create an Output for recipient_address with value
-        let script = Self::output_script_creation_for(recipient_address);
-        let script_length = CompactSize::from_usize_to_compact_size(script.len());
-
-        let output = Output::new(value, script_length, script);
-        outputs.push(output);
+        // Create an Output for each recipient_script with its value
+        for (recipient_script, value) in recipients {
+            let script = recipient_script.to_vec();
+            let script_length = CompactSize::from_usize_to_compact_size(script.len());
 
-        // Create an Output for fee
-        // let script = Self::output_script_fee_creation();
-        // let script_length = CompactSize::from_usize_to_compact_size(script.len());
+            let output = Output::new(*value, script_length, script);
+            outputs.push(output);
+        }
 
-        // let output = Output::new(fee, script_length, script);
-        // outputs.push(output);
+        // The fee is what the inputs have left over the outputs
 
-        // Create an Output for sender_pubkey with change
-        let script = Self::output_script_creation_for(sender_address);
+        // Create an Output for change_script with change, unless it would be dust
+        if change <= 0 {
+            return outputs;
+        }
+        let script = change_script.to_vec();
         let script_length = CompactSize::from_usize_to_compact_size(script.len());
 
         let output = Output::new(change, script_length, script);
@@ -190,28 +543,15 @@ impl P2PKH {
     }
 
     /// Output script for recipient or self (returning change). Must receive a pubkey address
-    fn output_script_creation_for(address: &[u8]) -> Vec<u8> {
+    fn _output_script_creation_for(address: &[u8]) -> Vec<u8> {
         let mut hash = address.to_owned();
 
         hash.remove(0); // remove version
         let checksum_pos = hash.len() - 4;
         hash = hash[0..checksum_pos].to_vec(); // remove checksum
 
-        let mut script = vec![];
-        // OP_DUP
-        script.push(118);
-
-        // OP_HASH160
-        script.push(169);
-        script.push(hash.len() as u8);
-        script.extend_from_slice(&hash);
-
-        // OP_EQUALVERIFY
-        script.push(136);
-
-        // OP_CHECKSIG
-        script.push(172);
-        script
+        // OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+        p2pkh_script(&hash)
     }
 
     /// Output script for fee
@@ -221,39 +561,58 @@ impl P2PKH {
         script
     }
 
-    /// Signs the transaction and returns the signature serialized
-    fn sign_transaction(
+    /// Signs each input with the key whose script it spends, the first key if none matches.
+    /// P2PKH inputs get a signature script, P2WPKH inputs a witness with a BIP143 signature
+    /// and P2TR inputs a witness with a Schnorr signature of the tweaked key
+    pub fn sign_transaction(
         transaction: &mut TXMessage,
-        keys_handler: &KeysHandler,
-        mut scripts: Vec<Vec<u8>>,
+        keys: &[KeysHandler],
+        previous_outputs: &[Output],
     ) {
-        //let mut signatures = vec![];
         println!(
             "before adding script {:?}",
             u8_vec_to_hex_string(&transaction.serialize())
         );
-        let mut x = 0;
-        while !scripts.is_empty() {
+        for (index, previous_output) in previous_outputs.iter().enumerate() {
+            let script = previous_output.get_script();
+            println!("script:{:?}", script);
+            let keys_handler = Self::keys_for_script(keys, &script);
+            let pub_key = keys_handler.public_key.clone();
+
+            if is_p2wpkh(&script) {
+                // The script code of a P2WPKH input is the P2PKH script of its hash
+                let script_code = p2pkh_script(&script[2..]);
+                let mut signature = Self::generate_ecdsa_signature(
+                    keys_handler.get_private_key(),
+                    &transaction.segwit_sig_hash(index, &script_code, previous_output.value),
+                );
+                signature.push(1_u8.to_be());
+                transaction.set_witness(index, vec![signature, pub_key]);
+                continue;
+            }
+
+            if is_p2tr(&script) {
+                let sig_hash = transaction.taproot_sig_hash(
+                    index,
+                    previous_outputs,
+                    TAPROOT_SIGHASH_DEFAULT,
+                    None,
+                );
+                let keypair = tweak_keypair(keys_handler.get_private_key());
+                if let (Some(sig_hash), Ok(keypair)) = (sig_hash, keypair) {
+                    // SIGHASH_DEFAULT signatures have no hash type byte
+                    transaction.set_witness(index, vec![sign_schnorr(&keypair, &sig_hash)]);
+                }
+                continue;
+            }
+
             let mut tx_clone = transaction.clone();
-            println!("script:{:?}", scripts[0]);
-            //scripts.remove(0);
-            tx_clone.update_empty_script(scripts.remove(0));
-            //let tx = transaction.clone();
-            // let mut tx_seriaized = tx_clone.serialize();
-            // //tx_seriaized.extend_from_slice(&[1,0,0,0]);
-            // //tx_seriaized.reverse();
-            // let mut tx_hash = match calculate_hash_for_serialized(tx_seriaized) {
-            //     Some(a) => a,
-            //     None => return,
-            // };
-            //println!("before adding sig {:?}",u8_vec_to_hex_string(&tx_clone.serialize()));
-            //tx_hash.reverse();
+            tx_clone.input_list[index].update_script(script);
             // encriptar usando la private key
             let signature = Self::generate_ecdsa_signature(
                 keys_handler.get_private_key(),
-                &tx_clone.sig_hash(x),
+                &tx_clone.sig_hash(index as u32),
             );
-            let pub_key = keys_handler.public_key.clone();
             // signature + public key -> input script
             let mut input_script = Vec::new();
             input_script.extend_from_slice(&[signature.len() as u8 + 1]);
@@ -262,13 +621,23 @@ impl P2PKH {
             input_script.extend_from_slice(&[keys_handler.public_key.len() as u8]);
             input_script.extend_from_slice(&pub_key);
 
-            transaction.update_empty_script(input_script);
-            x += 1;
+            transaction.input_list[index].update_script(input_script);
         }
     }
 
+    /// Returns the key that can spend a P2PKH, P2WPKH or P2TR script
+    fn keys_for_script<'a>(keys: &'a [KeysHandler], script: &[u8]) -> &'a KeysHandler {
+        keys.iter()
+            .find(|keys_handler| {
+                keys_handler.get_script() == script
+                    || keys_handler.get_segwit_script() == script
+                    || keys_handler.get_taproot_script() == script
+            })
+            .unwrap_or(&keys[0])
+    }
+
     /// Input signature script creation
-    fn generate_ecdsa_signature(private_key: &[u8], tx_hash: &[u8]) -> Vec<u8> {
+    pub fn generate_ecdsa_signature(private_key: &[u8], tx_hash: &[u8]) -> Vec<u8> {
         // Create a Secp256k1 context
         let secp = Secp256k1::new();
 
@@ -294,6 +663,8 @@ impl P2PKH {
 mod transactions_handler_test {
 
     use super::*;
+    use crate::node::validation_engine::taproot::{check_key_path_spend, check_taproot_spends};
+    use crate::utils::script_tools::script_for_address;
 
     #[test]
     fn test_create_transaction() {
@@ -306,14 +677,14 @@ mod transactions_handler_test {
             117, 49, 65, 53, 55, 67, 115, 57, 112, 75, 75, 51, 105, 88, 87,
         ];
 
-        let value = 2;
-        let fee = 1;
+        let value = 25_000;
+        let fee_rate = 20;
 
         let utxos = vec![
             (
                 Outpoint::new([0u8; 32], 0),
                 Output::new(
-                    5,
+                    50_000,
                     CompactSize::from_usize_to_compact_size(34),
                     vec![3u8; 34],
                 ),
@@ -321,7 +692,7 @@ mod transactions_handler_test {
             (
                 Outpoint::new([0u8; 32], 0),
                 Output::new(
-                    3,
+                    30_000,
                     CompactSize::from_usize_to_compact_size(34),
                     vec![2u8; 34],
                 ),
@@ -329,7 +700,7 @@ mod transactions_handler_test {
             (
                 Outpoint::new([0u8; 32], 0),
                 Output::new(
-                    2,
+                    20_000,
                     CompactSize::from_usize_to_compact_size(34),
                     vec![1u8; 34],
                 ),
@@ -337,7 +708,7 @@ mod transactions_handler_test {
             (
                 Outpoint::new([0u8; 32], 0),
                 Output::new(
-                    2,
+                    20_000,
                     CompactSize::from_usize_to_compact_size(34),
                     vec![1u8; 34],
                 ),
@@ -345,109 +716,462 @@ mod transactions_handler_test {
         ];
 
         let (transaction, _) =
-            P2PKH::create_transaction(&keys_handler, &utxos, recipient_address, value, fee)
+            P2PKH::create_transaction(&keys_handler, &utxos, recipient_address, value, fee_rate)
                 .unwrap();
 
         println!("transaction: {:?}", transaction);
 
-        assert_eq!(transaction.get_input().len(), 2);
-        assert_eq!(transaction.get_output().len(), 2);
+        // The utxo of 30000 pays amount and fee with less excess than a change output would cost
+        assert_eq!(transaction.get_input().len(), 1);
+        assert_eq!(transaction.get_output().len(), 1);
     }
 
     #[test]
-    fn test_get_utxos_needed_size_ok() {
-        let utxos = vec![
-            (
-                Outpoint::new([0u8; 32], 0),
-                Output::new(
-                    5,
-                    CompactSize::from_usize_to_compact_size(34),
-                    vec![3u8; 34],
-                ),
+    fn test_create_transaction_with_keys_signs_with_owner_key() {
+        let first =
+            KeysHandler::new("5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd")
+                .unwrap();
+        let second =
+            KeysHandler::new("18e14a7b6a307f426a94f8114701e7c8e774e7f9a47e2c2035db29a206321725")
+                .unwrap();
+        let script = second.get_script();
+        let utxos = vec![(
+            Outpoint::new([0u8; 32], 0),
+            Output::new(
+                10_000,
+                CompactSize::from_usize_to_compact_size(script.len()),
+                script,
             ),
+        )];
+        let recipient = script_for_address("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc").unwrap();
+        let change = first.get_script();
+
+        let (transaction, _) = P2PKH::create_transaction_with_keys(
+            &[first.clone(), second.clone()],
+            &utxos,
+            &recipient,
+            &change,
+            4_000,
+            1,
+        )
+        .unwrap();
+
+        assert!(transaction.get_input()[0]
+            .get_script()
+            .ends_with(&second.public_key));
+        assert_eq!(transaction.get_output()[1].get_script(), change);
+        assert!(!transaction.has_witness());
+    }
+
+    #[test]
+    fn test_create_transaction_spending_p2wpkh() {
+        let first =
+            KeysHandler::new("5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd")
+                .unwrap();
+        let second =
+            KeysHandler::new("18e14a7b6a307f426a94f8114701e7c8e774e7f9a47e2c2035db29a206321725")
+                .unwrap();
+        let utxos: Vec<(Outpoint, Output)> = [
+            (4_000, first.get_script()),
+            (6_000, second.get_segwit_script()),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(index, (value, script))| {
             (
-                Outpoint::new([0u8; 32], 0),
+                Outpoint::new([index as u8; 32], 0),
                 Output::new(
-                    3,
-                    CompactSize::from_usize_to_compact_size(34),
-                    vec![2u8; 34],
+                    value,
+                    CompactSize::from_usize_to_compact_size(script.len()),
+                    script,
                 ),
-            ),
+            )
+        })
+        .collect();
+        let recipient = script_for_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+
+        let (transaction, _) = P2PKH::create_transaction_with_keys(
+            &[first.clone(), second.clone()],
+            &utxos,
+            &recipient,
+            &first.get_segwit_script(),
+            8_000,
+            1,
+        )
+        .unwrap();
+
+        // The P2PKH input has a signature script and the P2WPKH one a witness
+        assert!(transaction.get_input()[0]
+            .get_script()
+            .ends_with(&first.public_key));
+        assert!(transaction.get_witness(0).is_empty());
+        assert!(transaction.get_input()[1].get_script().is_empty());
+        let witness = transaction.get_witness(1);
+        assert_eq!(witness[1], second.public_key);
+        assert_eq!(transaction.get_output()[0].get_script(), recipient);
+
+        // The witness signature is valid for the BIP143 sighash
+        let sig_hash = transaction.segwit_sig_hash(1, &second.get_script(), 6_000);
+        let signature = Signature::from_der(&witness[0][..witness[0].len() - 1]).unwrap();
+        let public_key = secp256k1::PublicKey::from_slice(&second.public_key).unwrap();
+        assert!(Secp256k1::new()
+            .verify_ecdsa(
+                &Message::from_slice(&sig_hash).unwrap(),
+                &signature,
+                &public_key
+            )
+            .is_ok());
+        assert!(transaction.vsize() < transaction.serialize().len());
+
+        // The size estimated before signing covers the signed transaction and pays the fee rate
+        let prevouts: Vec<Output> = utxos.iter().map(|(_, output)| output.clone()).collect();
+        let estimate = P2PKH::estimate_vsize(&prevouts, &transaction.get_output());
+        assert!(estimate >= transaction.vsize() && estimate <= transaction.vsize() + 2);
+        assert!(P2PKH::fee_paid(&utxos, &transaction) >= transaction.vsize() as i64);
+    }
+
+    #[test]
+    fn test_create_transaction_spending_p2tr() {
+        let first =
+            KeysHandler::new("5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd")
+                .unwrap();
+        let second =
+            KeysHandler::new("18e14a7b6a307f426a94f8114701e7c8e774e7f9a47e2c2035db29a206321725")
+                .unwrap();
+        let utxos: Vec<(Outpoint, Output)> = [
+            (4_000, first.get_taproot_script()),
+            (6_000, second.get_segwit_script()),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(index, (value, script))| {
             (
-                Outpoint::new([0u8; 32], 0),
+                Outpoint::new([index as u8; 32], 0),
                 Output::new(
-                    2,
-                    CompactSize::from_usize_to_compact_size(34),
-                    vec![1u8; 34],
+                    value,
+                    CompactSize::from_usize_to_compact_size(script.len()),
+                    script,
                 ),
-            ),
+            )
+        })
+        .collect();
+        let prevouts: Vec<Output> = utxos.iter().map(|(_, output)| output.clone()).collect();
+
+        let (mut transaction, _) = P2PKH::create_transaction_with_keys(
+            &[first.clone(), second.clone()],
+            &utxos,
+            &second.get_taproot_script(),
+            &first.get_taproot_script(),
+            8_000,
+            1,
+        )
+        .unwrap();
+
+        // The P2TR input has a single 64 bytes signature in its witness
+        assert!(transaction.get_input()[0].get_script().is_empty());
+        assert_eq!(transaction.get_witness(0).len(), 1);
+        assert_eq!(transaction.get_witness(0)[0].len(), 64);
+        assert!(check_key_path_spend(&transaction, 0, &prevouts));
+        assert!(check_taproot_spends(
+            &transaction,
+            &prevouts.iter().cloned().map(Some).collect::<Vec<_>>()
+        ));
+
+        // Any change to the transaction invalidates the signature
+        transaction.time += 1;
+        assert!(!check_key_path_spend(&transaction, 0, &prevouts));
+    }
+
+    fn utxos_of(values: &[i64]) -> Vec<(Outpoint, Output)> {
+        values
+            .iter()
+            .map(|value| {
+                (
+                    Outpoint::new([0u8; 32], 0),
+                    Output::new(
+                        *value,
+                        CompactSize::from_usize_to_compact_size(34),
+                        vec![3u8; 34],
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_create_sweep() {
+        let foreign =
+            KeysHandler::new("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ").unwrap();
+        let utxos: Vec<(Outpoint, Output)> = [
+            (7_000, foreign.get_script()),
+            (5_000, foreign.get_taproot_script()),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(index, (value, script))| {
             (
-                Outpoint::new([0u8; 32], 0),
+                Outpoint::new([index as u8; 32], 0),
                 Output::new(
-                    2,
-                    CompactSize::from_usize_to_compact_size(34),
-                    vec![1u8; 34],
+                    value,
+                    CompactSize::from_usize_to_compact_size(script.len()),
+                    script,
                 ),
-            ),
-        ];
+            )
+        })
+        .collect();
+        let recipient = script_for_address("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc").unwrap();
+
+        let (transaction, fee) = P2PKH::create_sweep(&foreign, &utxos, &recipient, 2).unwrap();
+        assert_eq!(transaction.get_input().len(), 2);
+        assert_eq!(transaction.get_output_amounts(), vec![12_000 - fee]);
+        assert_eq!(transaction.get_output()[0].get_script(), recipient);
+        // The uncompressed public key signs the legacy input and the fee rate is still paid
+        assert!(transaction.get_input()[0]
+            .get_script()
+            .ends_with(&foreign.public_key));
+        assert!(fee >= 2 * transaction.vsize() as i64);
+
+        assert!(P2PKH::create_sweep(&foreign, &[], &recipient, 2).is_err());
+        assert!(P2PKH::create_sweep(&foreign, &utxos, &recipient, 100).is_err());
+    }
 
-        let (utxos_needed, _) = P2PKH::get_utxos_needed(
+    #[test]
+    fn test_get_utxos_needed_size_ok() {
+        let utxos = utxos_of(&[50_000, 30_000, 20_000, 20_000]);
+        let script = p2pkh_script(&[1u8; 20]);
+        let coin_control = CoinControl::default();
+
+        let recipients = [(script.clone(), 25_000)];
+        let selection = P2PKH::get_utxos_needed(
             &utxos,
-            3,
-            &[
-                110, 51, 115, 118, 117, 100, 104, 109, 55, 98, 116, 54, 106, 51, 110, 84, 84, 57,
-                117, 117, 49, 65, 53, 55, 67, 115, 57, 112, 75, 75, 51, 105, 88, 87,
-            ],
-        );
-        assert_eq!(utxos_needed.len(), 2);
+            &recipients,
+            20,
+            &script,
+            &coin_control,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(selection.utxos.len(), 1);
+        assert_eq!(selection.utxos[0].1.value, 30_000);
+        // The excess is below the dust limit and goes to the fee
+        assert_eq!(selection.change, 0);
     }
 
     #[test]
     fn test_get_utxos_needed_change_ok() {
-        let utxos = vec![
-            (
-                Outpoint::new([0u8; 32], 0),
-                Output::new(
-                    5,
-                    CompactSize::from_usize_to_compact_size(34),
-                    vec![3u8; 34],
-                ),
-            ),
-            (
-                Outpoint::new([0u8; 32], 0),
-                Output::new(
-                    3,
-                    CompactSize::from_usize_to_compact_size(34),
-                    vec![2u8; 34],
-                ),
-            ),
-            (
-                Outpoint::new([0u8; 32], 0),
-                Output::new(
-                    2,
-                    CompactSize::from_usize_to_compact_size(34),
-                    vec![1u8; 34],
-                ),
-            ),
-            (
-                Outpoint::new([0u8; 32], 0),
-                Output::new(
-                    2,
-                    CompactSize::from_usize_to_compact_size(34),
-                    vec![1u8; 34],
-                ),
-            ),
-        ];
+        let utxos = utxos_of(&[50_000, 30_000, 20_000, 20_000]);
+        let script = p2pkh_script(&[1u8; 20]);
+        let coin_control = CoinControl::default();
 
-        let (_, change) = P2PKH::get_utxos_needed(
+        let recipients = [(script.clone(), 100_000)];
+        let selection = P2PKH::get_utxos_needed(
             &utxos,
-            3,
-            &[
-                110, 51, 115, 118, 117, 100, 104, 109, 55, 98, 116, 54, 106, 51, 110, 84, 84, 57,
-                117, 117, 49, 65, 53, 55, 67, 115, 57, 112, 75, 75, 51, 105, 88, 87,
-            ],
+            &recipients,
+            20,
+            &script,
+            &coin_control,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(selection.utxos.len(), 4);
+        // Inputs minus amount and the fee of 11 + 4 * 148 + 2 * 34 vbytes at 20 sat/vB
+        assert_eq!(selection.change, 120_000 - 100_000 - 671 * 20);
+
+        let recipients = [(script.clone(), 120_000)];
+        assert!(P2PKH::get_utxos_needed(
+            &utxos,
+            &recipients,
+            20,
+            &script,
+            &coin_control,
+            &HashMap::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_create_unsigned_transaction_refuses_bad_fees() {
+        let utxos = utxos_of(&[50_000]);
+        let script = p2pkh_script(&[1u8; 20]);
+
+        assert!(P2PKH::create_unsigned_transaction(&utxos, &script, &script, 10_000, 10).is_ok());
+        assert!(P2PKH::create_unsigned_transaction(
+            &utxos,
+            &script,
+            &script,
+            10_000,
+            MAX_FEE_RATE + 1
+        )
+        .is_err());
+        assert!(P2PKH::create_unsigned_transaction(&utxos, &script, &script, 100, 10).is_err());
+        assert!(P2PKH::create_unsigned_transaction(&utxos, &script, &script, 60_000, 1).is_err());
+    }
+
+    #[test]
+    fn test_create_controlled_transaction() {
+        let mut utxos = utxos_of(&[50_000, 30_000, 20_000]);
+        for (index, (outpoint, _)) in utxos.iter_mut().enumerate() {
+            *outpoint = Outpoint::new([index as u8; 32], 0);
+        }
+        let script = p2pkh_script(&[1u8; 20]);
+        let mut coin_control = CoinControl::default();
+
+        // The frozen utxo of 30000 would pay the amount without change
+        coin_control.freeze(utxos[1].0);
+        let (_, spent) = P2PKH::create_controlled_transaction(
+            &utxos,
+            &script,
+            &script,
+            25_000,
+            20,
+            &coin_control,
+        )
+        .unwrap();
+        assert!(!spent.contains(&utxos[1]));
+
+        // Every selected utxo is spent, even if one would be enough
+        coin_control.select(utxos[0].0);
+        coin_control.select(utxos[2].0);
+        let (transaction, spent) = P2PKH::create_controlled_transaction(
+            &utxos,
+            &script,
+            &script,
+            10_000,
+            20,
+            &coin_control,
+        )
+        .unwrap();
+        assert_eq!(spent, vec![utxos[0].clone(), utxos[2].clone()]);
+        assert_eq!(transaction.get_output().len(), 2);
+        assert!(P2PKH::create_controlled_transaction(
+            &utxos,
+            &script,
+            &script,
+            70_000,
+            20,
+            &coin_control
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_create_batch_transaction() {
+        let utxos = utxos_of(&[50_000, 30_000, 20_000]);
+        let change_script = p2pkh_script(&[1u8; 20]);
+        let recipients: Vec<(Vec<u8>, i64)> = (2..5u8)
+            .map(|byte| (p2pkh_script(&[byte; 20]), 10_000 * byte as i64))
+            .collect();
+        let coin_control = CoinControl::default();
+
+        let (transaction, spent) = P2PKH::create_batch_transaction(
+            &utxos,
+            &recipients,
+            &change_script,
+            10,
+            &coin_control,
+            &HashMap::new(),
+        )
+        .unwrap();
+        // Every recipient is paid in order and the change goes last
+        let outputs = transaction.get_output();
+        assert_eq!(outputs.len(), 4);
+        for (output, (script, amount)) in outputs.iter().zip(recipients.iter()) {
+            assert_eq!(&output.get_script(), script);
+            assert_eq!(output.value, *amount);
+        }
+        assert_eq!(outputs[3].get_script(), change_script);
+        let previous_outputs: Vec<Output> = spent.iter().map(|(_, o)| o.clone()).collect();
+        let vsize = P2PKH::estimate_vsize(&previous_outputs, &outputs);
+        assert_eq!(P2PKH::fee_paid(&spent, &transaction), vsize as i64 * 10);
+
+        assert!(P2PKH::create_batch_transaction(
+            &utxos,
+            &[],
+            &change_script,
+            10,
+            &coin_control,
+            &HashMap::new()
+        )
+        .is_err());
+        let mut dust = recipients.clone();
+        dust.push((change_script.clone(), 100));
+        assert!(P2PKH::create_batch_transaction(
+            &utxos,
+            &dust,
+            &change_script,
+            10,
+            &coin_control,
+            &HashMap::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_create_replacement_and_child() {
+        let mut utxos = utxos_of(&[20_000, 50_000]);
+        for (index, (outpoint, _)) in utxos.iter_mut().enumerate() {
+            *outpoint = Outpoint::new([index as u8 + 1; 32], 0);
+        }
+        let script = p2pkh_script(&[1u8; 20]);
+        let recipient = p2pkh_script(&[2u8; 20]);
+        let mut coin_control = CoinControl::default();
+        coin_control.select(utxos[0].0);
+        let (transaction, spent) = P2PKH::create_batch_transaction(
+            &utxos,
+            &[(recipient.clone(), 15_000)],
+            &script,
+            2,
+            &coin_control,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert!(transaction
+            .get_input()
+            .iter()
+            .all(|input| input.get_sequence_number() == RBF_SEQUENCE));
+        let pending = PendingTransaction::new(transaction, spent, Some(1));
+
+        // The change pays the new fee
+        assert!(P2PKH::create_replacement(
+            &pending,
+            &utxos,
+            &script,
+            pending.fee_rate(),
+            &HashMap::new()
+        )
+        .is_err());
+        let (replacement, spent) =
+            P2PKH::create_replacement(&pending, &utxos, &script, 10, &HashMap::new()).unwrap();
+        assert_eq!(spent, vec![utxos[0].clone()]);
+        assert_eq!(replacement.get_output()[0].value, 15_000);
+        let prevouts = vec![utxos[0].1.clone()];
+        let vsize = P2PKH::estimate_vsize(&prevouts, &replacement.get_output()) as i64;
+        assert!(P2PKH::fee_paid(&spent, &replacement) >= pending.fee() + vsize);
+        // Another utxo is added when the change can't pay it
+        let (_, spent) =
+            P2PKH::create_replacement(&pending, &utxos, &script, 30, &HashMap::new()).unwrap();
+        assert_eq!(spent, utxos);
+
+        let (change, _) = pending.change().unwrap();
+        let (child, spent) = P2PKH::create_child(&pending, &utxos[1..], &script, 10).unwrap();
+        assert_eq!(spent.len(), 1);
+        assert_eq!(child.get_input()[0].get_outpoint(), change);
+        let prevouts = vec![spent[0].1.clone()];
+        let vsize = P2PKH::estimate_vsize(&prevouts, &child.get_output());
+        let package_vsize = (pending.get_transaction().vsize() + vsize) as i64;
+        assert_eq!(
+            P2PKH::fee_paid(&spent, &child) + pending.fee(),
+            10 * package_vsize
+        );
+        let (_, spent) = P2PKH::create_child(&pending, &utxos, &script, 40).unwrap();
+        assert_eq!(spent, vec![pending.change().unwrap(), utxos[1].clone()]);
+        assert!(P2PKH::create_child(&pending, &utxos, &script, 1).is_err());
+        let no_change = PendingTransaction::new(
+            pending.get_transaction().clone(),
+            pending.get_spent().to_vec(),
+            None,
         );
-        assert_eq!(change, 1);
+        assert!(P2PKH::create_child(&no_change, &utxos, &script, 10).is_err());
     }
 
     #[test]
@@ -500,14 +1224,12 @@ mod transactions_handler_test {
     #[test]
     fn test_create_transaction_outputs() {
         let outputs = P2PKH::create_transaction_outputs(
-            2,
-            1,
+            &[(P2PKH::_output_script_creation_for(&[1u8; 34]), 2)],
             1,
-            &[
+            &P2PKH::_output_script_creation_for(&[
                 110, 51, 115, 118, 117, 100, 104, 109, 55, 98, 116, 54, 106, 51, 110, 84, 84, 57,
                 117, 117, 49, 65, 53, 55, 67, 115, 57, 112, 75, 75, 51, 105, 88, 87,
-            ],
-            &[1u8; 34],
+            ]),
         );
 
         let expected_outputs = [
@@ -565,7 +1287,7 @@ mod transactions_handler_test {
             57, 117, 117, 49, 65, 53, 55, 67, 115, 57, 112, 75, 75, 136, 172,
         ];
 
-        let script = P2PKH::output_script_creation_for(&address_clone);
+        let script = P2PKH::_output_script_creation_for(&address_clone);
         assert_eq!(script, expected_script);
     }
 
@@ -1,10 +1,35 @@
-use crate::message_structs::{outpoint::Outpoint, tx_message::TXMessage};
-use crate::utils::script_tools::from_adderss_to_vec;
+use crate::message_structs::{
+    block_headers::DEFAULT_BIRTHDAY, outpoint::Outpoint, tx_message::TXMessage,
+};
+use crate::utils::array_tools::{
+    cast_str_to_bytes_vec, reverse_array, u8_array_to_hex_string, u8_vec_to_hex_string,
+};
+use crate::utils::script_tools::script_for_address;
+use std::collections::HashMap;
 use std::error::Error;
 
-use super::{keys_handler::KeysHandler, transactions_handler::P2PKH};
+use super::{
+    coin_selection::{spending_vsize, CoinControl},
+    descriptor::Descriptor,
+    descriptor_account::DescriptorAccount,
+    encryption::WalletEncryption,
+    hd_account::HdAccount,
+    hd_keys::{ExtendedPrivKey, ExtendedPubKey, Network},
+    history::{current_time, BlockPosition, HistoryEntry, TransactionHistory},
+    keys_handler::{AddressType, KeysHandler},
+    message_signing,
+    mnemonic::Mnemonic,
+    payment_request::{PaymentRequest, PaymentUri},
+    payments::{checked_total, MAX_MONEY},
+    pending::PendingTransaction,
+    psbt::Psbt,
+    transactions_handler::{TransactionResult, P2PKH},
+};
 use crate::message_structs::output::Output;
 
+/// Third field of the wallets file lines of watch only wallets
+const DESCRIPTORS_TAG: &str = "descriptors";
+
 pub struct Wallet {
     id: usize,
     name: String,
@@ -12,6 +37,15 @@ pub struct Wallet {
     pending_balance: i32,
     pub keys_handler: KeysHandler,
     utxos: Vec<(Outpoint, Output)>, // Output for same address have same script, so there's no way to store the utxos with a unique id
+    encrypted_key: Option<Vec<u8>>,
+    hd_account: Option<HdAccount>, // keys_handler is its last receive key
+    descriptor_account: Option<DescriptorAccount>, // watch only, keys_handler has no keys
+    address_type: AddressType,     // type of the address shown to the user and of the change
+    coin_control: CoinControl,
+    pending: Vec<PendingTransaction>, // sent and not confirmed yet, in the order they were sent
+    history: TransactionHistory,
+    birthday: u32,                 // time of the first block that may pay to the wallet
+    requests: Vec<PaymentRequest>, // payments asked for, in the order they were created
 }
 
 impl Clone for Wallet {
@@ -23,13 +57,38 @@ impl Clone for Wallet {
             keys_handler: self.keys_handler.clone(),
             utxos: self.utxos.clone(),
             pending_balance: 0,
+            encrypted_key: self.encrypted_key.clone(),
+            hd_account: self.hd_account.clone(),
+            descriptor_account: self.descriptor_account.clone(),
+            address_type: self.address_type,
+            coin_control: self.coin_control.clone(),
+            pending: self.pending.clone(),
+            history: self.history.clone(),
+            birthday: self.birthday,
+            requests: self.requests.clone(),
         }
     }
 }
 
 impl Wallet {
-    /// Create a Wallet whenever the creation of a KeysHandler has been successful
+    /// Create a Wallet whenever the creation of a KeysHandler has been successful.
+    /// An extended private key (xprv/tprv) creates a HD wallet. An extended public key (xpub/tpub),
+    /// output descriptors or a list of addresses create a watch only wallet
     pub fn new(id: usize, name: String, private_key: String) -> Option<Wallet> {
+        if let Ok(xprv) = ExtendedPrivKey::decode(&private_key) {
+            return Self::new_hd(id, name, HdAccount::from_xprv(xprv, 0, 0).ok()?);
+        }
+        if let Ok(xpub) = ExtendedPubKey::decode(&private_key) {
+            return Self::new_hd(id, name, HdAccount::from_xpub(xpub, 0, 0).ok()?);
+        }
+        if let Some(descriptors) = Self::addresses_as_descriptors(&private_key) {
+            let descriptor_account = DescriptorAccount::parse(&descriptors, 0, 0).ok()?;
+            return Self::from_descriptors(id, name, descriptor_account);
+        }
+        if private_key.contains('(') {
+            let descriptor_account = DescriptorAccount::parse(&private_key, 0, 0).ok()?;
+            return Self::from_descriptors(id, name, descriptor_account);
+        }
         let keys_handler = match KeysHandler::new(&private_key) {
             Some(keys_handler) => keys_handler,
             None => return None,
@@ -41,9 +100,116 @@ impl Wallet {
             keys_handler,
             utxos: Vec::new(),
             pending_balance: 0,
+            encrypted_key: None,
+            hd_account: None,
+            descriptor_account: None,
+            address_type: AddressType::Legacy,
+            coin_control: CoinControl::default(),
+            pending: vec![],
+            history: TransactionHistory::new(),
+            birthday: DEFAULT_BIRTHDAY,
+            requests: vec![],
         })
     }
 
+    /// Creates a HD wallet, issuing its first receive address if it has none
+    pub fn new_hd(id: usize, name: String, mut hd_account: HdAccount) -> Option<Wallet> {
+        let keys_handler = match hd_account.last_receive_keys() {
+            Some(keys_handler) => keys_handler,
+            None => hd_account.next_receive_keys().ok()?,
+        };
+        Some(Wallet {
+            id,
+            name,
+            balance: 0,
+            keys_handler,
+            utxos: Vec::new(),
+            pending_balance: 0,
+            encrypted_key: None,
+            hd_account: Some(hd_account),
+            descriptor_account: None,
+            address_type: AddressType::Legacy,
+            coin_control: CoinControl::default(),
+            pending: vec![],
+            history: TransactionHistory::new(),
+            birthday: DEFAULT_BIRTHDAY,
+            requests: vec![],
+        })
+    }
+
+    /// Returns an addr() descriptor for each address of a list separated by commas or whitespace,
+    /// None if any of them isn't a valid address
+    fn addresses_as_descriptors(addresses: &str) -> Option<String> {
+        let addresses: Vec<&str> = addresses
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|address| !address.is_empty())
+            .collect();
+        if addresses.is_empty() {
+            return None;
+        }
+        let mut descriptors = vec![];
+        for address in addresses {
+            script_for_address(address).ok()?;
+            descriptors.push(format!("addr({})", address));
+        }
+        Some(descriptors.join(" "))
+    }
+
+    /// Creates a watch only wallet whose scripts are derived from output descriptors,
+    /// issuing its first receive address if it has none
+    pub fn from_descriptors(
+        id: usize,
+        name: String,
+        mut descriptor_account: DescriptorAccount,
+    ) -> Option<Wallet> {
+        if descriptor_account.get_issued().0 == 0 {
+            descriptor_account.next_receive_address().ok()?;
+        }
+        Some(Wallet {
+            id,
+            name,
+            balance: 0,
+            keys_handler: KeysHandler::from_public_key(vec![]),
+            utxos: Vec::new(),
+            pending_balance: 0,
+            encrypted_key: None,
+            hd_account: None,
+            descriptor_account: Some(descriptor_account),
+            address_type: AddressType::Legacy,
+            coin_control: CoinControl::default(),
+            pending: vec![],
+            history: TransactionHistory::new(),
+            birthday: DEFAULT_BIRTHDAY,
+            requests: vec![],
+        })
+    }
+
+    /// Creates a watch only m-of-n multisig wallet of public keys or extended public keys,
+    /// paying to P2WSH scripts if `segwit` or else to P2SH ones. Its transactions are signed
+    /// as PSBTs by the participants
+    pub fn new_multisig(
+        id: usize,
+        name: String,
+        threshold: usize,
+        keys: &[&str],
+        segwit: bool,
+    ) -> Option<Wallet> {
+        let (receive, change) = Descriptor::multisig(threshold, keys, segwit).ok()?;
+        let descriptor_account = DescriptorAccount::new(receive, change, vec![], 0, 0).ok()?;
+        Self::from_descriptors(id, name, descriptor_account)
+    }
+
+    /// Creates (or restores) a HD wallet from a BIP39 mnemonic and optional passphrase
+    pub fn from_mnemonic(
+        id: usize,
+        name: String,
+        mnemonic: &Mnemonic,
+        passphrase: &str,
+    ) -> Option<Wallet> {
+        let hd_account = HdAccount::from_seed(&mnemonic.to_seed(passphrase)).ok()?;
+        Self::new_hd(id, name, hd_account)
+    }
+
     /// Creates a Wallet with the data supplied from the files
     pub fn open(id: usize, name: String, private_key: String, balance: u32) -> Wallet {
         let keys_handler = match KeysHandler::new(&private_key) {
@@ -58,9 +224,216 @@ impl Wallet {
             balance,
             utxos: Vec::new(),
             pending_balance: 0,
+            encrypted_key: None,
+            hd_account: None,
+            descriptor_account: None,
+            address_type: AddressType::Legacy,
+            coin_control: CoinControl::default(),
+            pending: vec![],
+            history: TransactionHistory::new(),
+            birthday: DEFAULT_BIRTHDAY,
+            requests: vec![],
+        }
+    }
+
+    /// Splits the address type at the end of a line of the wallets file,
+    /// lines saved before it was stored show legacy addresses
+    fn split_address_type(line: &str) -> (&str, AddressType) {
+        let line = line.trim();
+        match line.rsplit_once(',') {
+            Some((fields, tag)) => match AddressType::from_tag(tag) {
+                Some(address_type) => (fields, address_type),
+                None => (line, AddressType::Legacy),
+            },
+            None => (line, AddressType::Legacy),
+        }
+    }
+
+    /// Creates a Wallet from a line of the wallets file: `id,name,private_key,balance,address_type`,
+    /// HD wallets store the account tprv (tpub if watch only) and insert the amount of receive and change keys issued
+    /// before the address type
+    pub fn open_line(line: &str) -> Option<Wallet> {
+        if let Some(wallet) = Self::open_descriptors_line(line) {
+            return Some(wallet);
+        }
+        let (fields, address_type) = Self::split_address_type(line);
+        let parts: Vec<&str> = fields.split(',').collect();
+        let id = parts.first()?.parse().ok()?;
+        let name = parts.get(1)?.to_string();
+        let balance = parts.get(3)?.parse().ok()?;
+        let mut wallet = match parts.len() {
+            4 => {
+                KeysHandler::new(parts[2])?;
+                Self::open(id, name, parts[2].to_string(), balance)
+            }
+            6 => {
+                let (receive, change) = (parts[4].parse().ok()?, parts[5].parse().ok()?);
+                let hd_account = match ExtendedPrivKey::decode(parts[2]) {
+                    Ok(xprv) => HdAccount::from_xprv(xprv, receive, change).ok()?,
+                    Err(_) => HdAccount::from_xpub(
+                        ExtendedPubKey::decode(parts[2]).ok()?,
+                        receive,
+                        change,
+                    )
+                    .ok()?,
+                };
+                let mut wallet = Self::new_hd(id, name, hd_account)?;
+                wallet.balance = balance;
+                wallet
+            }
+            _ => return None,
+        };
+        wallet.address_type = address_type;
+        Some(wallet)
+    }
+
+    /// Creates a watch only Wallet from its line of the wallets file, the same with or without encryption:
+    /// `id,name,descriptors,balance,receive_issued,change_issued,descriptor[ change_descriptor]`.
+    /// The descriptors go last since they have commas
+    fn open_descriptors_line(line: &str) -> Option<Wallet> {
+        let parts: Vec<&str> = line.trim().splitn(7, ',').collect();
+        if parts.len() != 7 || parts[2] != DESCRIPTORS_TAG {
+            return None;
+        }
+        let descriptor_account =
+            DescriptorAccount::parse(parts[6], parts[4].parse().ok()?, parts[5].parse().ok()?)
+                .ok()?;
+        let mut wallet = Self::from_descriptors(
+            parts[0].parse().ok()?,
+            parts[1].to_string(),
+            descriptor_account,
+        )?;
+        wallet.balance = parts[3].parse().ok()?;
+        Some(wallet)
+    }
+
+    /// Line of a watch only wallet in the wallets file, None for the other wallets
+    fn descriptors_line(&self) -> Option<String> {
+        let descriptor_account = self.descriptor_account.as_ref()?;
+        let (receive, change) = descriptor_account.get_issued();
+        Some(format!(
+            "{},{},{},{},{},{},{}",
+            self.id,
+            self.name,
+            DESCRIPTORS_TAG,
+            self.balance,
+            receive,
+            change,
+            descriptor_account.export().join(" ")
+        ))
+    }
+
+    /// Creates a locked Wallet from an encrypted line of the wallets file:
+    /// `id,name,public_key,encrypted_key,balance,address_type`, HD wallets store the account tpub
+    /// and insert the amount of receive and change keys issued before the address type
+    pub fn open_encrypted(line: &str) -> Option<Wallet> {
+        if let Some(wallet) = Self::open_descriptors_line(line) {
+            return Some(wallet);
+        }
+        let (fields, address_type) = Self::split_address_type(line);
+        let parts: Vec<&str> = fields.split(',').collect();
+        let mut wallet = match parts.len() {
+            // Watch only HD wallets have no key to encrypt
+            6 => return Self::open_line(line),
+            5 => {
+                let public_key = cast_str_to_bytes_vec(parts[2]).ok()?;
+                Wallet {
+                    id: parts[0].parse().ok()?,
+                    name: parts[1].to_string(),
+                    keys_handler: KeysHandler::from_public_key(public_key),
+                    balance: 0,
+                    utxos: Vec::new(),
+                    pending_balance: 0,
+                    encrypted_key: None,
+                    hd_account: None,
+                    descriptor_account: None,
+                    address_type: AddressType::Legacy,
+                    coin_control: CoinControl::default(),
+                    pending: vec![],
+                    history: TransactionHistory::new(),
+                    birthday: DEFAULT_BIRTHDAY,
+                    requests: vec![],
+                }
+            }
+            7 => {
+                let xpub = ExtendedPubKey::decode(parts[2]).ok()?;
+                let hd_account =
+                    HdAccount::from_xpub(xpub, parts[5].parse().ok()?, parts[6].parse().ok()?)
+                        .ok()?;
+                Self::new_hd(parts[0].parse().ok()?, parts[1].to_string(), hd_account)?
+            }
+            _ => return None,
+        };
+        wallet.balance = parts[4].parse().ok()?;
+        wallet.encrypted_key = Some(cast_str_to_bytes_vec(parts[3]).ok()?);
+        wallet.address_type = address_type;
+        Some(wallet)
+    }
+
+    /// Encrypts the private key, the wallet must be unlocked
+    /// # Errors
+    /// Returns an error if the private key is not available or the encryption is locked
+    pub fn encrypt_key(&mut self, encryption: &WalletEncryption) -> Result<(), Box<dyn Error>> {
+        if self.is_watch_only() {
+            return Ok(());
+        }
+        if self.is_locked() {
+            return Err("Wallet is locked".into());
+        }
+        let private_key = match &self.hd_account {
+            Some(hd_account) => hd_account.get_xprv(),
+            None => self.keys_handler.get_privkey(),
+        };
+        self.encrypted_key = Some(encryption.encrypt(private_key.as_bytes())?);
+        Ok(())
+    }
+
+    /// Decrypts the private key so the wallet can sign
+    /// # Errors
+    /// Returns an error if the key can't be decrypted or doesn't belong to this wallet
+    pub fn unlock(&mut self, encryption: &WalletEncryption) -> Result<(), Box<dyn Error>> {
+        let encrypted_key = match &self.encrypted_key {
+            Some(encrypted_key) => encrypted_key,
+            None => return Ok(()),
+        };
+        let private_key = String::from_utf8(encryption.decrypt(encrypted_key)?)?;
+        let unlocked = match &mut self.hd_account {
+            Some(hd_account) => {
+                let unlocked = hd_account.unlock(&private_key);
+                if let Some(keys_handler) = hd_account.last_receive_keys() {
+                    self.keys_handler = keys_handler;
+                }
+                unlocked
+            }
+            None => self.keys_handler.unlock(&private_key),
+        };
+        if !unlocked {
+            return Err("The decrypted key doesn't belong to the wallet".into());
+        }
+        Ok(())
+    }
+
+    /// Forgets the private key if it is stored encrypted
+    pub fn lock(&mut self) {
+        if self.encrypted_key.is_some() {
+            self.keys_handler.lock();
+            if let Some(hd_account) = &mut self.hd_account {
+                hd_account.lock();
+            }
         }
     }
 
+    /// Returns true if the private key isn't available to sign
+    pub fn is_locked(&self) -> bool {
+        !self.keys_handler.has_private_key()
+    }
+
+    /// Returns true if the wallet has no private key, neither available nor encrypted:
+    /// it watches the scripts of its descriptors or the keys of an extended public key
+    pub fn is_watch_only(&self) -> bool {
+        self.descriptor_account.is_some() || (self.encrypted_key.is_none() && self.is_locked())
+    }
+
     /// Replace the actual value of the wallet's balance
     pub fn replace_balance(&mut self, income: u32) {
         println!("Replacing balance from {} to {}", self.balance, income);
@@ -77,60 +450,707 @@ impl Wallet {
         self.balance = 0;
     }
 
+    /// Creates and signs the transaction paying `amount` at `fee_rate` sat/vB,
+    /// returns it with the fee it pays. Its utxos are taken out of the wallet
+    /// # Errors
+    /// Returns an error if the wallet can't sign, the address is invalid or
+    /// the balance can't pay the amount and the fee
     pub fn create_transaction(
         &mut self,
         address: &str,
         amount: i32,
-        fee: i32,
-    ) -> Result<TXMessage, Box<dyn Error>> {
+        fee_rate: u64,
+    ) -> Result<(TXMessage, i64), Box<dyn Error>> {
+        self.create_batch_transaction(&[(address.to_string(), amount as i64)], fee_rate)
+    }
+
+    /// Creates and signs a single transaction paying every (address, amount) payment,
+    /// returns it with the fee it pays. Its utxos are taken out of the wallet
+    /// # Errors
+    /// Returns an error if the wallet can't sign, an address is invalid or
+    /// the balance can't pay the amounts and the fee
+    pub fn create_batch_transaction(
+        &mut self,
+        payments: &[(String, i64)],
+        fee_rate: u64,
+    ) -> Result<(TXMessage, i64), Box<dyn Error>> {
         println!("wallet create transaction");
-        if amount + fee > self.balance as i32 {
+        self.check_can_sign()?;
+
+        let (mut transaction, utxos_used) = self.build_unsigned_transaction(payments, fee_rate)?;
+        println!("wallet create transaction P2PKH");
+        let fee = self.sign_spending(&mut transaction, &utxos_used);
+        // The amounts were checked when building it
+        let amount = checked_total(payments.iter().map(|(_, amount)| *amount)).unwrap_or_default();
+        // The change goes after the payments
+        let change_index =
+            Some(payments.len()).filter(|index| transaction.get_output().len() > *index);
+        let pending = PendingTransaction::new(transaction.clone(), utxos_used, change_index);
+        self.record_sent(&pending, String::new());
+        self.pending.push(pending);
+        self.pending_balance -= (amount + fee) as i32;
+
+        println!("wallet before send {:?}", self.pending_balance);
+        Ok((transaction, fee))
+    }
+
+    /// Creates the transaction without signing it, to be signed outside the wallet.
+    /// Its utxos stay available until a transaction spending them is seen
+    /// # Errors
+    /// Returns an error if the balance isn't enough or the address is invalid
+    pub fn create_unsigned_transaction(
+        &mut self,
+        address: &str,
+        amount: i32,
+        fee_rate: u64,
+    ) -> Result<TXMessage, Box<dyn Error>> {
+        let payments = [(address.to_string(), amount as i64)];
+        let (transaction, _) = self.build_unsigned_transaction(&payments, fee_rate)?;
+        Ok(transaction)
+    }
+
+    /// Creates the PSBT of an unsigned transaction, with the utxos its inputs spend so
+    /// other signers can sign it. `previous_transactions` are looked up by their hex id for
+    /// the inputs that need the whole transaction they spend.
+    /// Its utxos stay available like in create_unsigned_transaction
+    /// # Errors
+    /// Returns an error if the balance isn't enough, the address is invalid or a previous
+    /// transaction is missing
+    pub fn create_psbt(
+        &mut self,
+        address: &str,
+        amount: i32,
+        fee_rate: u64,
+        previous_transactions: &HashMap<String, TXMessage>,
+    ) -> Result<Psbt, Box<dyn Error>> {
+        let payments = [(address.to_string(), amount as i64)];
+        self.create_batch_psbt(&payments, fee_rate, previous_transactions)
+    }
+
+    /// Same as create_psbt but paying every (address, amount) payment in one transaction
+    /// # Errors
+    /// Returns an error if the balance isn't enough, an address is invalid or a previous
+    /// transaction is missing
+    pub fn create_batch_psbt(
+        &mut self,
+        payments: &[(String, i64)],
+        fee_rate: u64,
+        previous_transactions: &HashMap<String, TXMessage>,
+    ) -> Result<Psbt, Box<dyn Error>> {
+        let (transaction, utxos) = self.build_unsigned_transaction(payments, fee_rate)?;
+        let prevouts: Vec<(Output, Option<TXMessage>)> = utxos
+            .into_iter()
+            .map(|(outpoint, output)| {
+                // Utxos of the wallet keep the id of their transaction in internal byte order
+                let id = u8_array_to_hex_string(&reverse_array(&outpoint.get_hash()));
+                (output, previous_transactions.get(&id).cloned())
+            })
+            .collect();
+        let mut psbt = Psbt::new(transaction, &prevouts)?;
+        // Signers of P2SH and P2WSH inputs need the scripts behind their hashes
+        if let Some(descriptor_account) = &self.descriptor_account {
+            for (index, (prevout, _)) in prevouts.iter().enumerate() {
+                if let Some((redeem_script, witness_script)) =
+                    descriptor_account.spending_scripts(&prevout.get_script())
+                {
+                    psbt.set_spending_scripts(index, redeem_script, witness_script);
+                }
+            }
+        }
+        Ok(psbt)
+    }
+
+    /// Signs the inputs of the PSBT that spend scripts of the wallet's keys,
+    /// returns the amount of signatures added
+    /// # Errors
+    /// Returns an error if the wallet is watch-only or locked
+    pub fn sign_psbt(&self, psbt: &mut Psbt) -> Result<usize, Box<dyn Error>> {
+        if self.is_watch_only() {
+            return Err("Watch-only wallets can't sign".into());
+        }
+        if self.is_locked() {
+            return Err("Wallet is locked".into());
+        }
+        psbt.sign(&self.signing_keys())
+    }
+
+    fn build_unsigned_transaction(
+        &mut self,
+        payments: &[(String, i64)],
+        fee_rate: u64,
+    ) -> TransactionResult {
+        let amount = checked_total(payments.iter().map(|(_, amount)| *amount))
+            .ok_or("The amounts add up to more than 21 million bitcoin")?;
+        if amount > self.balance as i64 {
             return Err("wallet Not enough balance".into());
         }
+        let mut recipients = Vec::new();
+        for (address, amount) in payments {
+            match script_for_address(address) {
+                Ok(script) => recipients.push((script, *amount)),
+                Err(_) => return Err(format!("Invalid recipient address {}", address).into()),
+            }
+        }
+        let change_script = self.next_change_script()?;
+        let input_vsizes = self.input_vsizes(&change_script);
+        P2PKH::create_batch_transaction(
+            &self.utxos,
+            &recipients,
+            &change_script,
+            fee_rate,
+            &self.coin_control,
+            &input_vsizes,
+        )
+    }
 
-        let address_vec = match from_adderss_to_vec(address) {
-            Ok(address) => address.to_vec(),
-            Err(_) => return Err("Error casting address to vec".into()),
+    /// Sizes of the inputs spending the utxos, those of the pending transactions and the
+    /// change script, the P2SH and P2WSH ones sized from the scripts of the descriptors
+    fn input_vsizes(&self, change_script: &[u8]) -> HashMap<Vec<u8>, usize> {
+        let descriptor_account = match &self.descriptor_account {
+            Some(descriptor_account) => descriptor_account,
+            None => return HashMap::new(),
         };
+        let pending_spent = self.pending.iter().flat_map(|pending| pending.get_spent());
+        let scripts = self
+            .utxos
+            .iter()
+            .chain(pending_spent)
+            .map(|(_, output)| output.get_script())
+            .chain(std::iter::once(change_script.to_vec()));
+        scripts
+            .filter_map(|script| {
+                let spending_scripts = descriptor_account.spending_scripts(&script)?;
+                let vsize = spending_vsize(&script, &spending_scripts);
+                Some((script, vsize))
+            })
+            .collect()
+    }
 
-        println!("wallet address_vec");
-        let (transaction, utxos_used): (TXMessage, Vec<(Outpoint, Output)>) =
-            match P2PKH::create_transaction(
-                &self.keys_handler,
-                &self.utxos,
-                &address_vec,
-                amount as i64,
-                fee as i64,
-            ) {
-                Ok((transaction, utxos_used)) => (transaction, utxos_used),
-                Err(_) => return Err("Error creating transaction".into()),
-            };
-
-        println!("wallet create transaction P2PKH");
-        for (x, (outpoint, utxo)) in utxos_used.iter().enumerate() {
-            // Output for same address have same script, so there's no way to store the utxos with a unique id
-            let index = utxos_used
-                .iter()
-                .position(|x| *x == (*outpoint, utxo.clone()))
-                .unwrap();
-            self.utxos.remove(index - x);
+    /// Keys that may own the wallet's utxos
+    fn signing_keys(&self) -> Vec<KeysHandler> {
+        match &self.hd_account {
+            Some(hd_account) => hd_account.watched_keys(),
+            None => vec![self.keys_handler.clone()],
         }
-        self.pending_balance -= amount + fee;
+    }
 
-        println!("wallet before send {:?}", self.pending_balance);
-        Ok(transaction)
+    /// Issues the script the change of a new transaction goes to
+    fn next_change_script(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        if let Some(descriptor_account) = &mut self.descriptor_account {
+            return descriptor_account.next_change_script();
+        }
+        let change_keys = match &mut self.hd_account {
+            Some(hd_account) => hd_account.next_change_keys()?,
+            None => self.keys_handler.clone(),
+        };
+        Ok(change_keys.script_of(self.address_type))
     }
 
+    /// Replaces the utxos of the wallet with the confirmed ones, leaving out those spent by
+    /// the transactions sent that aren't confirmed yet. Sent transactions are forgotten once
+    /// none of the outputs they spend is unspent or they expire
     pub fn update_utxos(&mut self, utxos: &[(Outpoint, Output)]) {
-        self.utxos = utxos.to_owned();
+        self.settle_pending(utxos);
+        self.utxos = utxos
+            .iter()
+            .filter(|(outpoint, _)| !self.pending.iter().any(|p| p.spends(outpoint)))
+            .cloned()
+            .collect();
+        self.coin_control.retain_utxos(&self.utxos);
         println!("actualiza balance {:?}", utxos);
         let mut income = 0;
-        for (_, output) in self.utxos.clone() {
+        for (_, output) in utxos.iter() {
             income += output.value;
         }
         self.replace_balance(income as u32);
     }
 
+    /// Keeps the sent transactions that spend a confirmed utxo or the change of a kept one,
+    /// unless they expired
+    fn settle_pending(&mut self, utxos: &[(Outpoint, Output)]) {
+        let now = current_time();
+        let mut kept: Vec<PendingTransaction> = vec![];
+        for pending in self.pending.drain(..) {
+            if pending.is_expired(now) {
+                continue;
+            }
+            let unconfirmed = pending.get_spent().iter().any(|(outpoint, _)| {
+                utxos.iter().any(|(utxo, _)| utxo == outpoint)
+                    || kept.iter().any(|parent| {
+                        parent
+                            .change()
+                            .is_some_and(|(change, _)| change == *outpoint)
+                    })
+            });
+            if unconfirmed {
+                kept.push(pending);
+            }
+        }
+        self.pending = kept;
+    }
+
+    /// Replaces a sent transaction that isn't confirmed with one paying `fee_rate` sat/vB (RBF),
+    /// returns the replacement with the fee it pays. Utxos added to pay the fee are taken out
+    /// # Errors
+    /// Returns an error if the wallet can't sign, the transaction isn't pending, it has
+    /// a child or the fee rate isn't higher
+    pub fn bump_fee(
+        &mut self,
+        txid: [u8; 32],
+        fee_rate: u64,
+    ) -> Result<(TXMessage, i64), Box<dyn Error>> {
+        self.check_can_sign()?;
+        let index = self.pending_index(txid)?;
+        let pending = self.pending[index].clone();
+        if let Some((change, _)) = pending.change() {
+            if self.pending.iter().any(|child| child.spends(&change)) {
+                return Err("A child spends the transaction, bump the fee of the child".into());
+            }
+        }
+        let change_script = match pending.change() {
+            Some((_, change)) => change.get_script(),
+            None => self.next_change_script()?,
+        };
+        let utxos = self.coin_control.spendable(&self.utxos);
+        let input_vsizes = self.input_vsizes(&change_script);
+        let (mut transaction, spent) =
+            P2PKH::create_replacement(&pending, &utxos, &change_script, fee_rate, &input_vsizes)?;
+        let fee = self.sign_spending(&mut transaction, &spent);
+
+        let recipients = pending.recipients().len();
+        let change_index = Some(recipients).filter(|index| transaction.get_output().len() > *index);
+        let replacement = PendingTransaction::new(transaction.clone(), spent, change_index);
+        let label = match self.history.remove(&txid) {
+            Some(replaced) => replaced.label,
+            None => String::new(),
+        };
+        self.record_sent(&replacement, label);
+        self.pending[index] = replacement;
+        self.pending_balance -= (fee - pending.fee()) as i32;
+        Ok((transaction, fee))
+    }
+
+    /// Creates a child of a sent transaction that isn't confirmed spending its change, so
+    /// both together pay `fee_rate` sat/vB (CPFP). Returns the child with the fee it pays
+    /// # Errors
+    /// Returns an error if the wallet can't sign, the transaction isn't pending,
+    /// has no change or already pays the fee rate
+    pub fn create_child(
+        &mut self,
+        txid: [u8; 32],
+        fee_rate: u64,
+    ) -> Result<(TXMessage, i64), Box<dyn Error>> {
+        self.check_can_sign()?;
+        let parent = self.pending[self.pending_index(txid)?].clone();
+        let change_script = self.next_change_script()?;
+        let utxos = self.coin_control.spendable(&self.utxos);
+        let (mut transaction, spent) =
+            P2PKH::create_child(&parent, &utxos, &change_script, fee_rate)?;
+        let fee = self.sign_spending(&mut transaction, &spent);
+
+        let child = PendingTransaction::new(transaction.clone(), spent, Some(0));
+        self.record_sent(&child, String::new());
+        self.pending.push(child);
+        self.pending_balance -= fee as i32;
+        Ok((transaction, fee))
+    }
+
+    /// Returns the sent transaction that isn't confirmed yet with the given id
+    pub fn get_pending_transaction(&self, txid: [u8; 32]) -> Option<&PendingTransaction> {
+        self.pending.iter().find(|pending| pending.get_id() == txid)
+    }
+
+    /// Forgets a sent transaction that isn't confirmed, like one peers evicted, with the
+    /// transactions spending its change. The utxos they spent can be spent again.
+    /// Returns the ids of the abandoned transactions
+    /// # Errors
+    /// Returns an error if the transaction isn't pending
+    pub fn abandon_transaction(&mut self, txid: [u8; 32]) -> Result<Vec<[u8; 32]>, Box<dyn Error>> {
+        let mut abandoned = vec![self.pending.remove(self.pending_index(txid)?)];
+        let mut index = 0;
+        while index < abandoned.len() {
+            if let Some((change, _)) = abandoned[index].change() {
+                let (children, kept): (Vec<_>, Vec<_>) =
+                    self.pending.drain(..).partition(|p| p.spends(&change));
+                self.pending = kept;
+                abandoned.extend(children);
+            }
+            index += 1;
+        }
+        let changes: Vec<Outpoint> = abandoned
+            .iter()
+            .chain(self.pending.iter())
+            .filter_map(|pending| pending.change().map(|(change, _)| change))
+            .collect();
+        for pending in abandoned.iter() {
+            let spent_value: i64 = pending.get_spent().iter().map(|(_, o)| o.value).sum();
+            let change_value = pending.change().map(|(_, o)| o.value).unwrap_or_default();
+            self.pending_balance =
+                (self.pending_balance + (spent_value - change_value) as i32).min(0);
+            for utxo in pending.get_spent() {
+                if !changes.contains(&utxo.0) && !self.utxos.contains(utxo) {
+                    self.utxos.push(utxo.clone());
+                }
+            }
+            self.history.remove(&pending.get_id());
+        }
+        Ok(abandoned.iter().map(PendingTransaction::get_id).collect())
+    }
+
+    /// Returns the lines the pending transactions are saved as
+    pub fn get_pending_lines(&self) -> Vec<String> {
+        self.pending
+            .iter()
+            .map(PendingTransaction::to_line)
+            .collect()
+    }
+
+    /// Adds a transaction saved in the pending file, returns false if the line is malformed
+    pub fn open_pending_line(&mut self, line: &str) -> bool {
+        match PendingTransaction::from_line(line) {
+            Some(pending) => {
+                self.pending.push(pending);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds a transaction sent by the wallet to its history, it pays the fee and every
+    /// output but the change
+    fn record_sent(&mut self, pending: &PendingTransaction, label: String) {
+        let paid: i64 = pending.recipients().iter().map(|(_, amount)| amount).sum();
+        self.history.add(HistoryEntry {
+            txid: pending.get_id(),
+            amount: -(paid + pending.fee()),
+            fee: Some(pending.fee()),
+            block: None,
+            timestamp: current_time(),
+            label,
+        });
+    }
+
+    /// Adds the transaction to the history if it pays to or spends from the wallet,
+    /// confirmed by the block if there's one. Must be called before the utxos it spends
+    /// are taken out of the wallet. Returns true if the transaction is in the history
+    pub fn record_transaction(
+        &mut self,
+        transaction: &TXMessage,
+        block: Option<BlockPosition>,
+    ) -> bool {
+        self.record_with_received(transaction, block, &[])
+    }
+
+    /// Adds the transactions of a block paying to or spending from the wallet to its history.
+    /// The outputs a transaction of the block pays to the wallet count as owned for the ones
+    /// after it, as they aren't utxos yet. Returns the amount of transactions in the history
+    pub fn record_block(&mut self, transactions: &[TXMessage], block: BlockPosition) -> usize {
+        self.connect_block(block.0, block.1);
+        let scripts = self.get_watched_scripts();
+        let mut received = vec![];
+        let mut found = 0;
+        for transaction in transactions {
+            if self.record_with_received(transaction, Some(block), &received) {
+                found += 1;
+            }
+            for (index, output) in transaction.get_output().into_iter().enumerate() {
+                if scripts.contains(&output.get_script()) {
+                    received.push((Outpoint::new(transaction.get_id(), index as u32), output));
+                }
+            }
+        }
+        found
+    }
+
+    /// Adds the transaction to the history counting the received outputs as owned
+    /// besides the utxos and the ones spent by pending transactions
+    fn record_with_received(
+        &mut self,
+        transaction: &TXMessage,
+        block: Option<BlockPosition>,
+        received: &[(Outpoint, Output)],
+    ) -> bool {
+        let owned: Vec<(Outpoint, Output)> = self
+            .utxos
+            .iter()
+            .chain(self.pending.iter().flat_map(|pending| pending.get_spent()))
+            .chain(received.iter())
+            .cloned()
+            .collect();
+        if block.is_some() {
+            self.settle_requests(transaction, &owned);
+        }
+        let txid = transaction.get_id();
+        if self.history.get(&txid).is_some() {
+            if let Some(block) = block {
+                self.history.confirm(&txid, block);
+            }
+            return true;
+        }
+        let entry = Self::history_entry(transaction, &self.get_watched_scripts(), &owned);
+        self.add_to_history(entry, block)
+    }
+
+    /// Adds the transactions of a block paying to or spending from the wallet to its history
+    /// again, to rebuild it from an older block. `received` keeps the outputs paid to the wallet
+    /// by the blocks already rescanned, as the ones spent later aren't utxos anymore.
+    /// Returns the amount of transactions found
+    pub fn rescan_block(
+        &mut self,
+        transactions: &[TXMessage],
+        block: BlockPosition,
+        received: &mut Vec<(Outpoint, Output)>,
+    ) -> usize {
+        self.connect_block(block.0, block.1);
+        let scripts = self.get_watched_scripts();
+        let mut found = 0;
+        for transaction in transactions {
+            self.settle_requests(transaction, received);
+            for (index, output) in transaction.get_output().into_iter().enumerate() {
+                if scripts.contains(&output.get_script()) {
+                    received.push((Outpoint::new(transaction.get_id(), index as u32), output));
+                }
+            }
+            let entry = Self::history_entry(transaction, &scripts, received);
+            if self.add_to_history(entry, Some(block)) {
+                found += 1;
+            }
+        }
+        found
+    }
+
+    /// Returns the outpoints the inputs of the transaction spend, the way the wallet keeps its utxos
+    fn spent_outpoints(transaction: &TXMessage) -> Vec<Outpoint> {
+        transaction
+            .get_input()
+            .iter()
+            .map(|input| {
+                // Utxos of the wallet keep the id of their transaction in internal byte order
+                let outpoint = input.get_outpoint();
+                Outpoint::new(outpoint.get_hash(), outpoint.get_index())
+            })
+            .collect()
+    }
+
+    /// Returns the entry of a transaction in the history, None if it neither pays to
+    /// the scripts nor spends the owned outputs. The fee is only known if all its inputs are owned
+    fn history_entry(
+        transaction: &TXMessage,
+        scripts: &[Vec<u8>],
+        owned: &[(Outpoint, Output)],
+    ) -> Option<HistoryEntry> {
+        let received: i64 = transaction
+            .get_output()
+            .iter()
+            .filter(|output| scripts.contains(&output.get_script()))
+            .map(|output| output.value)
+            .sum();
+        let mut spent = 0;
+        let mut all_inputs_owned = true;
+        for outpoint in Self::spent_outpoints(transaction) {
+            match owned.iter().find(|(utxo, _)| *utxo == outpoint) {
+                Some((_, output)) => spent += output.value,
+                None => all_inputs_owned = false,
+            }
+        }
+        if received == 0 && spent == 0 {
+            return None;
+        }
+        let outputs: i64 = transaction.get_output().iter().map(|o| o.value).sum();
+        Some(HistoryEntry {
+            txid: transaction.get_id(),
+            amount: received - spent,
+            fee: Some(spent - outputs).filter(|_| all_inputs_owned),
+            block: None,
+            timestamp: current_time(),
+            label: String::new(),
+        })
+    }
+
+    /// Adds the entry to the history confirmed by the block, if any. Returns false if there's no entry
+    fn add_to_history(
+        &mut self,
+        entry: Option<HistoryEntry>,
+        block: Option<BlockPosition>,
+    ) -> bool {
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let txid = entry.txid;
+        self.history.add(entry);
+        if let Some(block) = block {
+            self.history.confirm(&txid, block);
+        }
+        true
+    }
+
+    /// Unconfirms the transactions of blocks reorganized out by the one connected at `height`
+    pub fn connect_block(&mut self, hash: [u8; 32], height: u32) {
+        self.history.connect_block(hash, height);
+    }
+
+    /// Sets the label of a transaction of the history
+    /// # Errors
+    /// Returns an error if the transaction isn't in the history or the label has line breaks
+    pub fn set_label(&mut self, txid: [u8; 32], label: &str) -> Result<(), Box<dyn Error>> {
+        self.history.set_label(&txid, label)
+    }
+
+    pub fn get_birthday(&self) -> u32 {
+        self.birthday
+    }
+
+    /// Sets the time of the first block that may pay to the wallet, blocks are downloaded
+    /// from the birthday of the oldest wallet
+    pub fn set_birthday(&mut self, birthday: u32) {
+        self.birthday = birthday;
+    }
+
+    pub fn get_history(&self) -> &TransactionHistory {
+        &self.history
+    }
+
+    /// Adds an entry saved in the history file, returns false if the line is malformed
+    pub fn open_history_line(&mut self, line: &str) -> bool {
+        self.history.open_line(line)
+    }
+
+    /// Asks for a payment to a fresh receive address, or to the address of single key wallets.
+    /// Returns the BIP21 URI of the request, which is tracked until a transaction pays it
+    /// # Errors
+    /// Returns an error if the amount isn't positive or is above MAX_MONEY, the label or
+    /// message have line breaks or the address already has an open request
+    pub fn new_payment_request(
+        &mut self,
+        amount: Option<i64>,
+        label: &str,
+        message: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        if amount.is_some_and(|amount| amount <= 0 || amount > MAX_MONEY) {
+            return Err("The requested amount must be positive and at most 21M BTC".into());
+        }
+        if label.contains('\n') || message.contains('\n') {
+            return Err("The label and the message can't have line breaks".into());
+        }
+        let address = match self.hd_account.is_some() || self.descriptor_account.is_some() {
+            true => self.new_receive_address(self.address_type)?,
+            false => self.get_address(),
+        };
+        let uri = PaymentUri {
+            address,
+            amount,
+            label: label.to_string(),
+            message: message.to_string(),
+        };
+        let reused = self
+            .requests
+            .iter()
+            .any(|request| !request.is_paid() && request.uri.address == uri.address);
+        if reused {
+            return Err(format!(
+                "There's already an open payment request for the address {}",
+                uri.address
+            )
+            .into());
+        }
+        let text = uri.to_uri();
+        self.requests.push(PaymentRequest::new(uri, current_time()));
+        Ok(text)
+    }
+
+    pub fn get_payment_requests(&self) -> &[PaymentRequest] {
+        &self.requests
+    }
+
+    /// Adds a request saved in the requests file, returns false if the line is malformed
+    pub fn open_request_line(&mut self, line: &str) -> bool {
+        match PaymentRequest::from_line(line) {
+            Some(request) => {
+                self.requests.push(request);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the payment requests a confirmed transaction pays, each output settles one of them
+    fn settle_requests(&mut self, transaction: &TXMessage, owned: &[(Outpoint, Output)]) {
+        // Transactions spending outputs of the wallet are its own, like change to a reused address
+        let spends_owned = Self::spent_outpoints(transaction)
+            .iter()
+            .any(|outpoint| owned.iter().any(|(utxo, _)| utxo == outpoint));
+        if spends_owned {
+            return;
+        }
+        let mut settled_outputs = vec![];
+        for request in self.requests.iter_mut() {
+            request.check_payment(transaction, &mut settled_outputs);
+        }
+    }
+
+    fn pending_index(&self, txid: [u8; 32]) -> Result<usize, Box<dyn Error>> {
+        match self
+            .pending
+            .iter()
+            .position(|pending| pending.get_id() == txid)
+        {
+            Some(index) => Ok(index),
+            None => Err("The transaction isn't an unconfirmed transaction of the wallet".into()),
+        }
+    }
+
+    fn check_can_sign(&self) -> Result<(), Box<dyn Error>> {
+        if self.is_watch_only() {
+            return Err("Watch-only wallets can't sign".into());
+        }
+        if self.is_locked() {
+            return Err("Wallet is locked".into());
+        }
+        Ok(())
+    }
+
+    /// Signs a transaction of the wallet and takes the utxos it spends out of it,
+    /// the outpoints tell apart the utxos of the same address. Returns the fee it pays
+    fn sign_spending(&mut self, transaction: &mut TXMessage, spent: &[(Outpoint, Output)]) -> i64 {
+        let previous_outputs: Vec<Output> =
+            spent.iter().map(|(_, output)| output.clone()).collect();
+        P2PKH::sign_transaction(transaction, &self.signing_keys(), &previous_outputs);
+        self.utxos.retain(|utxo| !spent.contains(utxo));
+        self.coin_control.retain_utxos(&self.utxos);
+        P2PKH::fee_paid(spent, transaction)
+    }
+
+    /// Spends the utxo in the next transactions, all the selected utxos and nothing else are spent
+    /// # Errors
+    /// Returns an error if the outpoint isn't a utxo of the wallet
+    pub fn select_utxo(&mut self, outpoint: Outpoint) -> Result<(), Box<dyn Error>> {
+        self.check_utxo(&outpoint)?;
+        self.coin_control.select(outpoint);
+        Ok(())
+    }
+
+    /// Keeps the utxo out of the next transactions
+    /// # Errors
+    /// Returns an error if the outpoint isn't a utxo of the wallet
+    pub fn freeze_utxo(&mut self, outpoint: Outpoint) -> Result<(), Box<dyn Error>> {
+        self.check_utxo(&outpoint)?;
+        self.coin_control.freeze(outpoint);
+        Ok(())
+    }
+
+    /// Leaves every utxo to coin selection again
+    pub fn clear_coin_control(&mut self) {
+        self.coin_control = CoinControl::default();
+    }
+
+    fn check_utxo(&self, outpoint: &Outpoint) -> Result<(), Box<dyn Error>> {
+        match self.utxos.iter().any(|(utxo, _)| utxo == outpoint) {
+            true => Ok(()),
+            false => Err(format!("{} isn't a utxo of the wallet", outpoint).into()),
+        }
+    }
+
     /// Getters --------------------------
 
     pub fn get_balance(&self) -> u32 {
@@ -145,22 +1165,271 @@ impl Wallet {
         self.id
     }
 
+    pub fn get_utxos(&self) -> Vec<(Outpoint, Output)> {
+        self.utxos.clone()
+    }
+
+    pub fn get_coin_control(&self) -> &CoinControl {
+        &self.coin_control
+    }
+
     pub fn get_pending(&self) -> i32 {
         self.pending_balance
     }
 
     pub fn get_all_data(&self) -> String {
+        if let Some(line) = self.descriptors_line() {
+            return line;
+        }
+        if let Some(hd_account) = &self.hd_account {
+            let (receive, change) = hd_account.get_issued();
+            let key = match self.is_watch_only() {
+                true => hd_account.get_xpub(),
+                false => hd_account.get_xprv(),
+            };
+            return format!(
+                "{},{},{},{},{},{},{}",
+                self.id,
+                self.name,
+                key,
+                self.balance,
+                receive,
+                change,
+                self.address_type.tag()
+            );
+        }
         format!(
-            "{},{},{},{}",
+            "{},{},{},{},{}",
             self.id.clone(),
             self.name.clone(),
             self.keys_handler.get_privkey(),
-            self.balance.clone()
+            self.balance.clone(),
+            self.address_type.tag()
         )
     }
 
+    /// Returns the line stored in an encrypted wallets file, the private key is never written in plain text
+    /// # Errors
+    /// Returns an error if the private key wasn't encrypted
+    pub fn get_encrypted_data(&self) -> Result<String, Box<dyn Error>> {
+        if self.is_watch_only() {
+            return Ok(self.get_all_data());
+        }
+        let encrypted_key = match &self.encrypted_key {
+            Some(encrypted_key) => encrypted_key,
+            None => return Err("The private key is not encrypted".into()),
+        };
+        if let Some(hd_account) = &self.hd_account {
+            let (receive, change) = hd_account.get_issued();
+            return Ok(format!(
+                "{},{},{},{},{},{},{},{}",
+                self.id,
+                self.name,
+                hd_account.get_xpub(),
+                u8_vec_to_hex_string(encrypted_key),
+                self.balance,
+                receive,
+                change,
+                self.address_type.tag()
+            ));
+        }
+        Ok(format!(
+            "{},{},{},{},{},{}",
+            self.id,
+            self.name,
+            self.keys_handler.get_pubkey_string(),
+            u8_vec_to_hex_string(encrypted_key),
+            self.balance,
+            self.address_type.tag()
+        ))
+    }
+
+    /// Returns the receive address shown to the user, of the wallet address type
     pub fn get_address(&self) -> String {
-        self.keys_handler.get_address()
+        match &self.descriptor_account {
+            Some(descriptor_account) => descriptor_account.receive_address(),
+            None => self.keys_handler.address_of(self.address_type),
+        }
+    }
+
+    /// Returns every address handed out by the wallet, only one unless it is a HD wallet
+    pub fn get_addresses(&self) -> Vec<String> {
+        if let Some(descriptor_account) = &self.descriptor_account {
+            return descriptor_account.issued_addresses();
+        }
+        match &self.hd_account {
+            Some(hd_account) => hd_account
+                .issued_keys()
+                .iter()
+                .map(|keys_handler| keys_handler.address_of(self.address_type))
+                .collect(),
+            None => vec![self.get_address()],
+        }
+    }
+
+    /// Returns every address whose outputs belong to the wallet, of every address type.
+    /// HD wallets include the lookahead ones
+    pub fn get_watched_addresses(&self) -> Vec<String> {
+        if let Some(descriptor_account) = &self.descriptor_account {
+            return descriptor_account.watched_addresses();
+        }
+        match &self.hd_account {
+            Some(hd_account) => hd_account.watched_addresses(),
+            None => self.keys_handler.get_all_addresses(),
+        }
+    }
+
+    /// Returns every output script of the wallet, of every address type for its keys
+    /// or derived from its descriptors. HD wallets include the lookahead ones
+    pub fn get_watched_scripts(&self) -> Vec<Vec<u8>> {
+        if let Some(descriptor_account) = &self.descriptor_account {
+            return descriptor_account.watched_scripts();
+        }
+        let keys = match &self.hd_account {
+            Some(hd_account) => hd_account.watched_keys(),
+            None => vec![self.keys_handler.clone()],
+        };
+        let mut scripts = vec![];
+        for keys_handler in keys {
+            for address_type in [
+                AddressType::Legacy,
+                AddressType::SegWit,
+                AddressType::Taproot,
+            ] {
+                scripts.push(keys_handler.script_of(address_type));
+            }
+        }
+        scripts
+    }
+
+    /// Returns the output descriptors of the wallet, with their checksums. Keys are exported
+    /// as public keys (tpub for HD wallets) in a descriptor for each address type
+    pub fn export_descriptors(&self) -> Vec<String> {
+        if let Some(descriptor_account) = &self.descriptor_account {
+            return descriptor_account.export();
+        }
+        let keys = match &self.hd_account {
+            Some(hd_account) => vec![
+                format!("{}/0/*", hd_account.get_xpub()),
+                format!("{}/1/*", hd_account.get_xpub()),
+            ],
+            None => vec![self.keys_handler.get_pubkey_string()],
+        };
+        let mut descriptors = vec![];
+        for function in ["pkh", "wpkh", "tr"] {
+            for key in &keys {
+                if let Ok(descriptor) = Descriptor::parse(&format!("{}({})", function, key)) {
+                    descriptors.push(descriptor.export());
+                }
+            }
+        }
+        descriptors
+    }
+
+    /// Returns the private keys of the wallet in WIF for the network, each followed by its
+    /// legacy address. HD wallets export every key they have issued
+    /// # Errors
+    /// Returns an error if the wallet is watch-only or locked
+    pub fn export_wif(&self, network: Network) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.is_watch_only() {
+            return Err("Watch-only wallets have no private keys".into());
+        }
+        if self.is_locked() {
+            return Err("Unlock the wallets to export the private keys".into());
+        }
+        Ok(self
+            .signing_keys()
+            .iter()
+            .filter_map(|keys| Some(format!("{} {}", keys.get_wif(network)?, keys.get_address())))
+            .collect())
+    }
+
+    /// Signs the message with the key of one of the wallet's addresses, see `message_signing`
+    /// # Errors
+    /// Returns an error if the wallet is watch-only or locked, or the address isn't one of its keys
+    pub fn sign_message(&self, address: &str, message: &str) -> Result<String, Box<dyn Error>> {
+        if self.is_watch_only() {
+            return Err("Watch-only wallets can't sign".into());
+        }
+        if self.is_locked() {
+            return Err("Wallet is locked".into());
+        }
+        let keys = self
+            .signing_keys()
+            .into_iter()
+            .find(|keys| {
+                keys.get_all_addresses()
+                    .iter()
+                    .any(|owned| owned == address)
+            })
+            .ok_or("The address doesn't belong to the wallet")?;
+        message_signing::sign_message(&keys, &script_for_address(address)?, message)
+    }
+
+    /// Returns true if the address is watched by the wallet
+    pub fn is_mine(&self, address: &str) -> bool {
+        if let Some(descriptor_account) = &self.descriptor_account {
+            return descriptor_account.is_mine(address);
+        }
+        match &self.hd_account {
+            Some(hd_account) => hd_account.is_mine(address),
+            None => self
+                .keys_handler
+                .get_all_addresses()
+                .iter()
+                .any(|watched| watched == address),
+        }
+    }
+
+    /// Issues a fresh receive address of the given type, which becomes the one shown to the user.
+    /// Single key wallets only switch between the addresses of their key
+    /// # Errors
+    /// Returns an error if a single key wallet is asked for an address of the type it shows
+    pub fn new_receive_address(
+        &mut self,
+        address_type: AddressType,
+    ) -> Result<String, Box<dyn Error>> {
+        if let Some(descriptor_account) = &mut self.descriptor_account {
+            return descriptor_account.next_receive_address();
+        }
+        match &mut self.hd_account {
+            Some(hd_account) => self.keys_handler = hd_account.next_receive_keys()?,
+            None if address_type != self.address_type => {}
+            None => return Err("Only HD wallets can create new addresses".into()),
+        }
+        self.address_type = address_type;
+        Ok(self.get_address())
+    }
+
+    /// Marks the HD addresses found used by `is_used`, moving the lookahead window and showing
+    /// a fresh receive address if the current one was used. Returns true if anything changed
+    pub fn discover_addresses(&mut self, is_used: impl Fn(&str) -> bool) -> bool {
+        let hd_account = match &mut self.hd_account {
+            Some(hd_account) => hd_account,
+            None => return false,
+        };
+        match hd_account.discover(is_used) {
+            Ok(discovered) => {
+                if let Some(keys_handler) = hd_account.last_receive_keys() {
+                    self.keys_handler = keys_handler;
+                }
+                discovered
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Marks the descriptor scripts found used by `is_used`, moving their lookahead window.
+    /// Returns true if anything changed
+    pub fn discover_scripts(&mut self, is_used: impl Fn(&[u8]) -> bool) -> bool {
+        match &mut self.descriptor_account {
+            Some(descriptor_account) => descriptor_account.discover(is_used).unwrap_or(false),
+            None => false,
+        }
+    }
+
+    pub fn is_hd(&self) -> bool {
+        self.hd_account.is_some()
     }
 }
 
@@ -223,6 +1492,620 @@ mod wallet_tests {
         );
     }
 
+    #[test]
+    fn test_wallet_encrypted_data() {
+        let encryption =
+            WalletEncryption::new("passphrase", 4, std::time::Duration::from_secs(60)).unwrap();
+        let mut wallet = Wallet::new(
+            0,
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )
+        .unwrap();
+        wallet.encrypt_key(&encryption).unwrap();
+        let line = wallet.get_encrypted_data().unwrap();
+        assert!(!line.contains("5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd"));
+
+        let mut opened = Wallet::open_encrypted(&line).unwrap();
+        assert!(opened.is_locked());
+        assert_eq!(opened.get_address(), wallet.get_address());
+        assert!(opened
+            .create_transaction("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc", 1, 1)
+            .is_err());
+        opened.unlock(&encryption).unwrap();
+        assert!(!opened.is_locked());
+        opened.lock();
+        assert!(opened.is_locked());
+    }
+
+    #[test]
+    fn test_wif_wallet_line_and_export() {
+        let encryption =
+            WalletEncryption::new("passphrase", 4, std::time::Duration::from_secs(60)).unwrap();
+        let wif = "91gGn1HgSap6CbU12F6z3pJri26xzp7Ay1VW6NHCoEayNXwRpu2";
+        let mut wallet = Wallet::new(0, "imported".to_string(), wif.to_string()).unwrap();
+        assert_eq!(wallet.get_address(), "mvgbzkCSgKbYgaeG38auUzR7otscEGi8U7");
+        assert_eq!(
+            wallet.export_wif(Network::Testnet).unwrap(),
+            vec![format!("{} mvgbzkCSgKbYgaeG38auUzR7otscEGi8U7", wif)]
+        );
+        let opened = Wallet::open_line(&wallet.get_all_data()).unwrap();
+        assert_eq!(opened.get_address(), wallet.get_address());
+
+        // The uncompressed key is kept through encryption too
+        wallet.encrypt_key(&encryption).unwrap();
+        let mut opened = Wallet::open_encrypted(&wallet.get_encrypted_data().unwrap()).unwrap();
+        assert_eq!(opened.get_address(), wallet.get_address());
+        assert!(opened.export_wif(Network::Testnet).is_err());
+        opened.unlock(&encryption).unwrap();
+        assert_eq!(
+            opened.export_wif(Network::Testnet).unwrap(),
+            wallet.export_wif(Network::Testnet).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_wallet_signs_messages_of_its_addresses() {
+        let hd_account = HdAccount::from_seed(&[2u8; 32]).unwrap();
+        let mut wallet = Wallet::new_hd(0, "hd".to_string(), hd_account).unwrap();
+        let address = wallet.new_receive_address(AddressType::Taproot).unwrap();
+        let signature = wallet.sign_message(&address, "message").unwrap();
+        assert!(message_signing::verify_message(&address, "message", &signature).unwrap());
+        assert!(wallet
+            .sign_message("mvgbzkCSgKbYgaeG38auUzR7otscEGi8U7", "message")
+            .is_err());
+
+        let encryption =
+            WalletEncryption::new("passphrase", 4, std::time::Duration::from_secs(60)).unwrap();
+        wallet.encrypt_key(&encryption).unwrap();
+        wallet.lock();
+        assert!(wallet.sign_message(&address, "message").is_err());
+    }
+
+    #[test]
+    fn test_hd_wallet_fresh_addresses_and_file_line() {
+        let hd_account = HdAccount::from_seed(&[1u8; 32]).unwrap();
+        let mut wallet = Wallet::new_hd(0, "hd".to_string(), hd_account).unwrap();
+        let first = wallet.get_address();
+        let second = wallet.new_receive_address(AddressType::Legacy).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(wallet.get_address(), second);
+        assert_eq!(wallet.get_addresses(), vec![first, second.clone()]);
+
+        let line = wallet.get_all_data();
+        assert!(line.starts_with("0,hd,tprv"));
+        let opened = Wallet::open_line(&line).unwrap();
+        assert!(opened.is_hd());
+        assert_eq!(opened.get_address(), second);
+        assert_eq!(opened.get_addresses(), wallet.get_addresses());
+    }
+
+    #[test]
+    fn test_address_type_is_kept_in_wallet_lines() {
+        let encryption =
+            WalletEncryption::new("passphrase", 4, std::time::Duration::from_secs(60)).unwrap();
+        let private_key = "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd";
+        for address_type in [AddressType::SegWit, AddressType::Taproot] {
+            let hd_account = HdAccount::from_seed(&[1u8; 32]).unwrap();
+            for mut wallet in [
+                Wallet::new(0, "single".to_string(), private_key.to_string()).unwrap(),
+                Wallet::new_hd(1, "hd".to_string(), hd_account).unwrap(),
+            ] {
+                wallet.new_receive_address(address_type).unwrap();
+                let opened = Wallet::open_line(&wallet.get_all_data()).unwrap();
+                assert_eq!(opened.address_type, address_type);
+                assert_eq!(opened.get_address(), wallet.get_address());
+
+                wallet.encrypt_key(&encryption).unwrap();
+                let opened = Wallet::open_encrypted(&wallet.get_encrypted_data().unwrap()).unwrap();
+                assert_eq!(opened.address_type, address_type);
+                assert_eq!(opened.get_address(), wallet.get_address());
+            }
+        }
+
+        // Lines saved before the address type was stored show legacy addresses
+        let opened = Wallet::open_line(&format!("0,single,{},0", private_key)).unwrap();
+        assert_eq!(opened.address_type, AddressType::Legacy);
+    }
+
+    #[test]
+    fn test_hd_wallet_encrypted_line() {
+        let encryption =
+            WalletEncryption::new("passphrase", 4, std::time::Duration::from_secs(60)).unwrap();
+        let hd_account = HdAccount::from_seed(&[1u8; 32]).unwrap();
+        let mut wallet = Wallet::new_hd(0, "hd".to_string(), hd_account).unwrap();
+        wallet.new_receive_address(AddressType::Legacy).unwrap();
+        wallet.encrypt_key(&encryption).unwrap();
+        let line = wallet.get_encrypted_data().unwrap();
+        assert!(!line.contains("tprv"));
+
+        let mut opened = Wallet::open_encrypted(&line).unwrap();
+        assert!(opened.is_locked());
+        assert_eq!(opened.get_addresses(), wallet.get_addresses());
+        opened.unlock(&encryption).unwrap();
+        assert!(!opened.is_locked());
+        assert_eq!(opened.get_all_data(), wallet.get_all_data());
+    }
+
+    #[test]
+    fn test_xpub_wallet_is_watch_only() {
+        use crate::message_structs::compact_size::CompactSize;
+        use crate::node::wallets::hd_account::CHANGE_CHAIN;
+
+        let hd_account = HdAccount::from_seed(&[1u8; 32]).unwrap();
+        let hd_wallet = Wallet::new_hd(0, "hd".to_string(), hd_account.clone()).unwrap();
+        let mut wallet = Wallet::new(1, "cold".to_string(), hd_account.get_xpub()).unwrap();
+        assert!(wallet.is_watch_only());
+        assert!(!hd_wallet.is_watch_only());
+        assert_eq!(wallet.get_address(), hd_wallet.get_address());
+
+        let line = wallet.get_all_data();
+        assert!(line.starts_with("1,cold,tpub"));
+        assert_eq!(wallet.get_encrypted_data().unwrap(), line);
+        assert!(Wallet::open_line(&line).unwrap().is_watch_only());
+        assert!(Wallet::open_encrypted(&line).unwrap().is_watch_only());
+
+        let script = wallet.keys_handler.get_script();
+        let output = Output::new(
+            10000,
+            CompactSize::from_usize_to_compact_size(script.len()),
+            script,
+        );
+        wallet.update_utxos(&[(Outpoint::new([1; 32], 0), output)]);
+        assert!(wallet
+            .create_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 5000, 10)
+            .is_err());
+        let transaction = wallet
+            .create_unsigned_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 5000, 10)
+            .unwrap();
+        assert_eq!(transaction.input_list.len(), 1);
+        assert!(transaction.input_list[0].get_script().is_empty());
+        assert_eq!(
+            transaction.get_output()[1].get_script(),
+            hd_account
+                .derive_keys(CHANGE_CHAIN, 0)
+                .unwrap()
+                .get_script()
+        );
+        assert_eq!(wallet.get_balance(), 10000);
+    }
+
+    #[test]
+    fn test_watch_only_psbt_signed_by_hd_wallet() {
+        use crate::message_structs::compact_size::CompactSize;
+
+        let hd_account = HdAccount::from_seed(&[1u8; 32]).unwrap();
+        let hd_wallet = Wallet::new_hd(0, "hd".to_string(), hd_account.clone()).unwrap();
+        let mut wallet = Wallet::new(1, "cold".to_string(), hd_account.get_xpub()).unwrap();
+        let script = wallet.keys_handler.get_segwit_script();
+        let output = Output::new(
+            10000,
+            CompactSize::from_usize_to_compact_size(script.len()),
+            script,
+        );
+        wallet.update_utxos(&[(Outpoint::new([1; 32], 0), output)]);
+
+        let mut psbt = wallet
+            .create_psbt(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun",
+                5000,
+                10,
+                &HashMap::new(),
+            )
+            .unwrap();
+        // 147 vbytes at 10 sat/vB
+        assert_eq!(psbt.fee(), Some(1470));
+        assert!(wallet.sign_psbt(&mut psbt).is_err());
+        assert_eq!(hd_wallet.sign_psbt(&mut psbt).unwrap(), 1);
+        let transaction = psbt.finalize().unwrap();
+        assert_eq!(
+            transaction.get_witness(0)[1],
+            *hd_wallet.keys_handler.get_pubkey()
+        );
+        assert_eq!(wallet.get_balance(), 10000);
+    }
+
+    #[test]
+    fn test_multisig_wallet_psbt_signed_by_participants() {
+        use crate::message_structs::compact_size::CompactSize;
+
+        let accounts = [
+            HdAccount::from_seed(&[1u8; 32]).unwrap(),
+            HdAccount::from_seed(&[2u8; 32]).unwrap(),
+        ];
+        let xpubs: Vec<String> = accounts.iter().map(HdAccount::get_xpub).collect();
+        let keys: Vec<&str> = xpubs.iter().map(String::as_str).collect();
+        let mut wallet = Wallet::new_multisig(0, "treasury".to_string(), 2, &keys, true).unwrap();
+        assert!(wallet.is_watch_only());
+        assert!(wallet.get_address().starts_with("tb1q"));
+        assert_eq!(wallet.get_address().len(), 62);
+        let opened = Wallet::open_line(&wallet.get_all_data()).unwrap();
+        assert_eq!(opened.get_address(), wallet.get_address());
+        assert!(Wallet::new_multisig(1, "bad".to_string(), 3, &keys, true).is_none());
+
+        let script = wallet.get_watched_scripts()[0].clone();
+        let output = Output::new(
+            10000,
+            CompactSize::from_usize_to_compact_size(script.len()),
+            script,
+        );
+        wallet.update_utxos(&[(Outpoint::new([1; 32], 0), output)]);
+        let mut psbt = wallet
+            .create_psbt(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun",
+                5000,
+                10,
+                &HashMap::new(),
+            )
+            .unwrap();
+        assert!(psbt.get_inputs()[0].witness_script.is_some());
+
+        // Each participant signs with its own HD wallet
+        for (id, account) in accounts.into_iter().enumerate() {
+            let participant = Wallet::new_hd(id, "participant".to_string(), account).unwrap();
+            assert!(psbt.clone().finalize().is_err());
+            assert_eq!(participant.sign_psbt(&mut psbt).unwrap(), 1);
+        }
+        let transaction = psbt.finalize().unwrap();
+        assert_eq!(transaction.get_witness(0).len(), 4);
+        // The fee is estimated for 184 vbytes at 10 sat/vB, spending the 2-of-2 script
+        assert_eq!(transaction.get_output_amounts(), vec![5000, 3160]);
+    }
+
+    #[test]
+    fn test_address_list_wallet() {
+        let segwit = "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7";
+        let legacy = "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun";
+        let wallet = Wallet::new(0, "cold".to_string(), format!("{}, {}", legacy, segwit)).unwrap();
+        assert!(wallet.is_watch_only());
+        assert_eq!(wallet.get_address(), legacy);
+        assert_eq!(wallet.get_addresses(), vec![legacy, segwit]);
+        assert!(wallet.is_mine(segwit));
+        let opened = Wallet::open_line(&wallet.get_all_data()).unwrap();
+        assert_eq!(opened.get_addresses(), wallet.get_addresses());
+        assert!(Wallet::new(1, "bad".to_string(), format!("{} nope", legacy)).is_none());
+    }
+
+    #[test]
+    fn test_descriptor_wallet_line_and_export() {
+        let hd_account = HdAccount::from_seed(&[1u8; 32]).unwrap();
+        let hd_wallet = Wallet::new_hd(0, "hd".to_string(), hd_account.clone()).unwrap();
+        let descriptors = hd_wallet.export_descriptors();
+        assert_eq!(descriptors.len(), 6);
+        assert!(descriptors
+            .iter()
+            .all(|descriptor| descriptor.contains('#')));
+
+        // The exported pkh descriptors watch the same addresses as the HD wallet
+        let pkh = format!("{} {}", descriptors[0], descriptors[1]);
+        let mut wallet = Wallet::new(1, "watch".to_string(), pkh).unwrap();
+        assert!(wallet.is_watch_only());
+        assert_eq!(wallet.get_address(), hd_wallet.get_address());
+        assert!(wallet
+            .create_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 10, 1)
+            .is_err());
+        wallet.new_receive_address(AddressType::Legacy).unwrap();
+
+        let line = wallet.get_all_data();
+        assert!(line.starts_with("1,watch,descriptors,0,2,0,pkh(tpub"));
+        let opened = Wallet::open_line(&line).unwrap();
+        assert_eq!(opened.get_addresses(), wallet.get_addresses());
+        assert_eq!(opened.export_descriptors(), wallet.export_descriptors());
+        assert_eq!(wallet.get_encrypted_data().unwrap(), line);
+        assert!(Wallet::open_encrypted(&line).unwrap().is_watch_only());
+    }
+
+    #[test]
+    fn test_wallet_from_mnemonic() {
+        let mnemonic = Mnemonic::parse(
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+        )
+        .unwrap();
+        let wallet = Wallet::from_mnemonic(0, "hd".to_string(), &mnemonic, "").unwrap();
+        let restored = Wallet::from_mnemonic(1, "copy".to_string(), &mnemonic, "").unwrap();
+        let other = Wallet::from_mnemonic(2, "other".to_string(), &mnemonic, "pass").unwrap();
+        assert!(wallet.is_hd());
+        assert_eq!(wallet.get_address(), restored.get_address());
+        assert_ne!(wallet.get_address(), other.get_address());
+    }
+
+    #[test]
+    fn test_wallet_coin_control() {
+        use crate::message_structs::compact_size::CompactSize;
+
+        let mut wallet = Wallet::new(
+            0,
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )
+        .unwrap();
+        let script = wallet.keys_handler.get_script();
+        let utxos: Vec<(Outpoint, Output)> = [50_000, 30_000, 20_000]
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let length = CompactSize::from_usize_to_compact_size(script.len());
+                (
+                    Outpoint::new([index as u8; 32], 0),
+                    Output::new(*value, length, script.clone()),
+                )
+            })
+            .collect();
+        wallet.update_utxos(&utxos);
+        assert_eq!(wallet.get_utxos(), utxos);
+        assert!(wallet.freeze_utxo(Outpoint::new([9; 32], 0)).is_err());
+
+        wallet.freeze_utxo(utxos[0].0).unwrap();
+        wallet.select_utxo(utxos[2].0).unwrap();
+        assert!(wallet
+            .create_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 25_000, 10)
+            .is_err());
+        wallet.select_utxo(utxos[1].0).unwrap();
+        let (transaction, _) = wallet
+            .create_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 25_000, 10)
+            .unwrap();
+        assert_eq!(transaction.input_list.len(), 2);
+
+        // Only the frozen utxo is left and it stays frozen
+        assert_eq!(wallet.get_utxos(), vec![utxos[0].clone()]);
+        assert!(!wallet.get_coin_control().has_selection());
+        assert!(wallet.get_coin_control().is_frozen(&utxos[0].0));
+        assert!(wallet
+            .create_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 10_000, 10)
+            .is_err());
+        wallet.clear_coin_control();
+        assert!(wallet
+            .create_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 10_000, 10)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_wallet_batch_transaction() {
+        use crate::message_structs::compact_size::CompactSize;
+
+        let mut wallet = Wallet::new(
+            0,
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )
+        .unwrap();
+        let script = wallet.keys_handler.get_script();
+        let length = CompactSize::from_usize_to_compact_size(script.len());
+        let output = Output::new(50_000, length, script);
+        let funding = TXMessage::new(
+            2,
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![output.clone()],
+            0,
+        );
+        let utxos = vec![(Outpoint::new(funding.get_id(), 0), output)];
+        wallet.update_utxos(&utxos);
+        let transactions = HashMap::from([(u8_array_to_hex_string(&funding.get_id()), funding)]);
+
+        let payments = vec![
+            ("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(), 10_000),
+            ("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc".to_string(), 15_000),
+        ];
+        let mut invalid = payments.clone();
+        invalid.push(("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWd".to_string(), 1_000));
+        assert!(wallet.create_batch_transaction(&invalid, 10).is_err());
+        assert!(wallet
+            .create_batch_psbt(&invalid, 10, &transactions)
+            .is_err());
+        assert!(wallet
+            .create_batch_psbt(&payments, 10, &HashMap::new())
+            .is_err());
+
+        let psbt = wallet
+            .create_batch_psbt(&payments, 10, &transactions)
+            .unwrap();
+        assert_eq!(psbt.get_unsigned_tx().get_output().len(), 3);
+
+        let (transaction, fee) = wallet.create_batch_transaction(&payments, 10).unwrap();
+        let amounts = transaction.get_output_amounts();
+        assert_eq!(amounts[..2], [10_000, 15_000]);
+        assert_eq!(amounts.iter().sum::<i64>() + fee, 50_000);
+        assert!(wallet.get_utxos().is_empty());
+    }
+
+    #[test]
+    fn test_wallet_fee_bumping() {
+        use crate::message_structs::compact_size::CompactSize;
+
+        let mut wallet = Wallet::new(
+            0,
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )
+        .unwrap();
+        let script = wallet.keys_handler.get_script();
+        let utxos: Vec<(Outpoint, Output)> = [20_000, 50_000]
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let length = CompactSize::from_usize_to_compact_size(script.len());
+                (
+                    Outpoint::new([index as u8 + 1; 32], 0),
+                    Output::new(*value, length, script.clone()),
+                )
+            })
+            .collect();
+        wallet.update_utxos(&utxos);
+        wallet.select_utxo(utxos[0].0).unwrap();
+        let (transaction, fee) = wallet
+            .create_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 10_000, 2)
+            .unwrap();
+        assert!(wallet.bump_fee([9; 32], 10).is_err());
+
+        let (replacement, new_fee) = wallet.bump_fee(transaction.get_id(), 10).unwrap();
+        assert!(new_fee > fee);
+        assert!(wallet
+            .get_pending_transaction(transaction.get_id())
+            .is_none());
+        let pending = wallet
+            .get_pending_transaction(replacement.get_id())
+            .unwrap();
+        assert_eq!(pending.get_spent(), &utxos[..1]);
+
+        let (child, _) = wallet.create_child(replacement.get_id(), 20).unwrap();
+        assert!(wallet.get_pending_transaction(child.get_id()).is_some());
+        assert!(wallet.bump_fee(replacement.get_id(), 30).is_err());
+
+        // Utxos spent by unconfirmed transactions aren't spent again
+        wallet.update_utxos(&utxos);
+        assert_eq!(wallet.get_utxos(), vec![utxos[1].clone()]);
+        assert!(wallet.get_pending_transaction(child.get_id()).is_some());
+
+        // Abandoning a transaction abandons its child and frees the utxos they spent
+        let lines = wallet.get_pending_lines();
+        assert_eq!(
+            wallet.abandon_transaction(replacement.get_id()).unwrap(),
+            vec![replacement.get_id(), child.get_id()]
+        );
+        assert_eq!(wallet.get_utxos(), vec![utxos[1].clone(), utxos[0].clone()]);
+        assert!(wallet.abandon_transaction(replacement.get_id()).is_err());
+        // Saved pending transactions are pending again once opened
+        assert!(lines.iter().all(|line| wallet.open_pending_line(line)));
+        wallet.update_utxos(&utxos);
+        assert_eq!(wallet.get_utxos(), vec![utxos[1].clone()]);
+        assert!(wallet.get_pending_transaction(child.get_id()).is_some());
+        // Once confirmed they are forgotten
+        wallet.update_utxos(&utxos[1..]);
+        assert!(wallet
+            .get_pending_transaction(replacement.get_id())
+            .is_none());
+        assert!(wallet.get_pending_transaction(child.get_id()).is_none());
+    }
+
+    #[test]
+    fn test_wallet_history() {
+        use crate::message_structs::{compact_size::CompactSize, input::Input};
+
+        let mut wallet = Wallet::new(
+            0,
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )
+        .unwrap();
+        let script = wallet.keys_handler.get_script();
+        let length = CompactSize::from_usize_to_compact_size(script.len());
+        let input = Input::new(
+            Outpoint::new([7; 32], 0),
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            0xffffffff,
+        );
+        let incoming = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![input],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Output::new(50_000, length, script)],
+            0,
+        );
+        assert!(wallet.record_transaction(&incoming, None));
+        assert!(wallet.record_transaction(&incoming, Some(([10; 32], 100, 1_700_000_000))));
+        let entry = wallet.get_history().get(&incoming.get_id()).unwrap();
+        assert_eq!((entry.amount, entry.fee), (50_000, None));
+        assert_eq!(entry.confirmations(101), 2);
+        wallet.update_utxos(&[(
+            Outpoint::new(incoming.get_id(), 0),
+            incoming.get_output()[0].clone(),
+        )]);
+
+        let (transaction, fee) = wallet
+            .create_transaction("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun", 10_000, 2)
+            .unwrap();
+        wallet.set_label(transaction.get_id(), "rent").unwrap();
+        let (replacement, new_fee) = wallet.bump_fee(transaction.get_id(), 10).unwrap();
+        assert!(wallet.get_history().get(&transaction.get_id()).is_none());
+        let entry = wallet.get_history().get(&replacement.get_id()).unwrap();
+        assert_eq!(entry.amount, -(10_000 + new_fee));
+        assert_eq!((entry.fee, entry.label.as_str()), (Some(new_fee), "rent"));
+        assert!(new_fee > fee);
+
+        assert!(wallet.record_transaction(&replacement, Some(([11; 32], 101, 1_700_000_600))));
+        assert_eq!(
+            wallet
+                .get_history()
+                .get(&replacement.get_id())
+                .unwrap()
+                .height(),
+            Some(101)
+        );
+        // A reorg replaces the block that confirmed the payment
+        wallet.connect_block([12; 32], 101);
+        let entry = wallet.get_history().get(&replacement.get_id()).unwrap();
+        assert_eq!(entry.confirmations(101), 0);
+        assert_eq!(wallet.get_history().get_entries().len(), 2);
+        assert!(wallet.set_label([9; 32], "unknown").is_err());
+
+        // Outputs paid to the wallet are owned by the transactions after them in the block
+        let received = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Input::new(
+                Outpoint::new([8; 32], 0),
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                0xffffffff,
+            )],
+            CompactSize::from_usize_to_compact_size(1),
+            incoming.get_output(),
+            0,
+        );
+        let spending = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Input::new(
+                Outpoint::new(reverse_array(&received.get_id()), 0),
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                0xffffffff,
+            )],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Output::new(
+                49_000,
+                CompactSize::from_usize_to_compact_size(1),
+                vec![0x51],
+            )],
+            0,
+        );
+        let block = ([13; 32], 102, 1_700_001_200);
+        assert_eq!(wallet.record_block(&[received, spending.clone()], block), 2);
+        let entry = wallet.get_history().get(&spending.get_id()).unwrap();
+        assert_eq!((entry.amount, entry.fee), (-50_000, Some(1_000)));
+    }
+
+    #[test]
+    fn test_plain_wallet_has_no_new_addresses() {
+        let mut wallet = Wallet::new(
+            0,
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )
+        .unwrap();
+        assert!(!wallet.is_hd());
+        assert!(wallet.new_receive_address(AddressType::Legacy).is_err());
+        assert_eq!(wallet.get_addresses(), vec![wallet.get_address()]);
+        assert!(wallet.is_mine(&wallet.get_address()));
+        assert!(!wallet.is_mine("n3svudhm7bt6j3nTT9uu1A57Cs9pKK3iXW"));
+
+        // The same key can be shown as a segwit or a taproot address
+        let legacy = wallet.get_address();
+        let segwit = wallet.new_receive_address(AddressType::SegWit).unwrap();
+        assert!(segwit.starts_with("tb1q"));
+        assert_eq!(wallet.get_address(), segwit);
+        assert!(wallet.new_receive_address(AddressType::SegWit).is_err());
+        let taproot = wallet.new_receive_address(AddressType::Taproot).unwrap();
+        assert!(taproot.starts_with("tb1p"));
+        assert!(wallet.is_mine(&legacy));
+        assert!(wallet.is_mine(&segwit));
+        assert!(wallet.is_mine(&taproot));
+    }
+
     #[test]
     fn test_wallet_modify_balance() {
         let mut wallet = Wallet::new(
@@ -1,10 +1,46 @@
+use super::encryption::WalletEncryption;
+use super::hd_account::HdAccount;
+use super::hd_keys::Network;
+use super::history::{current_time, BlockPosition};
+use super::keys_handler::{AddressType, KeysHandler};
+use super::mnemonic::Mnemonic;
+use super::payments::Payment;
+use super::pending::PendingTransaction;
+use super::psbt::Psbt;
+use super::transactions_handler::P2PKH;
 use super::wallet::Wallet;
-use crate::{message_structs::tx_message::TXMessage, node::utxo_collector::UtxoCollector};
-use std::{collections::HashMap, error::Error};
+use crate::utils::array_tools::{txid_from_hex, txid_to_hex};
+use crate::utils::script_tools::{bitcoin_address_in_b58_output, script_for_address};
+use crate::{
+    message_structs::{
+        block_headers::DEFAULT_BIRTHDAY, block_message::BlockMessage, outpoint::Outpoint,
+        output::Output, tx_message::TXMessage,
+    },
+    node::utxo_collector::UtxoCollector,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    time::Duration,
+};
+
+/// File the wallets are saved to, after the encryption header if they have a passphrase
+pub const WALLETS_PATH: &str = "./storage/wallets.txt";
+/// Utxo shown in the coin control dialog: outpoint, address, value, selected and frozen
+pub type UtxoRow = (String, String, i64, bool, bool);
+/// Transaction shown in the history: txid, net amount, fee, height, confirmations, timestamp and label
+pub type HistoryRow = (String, i64, Option<i64>, Option<u32>, u32, u32, String);
+/// Payment request shown in the receive tab: address, amount, label, creation time, whether
+/// it was paid and its URI
+pub type PaymentRequestRow = (String, Option<i64>, String, u32, bool, String);
 
 pub struct WalletHandler {
     pub wallets: Vec<Wallet>,
     actual_wallet: usize,
+    suggested_fee_rates: Vec<(usize, Option<u64>)>,
+    encryption: Option<WalletEncryption>,
+    sealed: bool, // the wallets file is encrypted as a whole and wasn't opened yet
+    tip: u32,     // height of the last block, to count the confirmations of the history
 }
 
 impl Default for WalletHandler {
@@ -19,6 +55,10 @@ impl WalletHandler {
         WalletHandler {
             wallets: vec![],
             actual_wallet: 0,
+            suggested_fee_rates: vec![],
+            encryption: None,
+            sealed: false,
+            tip: 0,
         }
     }
 
@@ -27,10 +67,79 @@ impl WalletHandler {
         WalletHandler {
             wallets,
             actual_wallet: 0,
+            suggested_fee_rates: vec![],
+            encryption: None,
+            sealed: false,
+            tip: 0,
+        }
+    }
+
+    /// Creates a WalletHandler with locked wallets read from an encrypted file
+    pub fn open_encrypted(wallets: Vec<Wallet>, encryption: WalletEncryption) -> WalletHandler {
+        let mut wallet_handler = Self::open(wallets);
+        wallet_handler.encryption = Some(encryption);
+        wallet_handler
+    }
+
+    /// Creates a WalletHandler for a wallets file encrypted as a whole, its wallets are read
+    /// with `open_sealed_wallets` once the passphrase is given
+    pub fn open_sealed(encryption: WalletEncryption) -> WalletHandler {
+        let mut wallet_handler = Self::open_encrypted(vec![], encryption);
+        wallet_handler.sealed = true;
+        wallet_handler
+    }
+
+    /// Returns true if the wallets file is encrypted as a whole and wasn't opened yet
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// Reads the wallets from the sealed lines of the wallets file after the header,
+    /// the wallets must be unlocked
+    /// # Errors
+    /// Returns an error if the lines can't be decrypted or the wallets unlocked
+    pub fn open_sealed_wallets(&mut self, lines: &[String]) -> Result<(), Box<dyn Error>> {
+        let mut wallets: Vec<Wallet> = self
+            .unseal(lines)?
+            .iter()
+            .filter_map(|line| Wallet::open_encrypted(line))
+            .collect();
+        if let Some(encryption) = &self.encryption {
+            for wallet in wallets.iter_mut() {
+                wallet.unlock(encryption)?;
+            }
+        }
+        self.wallets = wallets;
+        self.actual_wallet = 0;
+        self.sealed = false;
+        Ok(())
+    }
+
+    /// Encrypts the lines of a wallets file as a whole if the wallets have a passphrase,
+    /// files are only written with encrypted keys until it's given once
+    /// # Errors
+    /// Returns an error if the lines can't be encrypted
+    pub fn seal(&self, lines: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+        match &self.encryption {
+            Some(encryption) if encryption.has_file_key() => encryption.seal(&lines),
+            _ => Ok(lines),
         }
     }
 
-    /// Create a new wallet as long as the data provided is correct
+    /// Decrypts the lines of a sealed wallets file, other files are returned as they are
+    /// # Errors
+    /// Returns an error if the passphrase wasn't given yet or the file was sealed with another one
+    pub fn unseal(&self, lines: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        match &self.encryption {
+            Some(encryption) => encryption.unseal(lines),
+            None => Ok(lines.to_vec()),
+        }
+    }
+
+    /// Create a new wallet as long as the data provided is correct, the key can be
+    /// a private key in hex, an extended private key (xprv/tprv) for a HD wallet or
+    /// output descriptors for a watch only wallet.
+    /// If the wallets are encrypted they have to be unlocked to encrypt the new key
     pub fn new_wallet(&mut self, order: (String, String)) -> bool {
         if self.exist_wallet(order.0.clone()) {
             return false;
@@ -38,15 +147,231 @@ impl WalletHandler {
         if order.0.is_empty() || order.1.is_empty() {
             return false;
         }
-        self.actual_wallet = self.wallets.len();
 
-        match Wallet::new(self.wallets.len(), order.0, order.1) {
-            Some(wallet) => self.wallets.push(wallet),
+        let wallet = match Wallet::new(self.wallets.len(), order.0, order.1) {
+            Some(wallet) => wallet,
             None => return false,
+        };
+        self.add_wallet(wallet)
+    }
+
+    /// Creates a HD wallet from a seed of 16 to 64 bytes
+    pub fn new_hd_wallet(&mut self, name: String, seed: &[u8]) -> bool {
+        if name.is_empty() || self.exist_wallet(name.clone()) {
+            return false;
         }
+        let hd_account = match HdAccount::from_seed(seed) {
+            Ok(hd_account) => hd_account,
+            Err(_) => return false,
+        };
+        match Wallet::new_hd(self.wallets.len(), name, hd_account) {
+            Some(wallet) => self.add_wallet(wallet),
+            None => false,
+        }
+    }
+
+    /// Creates a HD wallet from a new mnemonic of `word_count` words, which is returned to be written down
+    /// # Errors
+    /// Returns an error if the name is taken, the amount of words is invalid or the wallets are locked
+    pub fn new_mnemonic_wallet(
+        &mut self,
+        name: String,
+        word_count: usize,
+        passphrase: &str,
+    ) -> Result<Mnemonic, Box<dyn Error>> {
+        let mnemonic = Mnemonic::generate(word_count)?;
+        self.restore_wallet(name, &mnemonic.phrase(), passphrase)?;
+        // A new mnemonic can't have been paid to before now
+        if let Some(wallet) = self.wallets.last_mut() {
+            wallet.set_birthday(current_time());
+        }
+        Ok(mnemonic)
+    }
+
+    /// Restores a HD wallet from its mnemonic and passphrase, its addresses are found when the utxos are added
+    /// # Errors
+    /// Returns an error if the name is taken, the mnemonic is invalid or the wallets are locked
+    pub fn restore_wallet(
+        &mut self,
+        name: String,
+        phrase: &str,
+        passphrase: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if name.is_empty() || self.exist_wallet(name.clone()) {
+            return Err("Invalid or repeated wallet name".into());
+        }
+        let mnemonic = Mnemonic::parse(phrase)?;
+        let wallet = match Wallet::from_mnemonic(self.wallets.len(), name, &mnemonic, passphrase) {
+            Some(wallet) => wallet,
+            None => return Err("Error deriving the wallet keys".into()),
+        };
+        if !self.add_wallet(wallet) {
+            return Err("Unlock the wallets to add a new one".into());
+        }
+        Ok(())
+    }
+
+    /// Creates a `threshold` of n multisig wallet of the public keys or extended public keys,
+    /// separated by commas or whitespace, with P2WSH addresses if `segwit` or else P2SH ones
+    /// # Errors
+    /// Returns an error if the name is taken, the keys or threshold are invalid or the wallets are locked
+    pub fn new_multisig_wallet(
+        &mut self,
+        name: String,
+        threshold: usize,
+        keys: &str,
+        segwit: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if name.is_empty() || self.exist_wallet(name.clone()) {
+            return Err("Invalid or repeated wallet name".into());
+        }
+        let keys: Vec<&str> = keys
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|key| !key.is_empty())
+            .collect();
+        let wallet = match Wallet::new_multisig(self.wallets.len(), name, threshold, &keys, segwit)
+        {
+            Some(wallet) => wallet,
+            None => return Err("Invalid multisig keys or threshold".into()),
+        };
+        if !self.add_wallet(wallet) {
+            return Err("Unlock the wallets to add a new one".into());
+        }
+        Ok(())
+    }
+
+    /// Encrypts the key of a new wallet if needed and makes it the actual wallet
+    fn add_wallet(&mut self, mut wallet: Wallet) -> bool {
+        if self.sealed {
+            return false;
+        }
+        if let Some(encryption) = &self.encryption {
+            if wallet.encrypt_key(encryption).is_err() {
+                return false;
+            }
+        }
+        self.actual_wallet = self.wallets.len();
+        self.wallets.push(wallet);
         true
     }
 
+    /// Encrypts the private keys of every wallet with a new passphrase and locks them
+    /// # Errors
+    /// Returns an error if the wallets are already encrypted
+    pub fn encrypt_wallets(&mut self, encryption: WalletEncryption) -> Result<(), Box<dyn Error>> {
+        if self.encryption.is_some() {
+            return Err("Wallets are already encrypted".into());
+        }
+        for wallet in self.wallets.iter_mut() {
+            wallet.encrypt_key(&encryption)?;
+        }
+        self.encryption = Some(encryption);
+        self.lock();
+        Ok(())
+    }
+
+    /// Decrypts the private keys, they are forgotten after `timeout`
+    /// # Errors
+    /// Returns an error if the wallets aren't encrypted or the passphrase is wrong
+    pub fn unlock(&mut self, passphrase: &str, timeout: Duration) -> Result<(), Box<dyn Error>> {
+        let encryption = match &mut self.encryption {
+            Some(encryption) => encryption,
+            None => return Err("Wallets are not encrypted".into()),
+        };
+        encryption.unlock(passphrase, timeout)?;
+        let mut result = Ok(());
+        for wallet in self.wallets.iter_mut() {
+            if let Err(e) = wallet.unlock(encryption) {
+                result = Err(e);
+                break;
+            }
+        }
+        if result.is_err() {
+            self.lock();
+        }
+        result
+    }
+
+    /// Forgets the decrypted private keys
+    pub fn lock(&mut self) {
+        if let Some(encryption) = &mut self.encryption {
+            encryption.lock();
+        }
+        for wallet in self.wallets.iter_mut() {
+            wallet.lock();
+        }
+    }
+
+    /// Locks the wallets if their unlock timeout expired, returns true if they were locked
+    pub fn lock_if_expired(&mut self) -> bool {
+        match &self.encryption {
+            Some(encryption) if encryption.expired() => {
+                self.lock();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Re-encrypts every private key with a new passphrase
+    /// # Errors
+    /// Returns an error if the old passphrase is wrong, nothing changes in that case
+    pub fn change_passphrase(
+        &mut self,
+        old_passphrase: &str,
+        new_encryption: WalletEncryption,
+    ) -> Result<(), Box<dyn Error>> {
+        self.unlock(old_passphrase, Duration::from_secs(60))?;
+        let mut wallets = self.wallets.clone();
+        for wallet in wallets.iter_mut() {
+            if let Err(e) = wallet.encrypt_key(&new_encryption) {
+                self.lock();
+                return Err(e);
+            }
+        }
+        self.wallets = wallets;
+        self.encryption = Some(new_encryption);
+        self.lock();
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Returns true if the wallets are encrypted and their keys aren't available
+    pub fn is_locked(&self) -> bool {
+        match &self.encryption {
+            Some(encryption) => !encryption.is_unlocked(),
+            None => false,
+        }
+    }
+
+    /// Returns the lines of the wallets file. When there's a passphrase the keys are encrypted
+    /// and the wallets lines are sealed after the header
+    /// # Errors
+    /// Returns an error if any wallet can't be encrypted, so it isn't left out of the file
+    pub fn get_file_lines(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let encryption = match &self.encryption {
+            Some(encryption) => encryption,
+            None => return Ok(self.wallets.iter().map(|w| w.get_all_data()).collect()),
+        };
+        let mut wallet_lines = vec![];
+        for wallet in self.wallets.iter() {
+            match wallet.get_encrypted_data() {
+                Ok(line) => wallet_lines.push(line),
+                Err(error) => {
+                    return Err(
+                        format!("Error encrypting wallet {}: {}", wallet.get_name(), error).into(),
+                    )
+                }
+            }
+        }
+        let mut lines = vec![encryption.header()];
+        lines.extend(self.seal(wallet_lines)?);
+        Ok(lines)
+    }
+
     /// Returns positive if a wallet with that name already exists
     fn exist_wallet(&mut self, new_name: String) -> bool {
         for wallet in self.wallets.iter() {
@@ -104,10 +429,7 @@ impl WalletHandler {
             "balance_send_fix".to_string(),
             actual_wallet.get_balance().to_string(),
         );
-        hash.insert(
-            "address_label".to_string(),
-            actual_wallet.keys_handler.get_address(),
-        );
+        hash.insert("address_label".to_string(), actual_wallet.get_address());
         hash.insert(
             "label_available".to_string(),
             actual_wallet.get_balance().to_string(),
@@ -124,10 +446,42 @@ impl WalletHandler {
             "label_total".to_string(),
             (actual_wallet.get_balance() as i32 + actual_wallet.get_pending()).to_string(),
         );
+        if let Some(suggested_fee) = self.suggested_fee_text() {
+            hash.insert("label_suggested_fee".to_string(), suggested_fee);
+        }
 
         hash
     }
 
+    /// Stores the fee rates (sat/vB) estimated for each confirmation target
+    pub fn set_suggested_fee_rates(&mut self, fee_rates: Vec<(usize, Option<u64>)>) {
+        self.suggested_fee_rates = fee_rates;
+    }
+
+    /// Returns the suggested fee in satoshis for a typical transaction (one input, recipient and change) for each target
+    pub fn get_suggested_fees(&self) -> Vec<(usize, u64)> {
+        let size = P2PKH::estimate_size(1, 2) as u64;
+        self.suggested_fee_rates
+            .iter()
+            .filter_map(|(target, fee_rate)| fee_rate.map(|rate| (*target, rate * size)))
+            .collect()
+    }
+
+    /// Text shown next to the fee rate entry, None if there are no estimations yet
+    fn suggested_fee_text(&self) -> Option<String> {
+        let fee_rates: Vec<String> = self
+            .suggested_fee_rates
+            .iter()
+            .filter_map(|(target, fee_rate)| {
+                fee_rate.map(|rate| format!("{} sat/vB ({} blocks)", rate, target))
+            })
+            .collect();
+        if fee_rates.is_empty() {
+            return None;
+        }
+        Some(format!("Suggested fee rate: {}", fee_rates.join(" / ")))
+    }
+
     pub fn get_actual_balance(&self) -> u32 {
         self.wallets[self.actual_wallet].get_balance()
     }
@@ -136,29 +490,648 @@ impl WalletHandler {
         self.wallets.len() == 0
     }
 
+    /// Creates and signs a transaction of the actual wallet, returns it with the fee it pays.
+    /// Order of the tuple: (address, label, amount, fee rate in sat/vB)
     pub fn create_transaction(
         &mut self,
         order: (String, String, i32, i32),
-    ) -> Result<TXMessage, Box<dyn Error>> {
+    ) -> Result<(TXMessage, i64), Box<dyn Error>> {
         let address = order.0;
         let amount = order.2;
-        let fee = order.3;
-        return self.wallets[self.actual_wallet].create_transaction(&address, amount, fee);
+        let fee_rate = Self::order_fee_rate(order.3);
+        let wallet = &mut self.wallets[self.actual_wallet];
+        let (transaction, fee) = wallet.create_transaction(&address, amount, fee_rate)?;
+        wallet.set_label(transaction.get_id(), &order.1.replace(['\n', '\r'], " "))?;
+        Ok((transaction, fee))
+    }
+
+    /// Creates and signs a single transaction of the actual wallet paying every payment,
+    /// returns it with the fee it pays
+    /// # Errors
+    /// Returns an error if there are no wallets or the transaction can't be created
+    pub fn create_batch_transaction(
+        &mut self,
+        payments: &[Payment],
+        fee_rate: i32,
+    ) -> Result<(TXMessage, i64), Box<dyn Error>> {
+        let wallet = match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet,
+            None => return Err("There are no wallets".into()),
+        };
+        let (transaction, fee) = wallet.create_batch_transaction(
+            &Self::batch_order(payments),
+            Self::order_fee_rate(fee_rate),
+        )?;
+        let labels: Vec<&str> = payments
+            .iter()
+            .map(|(_, label, _)| label.as_str())
+            .filter(|label| !label.is_empty())
+            .collect();
+        wallet.set_label(transaction.get_id(), &labels.join(", "))?;
+        Ok((transaction, fee))
+    }
+
+    /// Creates the PSBT of a transaction of the actual wallet paying every payment, encoded in base64.
+    /// `previous_transactions` are the known transactions by their hex id
+    /// # Errors
+    /// Returns an error if there are no wallets or the transaction can't be created
+    pub fn create_batch_psbt(
+        &mut self,
+        payments: &[Payment],
+        fee_rate: i32,
+        previous_transactions: &HashMap<String, TXMessage>,
+    ) -> Result<String, Box<dyn Error>> {
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => {
+                let fee_rate = Self::order_fee_rate(fee_rate);
+                let payments = Self::batch_order(payments);
+                let psbt = wallet.create_batch_psbt(&payments, fee_rate, previous_transactions)?;
+                Ok(psbt.to_base64())
+            }
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Addresses and amounts of the payments, the labels go to the history
+    fn batch_order(payments: &[Payment]) -> Vec<(String, i64)> {
+        payments
+            .iter()
+            .map(|(address, _, amount)| (address.clone(), *amount))
+            .collect()
+    }
+
+    /// Replaces the unconfirmed transaction of the actual wallet whose id is shown in hex
+    /// with one paying the fee rate (RBF), returns it with the fee it pays
+    /// # Errors
+    /// Returns an error if there are no wallets, the id is invalid or the fee can't be bumped
+    pub fn bump_fee(
+        &mut self,
+        txid: &str,
+        fee_rate: i32,
+    ) -> Result<(TXMessage, i64), Box<dyn Error>> {
+        let txid = txid_from_hex(txid.trim())?;
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet.bump_fee(txid, Self::order_fee_rate(fee_rate)),
+            None => Err("There are no wallets".into()),
+        }
     }
 
+    /// Abandons the unconfirmed transaction of the actual wallet whose id is shown in hex,
+    /// with the transactions spending its change. Returns the ids of the abandoned ones
+    /// # Errors
+    /// Returns an error if there are no wallets, the id is invalid or the transaction isn't pending
+    pub fn abandon_transaction(&mut self, txid: &str) -> Result<Vec<[u8; 32]>, Box<dyn Error>> {
+        let txid = txid_from_hex(txid.trim())?;
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet.abandon_transaction(txid),
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Creates a child of the unconfirmed transaction of the actual wallet whose id is shown in hex
+    /// so both pay the fee rate (CPFP), returns the parent and the child with the fee it pays
+    /// # Errors
+    /// Returns an error if there are no wallets, the id is invalid or the child can't be created
+    pub fn create_child(
+        &mut self,
+        txid: &str,
+        fee_rate: i32,
+    ) -> Result<(PendingTransaction, (TXMessage, i64)), Box<dyn Error>> {
+        let txid = txid_from_hex(txid.trim())?;
+        let wallet = match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet,
+            None => return Err("There are no wallets".into()),
+        };
+        let child = wallet.create_child(txid, Self::order_fee_rate(fee_rate))?;
+        match wallet.get_pending_transaction(txid) {
+            Some(parent) => Ok((parent.clone(), child)),
+            None => Err("The transaction isn't an unconfirmed transaction of the wallet".into()),
+        }
+    }
+
+    /// Fee rate of a transaction order, negative rates are treated as 0
+    fn order_fee_rate(fee_rate: i32) -> u64 {
+        fee_rate.max(0) as u64
+    }
+
+    /// Creates the transaction of the actual wallet without signing it, for watch only wallets
+    /// whose keys are elsewhere. Order of the tuple: (address, label, amount, fee rate)
+    /// # Errors
+    /// Returns an error if there are no wallets or the transaction can't be built
+    pub fn create_unsigned_transaction(
+        &mut self,
+        order: (String, String, i32, i32),
+    ) -> Result<TXMessage, Box<dyn Error>> {
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => {
+                wallet.create_unsigned_transaction(&order.0, order.2, Self::order_fee_rate(order.3))
+            }
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Creates the PSBT of a transaction of the actual wallet, encoded in base64.
+    /// `previous_transactions` are the known transactions by their hex id
+    /// # Errors
+    /// Returns an error if there are no wallets or the transaction can't be created
+    pub fn create_psbt(
+        &mut self,
+        order: (String, String, i32, i32),
+        previous_transactions: &HashMap<String, TXMessage>,
+    ) -> Result<String, Box<dyn Error>> {
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => {
+                let fee_rate = Self::order_fee_rate(order.3);
+                let psbt =
+                    wallet.create_psbt(&order.0, order.2, fee_rate, previous_transactions)?;
+                Ok(psbt.to_base64())
+            }
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Signs a base64 PSBT with every wallet that can sign, returns it with the signatures added
+    /// # Errors
+    /// Returns an error if the PSBT is invalid or no wallet can sign it
+    pub fn sign_psbt(&self, encoded: &str) -> Result<String, Box<dyn Error>> {
+        let mut psbt = Psbt::from_base64(encoded)?;
+        let mut signatures = 0;
+        for wallet in self.wallets.iter().filter(|wallet| !wallet.is_watch_only()) {
+            if let Ok(added) = wallet.sign_psbt(&mut psbt) {
+                signatures += added;
+            }
+        }
+        if signatures == 0 {
+            return Err("No wallet can sign the PSBT".into());
+        }
+        Ok(psbt.to_base64())
+    }
+
+    /// Combines base64 PSBTs of the same transaction separated by whitespace
+    /// # Errors
+    /// Returns an error if a PSBT is invalid or they are of different transactions
+    pub fn combine_psbts(encoded: &str) -> Result<String, Box<dyn Error>> {
+        let mut psbts = encoded.split_whitespace().map(Psbt::from_base64);
+        let mut combined = match psbts.next() {
+            Some(psbt) => psbt?,
+            None => return Err("There are no PSBTs to combine".into()),
+        };
+        for psbt in psbts {
+            combined.combine(&psbt?)?;
+        }
+        Ok(combined.to_base64())
+    }
+
+    /// Finalizes a base64 PSBT, returns the signed transaction and its fee
+    /// # Errors
+    /// Returns an error if the PSBT is invalid or not fully signed
+    pub fn finalize_psbt(encoded: &str) -> Result<(TXMessage, i64), Box<dyn Error>> {
+        let mut psbt = Psbt::from_base64(encoded)?;
+        let transaction = psbt.finalize()?;
+        Ok((transaction, psbt.fee().unwrap_or(0)))
+    }
+
+    /// Returns true if the actual wallet can't sign
+    pub fn is_watch_only(&self) -> bool {
+        match self.wallets.get(self.actual_wallet) {
+            Some(wallet) => wallet.is_watch_only(),
+            None => false,
+        }
+    }
+
+    /// Issues a fresh receive address of the given type for the actual wallet
+    /// # Errors
+    /// Returns an error if there are no wallets or the actual one can't show the address
+    pub fn new_receive_address(
+        &mut self,
+        address_type: AddressType,
+    ) -> Result<String, Box<dyn Error>> {
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet.new_receive_address(address_type),
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Adds to each wallet the utxos paying any of its watched addresses. HD wallets first
+    /// mark the addresses with utxos as used, moving their lookahead window until no new one is found
     pub fn add_utxo_to_wallets(&mut self, tx_collector: &UtxoCollector) {
-        // Add utxos to each of the wallets
         for wallet in self.wallets.iter_mut() {
-            println!("aca entra");
-            let addr = wallet.keys_handler.get_address().clone();
+            wallet.discover_addresses(|address| tx_collector.utxos.contains_key(address));
+            wallet.discover_scripts(|script| tx_collector.has_script(script));
+            if let Some(utxos) = tx_collector.utxos_for_scripts(&wallet.get_watched_scripts()) {
+                wallet.update_utxos(&utxos);
+            }
+        }
+    }
+
+    /// Returns the utxos of the actual wallet and whether they are selected or frozen
+    pub fn get_utxo_rows(&self) -> Vec<UtxoRow> {
+        let wallet = match self.wallets.get(self.actual_wallet) {
+            Some(wallet) => wallet,
+            None => return vec![],
+        };
+        let coin_control = wallet.get_coin_control();
+        wallet
+            .get_utxos()
+            .iter()
+            .map(|(outpoint, output)| {
+                (
+                    outpoint.to_string(),
+                    bitcoin_address_in_b58_output(&output.get_script()),
+                    output.value,
+                    coin_control.is_selected(outpoint),
+                    coin_control.is_frozen(outpoint),
+                )
+            })
+            .collect()
+    }
+
+    /// Replaces the utxos of the actual wallet that must be spent and those that can't be,
+    /// given as txid:index
+    /// # Errors
+    /// Returns an error if there are no wallets or an outpoint isn't a utxo of the wallet
+    pub fn set_coin_control(
+        &mut self,
+        selected: &[String],
+        frozen: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        let parse = |outpoints: &[String]| -> Result<Vec<Outpoint>, Box<dyn Error>> {
+            outpoints.iter().map(|text| Outpoint::parse(text)).collect()
+        };
+        let (selected, frozen) = (parse(selected)?, parse(frozen)?);
+        let wallet = match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet,
+            None => return Err("There are no wallets".into()),
+        };
+        // Nothing changes if any of them is wrong
+        let utxos = wallet.get_utxos();
+        if let Some(unknown) = selected
+            .iter()
+            .chain(frozen.iter())
+            .find(|outpoint| !utxos.iter().any(|(utxo, _)| utxo == *outpoint))
+        {
+            return Err(format!("{} isn't a utxo of the wallet", unknown).into());
+        }
+        wallet.clear_coin_control();
+        for outpoint in selected {
+            wallet.select_utxo(outpoint)?;
+        }
+        for outpoint in frozen {
+            wallet.freeze_utxo(outpoint)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the output descriptors of the actual wallet, empty if there's none
+    pub fn export_descriptors(&self) -> Vec<String> {
+        match self.get_actual_wallet() {
+            Some(wallet) => wallet.export_descriptors(),
+            None => vec![],
+        }
+    }
 
-            let utxos = match tx_collector.utxos.get(&addr) {
-                Some(utxos) => utxos,
+    /// Returns the private keys of the actual wallet in WIF for the network
+    /// # Errors
+    /// Returns an error if there are no wallets or the actual one is watch-only or locked
+    pub fn export_wif(&self, network: Network) -> Result<Vec<String>, Box<dyn Error>> {
+        match self.wallets.get(self.actual_wallet) {
+            Some(wallet) => wallet.export_wif(network),
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Signs the message with the key of an address of the actual wallet
+    /// # Errors
+    /// Returns an error if there are no wallets or the actual one can't sign for the address
+    pub fn sign_message(&self, address: &str, message: &str) -> Result<String, Box<dyn Error>> {
+        match self.wallets.get(self.actual_wallet) {
+            Some(wallet) => wallet.sign_message(address.trim(), message),
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Builds and signs a transaction moving every utxo of a foreign private key, in WIF
+    /// or hex, to the receive address of the actual wallet. Its utxos are looked for in the
+    /// stored blocks, serialized by the hash of their header. Returns it with the fee it pays
+    /// # Errors
+    /// Returns an error if there are no wallets, the key is invalid or its utxos can't pay the fee
+    pub fn sweep(
+        &self,
+        private_key: &str,
+        blocks: &HashMap<[u8; 32], Vec<u8>>,
+        fee_rate: i32,
+    ) -> Result<(TXMessage, i64), Box<dyn Error>> {
+        let wallet = match self.wallets.get(self.actual_wallet) {
+            Some(wallet) => wallet,
+            None => return Err("There are no wallets".into()),
+        };
+        let keys_handler = match KeysHandler::new(private_key.trim()) {
+            Some(keys_handler) => keys_handler,
+            None => return Err("Invalid private key, it must be in WIF or hex".into()),
+        };
+        let scripts: Vec<Vec<u8>> = [
+            AddressType::Legacy,
+            AddressType::SegWit,
+            AddressType::Taproot,
+        ]
+        .into_iter()
+        .map(|address_type| keys_handler.script_of(address_type))
+        .filter(|script| !script.is_empty())
+        .collect();
+        let utxos = Self::utxos_in_blocks(blocks, &scripts)?;
+        let recipient_script = script_for_address(&wallet.get_address())?;
+        P2PKH::create_sweep(
+            &keys_handler,
+            &utxos,
+            &recipient_script,
+            Self::order_fee_rate(fee_rate),
+        )
+    }
+
+    /// Adds a relayed sweep to the history of the actual wallet, labeled as such
+    /// # Errors
+    /// Returns an error if there are no wallets or the sweep doesn't pay to it
+    pub fn record_sweep(&mut self, transaction: &TXMessage) -> Result<(), Box<dyn Error>> {
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => {
+                wallet.record_transaction(transaction, None);
+                wallet.set_label(transaction.get_id(), "Sweep")
+            }
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Returns the outputs of the stored blocks paying to the scripts that no stored block spends
+    /// # Errors
+    /// Returns an error if a stored block can't be read
+    fn utxos_in_blocks(
+        blocks: &HashMap<[u8; 32], Vec<u8>>,
+        scripts: &[Vec<u8>],
+    ) -> Result<Vec<(Outpoint, Output)>, Box<dyn Error>> {
+        let mut outputs = vec![];
+        let mut spent = HashSet::new();
+        for serialized in blocks.values() {
+            let block = BlockMessage::deserialize(&mut serialized.clone())?;
+            for transaction in block.get_tx() {
+                for input in transaction.get_input() {
+                    let outpoint = input.get_outpoint();
+                    spent.insert((outpoint.get_hash(), outpoint.get_index()));
+                }
+                for (index, output) in transaction.get_output().into_iter().enumerate() {
+                    if scripts.contains(&output.get_script()) {
+                        outputs.push(((transaction.get_id(), index as u32), output));
+                    }
+                }
+            }
+        }
+        // Blocks aren't kept in order, so outputs are only known unspent once all are read
+        Ok(outputs
+            .into_iter()
+            .filter(|(outpoint, _)| !spent.contains(outpoint))
+            .map(|((txid, index), output)| (Outpoint::new(txid, index), output))
+            .collect())
+    }
+
+    /// Adds the transactions of a block paying to or spending from each wallet to its history,
+    /// after unconfirming those of blocks it reorganizes out.
+    /// Must be called before the utxos of the wallets are updated with the block
+    pub fn record_block(&mut self, transactions: &[TXMessage], block: BlockPosition) {
+        for wallet in self.wallets.iter_mut() {
+            wallet.record_block(transactions, block);
+        }
+    }
+
+    /// Adds an unconfirmed transaction to the history of the wallets it pays to or spends from
+    pub fn record_transaction(&mut self, transaction: &TXMessage) {
+        for wallet in self.wallets.iter_mut() {
+            wallet.record_transaction(transaction, None);
+        }
+    }
+
+    /// Sets the height of the last block, confirmations are counted up to it
+    pub fn set_tip(&mut self, height: u32) {
+        self.tip = height;
+    }
+
+    /// Returns the history of the actual wallet, empty if there's none
+    pub fn get_history_rows(&self) -> Vec<HistoryRow> {
+        let wallet = match self.wallets.get(self.actual_wallet) {
+            Some(wallet) => wallet,
+            None => return vec![],
+        };
+        wallet
+            .get_history()
+            .get_entries()
+            .iter()
+            .map(|entry| {
+                (
+                    txid_to_hex(&entry.txid),
+                    entry.amount,
+                    entry.fee,
+                    entry.height(),
+                    entry.confirmations(self.tip),
+                    entry.timestamp,
+                    entry.label.clone(),
+                )
+            })
+            .collect()
+    }
+
+    /// Sets the label of a transaction of the actual wallet whose id is shown in hex
+    /// # Errors
+    /// Returns an error if there are no wallets, the id is invalid or isn't in the history
+    pub fn set_label(&mut self, txid: &str, label: &str) -> Result<(), Box<dyn Error>> {
+        let txid = txid_from_hex(txid.trim())?;
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet.set_label(txid, label.trim()),
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Returns the history of the actual wallet in CSV
+    /// # Errors
+    /// Returns an error if there are no wallets
+    pub fn history_csv(&self) -> Result<String, Box<dyn Error>> {
+        match self.wallets.get(self.actual_wallet) {
+            Some(wallet) => Ok(wallet.get_history().to_csv(self.tip)),
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Returns the lines of the history file, each history line after the id of its wallet
+    pub fn get_history_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for wallet in self.wallets.iter() {
+            for line in wallet.get_history().get_file_lines() {
+                lines.push(format!("{},{}", wallet.get_id(), line));
+            }
+        }
+        lines
+    }
+
+    /// Adds the entries of the history file to their wallets, lines of unknown wallets are skipped
+    pub fn open_history(&mut self, lines: &[String]) {
+        for line in lines {
+            let (id, entry) = match line.split_once(',') {
+                Some((id, entry)) => (id.parse::<usize>().ok(), entry),
                 None => continue,
             };
+            if let Some(wallet) = self.wallets.iter_mut().find(|w| Some(w.get_id()) == id) {
+                wallet.open_history_line(entry);
+            }
+        }
+    }
 
-            wallet.update_utxos(utxos);
+    /// Returns the lines of the pending file, each unconfirmed transaction after the id of its wallet
+    pub fn get_pending_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for wallet in self.wallets.iter() {
+            for line in wallet.get_pending_lines() {
+                lines.push(format!("{},{}", wallet.get_id(), line));
+            }
         }
+        lines
+    }
+
+    /// Adds the transactions of the pending file to their wallets, lines of unknown wallets are skipped
+    pub fn open_pending(&mut self, lines: &[String]) {
+        for line in lines {
+            let (id, pending) = match line.split_once(',') {
+                Some((id, pending)) => (id.parse::<usize>().ok(), pending),
+                None => continue,
+            };
+            if let Some(wallet) = self.wallets.iter_mut().find(|w| Some(w.get_id()) == id) {
+                wallet.open_pending_line(pending);
+            }
+        }
+    }
+
+    /// Creates a payment request of the actual wallet, returns its BIP21 URI
+    /// # Errors
+    /// Returns an error if there are no wallets or the request is invalid
+    pub fn new_payment_request(
+        &mut self,
+        (amount, label, message): (Option<i64>, String, String),
+    ) -> Result<String, Box<dyn Error>> {
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet.new_payment_request(amount, label.trim(), message.trim()),
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Returns the payment requests of the actual wallet, the newest first
+    pub fn get_payment_request_rows(&self) -> Vec<PaymentRequestRow> {
+        let wallet = match self.wallets.get(self.actual_wallet) {
+            Some(wallet) => wallet,
+            None => return vec![],
+        };
+        wallet
+            .get_payment_requests()
+            .iter()
+            .rev()
+            .map(|request| {
+                (
+                    request.uri.address.clone(),
+                    request.uri.amount,
+                    request.uri.label.clone(),
+                    request.created,
+                    request.is_paid(),
+                    request.uri.to_uri(),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the lines of the payment requests file, each request after the id of its wallet
+    pub fn get_request_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for wallet in self.wallets.iter() {
+            for request in wallet.get_payment_requests() {
+                lines.push(format!("{},{}", wallet.get_id(), request.to_line()));
+            }
+        }
+        lines
+    }
+
+    /// Adds the requests of the payment requests file to their wallets, lines of unknown
+    /// wallets are skipped
+    pub fn open_payment_requests(&mut self, lines: &[String]) {
+        for line in lines {
+            let (id, request) = match line.split_once(',') {
+                Some((id, request)) => (id.parse::<usize>().ok(), request),
+                None => continue,
+            };
+            if let Some(wallet) = self.wallets.iter_mut().find(|w| Some(w.get_id()) == id) {
+                wallet.open_request_line(request);
+            }
+        }
+    }
+
+    /// Returns the time blocks are downloaded from, the birthday of the oldest wallet
+    /// unless all of them are younger than the first block downloaded before wallets had one
+    pub fn download_start(&self) -> u32 {
+        self.wallets
+            .iter()
+            .map(Wallet::get_birthday)
+            .fold(DEFAULT_BIRTHDAY, u32::min)
+    }
+
+    /// Sets the birthday of the actual wallet
+    /// # Errors
+    /// Returns an error if there are no wallets
+    pub fn set_birthday(&mut self, birthday: u32) -> Result<(), Box<dyn Error>> {
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => {
+                wallet.set_birthday(birthday);
+                Ok(())
+            }
+            None => Err("There are no wallets".into()),
+        }
+    }
+
+    /// Adds the transactions of a block to the history of the actual wallet again,
+    /// returns the amount of them paying to or spending from it
+    pub fn rescan_block(
+        &mut self,
+        transactions: &[TXMessage],
+        block: BlockPosition,
+        received: &mut Vec<(Outpoint, Output)>,
+    ) -> usize {
+        match self.wallets.get_mut(self.actual_wallet) {
+            Some(wallet) => wallet.rescan_block(transactions, block, received),
+            None => 0,
+        }
+    }
+
+    /// Returns the lines of the birthdays file, the id of each wallet and its birthday
+    pub fn get_birthday_lines(&self) -> Vec<String> {
+        self.wallets
+            .iter()
+            .map(|wallet| format!("{},{}", wallet.get_id(), wallet.get_birthday()))
+            .collect()
+    }
+
+    /// Sets the birthdays saved in the birthdays file, lines of unknown wallets are skipped
+    pub fn open_birthdays(&mut self, lines: &[String]) {
+        for line in lines {
+            let (id, birthday) = match line.split_once(',') {
+                Some((id, birthday)) => (id.parse::<usize>().ok(), birthday.parse::<u32>()),
+                None => continue,
+            };
+            if let (Some(wallet), Ok(birthday)) = (
+                self.wallets.iter_mut().find(|w| Some(w.get_id()) == id),
+                birthday,
+            ) {
+                wallet.set_birthday(birthday);
+            }
+        }
+    }
+
+    /// Returns the index of the wallet watching the address, if any
+    pub fn wallet_of_address(&self, address: &str) -> Option<usize> {
+        self.wallets
+            .iter()
+            .position(|wallet| wallet.is_mine(address))
     }
 }
 
@@ -166,6 +1139,50 @@ impl WalletHandler {
 
 mod wallet_handler_tests {
     use super::*;
+    use crate::message_structs::{compact_size::CompactSize, input::Input};
+    use crate::utils::array_tools::{reverse_array, u8_array_to_hex_string};
+
+    fn utxo(value: i64, address: &str) -> (Outpoint, Output) {
+        let script = script_for_address(address).unwrap();
+        let output = Output::new(
+            value,
+            CompactSize::from_usize_to_compact_size(script.len()),
+            script,
+        );
+        (Outpoint::new(funding(&output).get_id(), 0), output)
+    }
+
+    /// Transaction creating the output of a `utxo`
+    fn funding(output: &Output) -> TXMessage {
+        // An input keeps it from being read back as a segwit transaction
+        let input = Input::new(
+            Outpoint::new([0; 32], u32::MAX),
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            u32::MAX,
+        );
+        TXMessage::new(
+            2,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![input],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![output.clone()],
+            0,
+        )
+    }
+
+    /// The transactions creating the utxos, by their hex id
+    fn known_transactions(utxos: &[(Outpoint, Output)]) -> HashMap<String, TXMessage> {
+        utxos
+            .iter()
+            .map(|(outpoint, output)| {
+                (
+                    u8_array_to_hex_string(&reverse_array(&outpoint.get_hash())),
+                    funding(output),
+                )
+            })
+            .collect()
+    }
 
     #[test]
     fn test_wallet_handler_new() {
@@ -221,6 +1238,679 @@ mod wallet_handler_tests {
         assert_eq!(wallet_handler.wallets.len(), 1);
     }
 
+    #[test]
+    fn test_wallet_handler_encryption() {
+        let timeout = Duration::from_secs(60);
+        let mut wallet_handler = WalletHandler::new();
+        wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        ));
+        let encryption = WalletEncryption::new("passphrase", 4, timeout).unwrap();
+        wallet_handler.encrypt_wallets(encryption).unwrap();
+        assert!(wallet_handler.is_locked());
+        assert!(wallet_handler.wallets[0].is_locked());
+        assert!(!wallet_handler.new_wallet((
+            "other".to_string(),
+            "18e14a7b6a307f426a94f8114701e7c8e774e7f9a47e2c2035db29a206321725".to_string()
+        )));
+
+        let lines = wallet_handler.get_file_lines().unwrap();
+        assert!(lines[0].starts_with("encrypted,"));
+        assert_eq!(lines[1], "sealed");
+        assert!(!lines[2].contains("test_name"));
+
+        assert!(wallet_handler.unlock("wrong", timeout).is_err());
+        wallet_handler.unlock("passphrase", timeout).unwrap();
+        assert!(!wallet_handler.wallets[0].is_locked());
+
+        let new_encryption = WalletEncryption::new("new passphrase", 4, timeout).unwrap();
+        assert!(wallet_handler
+            .change_passphrase("wrong", new_encryption)
+            .is_err());
+        let new_encryption = WalletEncryption::new("new passphrase", 4, timeout).unwrap();
+        wallet_handler
+            .change_passphrase("passphrase", new_encryption)
+            .unwrap();
+        assert!(wallet_handler.is_locked());
+        assert!(wallet_handler.unlock("passphrase", timeout).is_err());
+        wallet_handler.unlock("new passphrase", timeout).unwrap();
+        assert!(!wallet_handler.wallets[0].is_locked());
+    }
+
+    #[test]
+    fn test_sealed_wallets_file() {
+        let timeout = Duration::from_secs(60);
+        let mut wallet_handler = WalletHandler::new();
+        wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        ));
+        let encryption = WalletEncryption::new("passphrase", 4, timeout).unwrap();
+        wallet_handler.encrypt_wallets(encryption).unwrap();
+        let lines = wallet_handler.get_file_lines().unwrap();
+        let history = wallet_handler
+            .seal(vec!["0,history line".to_string()])
+            .unwrap();
+        assert!(!history[1].contains("history"));
+
+        let encryption = WalletEncryption::from_header(&lines[0]).unwrap();
+        let mut opened = WalletHandler::open_sealed(encryption);
+        assert!(opened.is_sealed());
+        assert!(opened.unseal(&history).is_err());
+        assert!(opened.open_sealed_wallets(&lines[1..]).is_err());
+        assert!(!opened.new_wallet((
+            "other".to_string(),
+            "18e14a7b6a307f426a94f8114701e7c8e774e7f9a47e2c2035db29a206321725".to_string()
+        )));
+
+        opened.unlock("passphrase", timeout).unwrap();
+        opened.open_sealed_wallets(&lines[1..]).unwrap();
+        assert!(!opened.is_sealed());
+        assert_eq!(opened.get_all_names(), vec!["test_name".to_string()]);
+        assert!(!opened.wallets[0].is_locked());
+        assert_eq!(
+            opened.unseal(&history).unwrap(),
+            vec!["0,history line".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_wallet_handler_new_hd_wallet() {
+        let mut wallet_handler = WalletHandler::new();
+        assert!(!wallet_handler.new_hd_wallet("hd".to_string(), &[1u8; 8]));
+        assert!(wallet_handler.new_hd_wallet("hd".to_string(), &[1u8; 32]));
+        assert!(!wallet_handler.new_hd_wallet("hd".to_string(), &[2u8; 32]));
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        let fresh = wallet_handler
+            .new_receive_address(AddressType::Legacy)
+            .unwrap();
+        assert_ne!(address, fresh);
+        assert_eq!(
+            wallet_handler.actual_wallet_get_data().get("address_label"),
+            Some(&fresh)
+        );
+        let segwit = wallet_handler
+            .new_receive_address(AddressType::SegWit)
+            .unwrap();
+        assert!(segwit.starts_with("tb1q"));
+        assert_eq!(
+            wallet_handler.actual_wallet_get_data().get("address_label"),
+            Some(&segwit)
+        );
+        let taproot = wallet_handler
+            .new_receive_address(AddressType::Taproot)
+            .unwrap();
+        assert!(taproot.starts_with("tb1p"));
+
+        let xprv = HdAccount::from_seed(&[3u8; 32]).unwrap().get_xprv();
+        assert!(wallet_handler.new_wallet(("from xprv".to_string(), xprv)));
+        assert!(wallet_handler.wallets[1].is_hd());
+    }
+
+    #[test]
+    fn test_wallet_handler_mnemonic_wallets() {
+        let mut wallet_handler = WalletHandler::new();
+        let mnemonic = wallet_handler
+            .new_mnemonic_wallet("hd".to_string(), 12, "")
+            .unwrap();
+        assert_eq!(mnemonic.word_count(), 12);
+        assert!(wallet_handler
+            .restore_wallet("hd".to_string(), &mnemonic.phrase(), "")
+            .is_err());
+        assert!(wallet_handler
+            .restore_wallet("restored".to_string(), "abandon about", "")
+            .is_err());
+        wallet_handler
+            .restore_wallet("restored".to_string(), &mnemonic.phrase(), "")
+            .unwrap();
+        assert_eq!(
+            wallet_handler.wallets[0].get_address(),
+            wallet_handler.wallets[1].get_address()
+        );
+
+        // A payment to the second receive address is found by the rescan
+        let second = wallet_handler
+            .new_receive_address(AddressType::Legacy)
+            .unwrap();
+        let mut tx_collector = UtxoCollector::new();
+        tx_collector.utxos.insert(
+            second.clone(),
+            vec![(
+                Outpoint::new([1; 32], 0),
+                Output::new(50, CompactSize::from_usize_to_compact_size(0), vec![]),
+            )],
+        );
+        let mut restored = WalletHandler::new();
+        restored
+            .restore_wallet("restored".to_string(), &mnemonic.phrase(), "")
+            .unwrap();
+        restored.add_utxo_to_wallets(&tx_collector);
+        assert!(restored.wallets[0].get_addresses().contains(&second));
+        assert_eq!(restored.get_actual_balance(), 50);
+        assert_eq!(restored.wallet_of_address(&second), Some(0));
+        // The used address isn't shown anymore
+        assert_ne!(restored.wallets[0].get_address(), second);
+    }
+
+    #[test]
+    fn test_wallet_handler_descriptor_wallet() {
+        let mut wallet_handler = WalletHandler::new();
+        let xpub = HdAccount::from_seed(&[1u8; 32]).unwrap().get_xpub();
+        let descriptors = format!("wpkh({}/0/*) wpkh({}/1/*)", xpub, xpub);
+        assert!(wallet_handler.new_wallet(("watch".to_string(), descriptors)));
+        assert!(!wallet_handler.new_wallet(("bad".to_string(), "wpkh(xyz)".to_string())));
+        let wallet = wallet_handler.get_actual_wallet().unwrap();
+        assert!(wallet.is_watch_only());
+        assert_eq!(
+            wallet_handler.export_descriptors(),
+            wallet.export_descriptors()
+        );
+
+        // Payments to the descriptor scripts are matched and counted
+        let address = wallet.get_address();
+        assert!(address.starts_with("tb1q"));
+        let mut tx_collector = UtxoCollector::new();
+        tx_collector
+            .utxos
+            .insert(address.clone(), vec![utxo(70, &address)]);
+        wallet_handler.add_utxo_to_wallets(&tx_collector);
+        assert_eq!(wallet_handler.get_actual_balance(), 70);
+        assert_ne!(
+            wallet_handler.get_actual_wallet().unwrap().get_address(),
+            address
+        );
+        assert!(wallet_handler
+            .create_transaction((
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                String::new(),
+                10,
+                1
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_wallet_handler_coin_control() {
+        let mut wallet_handler = WalletHandler::new();
+        assert!(wallet_handler.set_coin_control(&[], &[]).is_err());
+        assert!(wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        let (second_outpoint, second_output) = utxo(7000, &address);
+        let second = (
+            Outpoint::new([2; 32], second_outpoint.get_index()),
+            second_output,
+        );
+        let first = utxo(9000, &address);
+        let first_outpoint = first.0.to_string();
+        let mut tx_collector = UtxoCollector::new();
+        tx_collector
+            .utxos
+            .insert(address.clone(), vec![first, second]);
+        wallet_handler.add_utxo_to_wallets(&tx_collector);
+
+        let rows = wallet_handler.get_utxo_rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, first_outpoint);
+        assert_eq!(rows[0].1, address);
+        assert_eq!(rows[0].2, 9000);
+        assert!(!rows[0].3 && !rows[0].4);
+
+        let (first, second) = (rows[0].0.clone(), rows[1].0.clone());
+        wallet_handler
+            .set_coin_control(std::slice::from_ref(&second), std::slice::from_ref(&first))
+            .unwrap();
+        let rows = wallet_handler.get_utxo_rows();
+        assert!(!rows[0].3 && rows[0].4);
+        assert!(rows[1].3 && !rows[1].4);
+        assert!(wallet_handler
+            .set_coin_control(&[], &["nope:0".to_string()])
+            .is_err());
+        assert!(wallet_handler
+            .set_coin_control(&[], &[first_outpoint.replace(":0", ":1")])
+            .is_err());
+        assert!(wallet_handler.get_utxo_rows()[1].3);
+
+        // Only the selected utxo is spent
+        let (transaction, _) = wallet_handler
+            .create_transaction((
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                String::new(),
+                5000,
+                1,
+            ))
+            .unwrap();
+        assert_eq!(transaction.input_list.len(), 1);
+        assert_eq!(wallet_handler.get_utxo_rows()[0].0, first);
+    }
+
+    #[test]
+    fn test_wallet_handler_batch_from_csv() {
+        use crate::node::wallets::payments::parse_payments_csv;
+
+        let mut wallet_handler = WalletHandler::new();
+        let csv = "address,amount,label\n\
+            mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun,5000,first\n\
+            mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc,6000,second\n";
+        let payments = parse_payments_csv(csv).unwrap();
+        assert!(wallet_handler
+            .create_batch_transaction(&payments, 1)
+            .is_err());
+        assert!(wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        let mut tx_collector = UtxoCollector::new();
+        let utxos = vec![utxo(20_000, &address)];
+        tx_collector.utxos.insert(address.clone(), utxos.clone());
+        wallet_handler.add_utxo_to_wallets(&tx_collector);
+
+        let transactions = known_transactions(&utxos);
+        let psbt = wallet_handler.create_batch_psbt(&payments, 1, &transactions);
+        let psbt = Psbt::from_base64(&psbt.unwrap());
+        assert_eq!(psbt.unwrap().get_unsigned_tx().get_output().len(), 3);
+        let (transaction, fee) = wallet_handler
+            .create_batch_transaction(&payments, 1)
+            .unwrap();
+        let amounts = transaction.get_output_amounts();
+        assert_eq!(amounts[..2], [5000, 6000]);
+        assert_eq!(amounts.iter().sum::<i64>() + fee, 20_000);
+    }
+
+    #[test]
+    fn test_wallet_handler_history() {
+        let mut wallet_handler = WalletHandler::new();
+        assert!(wallet_handler.history_csv().is_err());
+        assert!(wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        let mut tx_collector = UtxoCollector::new();
+        tx_collector
+            .utxos
+            .insert(address.clone(), vec![utxo(20_000, &address)]);
+        wallet_handler.add_utxo_to_wallets(&tx_collector);
+
+        let order = (
+            "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+            "rent".to_string(),
+            5_000,
+            1,
+        );
+        let (transaction, fee) = wallet_handler.create_transaction(order).unwrap();
+        let txid = txid_to_hex(&transaction.get_id());
+        wallet_handler.record_block(
+            std::slice::from_ref(&transaction),
+            ([3; 32], 10, 1_700_000_000),
+        );
+        wallet_handler.set_tip(12);
+        assert_eq!(
+            wallet_handler.get_history_rows(),
+            vec![(
+                txid.clone(),
+                -(5_000 + fee),
+                Some(fee),
+                Some(10),
+                3,
+                1_700_000_000,
+                "rent".to_string()
+            )]
+        );
+        wallet_handler.set_label(&txid, "May rent").unwrap();
+        assert!(wallet_handler.set_label("00", "May rent").is_err());
+        assert!(wallet_handler
+            .history_csv()
+            .unwrap()
+            .contains("\"May rent\""));
+
+        // The history file gives each wallet back its entries
+        let lines = wallet_handler.get_history_lines();
+        assert!(lines[0].starts_with("0,"));
+        let mut opened = WalletHandler::new();
+        assert!(opened.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        opened.open_history(&lines);
+        opened.set_tip(12);
+        assert_eq!(opened.get_history_rows(), wallet_handler.get_history_rows());
+    }
+
+    #[test]
+    fn test_wallet_handler_sweep_and_wif_export() {
+        use crate::message_structs::block_headers::BlockHeader;
+
+        let stored = |transactions: Vec<TXMessage>| {
+            BlockMessage::new(
+                BlockHeader::new(1, [0; 32], [0; 32], 0, 0, 0),
+                CompactSize::from_usize_to_compact_size(transactions.len()),
+                transactions,
+            )
+            .serialize()
+        };
+        let mut wallet_handler = WalletHandler::new();
+        let mut blocks = HashMap::new();
+        let foreign = "cMzLdeGd5vEqxB8B6VFQoRopQ3sLAAvEzDAoQgvX54xwofSWj1fx";
+        assert!(wallet_handler.export_wif(Network::Testnet).is_err());
+        assert!(wallet_handler.sweep(foreign, &blocks, 1).is_err());
+        assert!(wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        let exported = wallet_handler.export_wif(Network::Testnet).unwrap();
+        assert!(exported[0].ends_with(&wallet_handler.get_actual_wallet().unwrap().get_address()));
+
+        let foreign_address = KeysHandler::new(foreign).unwrap().get_segwit_address();
+        assert!(wallet_handler.sweep(foreign, &blocks, 1).is_err());
+        let (_, paid) = utxo(10_000, &foreign_address);
+        let (_, already_spent) = utxo(5_000, &foreign_address);
+        let spent_funding = funding(&already_spent);
+        let spending = TXMessage::new(
+            2,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Input::new(
+                Outpoint::new(reverse_array(&spent_funding.get_id()), 0),
+                CompactSize::from_usize_to_compact_size(0),
+                vec![],
+                u32::MAX,
+            )],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![Output::new(
+                4_000,
+                CompactSize::from_usize_to_compact_size(1),
+                vec![0x51],
+            )],
+            0,
+        );
+        blocks.insert([1; 32], stored(vec![funding(&paid), spent_funding]));
+        blocks.insert([2; 32], stored(vec![spending]));
+        assert!(wallet_handler.sweep("not a key", &blocks, 1).is_err());
+        let (transaction, fee) = wallet_handler.sweep(foreign, &blocks, 1).unwrap();
+        assert_eq!(transaction.get_input().len(), 1);
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        assert_eq!(
+            transaction.get_output()[0].get_script(),
+            script_for_address(&address).unwrap()
+        );
+        // It's only in the history once relayed
+        assert!(wallet_handler.get_history_rows().is_empty());
+        wallet_handler.record_sweep(&transaction).unwrap();
+        let rows = wallet_handler.get_history_rows();
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].1, rows[0].6.as_str()), (10_000 - fee, "Sweep"));
+    }
+
+    #[test]
+    fn test_wallet_handler_payment_requests() {
+        let mut wallet_handler = WalletHandler::new();
+        let request = (Some(25_000), "Invoice 7".to_string(), "Coffee".to_string());
+        assert!(wallet_handler.new_payment_request(request.clone()).is_err());
+        assert!(wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        assert!(wallet_handler
+            .new_payment_request((Some(0), String::new(), String::new()))
+            .is_err());
+        let too_much = Some(crate::node::wallets::payments::MAX_MONEY + 1);
+        assert!(wallet_handler
+            .new_payment_request((too_much, String::new(), String::new()))
+            .is_err());
+        let uri = wallet_handler.new_payment_request(request).unwrap();
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        assert_eq!(
+            uri,
+            format!(
+                "bitcoin:{}?amount=0.00025&label=Invoice%207&message=Coffee",
+                address
+            )
+        );
+
+        // The address of a single key wallet has one open request at a time
+        let other = (Some(1_000), String::new(), String::new());
+        assert!(wallet_handler.new_payment_request(other.clone()).is_err());
+
+        let (owned, output) = utxo(25_000, &address);
+        let mut tx_collector = UtxoCollector::new();
+        tx_collector
+            .utxos
+            .insert(address.clone(), vec![(owned, output.clone())]);
+        wallet_handler.add_utxo_to_wallets(&tx_collector);
+        let paying = |inputs: Vec<Input>| {
+            TXMessage::new(
+                2,
+                CompactSize::from_usize_to_compact_size(inputs.len()),
+                inputs,
+                CompactSize::from_usize_to_compact_size(1),
+                vec![output.clone()],
+                0,
+            )
+        };
+        // Neither the wallet paying itself nor unconfirmed payments settle it
+        let own = paying(vec![Input::new(
+            Outpoint::new(owned.get_hash(), 0),
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            u32::MAX,
+        )]);
+        wallet_handler.record_block(std::slice::from_ref(&own), ([4; 32], 11, 1_700_000_000));
+        let transaction = paying(vec![]);
+        wallet_handler.record_transaction(&transaction);
+        assert!(!wallet_handler.get_payment_request_rows()[0].4);
+        wallet_handler.record_block(
+            std::slice::from_ref(&transaction),
+            ([5; 32], 12, 1_700_000_600),
+        );
+        let rows = wallet_handler.get_payment_request_rows();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0],
+            (
+                address,
+                Some(25_000),
+                "Invoice 7".to_string(),
+                rows[0].3,
+                true,
+                uri
+            )
+        );
+
+        assert!(wallet_handler.new_payment_request(other).is_ok());
+
+        let lines = wallet_handler.get_request_lines();
+        let mut opened = WalletHandler::new();
+        assert!(opened.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        opened.open_payment_requests(&lines);
+        assert_eq!(
+            opened.get_payment_request_rows(),
+            wallet_handler.get_payment_request_rows()
+        );
+    }
+
+    #[test]
+    fn test_wallet_handler_birthdays_and_rescan() {
+        let mut wallet_handler = WalletHandler::new();
+        assert_eq!(wallet_handler.download_start(), DEFAULT_BIRTHDAY);
+        assert!(wallet_handler.set_birthday(1_600_000_000).is_err());
+        assert!(wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        wallet_handler
+            .new_mnemonic_wallet("new".to_string(), 12, "")
+            .unwrap();
+        // A new wallet doesn't move the download start forward
+        assert_eq!(wallet_handler.download_start(), DEFAULT_BIRTHDAY);
+        wallet_handler.switch_wallet("test_name".to_string());
+        wallet_handler.set_birthday(1_600_000_000).unwrap();
+        assert_eq!(wallet_handler.download_start(), 1_600_000_000);
+
+        let lines = wallet_handler.get_birthday_lines();
+        assert_eq!(lines[0], "0,1600000000");
+        let mut opened = WalletHandler::new();
+        assert!(opened.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        opened.open_birthdays(&lines);
+        assert_eq!(opened.download_start(), 1_600_000_000);
+
+        // A block paying to the wallet and a later one spending it
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        let (_, paid) = utxo(20_000, &address);
+        let funding = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            CompactSize::from_usize_to_compact_size(1),
+            vec![paid.clone()],
+            0,
+        );
+        // Block transactions keep the ids they spend in the byte order of the wire
+        let input = Input::new(
+            Outpoint::new(reverse_array(&funding.get_id()), 0),
+            CompactSize::from_usize_to_compact_size(0),
+            vec![],
+            0xffffffff,
+        );
+        let (_, change) = utxo(14_000, &address);
+        let (_, payment) = utxo(5_000, "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun");
+        let spending = TXMessage::new(
+            1,
+            CompactSize::from_usize_to_compact_size(1),
+            vec![input],
+            CompactSize::from_usize_to_compact_size(2),
+            vec![payment, change],
+            0,
+        );
+
+        let mut rescanned = WalletHandler::new();
+        assert!(rescanned.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string(),
+        )));
+        let mut received = vec![];
+        let found = rescanned.rescan_block(
+            std::slice::from_ref(&funding),
+            ([3; 32], 10, 1_700_000_000),
+            &mut received,
+        );
+        assert_eq!(found, 1);
+        let found = rescanned.rescan_block(
+            std::slice::from_ref(&spending),
+            ([4; 32], 11, 1_700_000_600),
+            &mut received,
+        );
+        assert_eq!(found, 1);
+        let rows = rescanned.get_history_rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].1, rows[0].3), (20_000, Some(10)));
+        assert_eq!((rows[1].1, rows[1].2), (-6_000, Some(1_000)));
+    }
+
+    #[test]
+    fn test_wallet_handler_watch_only_unsigned_transaction() {
+        let mut wallet_handler = WalletHandler::new();
+        let xpub = HdAccount::from_seed(&[1u8; 32]).unwrap().get_xpub();
+        assert!(wallet_handler.new_wallet(("cold".to_string(), xpub)));
+        assert!(wallet_handler.is_watch_only());
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        let mut tx_collector = UtxoCollector::new();
+        tx_collector
+            .utxos
+            .insert(address.clone(), vec![utxo(9000, &address)]);
+        wallet_handler.add_utxo_to_wallets(&tx_collector);
+        assert_eq!(wallet_handler.get_actual_balance(), 9000);
+
+        let order = (
+            "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+            String::new(),
+            5000,
+            10,
+        );
+        assert!(wallet_handler.create_transaction(order.clone()).is_err());
+        let transaction = wallet_handler.create_unsigned_transaction(order).unwrap();
+        // The fee is 227 vbytes at 10 sat/vB
+        assert_eq!(transaction.get_output_amounts(), vec![5000, 1730]);
+    }
+
+    #[test]
+    fn test_wallet_handler_psbt_flow() {
+        let mut wallet_handler = WalletHandler::new();
+        let xpub = HdAccount::from_seed(&[1u8; 32]).unwrap().get_xpub();
+        assert!(wallet_handler.new_wallet(("cold".to_string(), xpub)));
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        let mut tx_collector = UtxoCollector::new();
+        let utxos = vec![utxo(9000, &address)];
+        tx_collector.utxos.insert(address.clone(), utxos.clone());
+        wallet_handler.add_utxo_to_wallets(&tx_collector);
+        let order = (
+            "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+            String::new(),
+            5000,
+            10,
+        );
+        // The legacy input needs the transaction it spends
+        assert!(wallet_handler
+            .create_psbt(order.clone(), &HashMap::new())
+            .is_err());
+        let psbt = wallet_handler
+            .create_psbt(order, &known_transactions(&utxos))
+            .unwrap();
+
+        // Only the cold wallet is there, nothing can sign
+        assert!(wallet_handler.sign_psbt(&psbt).is_err());
+        assert!(WalletHandler::finalize_psbt(&psbt).is_err());
+
+        assert!(wallet_handler.new_hd_wallet("hot".to_string(), &[1u8; 32]));
+        let signed = wallet_handler.sign_psbt(&psbt).unwrap();
+        let combined = WalletHandler::combine_psbts(&format!("{}\n{}", psbt, signed)).unwrap();
+        assert!(WalletHandler::combine_psbts("").is_err());
+        let (transaction, fee) = WalletHandler::finalize_psbt(&combined).unwrap();
+        assert_eq!(fee, 2270);
+        assert_eq!(transaction.get_output_amounts(), vec![5000, 1730]);
+        assert!(!transaction.input_list[0].get_script().is_empty());
+    }
+
+    #[test]
+    fn test_wallet_handler_multisig_wallet() {
+        let mut wallet_handler = WalletHandler::new();
+        let xpub = HdAccount::from_seed(&[1u8; 32]).unwrap().get_xpub();
+        let key = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let keys = format!("{}, {}", xpub, key);
+        assert!(wallet_handler
+            .new_multisig_wallet("treasury".to_string(), 3, &keys, false)
+            .is_err());
+        wallet_handler
+            .new_multisig_wallet("treasury".to_string(), 2, &keys, false)
+            .unwrap();
+        assert!(wallet_handler
+            .new_multisig_wallet("treasury".to_string(), 2, &keys, false)
+            .is_err());
+        assert!(wallet_handler.is_watch_only());
+
+        // Its P2SH utxos are tracked like any other address
+        let address = wallet_handler.get_actual_wallet().unwrap().get_address();
+        assert!(address.starts_with('2'));
+        let mut tx_collector = UtxoCollector::new();
+        tx_collector
+            .utxos
+            .insert(address.clone(), vec![utxo(9000, &address)]);
+        wallet_handler.add_utxo_to_wallets(&tx_collector);
+        assert_eq!(wallet_handler.get_actual_balance(), 9000);
+        let descriptors = wallet_handler.export_descriptors();
+        assert_eq!(descriptors.len(), 2);
+        assert!(descriptors[0].starts_with("sh(sortedmulti(2,"));
+    }
+
     #[test]
     fn test_wallet_handler_get_all_names() {
         let mut wallet_handler = WalletHandler::new();
@@ -301,6 +1991,27 @@ mod wallet_handler_tests {
         assert_eq!(wallet_handler.actual_wallet_get_data(), hash);
     }
 
+    #[test]
+    fn test_actual_wallet_get_data_with_suggested_fees() {
+        let mut wallet_handler = WalletHandler::new();
+        assert!(wallet_handler.new_wallet((
+            "test_name".to_string(),
+            "5032554e9d661af4e3fe58ef485231358925d39996830dac9eace8cadfbea9cd".to_string()
+        )));
+        wallet_handler.set_suggested_fee_rates(vec![(2, Some(10)), (6, Some(5)), (12, None)]);
+
+        assert_eq!(
+            wallet_handler.get_suggested_fees(),
+            vec![(2, 2260), (6, 1130)]
+        );
+        assert_eq!(
+            wallet_handler
+                .actual_wallet_get_data()
+                .get("label_suggested_fee"),
+            Some(&"Suggested fee rate: 10 sat/vB (2 blocks) / 5 sat/vB (6 blocks)".to_string())
+        );
+    }
+
     #[test]
     fn test_wallet_handler_get_actual_balance() {
         let mut wallet_handler = WalletHandler::new();
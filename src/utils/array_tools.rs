@@ -106,6 +106,17 @@ pub fn u8_array_to_hex_string(array: &[u8; 32]) -> String {
     hex_string
 }
 
+/// Shows a transaction id in hex the way explorers and other wallets do,
+/// the reverse of its internal byte order
+pub fn txid_to_hex(txid: &[u8; 32]) -> String {
+    u8_array_to_hex_string(&reverse_array(txid))
+}
+
+/// Parses a transaction id shown by txid_to_hex
+pub fn txid_from_hex(text: &str) -> Result<[u8; 32], Box<dyn Error>> {
+    Ok(reverse_array(&cast_str_to_fixed_bytes(text)?))
+}
+
 pub fn u8_vec_to_hex_string(array: &[u8]) -> String {
     let mut hex_string = String::with_capacity(array.len() * 2);
     for &byte in array.iter() {
@@ -259,4 +270,14 @@ mod array_tools_tests {
 
         assert_eq!(binary, expected_binary);
     }
+
+    #[test]
+    fn test_txid_hex_is_reversed() {
+        // Coinbase of the genesis block
+        let internal = "3ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a";
+        let shown = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let txid = cast_str_to_fixed_bytes(internal).unwrap();
+        assert_eq!(txid_to_hex(&txid), shown);
+        assert_eq!(txid_from_hex(shown).unwrap(), txid);
+    }
 }
@@ -0,0 +1,94 @@
+use std::error::Error;
+
+/// Standard base64 alphabet (RFC 4648), used by PSBTs
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const PADDING: u8 = b'=';
+
+/// Encodes the bytes in base64 with padding
+pub fn encode_base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = (group[0] as u32) << 16 | (group[1] as u32) << 8 | group[2] as u32;
+        for position in 0..4 {
+            if position <= chunk.len() {
+                let index = (bits >> (18 - 6 * position)) & 0x3f;
+                encoded.push(ALPHABET[index as usize] as char);
+            } else {
+                encoded.push(PADDING as char);
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes padded base64, whitespace is ignored
+/// # Errors
+/// Returns an error if there are invalid characters or the length or padding are wrong
+pub fn decode_base64(encoded: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let symbols: Vec<u8> = encoded
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if !symbols.len().is_multiple_of(4) {
+        return Err("Invalid base64 length".into());
+    }
+    let mut decoded = Vec::with_capacity(symbols.len() / 4 * 3);
+    for (number, group) in symbols.chunks(4).enumerate() {
+        let padding = group
+            .iter()
+            .rev()
+            .take_while(|&&byte| byte == PADDING)
+            .count();
+        let is_last = number == symbols.len() / 4 - 1;
+        if padding > 2 || (padding > 0 && !is_last) {
+            return Err("Invalid base64 padding".into());
+        }
+        let mut bits = 0u32;
+        for &symbol in &group[..4 - padding] {
+            let value = match ALPHABET.iter().position(|&byte| byte == symbol) {
+                Some(value) => value as u32,
+                None => return Err("Invalid base64 character".into()),
+            };
+            bits = bits << 6 | value;
+        }
+        bits <<= 6 * padding as u32;
+        let bytes = bits.to_be_bytes();
+        decoded.extend_from_slice(&bytes[1..4 - padding]);
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod base64_tests {
+    use super::*;
+
+    // RFC 4648 test vectors
+    #[test]
+    fn test_base64_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (data, encoded) in vectors {
+            assert_eq!(encode_base64(data.as_bytes()), encoded);
+            assert_eq!(decode_base64(encoded).unwrap(), data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_invalid_base64() {
+        assert!(decode_base64("Zm9").is_err());
+        assert!(decode_base64("Zm=v").is_err());
+        assert!(decode_base64("Zg==Zm9v").is_err());
+        assert!(decode_base64("Zm9*").is_err());
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode_base64(&encode_base64(&data)).unwrap(), data);
+    }
+}
@@ -0,0 +1,255 @@
+use std::error::Error;
+
+/// Human readable part of the testnet segwit addresses
+pub const TESTNET_HRP: &str = "tb";
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+/// Checksum constants of bech32 (BIP173, witness v0) and bech32m (BIP350, witness v1+)
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+const CHECKSUM_LEN: usize = 6;
+const MAX_LEN: usize = 90;
+
+/// Encodes a segwit address of the given witness version and program,
+/// bech32 (BIP173) for version 0 and bech32m (BIP350) for the rest
+/// # Errors
+/// Returns an error if the version or the program length are invalid
+pub fn encode_segwit_address(
+    hrp: &str,
+    version: u8,
+    program: &[u8],
+) -> Result<String, Box<dyn Error>> {
+    check_program(version, program)?;
+    let mut data = vec![version];
+    data.extend(convert_bits(program, 8, 5, true)?);
+    Ok(encode(hrp, &data, checksum_const(version)))
+}
+
+/// Decodes a segwit address (BIP173/BIP350) into its witness version and program
+/// # Errors
+/// Returns an error if the address is malformed, the checksum is wrong or the hrp doesn't match
+pub fn decode_segwit_address(hrp: &str, address: &str) -> Result<(u8, Vec<u8>), Box<dyn Error>> {
+    let (address_hrp, data, checksum) = decode(address)?;
+    if address_hrp != hrp {
+        return Err("The address belongs to another network".into());
+    }
+    let (version, program_data) = match data.split_first() {
+        Some(split) => split,
+        None => return Err("Empty segwit address".into()),
+    };
+    if checksum != checksum_const(*version) {
+        return Err("Invalid checksum variant for the witness version".into());
+    }
+    let program = convert_bits(program_data, 5, 8, false)?;
+    check_program(*version, &program)?;
+    Ok((*version, program))
+}
+
+/// Returns true if the string looks like a segwit address of the hrp (it isn't validated)
+pub fn is_segwit_address(hrp: &str, address: &str) -> bool {
+    address.to_lowercase().starts_with(&format!("{}1", hrp))
+}
+
+fn checksum_const(version: u8) -> u32 {
+    match version {
+        0 => BECH32_CONST,
+        _ => BECH32M_CONST,
+    }
+}
+
+/// Checks the witness version and the program length
+fn check_program(version: u8, program: &[u8]) -> Result<(), Box<dyn Error>> {
+    if version > 16 {
+        return Err("Invalid witness version".into());
+    }
+    if program.len() < 2 || program.len() > 40 {
+        return Err("Invalid witness program length".into());
+    }
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return Err("Invalid witness v0 program length".into());
+    }
+    Ok(())
+}
+
+/// Encodes the 5 bits values with the hrp and the checksum
+fn encode(hrp: &str, data: &[u8], checksum: u32) -> String {
+    let mut values = data.to_vec();
+    values.extend(create_checksum(hrp, data, checksum));
+    let mut encoded = format!("{}1", hrp);
+    for value in values {
+        encoded.push(CHARSET[value as usize] as char);
+    }
+    encoded
+}
+
+/// Splits the string in its hrp and its 5 bits values, checking the checksum.
+/// Also returns the checksum constant matched, bech32 or bech32m
+fn decode(address: &str) -> Result<(String, Vec<u8>, u32), Box<dyn Error>> {
+    if address.len() > MAX_LEN {
+        return Err("The address is too long".into());
+    }
+    if address.to_lowercase() != address && address.to_uppercase() != address {
+        return Err("Mixed case address".into());
+    }
+    let address = address.to_lowercase();
+    let separator = match address.rfind('1') {
+        Some(separator) if separator > 0 && separator + CHECKSUM_LEN < address.len() => separator,
+        _ => return Err("Invalid separator position".into()),
+    };
+    let hrp = &address[..separator];
+    if hrp.bytes().any(|c| !(33..=126).contains(&c)) {
+        return Err("Invalid hrp character".into());
+    }
+    let mut data = vec![];
+    for c in address[separator + 1..].bytes() {
+        match CHARSET.iter().position(|x| *x == c) {
+            Some(value) => data.push(value as u8),
+            None => return Err("Invalid data character".into()),
+        }
+    }
+    let checksum = polymod(&[hrp_expand(hrp), data.clone()].concat());
+    if checksum != BECH32_CONST && checksum != BECH32M_CONST {
+        return Err("Invalid checksum".into());
+    }
+    data.truncate(data.len() - CHECKSUM_LEN);
+    Ok((hrp.to_string(), data, checksum))
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|c| c & 31));
+    expanded
+}
+
+fn create_checksum(hrp: &str, data: &[u8], checksum: u32) -> Vec<u8> {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; CHECKSUM_LEN]);
+    let polymod = polymod(&values) ^ checksum;
+    (0..CHECKSUM_LEN)
+        .map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8)
+        .collect()
+}
+
+/// Regroups the bits of the values from `from` bits to `to` bits per value
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut accumulator: u32 = 0;
+    let mut bits = 0;
+    let max_value = (1 << to) - 1;
+    let mut converted = vec![];
+    for value in data {
+        if (*value as u32) >> from != 0 {
+            return Err("Invalid value to convert".into());
+        }
+        accumulator = (accumulator << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((accumulator >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((accumulator << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || (accumulator << (to - bits)) & max_value != 0 {
+        return Err("Invalid padding".into());
+    }
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod bech32_tests {
+    use super::*;
+    use crate::utils::array_tools::cast_str_to_bytes_vec;
+
+    // BIP173 test vectors
+    #[test]
+    fn test_encode_and_decode_segwit_addresses() {
+        let vectors = [
+            (
+                "bc",
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+                "751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            (
+                "tb",
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                "1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+        ];
+        for (hrp, address, program) in vectors {
+            let program = cast_str_to_bytes_vec(program).unwrap();
+            assert_eq!(encode_segwit_address(hrp, 0, &program).unwrap(), address);
+            assert_eq!(
+                decode_segwit_address(hrp, &address.to_uppercase()).unwrap(),
+                (0, program)
+            );
+        }
+    }
+
+    // BIP350 test vectors
+    #[test]
+    fn test_encode_and_decode_bech32m_addresses() {
+        let vectors = [
+            (
+                "bc",
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+            (
+                "tb",
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                "000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+            ),
+        ];
+        for (hrp, address, program) in vectors {
+            let program = cast_str_to_bytes_vec(program).unwrap();
+            assert_eq!(encode_segwit_address(hrp, 1, &program).unwrap(), address);
+            assert_eq!(decode_segwit_address(hrp, address).unwrap(), (1, program));
+        }
+        // A v1 program with a bech32 checksum and a v0 one with a bech32m checksum are invalid
+        assert!(decode_segwit_address(
+            "bc",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd"
+        )
+        .is_err());
+        assert!(decode_segwit_address(
+            "tb",
+            "tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_decode_invalid_addresses() {
+        // Wrong checksum
+        assert!(decode_segwit_address("bc", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5").is_err());
+        // Wrong network
+        assert!(decode_segwit_address("tb", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").is_err());
+        // Mixed case
+        assert!(decode_segwit_address(
+            "tb",
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7"
+        )
+        .is_err());
+        // Invalid program length
+        assert!(decode_segwit_address("bc", "bc1rw5uspcuh").is_err());
+    }
+}
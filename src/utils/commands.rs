@@ -4,6 +4,7 @@ use crate::message_structs::bitcoin_message_header::BitcoinMessageHeader;
 use crate::message_structs::block_headers::BlockHeader;
 use crate::message_structs::block_message::BlockMessage;
 use crate::message_structs::block_txn::BlockTxn;
+use crate::message_structs::fee_filter::FeeFilter;
 use crate::message_structs::filter_load_message::FilterLoadMessage;
 use crate::message_structs::get_block_message::GetBlockMessage;
 use crate::message_structs::get_block_txn::GetBlockTxn;
@@ -42,6 +43,7 @@ pub enum MessageType {
     VersionMessage,
     Tx,
     NotFound,
+    FeeFilter,
     Unknown,
     End,
 }
@@ -134,6 +136,10 @@ pub fn get_type(command: &[u8; 12]) -> MessageType {
         //MEMPOOL
         
         [b'm', b'e', b'm', b'p', b'o', b'o', b'l', 0x00, 0x00, 0x00, 0x00, 0x00,]=>MessageType::Mempool,
+        // FEE_FILTER
+        [b'f', b'e', b'e', b'f', b'i', b'l', b't', b'e', b'r', 0x00, 0x00, 0x00] => {
+            MessageType::FeeFilter
+        }
         [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] => MessageType::End,
         _ => MessageType::Unknown,
     }
@@ -318,6 +324,14 @@ pub fn match_command(
 
             Ok(Messages::InvOrGetDataMessage(inv_or_get_data))
         }
+        MessageType::FeeFilter => {
+            let fee_filter = match FeeFilter::deserialize(payload) {
+                Ok(msg) => msg,
+                Err(_e) => return Err("Error deserializing FeeFilter".into()),
+            };
+
+            Ok(Messages::FeeFilter(fee_filter))
+        }
         MessageType::End => Ok(Messages::End),
 
         MessageType::Unknown => Ok(Messages::Unknown),
@@ -518,4 +532,25 @@ mod commands_tests {
 
         assert_eq!(msg_type, MessageType::GetHeadersMessage);
     }
+
+    #[test]
+    fn test_fee_filter() {
+        let command = [
+            b'f', b'e', b'e', b'f', b'i', b'l', b't', b'e', b'r', 0x00, 0x00, 0x00,
+        ];
+        let msg_type = super::get_type(&command);
+
+        assert_eq!(msg_type, MessageType::FeeFilter);
+    }
+
+    #[test]
+    fn test_match_fee_filter() {
+        let command = [
+            b'f', b'e', b'e', b'f', b'i', b'l', b't', b'e', b'r', 0x00, 0x00, 0x00,
+        ];
+        let mut payload = FeeFilter::new(1000).serialize();
+        let message = match_command(&command, &mut payload).unwrap();
+
+        assert_eq!(message, Messages::FeeFilter(FeeFilter::new(1000)));
+    }
 }
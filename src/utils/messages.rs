@@ -5,6 +5,7 @@ use crate::message_structs::block_headers::BlockHeader;
 use crate::message_structs::block_message::BlockMessage;
 use crate::message_structs::block_txn::BlockTxn;
 use crate::message_structs::compact_size::CompactSize;
+use crate::message_structs::fee_filter::FeeFilter;
 use crate::message_structs::filter_load_message::FilterLoadMessage;
 use crate::message_structs::get_block_message::GetBlockMessage;
 use crate::message_structs::get_block_txn::GetBlockTxn;
@@ -38,15 +39,16 @@ pub enum Messages {
     Tx(TXMessage),
     VersionMessage(VersionMessage),
     Mempool(BitcoinMessageHeader),
+    FeeFilter(FeeFilter),
     End,
     Unknown,
 }
 
 impl Messages {
-    pub fn create_get_data(&self) -> InvOrGetDataMessage {
+    pub fn create_get_data(&self, start: u32) -> InvOrGetDataMessage {
         let get_data;
         if let Messages::HeadersMessage(headers) = self {
-            get_data = headers.create_get_data();
+            get_data = headers.create_get_data(start);
         } else {
             get_data = InvOrGetDataMessage::new(
                 CompactSize {
@@ -132,6 +134,10 @@ impl Messages {
         Messages::Mempool(msg)
     }
 
+    pub fn fee_filter(msg: FeeFilter) -> Self {
+        Messages::FeeFilter(msg)
+    }
+
     pub fn unknown() -> Self {
         Messages::Unknown
     }
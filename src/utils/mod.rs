@@ -1,4 +1,6 @@
 pub mod array_tools;
+pub mod base64;
+pub mod bech32;
 pub mod build_messages;
 pub mod commands;
 pub mod configs;
@@ -1,5 +1,24 @@
+use crate::utils::bech32::{decode_segwit_address, encode_segwit_address, TESTNET_HRP};
 use bitcoin_hashes::{ripemd160, sha256, sha256d, Hash};
 use bs58;
+use std::error::Error;
+
+const OP_0: u8 = 0;
+const OP_DUP: u8 = 118;
+const OP_HASH160: u8 = 169;
+const OP_EQUALVERIFY: u8 = 136;
+const OP_CHECKSIG: u8 = 172;
+const OP_EQUAL: u8 = 135;
+const OP_CHECKMULTISIG: u8 = 174;
+const OP_1: u8 = 0x51;
+const OP_PUSHDATA1: u8 = 76;
+const OP_PUSHDATA2: u8 = 77;
+/// Most keys a standard multisig script can have
+pub const MAX_MULTISIG_KEYS: usize = 16;
+/// Version byte of the testnet P2PKH addresses
+const P2PKH_VERSION: u8 = 0x6f;
+/// Version byte of the testnet P2SH addresses
+const P2SH_VERSION: u8 = 0xc4;
 
 ///Adds a an i number of items to a stack
 ///modify the start of last_addition
@@ -38,7 +57,12 @@ pub fn get_sig_and_pubkey(script: &[u8]) -> Vec<Vec<u8>> {
 
 ///It applys the hash160 to an stack an returns the hash as a vec
 fn op_hash160(stack: &mut [u8]) -> Vec<u8> {
-    let hash = sha256::Hash::hash(stack);
+    hash160(stack)
+}
+
+/// RIPEMD160(SHA256(data)), used to hash the public keys
+pub fn hash160(data: &[u8]) -> Vec<u8> {
+    let hash = sha256::Hash::hash(data);
     let rip = ripemd160::Hash::hash(&hash.to_byte_array());
     rip.to_byte_array().to_vec()
 }
@@ -53,7 +77,7 @@ fn calculate_checksum(payload: &[u8]) -> [u8; 4] {
 
 ///adds the information needed to tranform the pubKey hash into an address
 fn from_pubkey_hash_to_address(stack: Vec<u8>) -> Vec<u8> {
-    let mut addres = vec![0x6f];
+    let mut addres = vec![P2PKH_VERSION];
     addres.extend_from_slice(&stack);
     let checksum = calculate_checksum(&addres);
     addres.extend_from_slice(&checksum);
@@ -180,8 +204,24 @@ fn bitcoin_address_output(script: &[u8]) -> Vec<u8> {
     stack
 }
 
-/// turns the addres from a vec to a string with b58
+/// turns the addres from a vec to a string with b58, P2WPKH outputs give their bech32 address
 #[must_use] pub fn bitcoin_address_in_b58_output(script: &[u8]) -> String {
+    if is_p2wpkh(script) {
+        return segwit_address_of_hash(&script[2..]);
+    }
+    if is_p2wsh(script) {
+        return encode_segwit_address(TESTNET_HRP, 0, &script[2..]).unwrap_or_default();
+    }
+    if is_p2sh(script) {
+        return p2sh_address_of_hash(&script[2..22]);
+    }
+    if is_p2tr(script) {
+        return taproot_address_of_key(&script[2..]);
+    }
+    // Bare multisig outputs have no address
+    if multisig_keys(script).is_some() {
+        return String::new();
+    }
     let vector = bitcoin_address_output(script);
     let addres = bs58::encode(vector).into_string();
     addres
@@ -193,3 +233,270 @@ fn bitcoin_address_output(script: &[u8]) -> Vec<u8> {
 pub fn from_adderss_to_vec(address: &str) -> Result<Vec<u8>, bs58::decode::Error> {
     bs58::decode(address).into_vec()
 }
+
+/// Returns the address of the key spending a P2WPKH input, whose witness is the signature and the public key.
+/// Empty if the witness isn't a P2WPKH one
+pub fn bitcoin_address_in_witness(witness: &[Vec<u8>]) -> String {
+    match witness {
+        [_, pubkey] if pubkey.len() == 33 => segwit_address_of_hash(&hash160(pubkey)),
+        _ => String::new(),
+    }
+}
+
+/// Testnet bech32 address of a P2WPKH pubkey hash, empty if the hash isn't 20 bytes long
+pub fn segwit_address_of_hash(pubkey_hash: &[u8]) -> String {
+    if pubkey_hash.len() != 20 {
+        return String::new();
+    }
+    encode_segwit_address(TESTNET_HRP, 0, pubkey_hash).unwrap_or_default()
+}
+
+/// Testnet bech32m address of a taproot output key, empty if the key isn't 32 bytes long
+pub fn taproot_address_of_key(output_key: &[u8]) -> String {
+    if output_key.len() != 32 {
+        return String::new();
+    }
+    encode_segwit_address(TESTNET_HRP, 1, output_key).unwrap_or_default()
+}
+
+/// OP_DUP OP_HASH160 <pubkey hash> OP_EQUALVERIFY OP_CHECKSIG
+pub fn p2pkh_script(pubkey_hash: &[u8]) -> Vec<u8> {
+    let mut script = vec![OP_DUP, OP_HASH160, pubkey_hash.len() as u8];
+    script.extend_from_slice(pubkey_hash);
+    script.extend_from_slice(&[OP_EQUALVERIFY, OP_CHECKSIG]);
+    script
+}
+
+/// OP_0 <pubkey hash>, a witness v0 keyhash program
+pub fn p2wpkh_script(pubkey_hash: &[u8]) -> Vec<u8> {
+    let mut script = vec![OP_0, pubkey_hash.len() as u8];
+    script.extend_from_slice(pubkey_hash);
+    script
+}
+
+/// Returns true if the script is a P2PKH output script
+pub fn is_p2pkh(script: &[u8]) -> bool {
+    script.len() == 25
+        && script[..3] == [OP_DUP, OP_HASH160, 20]
+        && script[23..] == [OP_EQUALVERIFY, OP_CHECKSIG]
+}
+
+/// Returns true if the script is a P2WPKH output script
+pub fn is_p2wpkh(script: &[u8]) -> bool {
+    script.len() == 22 && script[0] == OP_0 && script[1] == 20
+}
+
+/// Testnet base58 address of a P2SH script hash
+pub fn p2sh_address_of_hash(script_hash: &[u8]) -> String {
+    let mut address = vec![P2SH_VERSION];
+    address.extend_from_slice(script_hash);
+    let checksum = calculate_checksum(&address);
+    address.extend_from_slice(&checksum);
+    bs58::encode(address).into_string()
+}
+
+/// OP_HASH160 <script hash> OP_EQUAL, paying to the hash of a redeem script
+pub fn p2sh_script(redeem_script: &[u8]) -> Vec<u8> {
+    let mut script = vec![OP_HASH160, 20];
+    script.extend(hash160(redeem_script));
+    script.push(OP_EQUAL);
+    script
+}
+
+/// Returns true if the script is a P2SH output script
+pub fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23 && script[0] == OP_HASH160 && script[1] == 20 && script[22] == OP_EQUAL
+}
+
+/// OP_0 <sha256 of the witness script>, a witness v0 scripthash program
+pub fn p2wsh_script(witness_script: &[u8]) -> Vec<u8> {
+    let mut script = vec![OP_0, 32];
+    script.extend_from_slice(&sha256::Hash::hash(witness_script).to_byte_array());
+    script
+}
+
+/// Returns true if the script is a P2WSH output script
+pub fn is_p2wsh(script: &[u8]) -> bool {
+    script.len() == 34 && script[0] == OP_0 && script[1] == 32
+}
+
+/// <m> <pubkey>... <n> OP_CHECKMULTISIG, spendable with `threshold` signatures of the keys
+/// # Errors
+/// Returns an error if the threshold or the amount of keys are invalid
+pub fn multisig_script(
+    threshold: usize,
+    public_keys: &[Vec<u8>],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if public_keys.is_empty() || public_keys.len() > MAX_MULTISIG_KEYS {
+        return Err("A multisig script must have between 1 and 16 keys".into());
+    }
+    if threshold == 0 || threshold > public_keys.len() {
+        return Err("Invalid multisig threshold".into());
+    }
+    let mut script = vec![OP_1 + threshold as u8 - 1];
+    for public_key in public_keys {
+        script.push(public_key.len() as u8);
+        script.extend_from_slice(public_key);
+    }
+    script.push(OP_1 + public_keys.len() as u8 - 1);
+    script.push(OP_CHECKMULTISIG);
+    Ok(script)
+}
+
+/// Threshold and public keys of a `multisig_script`, None if the script isn't one
+pub fn multisig_keys(script: &[u8]) -> Option<(usize, Vec<Vec<u8>>)> {
+    let (&first, rest) = script.split_first()?;
+    let (&last, rest) = rest.split_last()?;
+    let (&count, mut keys_data) = rest.split_last()?;
+    if last != OP_CHECKMULTISIG
+        || first < OP_1
+        || count < first
+        || count >= OP_1 + MAX_MULTISIG_KEYS as u8
+    {
+        return None;
+    }
+    let mut public_keys = vec![];
+    while let Some((&len, data)) = keys_data.split_first() {
+        if (len != 33 && len != 65) || data.len() < len as usize {
+            return None;
+        }
+        public_keys.push(data[..len as usize].to_vec());
+        keys_data = &data[len as usize..];
+    }
+    if public_keys.len() != (count - OP_1 + 1) as usize {
+        return None;
+    }
+    Some(((first - OP_1 + 1) as usize, public_keys))
+}
+
+/// Pushes the data to the stack with the smallest push: its length, OP_PUSHDATA1 or OP_PUSHDATA2
+pub fn push_data(data: &[u8]) -> Vec<u8> {
+    let mut script = match data.len() {
+        len if len < OP_PUSHDATA1 as usize => vec![len as u8],
+        len if len <= u8::MAX as usize => vec![OP_PUSHDATA1, len as u8],
+        len => {
+            let mut script = vec![OP_PUSHDATA2];
+            script.extend_from_slice(&(len as u16).to_le_bytes());
+            script
+        }
+    };
+    script.extend_from_slice(data);
+    script
+}
+
+/// OP_1 <output key>, a witness v1 taproot program
+pub fn p2tr_script(output_key: &[u8]) -> Vec<u8> {
+    let mut script = vec![OP_1, output_key.len() as u8];
+    script.extend_from_slice(output_key);
+    script
+}
+
+/// Returns true if the script is a P2TR output script
+pub fn is_p2tr(script: &[u8]) -> bool {
+    script.len() == 34 && script[0] == OP_1 && script[1] == 32
+}
+
+/// Returns the output script paying to a testnet address, P2PKH or P2SH (base58),
+/// P2WPKH or P2WSH (bech32) or P2TR (bech32m)
+/// # Errors
+/// Returns an error if the address can't be decoded or its type isn't supported
+pub fn script_for_address(address: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Ok((version, program)) = decode_segwit_address(TESTNET_HRP, address) {
+        if version == 0 && program.len() == 20 {
+            return Ok(p2wpkh_script(&program));
+        }
+        if version == 0 && program.len() == 32 {
+            let mut script = vec![OP_0, 32];
+            script.extend(program);
+            return Ok(script);
+        }
+        if version == 1 && program.len() == 32 {
+            return Ok(p2tr_script(&program));
+        }
+        return Err("Unsupported segwit address".into());
+    }
+    let decoded = from_adderss_to_vec(address)?;
+    if decoded.len() != 25 || (decoded[0] != P2PKH_VERSION && decoded[0] != P2SH_VERSION) {
+        return Err("Unsupported address".into());
+    }
+    if calculate_checksum(&decoded[..21]) != decoded[21..] {
+        return Err("Invalid address checksum".into());
+    }
+    if decoded[0] == P2SH_VERSION {
+        let mut script = vec![OP_HASH160, 20];
+        script.extend_from_slice(&decoded[1..21]);
+        script.push(OP_EQUAL);
+        return Ok(script);
+    }
+    Ok(p2pkh_script(&decoded[1..21]))
+}
+
+#[cfg(test)]
+mod script_tools_tests {
+    use super::*;
+    use crate::utils::array_tools::cast_str_to_bytes_vec;
+
+    #[test]
+    fn test_p2wpkh_output_address() {
+        let hash = cast_str_to_bytes_vec("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let script = p2wpkh_script(&hash);
+        assert!(is_p2wpkh(&script));
+        assert_eq!(
+            bitcoin_address_in_b58_output(&script),
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+        );
+        assert_eq!(
+            script_for_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap(),
+            script
+        );
+    }
+
+    #[test]
+    fn test_script_for_p2pkh_address() {
+        let script = script_for_address("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc").unwrap();
+        assert_eq!(script.len(), 25);
+        assert!(!is_p2wpkh(&script));
+        assert_eq!(
+            bitcoin_address_in_b58_output(&script),
+            "mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWc"
+        );
+        assert!(is_p2pkh(&script));
+        assert!(script_for_address("mw2DzXinK8KaqunpYgjnGyCYcgHVb3SJWd").is_err());
+        assert!(script_for_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").is_err());
+    }
+
+    #[test]
+    fn test_p2tr_output_address() {
+        let key = cast_str_to_bytes_vec(
+            "000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+        )
+        .unwrap();
+        let address = "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c";
+        let script = p2tr_script(&key);
+        assert!(is_p2tr(&script));
+        assert!(!is_p2wpkh(&script));
+        assert_eq!(bitcoin_address_in_b58_output(&script), address);
+        assert_eq!(script_for_address(address).unwrap(), script);
+    }
+
+    #[test]
+    fn test_multisig_keys() {
+        let keys = vec![vec![2u8; 33], vec![3u8; 33], vec![4u8; 65]];
+        let script = multisig_script(2, &keys).unwrap();
+        assert_eq!(multisig_keys(&script), Some((2, keys.clone())));
+        assert_eq!(multisig_keys(&script[..script.len() - 1]), None);
+        assert_eq!(multisig_keys(&p2sh_script(&script)), None);
+        let mut wrong_count = script.clone();
+        wrong_count[script.len() - 2] = OP_1 + 3;
+        assert_eq!(multisig_keys(&wrong_count), None);
+        assert!(bitcoin_address_in_b58_output(&script).is_empty());
+    }
+
+    #[test]
+    fn test_push_data() {
+        assert_eq!(push_data(&[7; 3]), vec![3, 7, 7, 7]);
+        assert_eq!(push_data(&[7; 80])[..2], [OP_PUSHDATA1, 80]);
+        assert_eq!(push_data(&[7; 300])[..3], [OP_PUSHDATA2, 0x2c, 0x01]);
+        assert_eq!(push_data(&[7; 300]).len(), 303);
+    }
+}
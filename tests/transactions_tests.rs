@@ -83,12 +83,12 @@ fn test_wallet_flow_transaction() {
 
     wallet_handler.add_utxo_to_wallets(&utxo_collector);
 
-    let tx = wallet_handler
+    let (tx, _) = wallet_handler
         .create_transaction((
             "mhnNeQGhRKe28qGXZEvfAbWhWZ2ritjuZg".to_string(),
             "label".to_string(),
             5000,
-            3000,
+            10,
         ))
         .unwrap();
 